@@ -0,0 +1,107 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+
+    println!("cargo:rustc-env=BUILD_GIT_HASH={}", git_hash());
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp());
+    println!("cargo:rustc-env=BUILD_RUSTC={}", rustc_version());
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features());
+    println!(
+        "cargo:rustc-env=BUILD_HAL_VERSION={}",
+        lock_version(&manifest_dir, "stm32g0xx-hal")
+    );
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
+
+fn git_hash() -> String {
+    let hash = command_output("git", &["rev-parse", "--short", "HEAD"]);
+    let dirty = command_output("git", &["status", "--porcelain", "--untracked-files=no"]);
+    match (hash, dirty) {
+        (Some(hash), Some(dirty)) if !dirty.is_empty() => format!("{}-dirty", hash),
+        (Some(hash), _) => hash,
+        _ => "unknown".into(),
+    }
+}
+
+fn rustc_version() -> String {
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".into())
+}
+
+fn features() -> String {
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|name| name.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    features.join(",")
+}
+
+fn lock_version(manifest_dir: &str, package: &str) -> String {
+    let lock = fs::read_to_string(Path::new(manifest_dir).join("Cargo.lock")).unwrap_or_default();
+    let name = format!("name = \"{}\"", package);
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line == name {
+            if let Some(version) = lines.next().and_then(|l| l.strip_prefix("version = ")) {
+                return version.trim_matches('"').into();
+            }
+        }
+    }
+    "unknown".into()
+}
+
+fn timestamp() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+
+    // Days since epoch to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    let tod = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        tod / 3600,
+        tod / 60 % 60,
+        tod % 60
+    )
+}
+
+fn command_output(cmd: &str, args: &[&str]) -> Option<String> {
+    Command::new(cmd)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+}
@@ -1,6 +1,7 @@
 /* Linker script for the STM32G071RB */
 MEMORY
 {
-  FLASH : ORIGIN = 0x08000000, LENGTH = 128K
+  /* The last 12K (pages 58-63) hold settings written at runtime, see src/flash.rs */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 116K
   RAM : ORIGIN = 0x20000000, LENGTH = 36K
 }
\ No newline at end of file
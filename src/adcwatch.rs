@@ -0,0 +1,130 @@
+use hal::rcc::Rcc;
+use hal::stm32;
+use hal::timer::TimerExt;
+
+use crate::clocks;
+
+/// Default and highest sample rate, each sample is two blocking conversions
+pub const DEFAULT_RATE_HZ: u32 = 100;
+pub const MAX_RATE_HZ: u32 = 1000;
+/// Statistics go to the shell once a second
+const REPORT_HZ: u32 = 1;
+
+/// Running statistics of the watched pin in millivolts
+#[derive(Clone, Copy)]
+pub struct Stats {
+    pub pin: u8,
+    pub last: u32,
+    pub min: u32,
+    pub max: u32,
+    pub sum: u64,
+    pub count: u32,
+}
+
+impl Stats {
+    pub fn avg(&self) -> u32 {
+        (self.sum / self.count.max(1) as u64) as u32
+    }
+}
+
+/// Samples a port A pin from TIM6 at a fixed rate for `adc watch`, the sampling task
+/// feeds the statistics and the shell prints them until a key is pressed
+pub struct AdcWatch {
+    tim: stm32::TIM6,
+    rate: u32,
+    stats: Option<Stats>,
+    /// Samples since the last report
+    pending: u32,
+    due: bool,
+}
+
+impl AdcWatch {
+    pub fn new(tim: stm32::TIM6, rcc: &mut Rcc) -> Self {
+        Self {
+            tim: tim.timer(rcc).release(),
+            rate: DEFAULT_RATE_HZ,
+            stats: None,
+            pending: 0,
+            due: false,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.stats.is_some()
+    }
+
+    pub fn pin(&self) -> Option<u8> {
+        self.stats.map(|stats| stats.pin)
+    }
+
+    pub fn start(&mut self, pin: u8, rate: u32) {
+        self.rate = rate;
+        self.stats = Some(Stats {
+            pin,
+            last: 0,
+            min: u32::MAX,
+            max: 0,
+            sum: 0,
+            count: 0,
+        });
+        self.pending = 0;
+        self.due = false;
+        self.retime();
+        self.tim.dier.modify(|_, w| w.uie().set_bit());
+    }
+
+    /// Final statistics, `None` if nothing was watched
+    pub fn stop(&mut self) -> Option<Stats> {
+        self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+        self.tim.dier.modify(|_, w| w.uie().clear_bit());
+        self.due = false;
+        self.stats.take()
+    }
+
+    /// Recomputes prescaler for the current timer clock
+    pub fn retime(&mut self) {
+        if self.stats.is_none() {
+            return;
+        }
+        let cycles = clocks::timer_clk() / self.rate;
+        let psc = (cycles - 1) / 0xffff;
+        let arr = cycles / (psc + 1) - 1;
+        let tim = &self.tim;
+        tim.cr1.modify(|_, w| w.cen().clear_bit());
+        tim.cnt.reset();
+        tim.psc.write(|w| unsafe { w.psc().bits(psc as u16) });
+        tim.arr.write(|w| unsafe { w.bits(arr) });
+        tim.cr1.modify(|_, w| w.cen().set_bit().urs().set_bit());
+    }
+
+    pub fn clear_irq(&mut self) {
+        self.tim.sr.modify(|_, w| w.uif().clear_bit());
+    }
+
+    /// Adds a sample, true once a report is due
+    pub fn record(&mut self, mv: u32) -> bool {
+        let stats = match self.stats.as_mut() {
+            Some(stats) => stats,
+            None => return false,
+        };
+        stats.last = mv;
+        stats.min = stats.min.min(mv);
+        stats.max = stats.max.max(mv);
+        stats.sum += mv as u64;
+        stats.count += 1;
+        self.pending += 1;
+        if self.pending >= self.rate / REPORT_HZ {
+            self.pending = 0;
+            self.due = true;
+        }
+        self.due
+    }
+
+    pub fn take_due(&mut self) -> Option<Stats> {
+        if !self.due {
+            return None;
+        }
+        self.due = false;
+        self.stats
+    }
+}
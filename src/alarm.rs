@@ -0,0 +1,46 @@
+use heapless::String;
+
+use crate::config::CMD_MAX_LEN;
+
+/// Shell command run when the RTC alarm fires. The alarm task only flags it, the shell
+/// task runs it like a scheduled job.
+pub struct Alarm {
+    line: Option<String<CMD_MAX_LEN>>,
+    due: bool,
+}
+
+impl Alarm {
+    pub fn new() -> Self {
+        Self {
+            line: None,
+            due: false,
+        }
+    }
+
+    pub fn set(&mut self, line: &str) {
+        self.line = Some(line.into());
+        self.due = false;
+    }
+
+    pub fn clear(&mut self) {
+        self.line = None;
+        self.due = false;
+    }
+
+    pub fn line(&self) -> Option<&str> {
+        self.line.as_deref()
+    }
+
+    pub fn mark_due(&mut self) {
+        self.due = self.line.is_some();
+    }
+
+    /// Command of a fired alarm, the alarm stays armed for the next day
+    pub fn take_due(&mut self) -> Option<String<CMD_MAX_LEN>> {
+        if !self.due {
+            return None;
+        }
+        self.due = false;
+        self.line.clone()
+    }
+}
@@ -0,0 +1,127 @@
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{self, Mutex};
+use heapless::{FnvIndexMap, String, Vec};
+
+use crate::catalog;
+use crate::config::CMD_MAX_LEN;
+
+/// Aliases defined at the same time, a power of two for the index map
+pub const MAX_ALIASES: usize = 8;
+pub const NAME_LEN: usize = 16;
+
+/// Kept in RAM until reset. The autocompletion reads them too, it lives in the shell and
+/// can't lock a shared resource.
+static ALIASES: Mutex<RefCell<FnvIndexMap<String<NAME_LEN>, String<CMD_MAX_LEN>, MAX_ALIASES>>> =
+    Mutex::new(RefCell::new(FnvIndexMap::new()));
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum AliasError {
+    BadName,
+    /// The name is taken by a built-in command
+    Builtin,
+    Empty,
+    Full,
+    NotFound,
+    LineTooLong,
+}
+
+impl AliasError {
+    pub fn message(self) -> &'static str {
+        match self {
+            AliasError::BadName => "alias name must be 1 to 16 letters, digits, - or _",
+            AliasError::Builtin => "a command of that name exists",
+            AliasError::Empty => "alias needs a command",
+            AliasError::Full => "no free alias slot",
+            AliasError::NotFound => "no such alias",
+            AliasError::LineTooLong => "expanded line too long",
+        }
+    }
+}
+
+/// Defines or replaces an alias from `name=command`, the command may be double quoted
+pub fn define(def: &str) -> Result<(), AliasError> {
+    let (name, line) = def.split_once('=').ok_or(AliasError::Empty)?;
+    let line = line.trim();
+    let line = line
+        .strip_prefix('"')
+        .and_then(|line| line.strip_suffix('"'))
+        .unwrap_or(line)
+        .trim();
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+    if name.is_empty() || name.len() > NAME_LEN || !name.chars().all(valid) {
+        return Err(AliasError::BadName);
+    }
+    if catalog::find(name).is_some() {
+        return Err(AliasError::Builtin);
+    }
+    if line.is_empty() {
+        return Err(AliasError::Empty);
+    }
+    interrupt::free(|cs| {
+        ALIASES
+            .borrow(cs)
+            .borrow_mut()
+            .insert(String::from(name), String::from(line))
+            .map(|_| ())
+            .map_err(|_| AliasError::Full)
+    })
+}
+
+pub fn remove(name: &str) -> Result<(), AliasError> {
+    if name.len() > NAME_LEN {
+        return Err(AliasError::NotFound);
+    }
+    let name: String<NAME_LEN> = String::from(name);
+    interrupt::free(|cs| ALIASES.borrow(cs).borrow_mut().remove(&name))
+        .map(|_| ())
+        .ok_or(AliasError::NotFound)
+}
+
+/// Names and commands in the order they were defined
+pub fn list() -> Vec<(String<NAME_LEN>, String<CMD_MAX_LEN>), MAX_ALIASES> {
+    interrupt::free(|cs| {
+        ALIASES
+            .borrow(cs)
+            .borrow()
+            .iter()
+            .map(|(name, line)| (name.clone(), line.clone()))
+            .collect()
+    })
+}
+
+/// The line an alias stands for with `args` appended, `None` when `cmd` is no alias.
+/// Expands once, an alias naming another alias is not followed.
+pub fn expand(cmd: &str, args: &str) -> Result<Option<String<CMD_MAX_LEN>>, AliasError> {
+    if cmd.len() > NAME_LEN {
+        return Ok(None);
+    }
+    let name: String<NAME_LEN> = String::from(cmd);
+    let line = match interrupt::free(|cs| ALIASES.borrow(cs).borrow().get(&name).cloned()) {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let mut expanded = line;
+    if !args.is_empty() {
+        expanded
+            .push(' ')
+            .and_then(|_| expanded.push_str(args))
+            .map_err(|_| AliasError::LineTooLong)?;
+    }
+    Ok(Some(expanded))
+}
+
+/// Rest of the first alias name starting with `prefix`, for the autocompletion
+pub fn suggest(prefix: &str) -> Option<String<CMD_MAX_LEN>> {
+    if prefix.is_empty() {
+        return None;
+    }
+    interrupt::free(|cs| {
+        ALIASES
+            .borrow(cs)
+            .borrow()
+            .keys()
+            .find(|name| name.starts_with(prefix))
+            .map(|name| String::from(&name[prefix.len()..]))
+    })
+}
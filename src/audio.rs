@@ -0,0 +1,65 @@
+/// Full-scale amplitude maps to full brightness
+const FULL_SCALE: u32 = 2048;
+/// Fixed-point fraction bits of the envelope level
+const FRAC_BITS: u32 = 8;
+
+/// Peak envelope follower driving LED brightness, stepped once per PWM period (1ms)
+pub struct Envelope {
+    attack_ms: u32,
+    decay_ms: u32,
+    gain: u32,
+    level: u32,
+}
+
+impl Envelope {
+    pub const MAX_TIME_MS: u32 = 10_000;
+    pub const MAX_GAIN: u32 = 64;
+
+    pub fn new() -> Self {
+        Self {
+            attack_ms: 5,
+            decay_ms: 200,
+            gain: 1,
+            level: 0,
+        }
+    }
+
+    pub fn attack_ms(&self) -> u32 {
+        self.attack_ms
+    }
+
+    pub fn set_attack_ms(&mut self, attack_ms: u32) {
+        self.attack_ms = attack_ms.max(1);
+    }
+
+    pub fn decay_ms(&self) -> u32 {
+        self.decay_ms
+    }
+
+    pub fn set_decay_ms(&mut self, decay_ms: u32) {
+        self.decay_ms = decay_ms.max(1);
+    }
+
+    pub fn gain(&self) -> u32 {
+        self.gain
+    }
+
+    pub fn set_gain(&mut self, gain: u32) {
+        self.gain = gain.max(1);
+    }
+
+    pub fn reset(&mut self) {
+        self.level = 0;
+    }
+
+    /// Moves towards the input amplitude, returns brightness in percent
+    pub fn step(&mut self, amplitude: u16) -> u8 {
+        let target = ((amplitude as u32 * self.gain * 100 / FULL_SCALE).min(100)) << FRAC_BITS;
+        if target > self.level {
+            self.level += (target - self.level).div_ceil(self.attack_ms);
+        } else {
+            self.level -= (self.level - target).div_ceil(self.decay_ms);
+        }
+        (self.level >> FRAC_BITS) as u8
+    }
+}
@@ -0,0 +1,44 @@
+use hal::stm32;
+
+/// TAMP backup register allocation
+#[derive(Clone, Copy)]
+pub enum Slot {
+    PowerFail = 0,
+    /// Standby resume state, or a `reboot safe` request
+    Standby = 1,
+    PidGains = 2,
+    PidKd = 3,
+    SignNonce = 4,
+}
+
+/// Enables access to the backup domain, must run before any read or write
+pub fn init() {
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    let pwr = unsafe { &*stm32::PWR::ptr() };
+    rcc.apbenr1
+        .modify(|_, w| w.rtcapben().set_bit().pwren().set_bit());
+    pwr.cr1.modify(|_, w| w.dbp().set_bit());
+    while pwr.cr1.read().dbp().bit_is_clear() {}
+}
+
+pub fn read(slot: Slot) -> u32 {
+    let tamp = unsafe { &*stm32::TAMP::ptr() };
+    match slot {
+        Slot::PowerFail => tamp.bkp0r.read().bits(),
+        Slot::Standby => tamp.bkp1r.read().bits(),
+        Slot::PidGains => tamp.bkp2r.read().bits(),
+        Slot::PidKd => tamp.bkp3r.read().bits(),
+        Slot::SignNonce => tamp.bkp4r.read().bits(),
+    }
+}
+
+pub fn write(slot: Slot, value: u32) {
+    let tamp = unsafe { &*stm32::TAMP::ptr() };
+    match slot {
+        Slot::PowerFail => tamp.bkp0r.write(|w| unsafe { w.bits(value) }),
+        Slot::Standby => tamp.bkp1r.write(|w| unsafe { w.bits(value) }),
+        Slot::PidGains => tamp.bkp2r.write(|w| unsafe { w.bits(value) }),
+        Slot::PidKd => tamp.bkp3r.write(|w| unsafe { w.bits(value) }),
+        Slot::SignNonce => tamp.bkp4r.write(|w| unsafe { w.bits(value) }),
+    }
+}
@@ -0,0 +1,149 @@
+use hal::stm32;
+use heapless::Vec;
+
+use crate::cycles;
+
+pub const MAX_STEPS: usize = 32;
+pub const MAX_READS: usize = 64;
+pub const MAX_LOOP: u32 = 10_000;
+/// Longest run with interrupts masked, the UART overruns on anything typed meanwhile
+pub const MAX_RUN_US: u64 = 100_000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Op {
+    Set(u8, bool),
+    Delay(u32),
+    Read(u8),
+    /// Runs the steps since the previous loop, or the start, this many times in total
+    Loop(u32),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BitbangError {
+    Full,
+    Empty,
+    TooLong,
+}
+
+impl BitbangError {
+    pub fn message(self) -> &'static str {
+        match self {
+            BitbangError::Full => "sequence is full",
+            BitbangError::Empty => "sequence is empty",
+            BitbangError::TooLong => "sequence runs too long",
+        }
+    }
+}
+
+/// Levels sampled by the read steps, in order
+pub struct Reads {
+    pub levels: Vec<bool, MAX_READS>,
+    pub dropped: u32,
+}
+
+/// Port A pin wiggling sequence for protocol experiments, built step by step from the
+/// shell and run with interrupts masked so the timing does not jitter
+pub struct Bitbang {
+    ops: Vec<Op, MAX_STEPS>,
+}
+
+impl Bitbang {
+    pub fn new() -> Self {
+        Self { ops: Vec::new() }
+    }
+
+    /// Copy of the steps, for listing them outside the lock
+    pub fn ops(&self) -> Vec<Op, MAX_STEPS> {
+        self.ops.clone()
+    }
+
+    pub fn push(&mut self, op: Op) -> Result<(), BitbangError> {
+        self.ops.push(op).map_err(|_| BitbangError::Full)
+    }
+
+    pub fn clear(&mut self) {
+        self.ops.clear();
+    }
+
+    /// Pins driven by set steps
+    pub fn outputs(&self) -> impl Iterator<Item = u8> + '_ {
+        self.ops.iter().filter_map(|op| match op {
+            Op::Set(pin, _) => Some(*pin),
+            _ => None,
+        })
+    }
+
+    /// Total of all delays in microseconds, loops included
+    pub fn duration_us(&self) -> u64 {
+        let mut total = 0;
+        let mut body = 0;
+        for op in self.ops.iter() {
+            match op {
+                Op::Delay(us) => body += *us as u64,
+                Op::Loop(count) => {
+                    total += body * *count as u64;
+                    body = 0;
+                }
+                _ => {}
+            }
+        }
+        total + body
+    }
+
+    /// Executes the sequence once, blocking the whole core until it is done
+    pub fn run(&self) -> Result<Reads, BitbangError> {
+        if self.ops.is_empty() {
+            return Err(BitbangError::Empty);
+        }
+        if self.duration_us() > MAX_RUN_US {
+            return Err(BitbangError::TooLong);
+        }
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let cycles_per_us = cycles::freq() / 1_000_000;
+        let mut reads = Reads {
+            levels: Vec::new(),
+            dropped: 0,
+        };
+
+        cortex_m::interrupt::free(|_| {
+            let mut pc = 0;
+            let mut loop_start = 0;
+            let mut remaining = None;
+            while let Some(op) = self.ops.get(pc) {
+                pc += 1;
+                match *op {
+                    Op::Set(pin, high) => {
+                        let bit = if high { pin } else { pin + 16 };
+                        gpio.bsrr.write(|w| unsafe { w.bits(1 << bit) });
+                    }
+                    Op::Delay(us) => {
+                        let start = cycles::now();
+                        let wait = us * cycles_per_us;
+                        while cycles::since(start) < wait {}
+                    }
+                    Op::Read(pin) => {
+                        let high = gpio.idr.read().bits() & (1 << pin) != 0;
+                        if reads.levels.push(high).is_err() {
+                            reads.dropped += 1;
+                        }
+                    }
+                    Op::Loop(count) => match remaining {
+                        None if count > 1 => {
+                            remaining = Some(count - 2);
+                            pc = loop_start;
+                        }
+                        Some(left) if left > 0 => {
+                            remaining = Some(left - 1);
+                            pc = loop_start;
+                        }
+                        _ => {
+                            remaining = None;
+                            loop_start = pc;
+                        }
+                    },
+                }
+            }
+        });
+        Ok(reads)
+    }
+}
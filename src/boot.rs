@@ -0,0 +1,157 @@
+use hal::stm32;
+
+pub const SYSTEM_MEMORY: u32 = 0x1fff_0000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BootTarget {
+    Flash,
+    SystemMemory,
+    Sram,
+}
+
+impl BootTarget {
+    pub fn name(self) -> &'static str {
+        match self {
+            BootTarget::Flash => "main flash",
+            BootTarget::SystemMemory => "system memory (ROM bootloader)",
+            BootTarget::Sram => "SRAM",
+        }
+    }
+}
+
+/// RCC_CSR reset flags, most specific first: every reset also drives NRST and sets PINRSTF
+const RESET_FLAGS: [(u32, &str); 7] = [
+    (31, "low-power"),
+    (30, "window watchdog"),
+    (29, "independent watchdog"),
+    (28, "software"),
+    (27, "brown-out or power-on"),
+    (25, "option byte load"),
+    (26, "reset pin"),
+];
+
+/// Cause of the last reset, the flags are left set
+pub fn reset_cause() -> &'static str {
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    let csr = rcc.csr.read().bits();
+    RESET_FLAGS
+        .iter()
+        .find(|(bit, _)| csr & (1 << bit) != 0)
+        .map_or("unknown", |(_, name)| name)
+}
+
+/// Snapshot of the boot related option bytes and pins
+pub struct BootConfig {
+    pub rdp_level: u8,
+    pub boot0_from_pin: bool,
+    pub boot0: bool,
+    pub boot1: bool,
+    pub flash_empty: bool,
+    pub bootloader_sp: u32,
+    pub bootloader_entry: u32,
+}
+
+impl BootConfig {
+    pub fn read() -> Self {
+        let flash = unsafe { &*stm32::FLASH::ptr() };
+        let gpioa = unsafe { &*stm32::GPIOA::ptr() };
+        let optr = flash.optr.read();
+
+        let rdp_level = match optr.rdp().bits() {
+            0xaa => 0,
+            0xcc => 2,
+            _ => 1,
+        };
+        // BOOT0 comes from PA14 unless nBOOT_SEL selects the nBOOT0 option bit
+        let boot0_from_pin = optr.n_boot_sel().bit_is_clear();
+        let boot0 = if boot0_from_pin {
+            gpioa.idr.read().bits() & (1 << 14) != 0
+        } else {
+            optr.n_boot0().bit_is_clear()
+        };
+
+        let vectors = SYSTEM_MEMORY as *const u32;
+        Self {
+            rdp_level,
+            boot0_from_pin,
+            boot0,
+            boot1: optr.n_boot1().bit_is_clear(),
+            flash_empty: flash.acr.read().empty().bit_is_set(),
+            bootloader_sp: unsafe { vectors.read_volatile() },
+            bootloader_entry: unsafe { vectors.add(1).read_volatile() },
+        }
+    }
+
+    /// Where the core starts after the next reset
+    pub fn reset_target(&self) -> BootTarget {
+        match (self.boot0, self.boot1) {
+            (false, _) if self.flash_empty => BootTarget::SystemMemory,
+            (false, _) => BootTarget::Flash,
+            (true, false) => BootTarget::SystemMemory,
+            (true, true) => BootTarget::Sram,
+        }
+    }
+
+    /// ROM bootloader has a sane vector table and is not locked out by RDP level 2
+    pub fn bootloader_reachable(&self) -> bool {
+        self.rdp_level < 2
+            && (0x2000_0000..=0x2000_9000).contains(&self.bootloader_sp)
+            && (SYSTEM_MEMORY..SYSTEM_MEMORY + 0x2000).contains(&(self.bootloader_entry & !1))
+    }
+}
+
+/// Resets the whole chip through the NVIC, the same as a reset pin pulse
+pub fn reboot() -> ! {
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
+/// Hands the core to the ROM bootloader as if it had booted there: the clock tree goes
+/// back to HSI16, every peripheral the firmware touched is reset and no interrupt is
+/// left enabled or pending, so the bootloader finds the chip the way it expects.
+pub fn jump_to_bootloader() -> ! {
+    cortex_m::interrupt::disable();
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+
+    rcc.cfgr.modify(|_, w| unsafe { w.sw().bits(0b000) });
+    while rcc.cfgr.read().sws().bits() != 0b000 {}
+    rcc.cr.modify(|_, w| w.pllon().clear_bit());
+
+    // Timers, the shell USART and DMA all sit behind these reset lines
+    rcc.apbrstr1.write(|w| unsafe { w.bits(!0) });
+    rcc.apbrstr1.write(|w| unsafe { w.bits(0) });
+    rcc.apbrstr2.write(|w| unsafe { w.bits(!0) });
+    rcc.apbrstr2.write(|w| unsafe { w.bits(0) });
+    rcc.ahbrstr.write(|w| unsafe { w.bits(!0) });
+    rcc.ahbrstr.write(|w| unsafe { w.bits(0) });
+
+    unsafe {
+        let syst = &*cortex_m::peripheral::SYST::ptr();
+        syst.csr.write(0);
+        let nvic = &*cortex_m::peripheral::NVIC::ptr();
+        nvic.icer[0].write(!0);
+        nvic.icpr[0].write(!0);
+    }
+
+    // Map system memory at 0 so the bootloader's own vector table is the live one
+    rcc.apbenr2.modify(|_, w| w.syscfgen().set_bit());
+    let syscfg = unsafe { &*stm32::SYSCFG_VREFBUF::ptr() };
+    syscfg
+        .cfgr1
+        .modify(|_, w| unsafe { w.mem_mode().bits(0b01) });
+
+    unsafe { start(SYSTEM_MEMORY as *const u32) }
+}
+
+/// Loads the stack pointer from the first word of a vector table and branches to the
+/// reset handler in the second, on the main stack
+///
+/// # Safety
+///
+/// `vectors` must point at a valid vector table, nothing of the caller survives.
+unsafe fn start(vectors: *const u32) -> ! {
+    let scb = &*cortex_m::peripheral::SCB::ptr();
+    scb.vtor.write(vectors as u32);
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+    cortex_m::asm::bootload(vectors)
+}
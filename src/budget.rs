@@ -0,0 +1,109 @@
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::interrupt::{self, Mutex};
+use heapless::String;
+
+use crate::cycles;
+use crate::metrics;
+
+pub const MIN_BUDGET_MS: u32 = 10;
+/// TIM2 wraps after a minute at 64MHz, longer budgets could not be measured
+pub const MAX_BUDGET_MS: u32 = 30_000;
+const NAME_LEN: usize = 16;
+
+/// Time a command may take, 0 while off
+static BUDGET_MS: AtomicU32 = AtomicU32::new(0);
+/// Start of the outermost command and how many commands run inside it, the lines of a
+/// script count against the `run` that started them
+static START: AtomicU32 = AtomicU32::new(0);
+static DEPTH: AtomicU32 = AtomicU32::new(0);
+static LAST: Mutex<RefCell<Option<Overrun>>> = Mutex::new(RefCell::new(None));
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BudgetError {
+    Range,
+}
+
+impl BudgetError {
+    pub fn message(self) -> &'static str {
+        match self {
+            BudgetError::Range => "budget must be 10-30000ms",
+        }
+    }
+}
+
+/// Last command that ran over its budget
+#[derive(Clone)]
+pub struct Overrun {
+    pub cmd: String<NAME_LEN>,
+    pub elapsed_ms: u32,
+    pub budget_ms: u32,
+}
+
+pub fn budget_ms() -> Option<u32> {
+    match BUDGET_MS.load(Ordering::Relaxed) {
+        0 => None,
+        ms => Some(ms),
+    }
+}
+
+pub fn set_budget_ms(ms: Option<u32>) -> Result<(), BudgetError> {
+    match ms {
+        Some(ms) if !(MIN_BUDGET_MS..=MAX_BUDGET_MS).contains(&ms) => Err(BudgetError::Range),
+        _ => {
+            BUDGET_MS.store(ms.unwrap_or(0), Ordering::Relaxed);
+            Ok(())
+        }
+    }
+}
+
+fn elapsed_ms() -> u32 {
+    cycles::since(START.load(Ordering::Relaxed)) / (cycles::freq() / 1000).max(1)
+}
+
+/// A command starts, only the outermost one starts the clock
+pub fn enter() {
+    let depth = DEPTH.load(Ordering::Relaxed);
+    if depth == 0 {
+        START.store(cycles::now(), Ordering::Relaxed);
+    }
+    DEPTH.store(depth + 1, Ordering::Relaxed);
+}
+
+/// A command returned. Once the outermost one ran over the budget this logs it and
+/// returns the time it took.
+pub fn leave(cmd: &str) -> Option<u32> {
+    let depth = DEPTH.load(Ordering::Relaxed).saturating_sub(1);
+    DEPTH.store(depth, Ordering::Relaxed);
+    let budget_ms = budget_ms()?;
+    let elapsed_ms = elapsed_ms();
+    if depth != 0 || elapsed_ms <= budget_ms {
+        return None;
+    }
+    let mut name = String::new();
+    for c in cmd.chars().take(NAME_LEN) {
+        name.push(c).ok();
+    }
+    let overrun = Overrun {
+        cmd: name,
+        elapsed_ms,
+        budget_ms,
+    };
+    interrupt::free(|cs| LAST.borrow(cs).replace(Some(overrun)));
+    metrics::COMMAND_TIMEOUTS.inc();
+    Some(elapsed_ms)
+}
+
+/// Busy-waits that poll for an edge or a key check this and give up early once the
+/// running command is over its budget
+pub fn expired() -> bool {
+    match budget_ms() {
+        Some(budget_ms) => DEPTH.load(Ordering::Relaxed) > 0 && elapsed_ms() > budget_ms,
+        None => false,
+    }
+}
+
+pub fn last() -> Option<Overrun> {
+    interrupt::free(|cs| LAST.borrow(cs).borrow().clone())
+}
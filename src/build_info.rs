@@ -0,0 +1,17 @@
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub timestamp: &'static str,
+    pub rustc: &'static str,
+    pub features: &'static str,
+    pub hal_version: &'static str,
+}
+
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    git_hash: env!("BUILD_GIT_HASH"),
+    timestamp: env!("BUILD_TIMESTAMP"),
+    rustc: env!("BUILD_RUSTC"),
+    features: env!("BUILD_FEATURES"),
+    hal_version: env!("BUILD_HAL_VERSION"),
+};
@@ -0,0 +1,253 @@
+use heapless::String;
+
+use crate::flash::{self, FlashError, Page, PAGE_SIZE};
+use crate::pattern::{BlinkPattern, PatternError, MAX_STEPS};
+use crate::pwmout::Channel;
+
+pub const MAX_BUNDLES: usize = 8;
+pub const NAME_LEN: usize = 12;
+/// Slot: NUL padded name and pattern, ringtone index, output pin or 0xff for none
+const SLOT_SIZE: usize = NAME_LEN + MAX_STEPS + 2;
+const NO_CHANNEL: u8 = 0xff;
+/// A ring gives up after a minute, like most alarm clocks
+pub const RING_MS: u64 = 60_000;
+/// Square wave for the piezo
+pub const TONE_DUTY: u8 = 50;
+
+type Melody = &'static [(u16, u16)];
+
+/// Built-in ringtones as frequency in Hertz and length in milliseconds, 0Hz is a rest.
+/// Each one loops until the ring ends.
+pub const RINGTONES: [(&str, Melody); 4] = [
+    ("beep", &[(2000, 100), (0, 100), (2000, 100), (0, 700)]),
+    (
+        "chime",
+        &[
+            (1319, 300),
+            (1047, 300),
+            (1175, 300),
+            (784, 600),
+            (0, 300),
+            (784, 300),
+            (1175, 300),
+            (1319, 300),
+            (1047, 600),
+            (0, 1200),
+        ],
+    ),
+    (
+        "scale",
+        &[
+            (523, 150),
+            (587, 150),
+            (659, 150),
+            (698, 150),
+            (784, 150),
+            (880, 150),
+            (988, 150),
+            (1047, 300),
+            (0, 600),
+        ],
+    ),
+    ("siren", &[(880, 400), (660, 400)]),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum BundleError {
+    BadName,
+    Ringtone,
+    NoSlot,
+    NotFound,
+    Pattern(PatternError),
+    Flash(FlashError),
+}
+
+impl BundleError {
+    pub fn message(self) -> &'static str {
+        match self {
+            BundleError::BadName => "bundle name must be 1 to 12 characters",
+            BundleError::Ringtone => "no such ringtone",
+            BundleError::NoSlot => "no free bundle slot",
+            BundleError::NotFound => "no such bundle",
+            BundleError::Pattern(err) => err.message(),
+            BundleError::Flash(err) => err.message(),
+        }
+    }
+}
+
+/// LED pattern, ringtone and output pin played together, kept in a flash page
+#[derive(Clone, Copy)]
+pub struct Bundle {
+    pub name: &'static str,
+    /// Bit string or built-in name, as given to `pattern`
+    pub pattern: &'static str,
+    pub ringtone: usize,
+    /// Pin the piezo hangs on, `None` for a silent bundle
+    pub channel: Option<Channel>,
+}
+
+pub fn ringtone(name: &str) -> Option<usize> {
+    RINGTONES
+        .iter()
+        .position(|(tone_name, _)| *tone_name == name)
+}
+
+fn field(bytes: &'static [u8]) -> Option<&'static str> {
+    let len = bytes.iter().position(|c| *c == 0).unwrap_or(bytes.len());
+    core::str::from_utf8(&bytes[..len]).ok()
+}
+
+fn slot(slot: usize) -> Option<Bundle> {
+    let bytes = &flash::read(Page::Bundles)[slot * SLOT_SIZE..][..SLOT_SIZE];
+    if bytes[0] == 0xff || bytes[0] == 0 {
+        return None;
+    }
+    let ringtone = bytes[NAME_LEN + MAX_STEPS] as usize;
+    Some(Bundle {
+        name: field(&bytes[..NAME_LEN])?,
+        pattern: field(&bytes[NAME_LEN..][..MAX_STEPS])?,
+        ringtone: ringtone.min(RINGTONES.len() - 1),
+        channel: Channel::from_pin(bytes[NAME_LEN + MAX_STEPS + 1]),
+    })
+}
+
+fn slot_of(name: &str) -> Option<usize> {
+    (0..MAX_BUNDLES).find(|n| slot(*n).is_some_and(|bundle| bundle.name == name))
+}
+
+pub fn find(name: &str) -> Option<Bundle> {
+    slot_of(name).and_then(slot)
+}
+
+pub fn list() -> impl Iterator<Item = Bundle> {
+    (0..MAX_BUNDLES).filter_map(slot)
+}
+
+/// Saves a bundle, replacing one of the same name
+pub fn save(
+    name: &str,
+    pattern: &str,
+    ringtone: usize,
+    channel: Option<Channel>,
+) -> Result<(), BundleError> {
+    if name.is_empty() || name.len() > NAME_LEN || name.contains(' ') {
+        return Err(BundleError::BadName);
+    }
+    BlinkPattern::new()
+        .set(pattern)
+        .map_err(BundleError::Pattern)?;
+    if ringtone >= RINGTONES.len() {
+        return Err(BundleError::Ringtone);
+    }
+    let n = slot_of(name)
+        .or_else(|| (0..MAX_BUNDLES).find(|n| slot(*n).is_none()))
+        .ok_or(BundleError::NoSlot)?;
+    let mut bytes = [0; SLOT_SIZE];
+    bytes[..name.len()].copy_from_slice(name.as_bytes());
+    bytes[NAME_LEN..][..pattern.len()].copy_from_slice(pattern.as_bytes());
+    bytes[NAME_LEN + MAX_STEPS] = ringtone as u8;
+    bytes[NAME_LEN + MAX_STEPS + 1] = channel.map_or(NO_CHANNEL, Channel::pin);
+    write_slot(n, &bytes)
+}
+
+pub fn delete(name: &str) -> Result<(), BundleError> {
+    let n = slot_of(name).ok_or(BundleError::NotFound)?;
+    write_slot(n, &[0xff; SLOT_SIZE])
+}
+
+fn write_slot(n: usize, bytes: &[u8; SLOT_SIZE]) -> Result<(), BundleError> {
+    let mut page = [0xff; PAGE_SIZE];
+    page.copy_from_slice(flash::read(Page::Bundles));
+    page[n * SLOT_SIZE..][..SLOT_SIZE].copy_from_slice(bytes);
+    flash::write(Page::Bundles, &page).map_err(BundleError::Flash)
+}
+
+/// What the ring task does next
+pub enum RingStep {
+    /// Wakeup of a ring that was stopped or restarted
+    Stale,
+    /// Tone on the channel until the monotonic time given, 0Hz is a rest
+    Note(Option<Channel>, u32, u64),
+    /// The ring timed out, the channel goes quiet
+    Done(Option<Channel>),
+}
+
+/// Plays a bundle: the shell sets its pattern, the ring task steps the ringtone and
+/// the shell puts the old pattern back once it ends
+pub struct Ringer {
+    /// Bumped on each start and stop, so a stale wakeup is ignored
+    id: u16,
+    bundle: Option<Bundle>,
+    note: usize,
+    end_ms: u64,
+    /// Pattern and animation state the bundle replaced
+    restore: Option<(String<MAX_STEPS>, bool)>,
+    finished: bool,
+}
+
+impl Ringer {
+    pub fn new() -> Self {
+        Self {
+            id: 0,
+            bundle: None,
+            note: 0,
+            end_ms: 0,
+            restore: None,
+            finished: false,
+        }
+    }
+
+    /// Returns the id the first wakeup has to carry
+    pub fn start(
+        &mut self,
+        bundle: Bundle,
+        now_ms: u64,
+        restore: (String<MAX_STEPS>, bool),
+    ) -> u16 {
+        self.id = self.id.wrapping_add(1);
+        self.bundle = Some(bundle);
+        self.note = 0;
+        self.end_ms = now_ms + RING_MS;
+        if self.restore.is_none() {
+            self.restore = Some(restore);
+        }
+        self.finished = false;
+        self.id
+    }
+
+    /// Ends the ring, returns its channel and the pattern and animation state to put back
+    pub fn stop(&mut self) -> Option<(Option<Channel>, String<MAX_STEPS>, bool)> {
+        self.id = self.id.wrapping_add(1);
+        self.finished = false;
+        let channel = self.bundle.take().and_then(|bundle| bundle.channel);
+        self.restore
+            .take()
+            .map(|(pattern, enabled)| (channel, pattern, enabled))
+    }
+
+    /// Bundle ringing, a timed out ring counts until the shell stopped it
+    pub fn ringing(&self) -> Option<&'static str> {
+        self.bundle.map(|bundle| bundle.name)
+    }
+
+    pub fn take_finished(&mut self) -> bool {
+        core::mem::take(&mut self.finished)
+    }
+
+    /// Wakeup from the monotonic, moves on to the next note of the ringtone
+    pub fn step(&mut self, id: u16, now_ms: u64) -> RingStep {
+        let bundle = match self.bundle {
+            Some(bundle) if id == self.id => bundle,
+            _ => return RingStep::Stale,
+        };
+        if now_ms >= self.end_ms {
+            self.id = self.id.wrapping_add(1);
+            self.finished = true;
+            return RingStep::Done(bundle.channel);
+        }
+        let melody = RINGTONES[bundle.ringtone].1;
+        let (freq, ms) = melody[self.note];
+        self.note = (self.note + 1) % melody.len();
+        RingStep::Note(bundle.channel, freq as u32, now_ms + ms as u64)
+    }
+}
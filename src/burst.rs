@@ -0,0 +1,112 @@
+use hal::rcc::Rcc;
+use hal::stm32;
+use hal::timer::TimerExt;
+
+use crate::clocks;
+use crate::pins::{self, Owner, PinError, Port};
+
+/// TIM1_CH2 on PA9 and TIM1_CH4 on PA11 are alternate function 2
+const BURST_AF: u32 = 2;
+
+/// PWM mode 2: low until the compare value, then high, so a stopped counter idles low
+const OC_PWM2: u8 = 0b111;
+
+/// Exactly N pulses from TIM1 in one-pulse mode. The repetition counter holds back the
+/// update event, and with it the stop, until the Nth period is over.
+pub struct Burst {
+    tim: stm32::TIM1,
+    pin: Option<u8>,
+    pulses: u32,
+    freq: u32,
+}
+
+impl Burst {
+    pub const MAX_PULSES: u32 = 0x1_0000;
+    pub const MAX_FREQ: u32 = 1_000_000;
+
+    pub fn new(tim: stm32::TIM1, rcc: &mut Rcc) -> Self {
+        Self {
+            tim: tim.timer(rcc).release(),
+            pin: None,
+            pulses: 0,
+            freq: 0,
+        }
+    }
+
+    /// Pin, pulse count and frequency of the latest burst
+    pub fn last(&self) -> Option<(u8, u32, u32)> {
+        self.pin.map(|pin| (pin, self.pulses, self.freq))
+    }
+
+    /// The counter clears its enable bit after the last period
+    pub fn is_running(&self) -> bool {
+        self.tim.cr1.read().cen().bit_is_set()
+    }
+
+    /// Starts `pulses` periods at `freq` Hertz with 50% duty, pins without a TIM1
+    /// channel are unsupported
+    pub fn start(&mut self, pin: u8, pulses: u32, freq: u32) -> Result<(), PinError> {
+        if pin != 9 && pin != 11 {
+            return Err(PinError::Unsupported);
+        }
+        pins::check(Port::A, pin, Owner::Burst)?;
+        self.stop();
+
+        let ratio = clocks::timer_clk() / freq;
+        let psc = (ratio - 1) / 0xffff;
+        let arr = ratio / (psc + 1) - 1;
+        let ccr = arr.div_ceil(2);
+
+        let tim = &self.tim;
+        tim.psc.write(|w| unsafe { w.bits(psc) });
+        tim.arr.write(|w| unsafe { w.bits(arr) });
+        tim.rcr.write(|w| unsafe { w.bits(pulses - 1) });
+        if pin == 9 {
+            tim.ccr2.write(|w| unsafe { w.bits(ccr) });
+            tim.ccmr1_output()
+                .modify(|_, w| unsafe { w.oc2m().bits(OC_PWM2) });
+            tim.ccer.modify(|_, w| w.cc2e().set_bit());
+        } else {
+            tim.ccr4.write(|w| unsafe { w.bits(ccr) });
+            tim.ccmr2_output()
+                .modify(|_, w| unsafe { w.oc4m().bits(OC_PWM2) });
+            tim.ccer.modify(|_, w| w.cc4e().set_bit());
+        }
+        // Loads prescaler and repetition counter without starting the count
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.write(|w| unsafe { w.bits(0) });
+        tim.bdtr.modify(|_, w| w.moe().set_bit());
+        Self::set_pin_mode(pin);
+        pins::claim(Port::A, pin, Owner::Burst)?;
+        tim.cr1.modify(|_, w| w.opm().set_bit().cen().set_bit());
+
+        self.pin = Some(pin);
+        self.pulses = pulses;
+        self.freq = freq;
+        Ok(())
+    }
+
+    /// Cuts a running burst short, the outputs go high impedance
+    pub fn stop(&mut self) {
+        let tim = &self.tim;
+        tim.cr1.modify(|_, w| w.cen().clear_bit());
+        tim.bdtr.modify(|_, w| w.moe().clear_bit());
+        tim.ccer
+            .modify(|_, w| w.cc2e().clear_bit().cc4e().clear_bit());
+        tim.cnt.reset();
+        if let Some(pin) = self.pin {
+            pins::release(Port::A, pin, Owner::Burst);
+        }
+    }
+
+    fn set_pin_mode(pin: u8) {
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let shift = pin * 2;
+        let af_shift = (pin - 8) * 4;
+        gpio.afrh.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0xf << af_shift)) | (BURST_AF << af_shift))
+        });
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b10 << shift)) });
+    }
+}
@@ -0,0 +1,159 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::flash::{self, FlashError, Page};
+use crate::telemetry::crc16;
+
+/// Marks a calibration record, the low byte is the layout version
+const MAGIC: u32 = 0xca1b_0002;
+/// Magic, one i32 per field, CRC-16 of the preceding bytes. Starts the calibration page
+const RECORD_LEN: usize = 4 + FIELDS.len() * 4 + 2;
+
+/// Writes are refused until `unlock`, the shell locks again on any other command
+static UNLOCKED: AtomicBool = AtomicBool::new(false);
+
+/// Per-board value measured at the factory
+pub struct Field {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub min: i32,
+    pub max: i32,
+    pub default: i32,
+}
+
+pub const ADC_OFFSET: usize = 0;
+pub const VREF_CAL: usize = 1;
+pub const TOUCH_BASELINE: usize = 4;
+pub const HSI_TRIM: usize = 5;
+
+pub const FIELDS: [Field; 6] = [
+    Field {
+        name: "adc_offset",
+        unit: "mV",
+        min: -500,
+        max: 500,
+        default: 0,
+    },
+    // 0 keeps the VREFINT value programmed by ST
+    Field {
+        name: "vref_cal",
+        unit: "raw",
+        min: 0,
+        max: 4095,
+        default: 0,
+    },
+    // Pulse widths of the servo end stops, kept for servo firmware built on this board
+    Field {
+        name: "servo_min",
+        unit: "us",
+        min: 500,
+        max: 2500,
+        default: 1000,
+    },
+    Field {
+        name: "servo_max",
+        unit: "us",
+        min: 500,
+        max: 2500,
+        default: 2000,
+    },
+    // 0 leaves the touch pad uncalibrated
+    Field {
+        name: "touch_baseline",
+        unit: "cycles",
+        min: 0,
+        max: 100_000,
+        default: 0,
+    },
+    // Reset value of HSITRIM, tuned with hsical
+    Field {
+        name: "hsi_trim",
+        unit: "step",
+        min: 0,
+        max: 127,
+        default: 64,
+    },
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CalError {
+    Locked,
+    UnknownField,
+    Range,
+    Flash(FlashError),
+}
+
+impl CalError {
+    pub fn message(self) -> &'static str {
+        match self {
+            CalError::Locked => "calibration is locked, run cal unlock first",
+            CalError::UnknownField => "unknown calibration field",
+            CalError::Range => "value out of range",
+            CalError::Flash(err) => err.message(),
+        }
+    }
+}
+
+pub fn find(name: &str) -> Option<usize> {
+    FIELDS.iter().position(|field| field.name == name)
+}
+
+/// Stored values, `None` while the page is blank or the record is corrupt
+pub fn load() -> Option<[i32; FIELDS.len()]> {
+    let record = &flash::read(Page::Cal)[..RECORD_LEN];
+    let magic = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+    let crc = u16::from_le_bytes([record[RECORD_LEN - 2], record[RECORD_LEN - 1]]);
+    if magic != MAGIC || crc != crc16(&record[..RECORD_LEN - 2]) {
+        return None;
+    }
+    let mut values = [0; FIELDS.len()];
+    for (idx, value) in values.iter_mut().enumerate() {
+        let bytes = &record[4 + idx * 4..][..4];
+        *value = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    Some(values)
+}
+
+/// Stored value of a field or its default
+pub fn get(field: usize) -> i32 {
+    load().map_or(FIELDS[field].default, |values| values[field])
+}
+
+pub fn is_unlocked() -> bool {
+    UNLOCKED.load(Ordering::Relaxed)
+}
+
+pub fn unlock() {
+    UNLOCKED.store(true, Ordering::Relaxed);
+}
+
+pub fn lock() {
+    UNLOCKED.store(false, Ordering::Relaxed);
+}
+
+/// Changes one field after `unlock`, the others keep their stored or default values
+pub fn write(field: usize, value: i32) -> Result<(), CalError> {
+    if !is_unlocked() {
+        return Err(CalError::Locked);
+    }
+    store(field, value)
+}
+
+/// Changes one field regardless of the shell lock, for provisioning fixtures before the
+/// board has a key
+pub fn store(field: usize, value: i32) -> Result<(), CalError> {
+    let info = FIELDS.get(field).ok_or(CalError::UnknownField)?;
+    if !(info.min..=info.max).contains(&value) {
+        return Err(CalError::Range);
+    }
+    let mut values = load().unwrap_or_else(|| FIELDS.map(|field| field.default));
+    values[field] = value;
+
+    let mut record = [0; RECORD_LEN];
+    record[..4].copy_from_slice(&MAGIC.to_le_bytes());
+    for (idx, value) in values.iter().enumerate() {
+        record[4 + idx * 4..][..4].copy_from_slice(&value.to_le_bytes());
+    }
+    let crc = crc16(&record[..RECORD_LEN - 2]);
+    record[RECORD_LEN - 2..].copy_from_slice(&crc.to_le_bytes());
+    flash::update(Page::Cal, 0, &record).map_err(CalError::Flash)
+}
@@ -0,0 +1,176 @@
+#[derive(Clone, Copy, PartialEq)]
+pub enum CalcError {
+    Syntax,
+    UnknownVar,
+    DivZero,
+}
+
+impl CalcError {
+    pub fn message(self) -> &'static str {
+        match self {
+            CalcError::Syntax => "syntax error",
+            CalcError::UnknownVar => "unknown variable",
+            CalcError::DivZero => "division by zero",
+        }
+    }
+}
+
+/// Evaluates an integer expression: literals (decimal or `0x`), variables, parentheses,
+/// `! -` unary, `* / %`, `+ -`, comparisons, `&&` and `||` with C precedence.
+/// Comparisons and logic operators yield 1 or 0.
+pub fn eval(src: &str, vars: &mut dyn FnMut(&str) -> Option<i32>) -> Result<i32, CalcError> {
+    let mut parser = Parser {
+        src: src.as_bytes(),
+        pos: 0,
+        vars,
+    };
+    let value = parser.or()?;
+    parser.skip_space();
+    if parser.pos != parser.src.len() {
+        return Err(CalcError::Syntax);
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    vars: &'a mut dyn FnMut(&str) -> Option<i32>,
+}
+
+impl Parser<'_> {
+    fn or(&mut self) -> Result<i32, CalcError> {
+        let mut lhs = self.and()?;
+        while self.eat("||") {
+            let rhs = self.and()?;
+            lhs = (lhs != 0 || rhs != 0) as i32;
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<i32, CalcError> {
+        let mut lhs = self.cmp()?;
+        while self.eat("&&") {
+            let rhs = self.cmp()?;
+            lhs = (lhs != 0 && rhs != 0) as i32;
+        }
+        Ok(lhs)
+    }
+
+    fn cmp(&mut self) -> Result<i32, CalcError> {
+        let lhs = self.sum()?;
+        // Two character operators first, `<=` must not be read as `<`
+        for op in ["==", "!=", "<=", ">=", "<", ">"] {
+            if self.eat(op) {
+                let rhs = self.sum()?;
+                let res = match op {
+                    "==" => lhs == rhs,
+                    "!=" => lhs != rhs,
+                    "<=" => lhs <= rhs,
+                    ">=" => lhs >= rhs,
+                    "<" => lhs < rhs,
+                    _ => lhs > rhs,
+                };
+                return Ok(res as i32);
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn sum(&mut self) -> Result<i32, CalcError> {
+        let mut lhs = self.term()?;
+        loop {
+            if self.eat("+") {
+                lhs = lhs.wrapping_add(self.term()?);
+            } else if self.eat("-") {
+                lhs = lhs.wrapping_sub(self.term()?);
+            } else {
+                return Ok(lhs);
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<i32, CalcError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = if self.eat("*") {
+                b'*'
+            } else if self.eat("/") {
+                b'/'
+            } else if self.eat("%") {
+                b'%'
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.unary()?;
+            lhs = match op {
+                b'*' => lhs.wrapping_mul(rhs),
+                _ if rhs == 0 => return Err(CalcError::DivZero),
+                b'/' => lhs.wrapping_div(rhs),
+                _ => lhs.wrapping_rem(rhs),
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<i32, CalcError> {
+        if self.eat("-") {
+            return Ok(self.unary()?.wrapping_neg());
+        }
+        // `!=` is a comparison, never a negation
+        if !self.peek("!=") && self.eat("!") {
+            return Ok((self.unary()? == 0) as i32);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<i32, CalcError> {
+        self.skip_space();
+        if self.eat("(") {
+            let value = self.or()?;
+            if !self.eat(")") {
+                return Err(CalcError::Syntax);
+            }
+            return Ok(value);
+        }
+        let start = self.pos;
+        while self
+            .src
+            .get(self.pos)
+            .is_some_and(|c| c.is_ascii_alphanumeric() || *c == b'_')
+        {
+            self.pos += 1;
+        }
+        let token = core::str::from_utf8(&self.src[start..self.pos]).unwrap_or("");
+        match token.as_bytes().first() {
+            None => Err(CalcError::Syntax),
+            Some(c) if c.is_ascii_digit() => {
+                let value = match token.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => token.parse::<u32>().ok(),
+                };
+                value.map(|value| value as i32).ok_or(CalcError::Syntax)
+            }
+            Some(_) => (self.vars)(token).ok_or(CalcError::UnknownVar),
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while self.src.get(self.pos) == Some(&b' ') {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self, op: &str) -> bool {
+        self.skip_space();
+        self.src[self.pos..].starts_with(op.as_bytes())
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if self.peek(op) {
+            self.pos += op.len();
+            true
+        } else {
+            false
+        }
+    }
+}
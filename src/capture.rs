@@ -0,0 +1,115 @@
+use core::fmt::Write;
+
+use hal::stm32;
+
+use crate::cycles;
+use crate::shell::CR;
+
+pub const MAX_PINS: usize = 8;
+pub const MAX_EDGES: usize = 128;
+pub const MAX_DURATION_MS: u32 = 1000;
+
+/// Port A level changes, timestamps are cycles since the start of the capture
+pub struct Capture {
+    pins: [u8; MAX_PINS],
+    pin_count: usize,
+    initial: u16,
+    times: [u32; MAX_EDGES],
+    levels: [u16; MAX_EDGES],
+    len: usize,
+    overflow: bool,
+    duration: u32,
+    freq: u32,
+}
+
+impl Capture {
+    /// Polls port A for `duration_ms`, blocking the caller
+    pub fn run(pins: &[u8], duration_ms: u32) -> Self {
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let mut capture = Self {
+            pins: [0; MAX_PINS],
+            pin_count: pins.len().min(MAX_PINS),
+            initial: 0,
+            times: [0; MAX_EDGES],
+            levels: [0; MAX_EDGES],
+            len: 0,
+            overflow: false,
+            duration: 0,
+            freq: cycles::freq(),
+        };
+        capture.pins[..capture.pin_count].copy_from_slice(&pins[..capture.pin_count]);
+        let mask = capture.pins[..capture.pin_count]
+            .iter()
+            .fold(0u16, |mask, pin| mask | 1 << pin);
+        let duration = (capture.freq as u64 * duration_ms as u64 / 1000) as u32;
+        capture.duration = duration;
+
+        let start = cycles::now();
+        let mut level = gpio.idr.read().bits() as u16 & mask;
+        capture.initial = level;
+        loop {
+            let elapsed = cycles::since(start);
+            if elapsed >= duration {
+                break;
+            }
+            let now = gpio.idr.read().bits() as u16 & mask;
+            if now == level {
+                continue;
+            }
+            if capture.len == MAX_EDGES {
+                capture.overflow = true;
+                capture.duration = elapsed;
+                break;
+            }
+            capture.times[capture.len] = elapsed;
+            capture.levels[capture.len] = now;
+            capture.len += 1;
+            level = now;
+        }
+        capture
+    }
+
+    /// Value Change Dump, opens directly in PulseView and GTKWave
+    pub fn write_vcd(&self, out: &mut dyn Write) {
+        write!(
+            out,
+            "$timescale 1 ns $end{0:}$scope module porta $end{0:}",
+            CR
+        )
+        .ok();
+        for (idx, pin) in self.pins[..self.pin_count].iter().enumerate() {
+            write!(out, "$var wire 1 {} PA{} $end{}", Self::id(idx), pin, CR).ok();
+        }
+        write!(out, "$upscope $end{0:}$enddefinitions $end{0:}#0{0:}", CR).ok();
+        self.write_levels(out, self.initial, None);
+        let mut prev = self.initial;
+        for (time, level) in self.times[..self.len].iter().zip(self.levels.iter()) {
+            write!(out, "#{}{}", self.ns(*time), CR).ok();
+            self.write_levels(out, *level, Some(prev));
+            prev = *level;
+        }
+        if self.overflow {
+            write!(out, "$comment edge buffer full $end{}", CR).ok();
+        }
+        write!(out, "#{}{}", self.ns(self.duration), CR).ok();
+    }
+
+    fn ns(&self, cycles: u32) -> u64 {
+        cycles as u64 * 1_000_000_000 / self.freq as u64
+    }
+
+    /// Writes the pins that differ from `prev`, or every pin without it
+    fn write_levels(&self, out: &mut dyn Write, level: u16, prev: Option<u16>) {
+        for (idx, pin) in self.pins[..self.pin_count].iter().enumerate() {
+            let bit = level >> pin & 1;
+            if prev.is_some_and(|prev| prev >> pin & 1 == bit) {
+                continue;
+            }
+            write!(out, "{}{}{}", bit, Self::id(idx), CR).ok();
+        }
+    }
+
+    fn id(idx: usize) -> char {
+        (b'!' + idx as u8) as char
+    }
+}
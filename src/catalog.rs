@@ -0,0 +1,1258 @@
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::config::CMD_MAX_LEN;
+use crate::hw::Hw;
+use crate::pins;
+use crate::shell::{Env, Shell, CR};
+
+/// Longest synopsis `help` prints, longer ones are cut
+const SYNOPSIS_LEN: usize = 160;
+/// Synopses shorter than this share a line with the help text
+const HELP_COLUMN: usize = 10;
+
+/// Runs a command with the rest of the line
+pub type Handler = fn(&mut Env<'_>, &mut Shell, &str);
+
+/// Shell command with every accepted argument form. A form is a space separated list
+/// of literal words and `<placeholder>` arguments, an empty form takes no arguments.
+/// Dispatch, `help` and tab completion all come from this table.
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub forms: &'static [&'static str],
+    /// A typical line, empty for commands without arguments
+    pub example: &'static str,
+    /// Writes flash, drives pins or clocks, or can cut the link: signed mode wants a
+    /// signature on it
+    pub dangerous: bool,
+    pub run: Handler,
+}
+
+/// Value a host sends for a placeholder
+#[derive(Clone, Copy)]
+pub enum ArgType {
+    Int,
+    /// Decimal or `0x` prefixed
+    Addr,
+    Str,
+}
+
+impl ArgType {
+    fn name(self) -> &'static str {
+        match self {
+            ArgType::Int => "int",
+            ArgType::Addr => "addr",
+            ArgType::Str => "str",
+        }
+    }
+}
+
+pub const PARAMS: [(&str, ArgType); 42] = [
+    ("C", ArgType::Int),
+    ("Hz", ArgType::Int),
+    ("addr", ArgType::Addr),
+    ("args", ArgType::Str),
+    ("baud", ArgType::Int),
+    ("bytes", ArgType::Str),
+    ("cycles", ArgType::Int),
+    ("div", ArgType::Int),
+    ("dst", ArgType::Addr),
+    ("duty", ArgType::Int),
+    ("epoch_ms", ArgType::Int),
+    ("event", ArgType::Str),
+    ("expr", ArgType::Str),
+    ("field", ArgType::Str),
+    ("gain", ArgType::Int),
+    ("id", ArgType::Int),
+    ("len", ArgType::Int),
+    ("level", ArgType::Int),
+    ("mV", ArgType::Int),
+    ("max", ArgType::Int),
+    ("min", ArgType::Int),
+    ("mode", ArgType::Str),
+    ("ms", ArgType::Int),
+    ("n", ArgType::Int),
+    ("name", ArgType::Str),
+    ("pattern", ArgType::Str),
+    ("percent", ArgType::Int),
+    ("ppm", ArgType::Int),
+    ("pin", ArgType::Str),
+    ("ringtone", ArgType::Str),
+    ("seconds", ArgType::Int),
+    ("secs", ArgType::Int),
+    ("src", ArgType::Addr),
+    ("start", ArgType::Int),
+    ("step", ArgType::Int),
+    ("stop", ArgType::Int),
+    ("trim", ArgType::Int),
+    ("us", ArgType::Int),
+    ("value", ArgType::Int),
+    ("var", ArgType::Str),
+    ("word", ArgType::Addr),
+    ("x", ArgType::Str),
+];
+
+pub const COMMANDS: [CommandInfo; 106] = [
+    CommandInfo {
+        name: "on",
+        help: "Start animation",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.animation_command(shell, true),
+    },
+    CommandInfo {
+        name: "off",
+        help: "Stop animation",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.animation_command(shell, false),
+    },
+    CommandInfo {
+        name: "status",
+        help: "Get animation status",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.status_command(shell),
+    },
+    CommandInfo {
+        name: "standby",
+        help: "Sleep in Standby mode, then resume animation",
+        forms: &["<seconds>"],
+        example: "standby 10",
+        dangerous: true,
+        run: |env, shell, args| env.standby_command(shell, args),
+    },
+    CommandInfo {
+        name: "set",
+        help: "Set animation frequency in Hertz [1-100]",
+        forms: &["<Hz>"],
+        example: "set 5",
+        dangerous: false,
+        run: |env, shell, args| env.set_command(shell, args),
+    },
+    CommandInfo {
+        name: "adc",
+        help: "Sample a port A pin, print raw counts and millivolts, or watch it live",
+        forms: &["<pin>", "watch <pin>", "watch <pin> <Hz>"],
+        example: "adc pa0",
+        dangerous: false,
+        run: |env, shell, args| env.adc_command(shell, args),
+    },
+    CommandInfo {
+        name: "audio",
+        help: "Follow the PA0 input envelope on PA6 PWM",
+        forms: &["", "on", "off", "attack <ms>", "decay <ms>", "gain <gain>"],
+        example: "audio gain 4",
+        dangerous: true,
+        run: |env, shell, args| env.audio_command(shell, args),
+    },
+    CommandInfo {
+        name: "bitbang",
+        help: "Build a pin sequence, run it with interrupts masked",
+        forms: &[
+            "",
+            "set <pin> high",
+            "set <pin> low",
+            "delay <us>",
+            "read <pin>",
+            "loop <n>",
+            "run",
+            "clear",
+        ],
+        example: "bitbang set pa5 high",
+        dangerous: true,
+        run: |env, shell, args| env.bitbang_command(shell, args),
+    },
+    CommandInfo {
+        name: "bits",
+        help: "Read or modify a register bit field",
+        forms: &["<addr> <field>", "<addr> <field> = <value>"],
+        example: "bits 0x40021008 sw:0..2",
+        dangerous: true,
+        run: |_, shell, args| Env::bits_command(shell, args),
+    },
+    CommandInfo {
+        name: "burst",
+        help: "Send exactly n pulses on PA9 or PA11",
+        forms: &["", "<pin> <n> <Hz>", "off"],
+        example: "burst pa9 100 1000",
+        dangerous: true,
+        run: |env, shell, args| env.burst_command(shell, args),
+    },
+    CommandInfo {
+        name: "dfu-check",
+        help: "Check that the ROM bootloader is usable",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |_, shell, _| Env::dfu_check(shell),
+    },
+    CommandInfo {
+        name: "dim",
+        help: "Set LED brightness, the animation blinks at that level",
+        forms: &["", "<percent>"],
+        example: "dim 50",
+        dangerous: false,
+        run: |env, shell, args| env.dim_command(shell, args),
+    },
+    CommandInfo {
+        name: "cobs",
+        help: "Verify the COBS frame encoder and decoder",
+        forms: &["selftest"],
+        example: "cobs selftest",
+        dangerous: false,
+        run: |_, shell, args| Env::cobs_command(shell, args),
+    },
+    CommandInfo {
+        name: "describe",
+        help: "Print this command catalog as JSON",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.describe_command(shell),
+    },
+    CommandInfo {
+        name: "dma",
+        help: "Copy memory with DMA or benchmark it",
+        forms: &["copy <src> <dst> <len>", "bench"],
+        example: "dma copy 0x20000000 0x20001000 64",
+        dangerous: true,
+        run: |env, shell, args| env.dma_command(shell, args),
+    },
+    CommandInfo {
+        name: "driver",
+        help: "Bring an optional device and its bus up or down at runtime",
+        forms: &["", "list", "enable <name>", "disable <name>"],
+        example: "driver list",
+        dangerous: true,
+        run: |env, shell, args| env.driver_command(shell, args),
+    },
+    CommandInfo {
+        name: "energy",
+        help: "Estimate supply current from clocks and sleep time, on shows it live",
+        forms: &["", "on", "off"],
+        example: "energy on",
+        dangerous: false,
+        run: |env, shell, args| env.energy_command(shell, args),
+    },
+    CommandInfo {
+        name: "gpio",
+        help: "Read, drive or configure port A pins at runtime",
+        forms: &[
+            "",
+            "get <pin>",
+            "set <pin> high",
+            "set <pin> low",
+            "mode <pin> <mode>",
+        ],
+        example: "gpio mode pa5 output",
+        dangerous: true,
+        run: |env, shell, args| env.gpio_command(shell, args),
+    },
+    CommandInfo {
+        name: "pins",
+        help: "Mode, pull and owner of every package pin",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |_, shell, _| pins::write_report(shell),
+    },
+    CommandInfo {
+        name: "health",
+        help: "Warn when temperature or supply is out of bounds",
+        forms: &[
+            "",
+            "on",
+            "off",
+            "temp <C>",
+            "vdd <min> <max>",
+            "throttle on",
+            "throttle off",
+        ],
+        example: "health temp 60",
+        dangerous: false,
+        run: |env, shell, args| env.health_command(shell, args),
+    },
+    CommandInfo {
+        name: "hw",
+        help: "List optional hardware detected at boot",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.hw_command(shell),
+    },
+    CommandInfo {
+        name: "i2c",
+        help: "Probe I2C1 addresses 0x08-0x77, print its traffic or transfer raw bytes",
+        forms: &["scan", "sniff <ms>", "w <addr> <bytes>", "r <addr> <len>"],
+        example: "i2c r 0x48 2",
+        dangerous: true,
+        run: |env, shell, args| env.i2c_command(shell, args),
+    },
+    CommandInfo {
+        name: "latency",
+        help: "Report worst-case interrupt to task latency",
+        forms: &["irq", "reset"],
+        example: "latency irq",
+        dangerous: false,
+        run: |_, shell, args| Env::latency_command(shell, args),
+    },
+    CommandInfo {
+        name: "monitor",
+        help: "Periodically print watched variables",
+        forms: &["", "add <var>", "remove <var>", "interval <ms>", "off"],
+        example: "monitor add uptime",
+        dangerous: false,
+        run: |env, shell, args| env.monitor_command(shell, args),
+    },
+    CommandInfo {
+        name: "nmea",
+        help: "Frame output lines as $...*CS with checksum",
+        forms: &["", "on", "off"],
+        example: "nmea on",
+        dangerous: false,
+        run: |_, shell, args| Env::nmea_command(shell, args),
+    },
+    CommandInfo {
+        name: "powerprofile",
+        help: "Scale core clock down while idle",
+        forms: &["", "performance", "lowpower", "auto"],
+        example: "powerprofile lowpower",
+        dangerous: true,
+        run: |env, shell, args| env.powerprofile_command(shell, args),
+    },
+    CommandInfo {
+        name: "pvd",
+        help: "Supervise supply voltage with the PVD",
+        forms: &["", "<level>", "off"],
+        example: "pvd 3",
+        dangerous: false,
+        run: |env, shell, args| env.pvd_command(shell, args),
+    },
+    CommandInfo {
+        name: "pwmout",
+        help: "Generate test PWM on PA6 or PA7",
+        forms: &["", "<pin> <Hz> <duty>", "off"],
+        example: "pwmout pa6 1000 25",
+        dangerous: true,
+        run: |env, shell, args| env.pwmout_command(shell, args),
+    },
+    CommandInfo {
+        name: "res",
+        help: "List shared resources and lock statistics",
+        forms: &["", "reset"],
+        example: "res reset",
+        dangerous: false,
+        run: |_, shell, args| Env::res_command(shell, args),
+    },
+    CommandInfo {
+        name: "stamp",
+        help: "Prefix output lines with a timestamp",
+        forms: &["", "off", "uptime", "rtc", "rtc-ms"],
+        example: "stamp uptime",
+        dangerous: false,
+        run: |_, shell, args| Env::stamp_command(shell, args),
+    },
+    CommandInfo {
+        name: "sweep",
+        help: "Sweep PWM output (or LED) frequency",
+        forms: &["<start> <stop> <step> <ms>", "off"],
+        example: "sweep 1 50 1 200",
+        dangerous: false,
+        run: |env, shell, args| env.sweep_command(shell, args),
+    },
+    CommandInfo {
+        name: "trace",
+        help: "Inspect recent shell input and output",
+        forms: &["", "dump", "clear"],
+        example: "trace dump",
+        dangerous: false,
+        run: |env, shell, args| env.trace_command(shell, args),
+    },
+    CommandInfo {
+        name: "trig",
+        help: "Emit scope trigger pulse on a port A pin",
+        forms: &["", "<pin>", "width <cycles>", "on <event>", "off <event>"],
+        example: "trig on dispatch",
+        dangerous: true,
+        run: |env, shell, args| env.trig_command(shell, args),
+    },
+    CommandInfo {
+        name: "mco",
+        help: "Route an internal clock to PA9 for a frequency counter",
+        forms: &[
+            "",
+            "sysclk <div>",
+            "hsi16 <div>",
+            "hse <div>",
+            "pllr <div>",
+            "lsi <div>",
+            "lse <div>",
+            "off",
+        ],
+        example: "mco sysclk 16",
+        dangerous: true,
+        run: |_, shell, args| Env::mco_command(shell, args),
+    },
+    CommandInfo {
+        name: "metrics",
+        help: "Dump counters and gauges in Prometheus text format",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.metrics_command(shell),
+    },
+    CommandInfo {
+        name: "telemetry",
+        help: "Push COBS framed binary packets between 0x00 delimiters",
+        forms: &["", "on <ms>", "off"],
+        example: "telemetry on 1000",
+        dangerous: false,
+        run: |env, shell, args| env.telemetry_command(shell, args),
+    },
+    CommandInfo {
+        name: "capture",
+        help: "Record port A edges and dump them as VCD",
+        forms: &["<ms> <pin>"],
+        example: "capture 100 pa0",
+        dangerous: false,
+        run: |_, shell, args| Env::capture_command(shell, args),
+    },
+    CommandInfo {
+        name: "clkgate",
+        help: "List running peripheral clocks or stop an unclaimed one",
+        forms: &["", "off <periph>"],
+        example: "clkgate off GPIOC",
+        dangerous: true,
+        run: |_, shell, args| Env::clkgate_command(shell, args),
+    },
+    CommandInfo {
+        name: "wave",
+        help: "Play pasted brightness samples (%) on PA6 PWM",
+        forms: &["", "upload", "play <Hz>", "play <Hz> loop", "stop"],
+        example: "wave play 50 loop",
+        dangerous: true,
+        run: |env, shell, args| env.wave_command(shell, args),
+    },
+    CommandInfo {
+        name: "touch",
+        help: "Touch pad on PB1 (charged from PB0) toggles animation",
+        forms: &["cal", "read", "on", "off", "threshold <percent>"],
+        example: "touch threshold 20",
+        dangerous: false,
+        run: |env, shell, args| env.touch_command(shell, args),
+    },
+    CommandInfo {
+        name: "dist",
+        help: "HC-SR04 distance (PA1 trig, PA4 echo) or map it to blink rate",
+        forms: &["", "map on", "map off"],
+        example: "dist map on",
+        dangerous: false,
+        run: |env, shell, args| env.dist_command(shell, args),
+    },
+    CommandInfo {
+        name: "motion",
+        help: "PIR on PA8 counts motion, rule runs animation after it",
+        forms: &["", "reset", "rule <seconds>", "rule off"],
+        example: "motion rule 30",
+        dangerous: false,
+        run: |env, shell, args| env.motion_command(shell, args),
+    },
+    CommandInfo {
+        name: "out",
+        help: "Switch output channels on PB2..PB5 with max-on watchdog",
+        forms: &[
+            "",
+            "<n> on",
+            "<n> off",
+            "<n> pulse <ms>",
+            "<n> max <ms>",
+            "<n> max off",
+        ],
+        example: "out 0 pulse 500",
+        dangerous: true,
+        run: |env, shell, args| env.out_command(shell, args),
+    },
+    CommandInfo {
+        name: "phase",
+        help: "Delay from the sync second boundary to the LED switching on",
+        forms: &["", "<ms>"],
+        example: "phase 250",
+        dangerous: false,
+        run: |env, shell, args| env.phase_command(shell, args),
+    },
+    CommandInfo {
+        name: "sync",
+        help: "Pulse PA10 every second or lock the animation to its pulses",
+        forms: &["", "off", "out", "in"],
+        example: "sync out",
+        dangerous: true,
+        run: |env, shell, args| env.sync_command(shell, args),
+    },
+    CommandInfo {
+        name: "thermostat",
+        help: "Hold temperature with a heater on an output channel",
+        forms: &["", "on", "off", "setpoint <C>", "hyst <C>", "out <n>"],
+        example: "thermostat setpoint 40",
+        dangerous: true,
+        run: |env, shell, args| env.thermostat_command(shell, args),
+    },
+    CommandInfo {
+        name: "morse",
+        help: "Send text as Morse code on the LED at the blink rate, Ctrl+C aborts",
+        forms: &["<text>"],
+        example: "morse sos",
+        dangerous: false,
+        run: |env, shell, args| env.morse_command(shell, args),
+    },
+    CommandInfo {
+        name: "pattern",
+        help: "Blink a bit sequence or a built-in pattern instead of the plain toggle",
+        forms: &["", "list", "<bits>", "<name>"],
+        example: "pattern heartbeat",
+        dangerous: false,
+        run: |env, shell, args| env.pattern_command(shell, args),
+    },
+    CommandInfo {
+        name: "pid",
+        help: "PID loop from PA0 voltage to PA7 PWM duty",
+        forms: &[
+            "",
+            "status",
+            "on",
+            "off",
+            "set kp <x>",
+            "set ki <x>",
+            "set kd <x>",
+            "target <mV>",
+            "csv on",
+            "csv off",
+        ],
+        example: "pid target 1500",
+        dangerous: true,
+        run: |env, shell, args| env.pid_command(shell, args),
+    },
+    CommandInfo {
+        name: "count",
+        help: "Count edges on a port A pin, gate sets the rate window",
+        forms: &[
+            "",
+            "<pin>",
+            "<pin> rise",
+            "<pin> fall",
+            "<pin> both",
+            "read",
+            "reset",
+            "gate <ms>",
+            "gate off",
+            "off",
+        ],
+        example: "count pa0 rise",
+        dangerous: false,
+        run: |env, shell, args| env.count_command(shell, args),
+    },
+    CommandInfo {
+        name: "cpu",
+        help: "Print CPU load over the last second",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.cpu_command(shell, false),
+    },
+    CommandInfo {
+        name: "loadgen",
+        help: "Burn CPU at the shell priority to test behaviour under load",
+        forms: &["", "<percent>"],
+        example: "loadgen 50",
+        dangerous: false,
+        run: |env, shell, args| env.loadgen_command(shell, args),
+    },
+    CommandInfo {
+        name: "top",
+        help: "Split CPU time into idle, load generator and other tasks",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.cpu_command(shell, true),
+    },
+    CommandInfo {
+        name: "timerstat",
+        help: "Count late and missed blink timer activations",
+        forms: &["", "reset"],
+        example: "timerstat reset",
+        dangerous: false,
+        run: |_, shell, args| Env::timerstat_command(shell, args),
+    },
+    CommandInfo {
+        name: "after",
+        help: "Run a command later, list the waiting ones without arguments",
+        forms: &["", "<secs> <command>"],
+        example: "after 10 off",
+        dangerous: false,
+        run: |env, shell, args| env.after_command(shell, args),
+    },
+    CommandInfo {
+        name: "every",
+        help: "Repeat a command every few seconds until its job is killed",
+        forms: &["<secs> <command>"],
+        example: "every 60 status",
+        dangerous: false,
+        run: |env, shell, args| env.every_command(shell, args),
+    },
+    CommandInfo {
+        name: "jobs",
+        help: "List waiting after and every jobs with their ids",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.jobs_list(shell),
+    },
+    CommandInfo {
+        name: "killjob",
+        help: "Drop a waiting or repeating job",
+        forms: &["<id>"],
+        example: "killjob 1",
+        dangerous: false,
+        run: |env, shell, args| env.killjob_command(shell, args),
+    },
+    CommandInfo {
+        name: "assert",
+        help: "Check an expression over monitor variables, print PASS or FAIL",
+        forms: &["<expr>"],
+        example: "assert blink_freq == 2",
+        dangerous: false,
+        run: |env, shell, args| env.assert_command(shell, args),
+    },
+    CommandInfo {
+        name: "record",
+        help: "Record typed commands into a script saved in flash",
+        forms: &["start <name>", "stop", "delete <name>"],
+        example: "record start demo",
+        dangerous: true,
+        run: |env, shell, args| env.record_command(shell, args),
+    },
+    CommandInfo {
+        name: "run",
+        help: "List saved scripts or replay one with $1..$9 arguments",
+        forms: &["", "<name> <args>", "-k <name> <args>"],
+        example: "run demo",
+        dangerous: true,
+        run: |env, shell, args| env.run_command(shell, args),
+    },
+    CommandInfo {
+        name: "cal",
+        help: "Show or write write-protected per-board calibration",
+        forms: &["", "show", "unlock", "lock", "write <field> <value>"],
+        example: "cal write adc_offset 5",
+        dangerous: true,
+        run: |_, shell, args| Env::cal_command(shell, args),
+    },
+    CommandInfo {
+        name: "date",
+        help: "Print or set the RTC calendar, it keeps running across resets",
+        forms: &["", "set <date> <time>"],
+        example: "date set 2024-05-01 12:00:00",
+        dangerous: true,
+        run: |_, shell, args| Env::date_command(shell, args),
+    },
+    CommandInfo {
+        name: "time",
+        help: "Print the RTC time of day",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |_, shell, _| Env::time_command(shell),
+    },
+    CommandInfo {
+        name: "alias",
+        help: "Define an alias as name=command, list the aliases without arguments",
+        forms: &["", "list", "<definition>"],
+        example: "alias fast=\"set 50\"",
+        dangerous: true,
+        run: |_, shell, args| Env::alias_command(shell, args),
+    },
+    CommandInfo {
+        name: "unalias",
+        help: "Remove an alias",
+        forms: &["<name>"],
+        example: "unalias fast",
+        dangerous: true,
+        run: |_, shell, args| Env::unalias_command(shell, args),
+    },
+    CommandInfo {
+        name: "rc",
+        help: "List, extend or clear the commands run after every reset",
+        forms: &["", "list", "add <command>", "clear"],
+        example: "rc add set 10",
+        dangerous: true,
+        run: |_, shell, args| Env::rc_command(shell, args),
+    },
+    CommandInfo {
+        name: "coredump",
+        help: "Show, dump or erase the crash saved by the last panic or HardFault",
+        forms: &["", "info", "read", "erase"],
+        example: "coredump read",
+        dangerous: true,
+        run: |_, shell, args| Env::coredump_command(shell, args),
+    },
+    CommandInfo {
+        name: "budget",
+        help: "Report commands that run longer than a time budget, off by default",
+        forms: &["", "off", "<ms>"],
+        example: "budget 500",
+        dangerous: false,
+        run: |_, shell, args| Env::budget_command(shell, args),
+    },
+    CommandInfo {
+        name: "baud",
+        help: "Print or change the shell baud rate, rolls back unless confirmed",
+        forms: &["", "<baud>"],
+        example: "baud 460800",
+        dangerous: true,
+        run: |env, shell, args| env.baud_command(shell, args),
+    },
+    CommandInfo {
+        name: "confirm",
+        help: "Keep a baud or power profile change",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.confirm_command(shell),
+    },
+    CommandInfo {
+        name: "revert",
+        help: "Roll back an unconfirmed baud or power profile change now",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.revert_command(shell),
+    },
+    CommandInfo {
+        name: "save",
+        help: "Keep blink frequency, animation state, prompt and shell history across resets",
+        forms: &[""],
+        example: "",
+        dangerous: true,
+        run: |env, shell, _| env.save_command(shell),
+    },
+    CommandInfo {
+        name: "load",
+        help: "Go back to the saved settings",
+        forms: &[""],
+        example: "",
+        dangerous: true,
+        run: |env, shell, _| env.load_command(shell),
+    },
+    CommandInfo {
+        name: "defaults",
+        help: "Go back to the default settings until the next save",
+        forms: &[""],
+        example: "",
+        dangerous: true,
+        run: |env, shell, _| env.defaults_command(shell),
+    },
+    CommandInfo {
+        name: "prompt",
+        help: "Print or set the shell prompt, a space follows it",
+        forms: &["", "<text>"],
+        example: "prompt g071>",
+        dangerous: false,
+        run: |_, shell, args| Env::prompt_command(shell, args),
+    },
+    CommandInfo {
+        name: "md",
+        help: "Hexdump memory a word at a time, 64 bytes unless given",
+        forms: &["<addr>", "<addr> <len>"],
+        example: "md 0x20000000 32",
+        dangerous: false,
+        run: |_, shell, args| Env::md_command(shell, args),
+    },
+    CommandInfo {
+        name: "mw",
+        help: "Write a word to memory and read it back",
+        forms: &["<addr> <word>"],
+        example: "mw 0x50000014 0x20",
+        dangerous: true,
+        run: |_, shell, args| Env::mw_command(shell, args),
+    },
+    CommandInfo {
+        name: "info",
+        help: "Print the device ID, flash size, die revision, core clock and firmware build",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |_, shell, _| Env::info_command(shell),
+    },
+    CommandInfo {
+        name: "reboot",
+        help: "Reset the chip, safe skips the rc script and the drivers",
+        forms: &["", "safe"],
+        example: "reboot safe",
+        dangerous: true,
+        run: |_, shell, args| Env::reboot_command(shell, args),
+    },
+    CommandInfo {
+        name: "dfu",
+        help: "Jump to the ROM bootloader after a prompt, reset to return",
+        forms: &["", "now"],
+        example: "dfu now",
+        dangerous: true,
+        run: |_, shell, args| Env::dfu_command(shell, args),
+    },
+    CommandInfo {
+        name: "adjust",
+        help: "Slide a value with the arrow keys, applied live, Esc exits",
+        forms: &["brightness", "duty", "freq", "phase"],
+        example: "adjust brightness",
+        dangerous: false,
+        run: |env, shell, args| env.adjust_command(shell, args),
+    },
+    CommandInfo {
+        name: "tune",
+        help: "Short for adjust, blink frequency by default",
+        forms: &["", "freq", "duty"],
+        example: "tune duty",
+        dangerous: false,
+        run: |env, shell, args| {
+            env.adjust_command(shell, if args.is_empty() { "freq" } else { args })
+        },
+    },
+    CommandInfo {
+        name: "temp",
+        help: "Die temperature from the internal sensor and its factory calibration",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.temp_command(shell),
+    },
+    CommandInfo {
+        name: "vdda",
+        help: "Supply voltage from VREFINT and its factory calibration",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.vdda_command(shell),
+    },
+    CommandInfo {
+        name: "bundle",
+        help: "Save LED pattern, ringtone and pin bundles and ring them, e.g. from alarm",
+        forms: &[
+            "",
+            "tones",
+            "set <name> <pattern> <ringtone> <pin>",
+            "set <name> <pattern> <ringtone> off",
+            "del <name>",
+            "ring <name>",
+            "stop",
+        ],
+        example: "bundle set wake heartbeat chime pa6",
+        dangerous: true,
+        run: |env, shell, args| env.bundle_command(shell, args),
+    },
+    CommandInfo {
+        name: "stopwatch",
+        help: "Time laps on the monotonic",
+        forms: &["", "start", "lap", "stop"],
+        example: "stopwatch lap",
+        dangerous: false,
+        run: |env, shell, args| env.stopwatch_command(shell, args),
+    },
+    CommandInfo {
+        name: "countdown",
+        help: "Count down in front of the prompt and run a command at zero",
+        forms: &["", "<secs>", "<secs> <command>", "off"],
+        example: "countdown 90",
+        dangerous: false,
+        run: |env, shell, args| env.countdown_command(shell, args),
+    },
+    CommandInfo {
+        name: "alarm",
+        help: "Run a command daily at an RTC time with a note above the prompt",
+        forms: &["", "<time> <command>", "off"],
+        example: "alarm 07:30 on",
+        dangerous: false,
+        run: |env, shell, args| env.alarm_command(shell, args),
+    },
+    CommandInfo {
+        name: "timesync",
+        help: "Set the RTC from host Unix milliseconds and report drift since the last sync",
+        forms: &["", "<epoch_ms>"],
+        example: "timesync 1700000000000",
+        dangerous: true,
+        run: |_, shell, args| Env::timesync_command(shell, args),
+    },
+    CommandInfo {
+        name: "rtccal",
+        help: "Set RTC smooth calibration or measure drift against a PPS or the host",
+        forms: &["", "<ppm>", "host <ms>", "pps", "reset"],
+        example: "rtccal -12",
+        dangerous: true,
+        run: |env, shell, args| env.rtccal_command(shell, args),
+    },
+    CommandInfo {
+        name: "hsical",
+        help: "Trim HSI16 against a 1 PPS input or the host baud rate, save after cal unlock",
+        forms: &["", "<trim>", "up", "down", "pps <pin>", "uart", "save"],
+        example: "hsical pps pa0",
+        dangerous: true,
+        run: |_, shell, args| Env::hsical_command(shell, args),
+    },
+    CommandInfo {
+        name: "sign",
+        help: "Require an HMAC and nonce on dangerous commands",
+        forms: &["", "on", "off"],
+        example: "sign on",
+        dangerous: true,
+        run: |_, shell, args| Env::sign_command(shell, args),
+    },
+    CommandInfo {
+        name: "slave",
+        help: "Serve a register map as SPI1 slave on PD8, PA11, PA12 and PA15",
+        forms: &["", "on", "off"],
+        example: "slave on",
+        dangerous: true,
+        run: |env, shell, args| env.slave_command(shell, args),
+    },
+    CommandInfo {
+        name: "spi",
+        help: "Exchange hex bytes on SPI2 with CS on PB12, set mode and clock",
+        forms: &["xfer <bytes>", "cfg", "cfg <mode> <Hz>"],
+        example: "spi cfg 0 1000000",
+        dangerous: true,
+        run: |env, shell, args| env.spi_command(shell, args),
+    },
+    CommandInfo {
+        name: "verbosity",
+        help: "Set how chatty commands are",
+        forms: &["", "quiet", "normal", "verbose"],
+        example: "verbosity quiet",
+        dangerous: false,
+        run: |_, shell, args| Env::verbosity_command(shell, args),
+    },
+    CommandInfo {
+        name: "quiet",
+        help: "Print only errors and requested readouts",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |_, shell, _| Env::verbosity_command(shell, "quiet"),
+    },
+    CommandInfo {
+        name: "verbose",
+        help: "Echo applied values and print extra detail",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |_, shell, _| Env::verbosity_command(shell, "verbose"),
+    },
+    CommandInfo {
+        name: "statusbar",
+        help: "Pin uptime and animation state to a terminal row",
+        forms: &["", "on", "on top", "on bottom", "on <rows>", "off"],
+        example: "statusbar on bottom",
+        dangerous: false,
+        run: |env, shell, args| env.statusbar_command(shell, args),
+    },
+    CommandInfo {
+        name: "dashboard",
+        help: "Full-screen view of frequency, temperature, VDD and CPU load",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, args| env.dashboard_command(shell, args),
+    },
+    CommandInfo {
+        name: "report",
+        help: "Print a diagnostic block with a checksum for bug reports",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |env, shell, _| env.report_command(shell),
+    },
+    CommandInfo {
+        name: "led",
+        help: "Show who drives the LED or force the alarm or SOS pattern",
+        forms: &["", "alarm on", "alarm off", "sos on", "sos off"],
+        example: "led sos on",
+        dangerous: false,
+        run: |env, shell, args| env.led_command(shell, args),
+    },
+    CommandInfo {
+        name: "uptime",
+        help: "Time since boot in days, hours, minutes and seconds",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |_, shell, _| Env::uptime_command(shell),
+    },
+    CommandInfo {
+        name: "version",
+        help: "Print firmware build information",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |_, shell, _| Env::version_command(shell),
+    },
+    CommandInfo {
+        name: "clear",
+        help: "Clear screen",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |_, shell, _| {
+            shell.clear().ok();
+        },
+    },
+    CommandInfo {
+        name: "help",
+        help: "Print this message",
+        forms: &[""],
+        example: "",
+        dangerous: false,
+        run: |_, shell, _| Env::help_command(shell),
+    },
+];
+
+/// Runtime state a command reads back with its empty form and changes with the rest
+pub struct Setting {
+    pub name: &'static str,
+    pub command: &'static str,
+    pub get: &'static str,
+}
+
+pub const SETTINGS: [Setting; 14] = [
+    Setting {
+        name: "blink_freq",
+        command: "set",
+        get: "status",
+    },
+    Setting {
+        name: "blink_phase",
+        command: "phase",
+        get: "phase",
+    },
+    Setting {
+        name: "clock_profile",
+        command: "powerprofile",
+        get: "powerprofile",
+    },
+    Setting {
+        name: "health",
+        command: "health",
+        get: "health",
+    },
+    Setting {
+        name: "hsi_trim",
+        command: "hsical",
+        get: "hsical",
+    },
+    Setting {
+        name: "led_brightness",
+        command: "dim",
+        get: "dim",
+    },
+    Setting {
+        name: "nmea",
+        command: "nmea",
+        get: "nmea",
+    },
+    Setting {
+        name: "prompt",
+        command: "prompt",
+        get: "prompt",
+    },
+    Setting {
+        name: "pvd_level",
+        command: "pvd",
+        get: "pvd",
+    },
+    Setting {
+        name: "stamp",
+        command: "stamp",
+        get: "stamp",
+    },
+    Setting {
+        name: "sync",
+        command: "sync",
+        get: "sync",
+    },
+    Setting {
+        name: "telemetry",
+        command: "telemetry",
+        get: "telemetry",
+    },
+    Setting {
+        name: "thermostat",
+        command: "thermostat",
+        get: "thermostat",
+    },
+    Setting {
+        name: "verbosity",
+        command: "verbosity",
+        get: "verbosity",
+    },
+];
+
+fn param_type(name: &str) -> ArgType {
+    PARAMS
+        .iter()
+        .find(|(param, _)| *param == name)
+        .map_or(ArgType::Str, |(_, ty)| *ty)
+}
+
+fn write_forms(out: &mut dyn Write, forms: &[&str]) {
+    out.write_str("\"forms\":[").ok();
+    for (idx, form) in forms.iter().enumerate() {
+        if idx > 0 {
+            out.write_str(",").ok();
+        }
+        out.write_str("[").ok();
+        for (idx, token) in form.split_whitespace().enumerate() {
+            if idx > 0 {
+                out.write_str(",").ok();
+            }
+            match token.strip_prefix('<').and_then(|t| t.strip_suffix('>')) {
+                Some(arg) => write!(
+                    out,
+                    "{{\"arg\":\"{}\",\"type\":\"{}\"}}",
+                    arg,
+                    param_type(arg).name()
+                ),
+                None => write!(out, "{{\"word\":\"{}\"}}", token),
+            }
+            .ok();
+        }
+        out.write_str("]").ok();
+    }
+    out.write_str("]").ok();
+}
+
+pub fn find(name: &str) -> Option<&'static CommandInfo> {
+    COMMANDS.iter().find(|cmd| cmd.name == name)
+}
+
+/// Name and argument forms, bracketed when the command also runs bare
+fn write_synopsis(out: &mut dyn Write, cmd: &CommandInfo) {
+    out.write_str(cmd.name).ok();
+    let bare = cmd.forms.contains(&"");
+    let mut forms = cmd.forms.iter().filter(|form| !form.is_empty()).peekable();
+    if forms.peek().is_some() {
+        out.write_str(if bare { " [" } else { " " }).ok();
+        for (idx, form) in forms.enumerate() {
+            let sep = if idx == 0 { "" } else { "|" };
+            write!(out, "{}{}", sep, form).ok();
+        }
+        if bare {
+            out.write_str("]").ok();
+        }
+    }
+}
+
+/// Usage line of a command built from its forms, followed by its example
+pub fn write_usage(out: &mut dyn Write, cmd: &CommandInfo) {
+    write!(out, "{}usage: ", CR).ok();
+    write_synopsis(out, cmd);
+    out.write_str(CR).ok();
+    if !cmd.example.is_empty() {
+        write!(out, "example: {}{}", cmd.example, CR).ok();
+    }
+}
+
+/// Command list of `help` in alphabetical order, a long synopsis gets a line of its own
+pub fn write_help(out: &mut dyn Write) {
+    let mut order: [usize; COMMANDS.len()] = core::array::from_fn(|idx| idx);
+    order.sort_unstable_by_key(|idx| COMMANDS[*idx].name);
+    for cmd in order.iter().map(|idx| &COMMANDS[*idx]) {
+        let mut synopsis: String<SYNOPSIS_LEN> = String::new();
+        write_synopsis(&mut synopsis, cmd);
+        if synopsis.len() < HELP_COLUMN {
+            write!(out, "\t{:<10}{}{}", synopsis, cmd.help, CR)
+        } else {
+            write!(out, "\t{}{}\t          {}{}", synopsis, CR, cmd.help, CR)
+        }
+        .ok();
+    }
+}
+
+/// Tab completion over the command forms: the bare name, the name and a space when it
+/// takes arguments, and the name with each literal first word. The alphabetically
+/// first candidate for the prefix wins.
+pub fn complete(prefix: &str) -> Option<String<CMD_MAX_LEN>> {
+    if prefix.is_empty() {
+        return None;
+    }
+    let mut best: Option<String<CMD_MAX_LEN>> = None;
+    for cmd in COMMANDS.iter() {
+        for form in cmd.forms.iter() {
+            let mut words = form.split_whitespace();
+            let first = words.next();
+            let mut candidates: [String<CMD_MAX_LEN>; 2] = Default::default();
+            candidates[0].push_str(cmd.name).ok();
+            if let Some(first) = first {
+                candidates[0].push(' ').ok();
+                if !first.starts_with('<') {
+                    candidates[1].push_str(&candidates[0].clone()).ok();
+                    candidates[1].push_str(first).ok();
+                    if words.next().is_some() {
+                        candidates[1].push(' ').ok();
+                    }
+                }
+            }
+            for candidate in candidates.iter().filter(|c| !c.is_empty()) {
+                let better = best.as_ref().is_none_or(|best| candidate < best);
+                if candidate.starts_with(prefix) && better {
+                    best = Some(candidate.clone());
+                }
+            }
+        }
+    }
+    best.map(|best| String::from(&best[prefix.len()..]))
+}
+
+/// Machine-readable catalog of commands, driver commands and settings, one JSON line
+pub fn describe(out: &mut dyn Write, hw: &Hw) {
+    out.write_str("{\"commands\":[").ok();
+    for (idx, cmd) in COMMANDS.iter().enumerate() {
+        if idx > 0 {
+            out.write_str(",").ok();
+        }
+        write!(
+            out,
+            "{{\"name\":\"{}\",\"help\":\"{}\",\"dangerous\":{},",
+            cmd.name, cmd.help, cmd.dangerous
+        )
+        .ok();
+        write_forms(out, cmd.forms);
+        out.write_str("}").ok();
+    }
+    for (driver, present) in hw.drivers() {
+        write!(
+            out,
+            ",{{\"name\":\"{}\",\"help\":\"{}\",\"driver\":true,\"present\":{},",
+            driver.name(),
+            driver.description(),
+            present
+        )
+        .ok();
+        write_forms(out, driver.forms());
+        out.write_str("}").ok();
+    }
+    out.write_str("],\"settings\":[").ok();
+    for (idx, setting) in SETTINGS.iter().enumerate() {
+        if idx > 0 {
+            out.write_str(",").ok();
+        }
+        write!(
+            out,
+            "{{\"name\":\"{}\",\"command\":\"{}\",\"get\":\"{}\"}}",
+            setting.name, setting.command, setting.get
+        )
+        .ok();
+    }
+    out.write_str("]}").ok();
+}
@@ -0,0 +1,169 @@
+use core::fmt::Write;
+
+use hal::stm32;
+
+use crate::pins::{self, Port, PORTS};
+use crate::shell::CR;
+
+/// RCC register holding the clock enable bit of a peripheral
+#[derive(Clone, Copy, PartialEq)]
+enum Reg {
+    Iop,
+    Ahb,
+    Apb1,
+    Apb2,
+}
+
+/// Who keeps a peripheral clocked
+#[derive(Clone, Copy, PartialEq)]
+enum User {
+    /// Nothing in the app drives it
+    None,
+    Fixed(&'static str),
+    /// A GPIO port is in use while any of its pins has an owner
+    Port(Port),
+}
+
+/// Gateable peripheral clocks of the STM32G071 with the subsystem using each of them and
+/// the typical current the clock draws, in tenths of a microamp per MHz
+const GATES: [(&str, Reg, u8, User, u16); 36] = [
+    ("GPIOA", Reg::Iop, 0, User::Port(Port::A), 4),
+    ("GPIOB", Reg::Iop, 1, User::Port(Port::B), 4),
+    ("GPIOC", Reg::Iop, 2, User::Port(Port::C), 4),
+    ("GPIOD", Reg::Iop, 3, User::Port(Port::D), 4),
+    ("GPIOF", Reg::Iop, 5, User::Port(Port::F), 4),
+    ("DMA", Reg::Ahb, 0, User::Fixed("dma"), 32),
+    ("FLASH", Reg::Ahb, 8, User::Fixed("flash"), 0),
+    ("CRC", Reg::Ahb, 12, User::None, 4),
+    ("TIM2", Reg::Apb1, 0, User::Fixed("cycles"), 57),
+    ("TIM3", Reg::Apb1, 1, User::Fixed("pwmout"), 45),
+    ("TIM6", Reg::Apb1, 4, User::Fixed("adcwatch"), 11),
+    ("TIM7", Reg::Apb1, 5, User::Fixed("loadgen"), 11),
+    ("RTCAPB", Reg::Apb1, 10, User::Fixed("rtc"), 6),
+    ("WWDG", Reg::Apb1, 11, User::None, 4),
+    ("SPI2", Reg::Apb1, 14, User::Fixed("spi"), 20),
+    ("USART2", Reg::Apb1, 17, User::Fixed("uart"), 52),
+    ("USART3", Reg::Apb1, 18, User::None, 16),
+    ("USART4", Reg::Apb1, 19, User::None, 16),
+    ("LPUART1", Reg::Apb1, 20, User::None, 32),
+    ("I2C1", Reg::Apb1, 21, User::Fixed("i2c"), 40),
+    ("I2C2", Reg::Apb1, 22, User::None, 15),
+    ("CEC", Reg::Apb1, 24, User::None, 5),
+    ("UCPD1", Reg::Apb1, 25, User::None, 24),
+    ("UCPD2", Reg::Apb1, 26, User::None, 24),
+    ("DBG", Reg::Apb1, 27, User::None, 3),
+    ("PWR", Reg::Apb1, 28, User::Fixed("power"), 3),
+    ("DAC1", Reg::Apb1, 29, User::None, 15),
+    ("LPTIM2", Reg::Apb1, 30, User::None, 25),
+    ("LPTIM1", Reg::Apb1, 31, User::None, 25),
+    ("SYSCFG", Reg::Apb2, 0, User::None, 3),
+    ("TIM1", Reg::Apb2, 11, User::Fixed("burst"), 75),
+    ("SPI1", Reg::Apb2, 12, User::Fixed("slave"), 20),
+    ("TIM14", Reg::Apb2, 15, User::Fixed("ranger"), 20),
+    ("TIM16", Reg::Apb2, 17, User::Fixed("blink"), 26),
+    ("TIM17", Reg::Apb2, 18, User::Fixed("sys_tick"), 26),
+    ("ADC", Reg::Apb2, 20, User::Fixed("sensors"), 22),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GateError {
+    Unknown,
+    /// A subsystem still needs the clock
+    Used(&'static str, &'static str),
+}
+
+pub fn write_error(out: &mut dyn Write, err: GateError) {
+    match err {
+        GateError::Unknown => write!(out, "{0:}unknown peripheral{0:}", CR),
+        GateError::Used(periph, user) => {
+            write!(out, "{0:}{1:} is used by {2:}{0:}", CR, periph, user)
+        }
+    }
+    .ok();
+}
+
+fn bits(reg: Reg) -> u32 {
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    match reg {
+        Reg::Iop => rcc.iopenr.read().bits(),
+        Reg::Ahb => rcc.ahbenr.read().bits(),
+        Reg::Apb1 => rcc.apbenr1.read().bits(),
+        Reg::Apb2 => rcc.apbenr2.read().bits(),
+    }
+}
+
+/// Subsystem keeping the peripheral clocked, `None` when nothing would notice it stop
+fn claimed_by(user: User) -> Option<&'static str> {
+    match user {
+        User::None => None,
+        User::Fixed(name) => Some(name),
+        User::Port(port) => {
+            let bonded = PORTS[port as usize].2;
+            (0..16)
+                .filter(|pin| bonded & (1 << pin) != 0)
+                .find_map(|pin| pins::owner(port, pin))
+                .map(|owner| owner.name())
+        }
+    }
+}
+
+/// Every running peripheral clock with its user, unclaimed ones are flagged
+pub fn write_report(out: &mut dyn Write) {
+    let mut running = 0;
+    let mut unclaimed = 0;
+    write!(out, "{0:}Periph   Used by{0:}", CR).ok();
+    for (name, reg, bit, owner, _) in GATES.iter() {
+        if bits(*reg) & (1 << bit) == 0 {
+            continue;
+        }
+        running += 1;
+        match claimed_by(*owner) {
+            Some(user) => write!(out, "{:<8} {}{}", name, user, CR),
+            None => {
+                unclaimed += 1;
+                write!(out, "{:<8} - unclaimed{}", name, CR)
+            }
+        }
+        .ok();
+    }
+    write!(
+        out,
+        "{} of {} clocks on, {} unclaimed{}",
+        running,
+        GATES.len(),
+        unclaimed,
+        CR
+    )
+    .ok();
+}
+
+/// Current drawn by the running peripheral clocks, in tenths of a microamp per MHz
+pub fn running_load() -> u32 {
+    GATES
+        .iter()
+        .filter(|(_, reg, bit, ..)| bits(*reg) & (1 << bit) != 0)
+        .map(|(.., load)| *load as u32)
+        .sum()
+}
+
+/// Stops the clock of a peripheral nothing in the app uses
+pub fn off(name: &str) -> Result<(), GateError> {
+    let (name, reg, bit, owner, _) = GATES
+        .iter()
+        .find(|(gate, ..)| gate.eq_ignore_ascii_case(name))
+        .ok_or(GateError::Unknown)?;
+    if let Some(user) = claimed_by(*owner) {
+        return Err(GateError::Used(name, user));
+    }
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    let mask = !(1 << bit);
+    unsafe {
+        match reg {
+            Reg::Iop => rcc.iopenr.modify(|r, w| w.bits(r.bits() & mask)),
+            Reg::Ahb => rcc.ahbenr.modify(|r, w| w.bits(r.bits() & mask)),
+            Reg::Apb1 => rcc.apbenr1.modify(|r, w| w.bits(r.bits() & mask)),
+            Reg::Apb2 => rcc.apbenr2.modify(|r, w| w.bits(r.bits() & mask)),
+        }
+    }
+    Ok(())
+}
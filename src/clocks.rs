@@ -0,0 +1,198 @@
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use hal::rcc::Rcc;
+use hal::stm32::{self, tim16};
+
+use crate::config::TICK_HZ;
+
+const HSI_FREQ: u32 = 16_000_000;
+
+static TIMER_CLK: AtomicU32 = AtomicU32::new(HSI_FREQ);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Profile {
+    Performance,
+    LowPower,
+    Auto,
+}
+
+pub const PROFILES: [(&str, Profile); 3] = [
+    ("performance", Profile::Performance),
+    ("lowpower", Profile::LowPower),
+    ("auto", Profile::Auto),
+];
+
+impl Profile {
+    pub fn from_name(name: &str) -> Option<Profile> {
+        PROFILES
+            .iter()
+            .find(|(profile_name, _)| *profile_name == name)
+            .map(|(_, profile)| *profile)
+    }
+
+    pub fn name(self) -> &'static str {
+        PROFILES[self as usize].0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Speed {
+    Full,
+    Low,
+}
+
+impl Speed {
+    /// HSIDIV field value and resulting system clock
+    fn hsidiv(self) -> (u8, u32) {
+        match self {
+            Speed::Full => (0b000, HSI_FREQ),
+            Speed::Low => (0b011, HSI_FREQ / 8),
+        }
+    }
+}
+
+/// Clock scaling policy, decides the core speed from shell activity
+pub struct ClockPolicy {
+    profile: Profile,
+    speed: Speed,
+    idle: u32,
+    throttled: bool,
+}
+
+impl ClockPolicy {
+    /// Seconds without shell input before auto profile slows the core down
+    pub const IDLE_TIMEOUT_S: u32 = 5;
+
+    pub fn new() -> Self {
+        Self {
+            profile: Profile::Performance,
+            speed: Speed::Full,
+            idle: 0,
+            throttled: false,
+        }
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    pub fn set_profile(&mut self, profile: Profile) {
+        self.profile = profile;
+        self.idle = 0;
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Forces low speed regardless of profile, used for thermal derating
+    pub fn set_throttled(&mut self, throttled: bool) {
+        self.throttled = throttled;
+    }
+
+    pub fn activity(&mut self) {
+        self.idle = 0;
+    }
+
+    /// Advances idle counter by one system tick, returns true when idle timeout expires
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances idle counter by a number of ticks, returns true when idle timeout expires
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        let timeout = Self::IDLE_TIMEOUT_S * TICK_HZ;
+        let was_idle = self.idle >= timeout;
+        self.idle = self.idle.saturating_add(ticks);
+        self.profile == Profile::Auto && !was_idle && self.idle >= timeout
+    }
+
+    /// Speed the core should run at, PWM output keeps it at full resolution
+    pub fn target(&self, pwm_active: bool) -> Speed {
+        match self.profile {
+            _ if self.throttled => Speed::Low,
+            _ if pwm_active => Speed::Full,
+            Profile::Performance => Speed::Full,
+            Profile::LowPower => Speed::Low,
+            Profile::Auto if self.idle < Self::IDLE_TIMEOUT_S * TICK_HZ => Speed::Full,
+            Profile::Auto => Speed::Low,
+        }
+    }
+
+    /// Switches system clock divider, timers must be restarted afterwards
+    pub fn set_speed(&mut self, speed: Speed) {
+        let (div, freq) = speed.hsidiv();
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        rcc.cr.modify(|_, w| unsafe { w.hsidiv().bits(div) });
+        TIMER_CLK.store(freq, Ordering::Relaxed);
+        self.speed = speed;
+    }
+}
+
+/// Feeds USART2 from HSI16 directly so the baud rate survives clock scaling
+pub fn init(rcc: &mut Rcc) {
+    TIMER_CLK.store(rcc.clocks.apb_tim_clk.0, Ordering::Relaxed);
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    rcc.ccipr.modify(|_, w| unsafe { w.usart2sel().bits(0b10) });
+}
+
+/// Current timer kernel clock in Hertz
+pub fn timer_clk() -> u32 {
+    TIMER_CLK.load(Ordering::Relaxed)
+}
+
+/// Periodic update interrupt timer that follows the current timer clock
+pub struct PeriodicTimer<TIM> {
+    tim: TIM,
+}
+
+impl<TIM: Deref<Target = tim16::RegisterBlock>> PeriodicTimer<TIM> {
+    pub fn new(tim: TIM) -> Self {
+        Self { tim }
+    }
+
+    pub fn start(&mut self, freq: u32) {
+        let cycles = timer_clk() / freq;
+        let psc = (cycles - 1) / 0xffff;
+        let arr = cycles / (psc + 1) - 1;
+
+        self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+        self.tim.cnt.reset();
+        self.tim.psc.write(|w| unsafe { w.psc().bits(psc as u16) });
+        self.tim.arr.write(|w| unsafe { w.bits(arr) });
+        self.tim
+            .cr1
+            .modify(|_, w| w.cen().set_bit().urs().set_bit());
+    }
+
+    /// Timer clock cycles since the last update event
+    pub fn elapsed(&self) -> u32 {
+        let psc = self.tim.psc.read().bits();
+        self.tim.cnt.read().bits() * (psc + 1)
+    }
+
+    /// Timer clock cycles between update events
+    pub fn period(&self) -> u32 {
+        let psc = self.tim.psc.read().bits();
+        (self.tim.arr.read().bits() + 1) * (psc + 1)
+    }
+
+    /// Moves the counter so the next update event comes `cycles` timer clock cycles from now
+    pub fn align(&mut self, cycles: u32) {
+        let psc = self.tim.psc.read().bits();
+        let arr = self.tim.arr.read().bits();
+        let remaining = (cycles / (psc + 1)).clamp(1, arr + 1);
+        self.tim
+            .cnt
+            .write(|w| unsafe { w.bits(arr + 1 - remaining) });
+    }
+
+    pub fn listen(&mut self) {
+        self.tim.dier.write(|w| w.uie().set_bit());
+    }
+
+    pub fn clear_irq(&mut self) {
+        self.tim.sr.modify(|_, w| w.uif().clear_bit());
+    }
+}
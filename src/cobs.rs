@@ -0,0 +1,163 @@
+//! Consistent overhead byte stuffing: frames carry no zero bytes and end with a zero
+//! delimiter, so a receiver resynchronises on the next zero after lost or extra bytes
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CobsError {
+    Overflow,
+    Zero,
+    Truncated,
+}
+
+/// Longest frame `len` payload bytes can encode to, delimiter included
+pub const fn max_encoded_len(len: usize) -> usize {
+    len + len / 254 + 2
+}
+
+/// Encodes `src` into `dst` followed by the delimiter, returns the frame length
+pub fn encode(src: &[u8], dst: &mut [u8]) -> Result<usize, CobsError> {
+    if dst.len() < max_encoded_len(src.len()) {
+        return Err(CobsError::Overflow);
+    }
+    let mut code_idx = 0;
+    let mut out = 1;
+    let mut code = 1u8;
+    for (idx, byte) in src.iter().enumerate() {
+        if *byte == 0 {
+            dst[code_idx] = code;
+            code_idx = out;
+            out += 1;
+            code = 1;
+            continue;
+        }
+        dst[out] = *byte;
+        out += 1;
+        code += 1;
+        // A full block at the very end needs no empty block after it
+        if code == 0xff && idx + 1 < src.len() {
+            dst[code_idx] = code;
+            code_idx = out;
+            out += 1;
+            code = 1;
+        }
+    }
+    dst[code_idx] = code;
+    dst[out] = 0;
+    Ok(out + 1)
+}
+
+/// Decodes one frame, with or without its delimiter, returns the payload length
+pub fn decode(src: &[u8], dst: &mut [u8]) -> Result<usize, CobsError> {
+    let src = match src.split_last() {
+        Some((0, frame)) => frame,
+        _ => src,
+    };
+    let mut idx = 0;
+    let mut out = 0;
+    while idx < src.len() {
+        let code = src[idx];
+        if code == 0 {
+            return Err(CobsError::Zero);
+        }
+        idx += 1;
+        for _ in 1..code {
+            match src.get(idx) {
+                None => return Err(CobsError::Truncated),
+                Some(0) => return Err(CobsError::Zero),
+                Some(byte) => {
+                    *dst.get_mut(out).ok_or(CobsError::Overflow)? = *byte;
+                }
+            }
+            idx += 1;
+            out += 1;
+        }
+        if code != 0xff && idx < src.len() {
+            *dst.get_mut(out).ok_or(CobsError::Overflow)? = 0;
+            out += 1;
+        }
+    }
+    Ok(out)
+}
+
+pub const SELFTEST_LEN: usize = 256;
+
+/// Reference encodings, delimiter included
+pub const VECTORS: [(&[u8], &[u8]); 6] = [
+    (&[0x00], &[0x01, 0x01, 0x00]),
+    (&[0x00, 0x00], &[0x01, 0x01, 0x01, 0x00]),
+    (&[0x00, 0x11, 0x00], &[0x01, 0x02, 0x11, 0x01, 0x00]),
+    (
+        &[0x11, 0x22, 0x00, 0x33],
+        &[0x03, 0x11, 0x22, 0x02, 0x33, 0x00],
+    ),
+    (
+        &[0x11, 0x22, 0x33, 0x44],
+        &[0x05, 0x11, 0x22, 0x33, 0x44, 0x00],
+    ),
+    (
+        &[0x11, 0x00, 0x00, 0x00],
+        &[0x02, 0x11, 0x01, 0x01, 0x01, 0x00],
+    ),
+];
+
+/// Checks reference vectors, round trips of every length up to `SELFTEST_LEN` and
+/// rejection of a damaged frame. Returns the number of passed cases or the failing one
+pub fn selftest() -> Result<u32, u32> {
+    let mut payload = [0u8; SELFTEST_LEN];
+    let mut frame = [0u8; max_encoded_len(SELFTEST_LEN)];
+    let mut decoded = [0u8; SELFTEST_LEN];
+    let mut case = 0;
+
+    for (src, expected) in VECTORS.iter() {
+        case += 1;
+        match encode(src, &mut frame) {
+            Ok(len) if &frame[..len] == *expected => {}
+            _ => return Err(case),
+        }
+    }
+
+    // Block boundary: 254 non-zero bytes fill exactly one code block
+    case += 1;
+    for (idx, byte) in payload[..254].iter_mut().enumerate() {
+        *byte = idx as u8 + 1;
+    }
+    match encode(&payload[..254], &mut frame) {
+        Ok(256) if frame[0] == 0xff && frame[1..255] == payload[..254] && frame[255] == 0 => {}
+        _ => return Err(case),
+    }
+
+    let mut seed = 0x2545_f491u32;
+    for len in 0..=SELFTEST_LEN {
+        case += 1;
+        for byte in payload[..len].iter_mut() {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            // Roughly one zero in eight
+            *byte = if seed & 0x7 == 0 {
+                0
+            } else {
+                (seed >> 8) as u8 | 1
+            };
+        }
+        let frame_len = match encode(&payload[..len], &mut frame) {
+            Ok(frame_len) => frame_len,
+            Err(_) => return Err(case),
+        };
+        if frame[..frame_len - 1].contains(&0) || frame[frame_len - 1] != 0 {
+            return Err(case);
+        }
+        match decode(&frame[..frame_len], &mut decoded) {
+            Ok(out) if decoded[..out] == payload[..len] => {}
+            _ => return Err(case),
+        }
+    }
+
+    // A zero dropped into the middle of a frame must not decode
+    case += 1;
+    let frame_len = encode(&[0x11, 0x22, 0x33, 0x44], &mut frame).unwrap_or(0);
+    frame[2] = 0;
+    if decode(&frame[..frame_len], &mut decoded).is_ok() {
+        return Err(case);
+    }
+    Ok(case)
+}
@@ -0,0 +1,73 @@
+use cortex_m::peripheral::NVIC;
+use hal::stm32::{self, Interrupt};
+
+/// Task priorities. RTIC only takes literals in task attributes, so every task
+/// repeats its value there and `check_priorities` catches a mismatch at boot.
+pub const SHELL_PRIORITY: u8 = 1;
+pub const SERIAL_PRIORITY: u8 = 2;
+pub const BLINK_PRIORITY: u8 = 2;
+pub const SYS_TICK_PRIORITY: u8 = 2;
+pub const WAVE_PRIORITY: u8 = 2;
+pub const PIN_EDGE_PRIORITY: u8 = 2;
+pub const LOAD_PRIORITY: u8 = 1;
+pub const POWER_PRIORITY: u8 = 3;
+pub const RX_EDGE_PRIORITY: u8 = 3;
+pub const RX_DMA_PRIORITY: u8 = 3;
+pub const LED_PWM_PRIORITY: u8 = 2;
+pub const SPI_SLAVE_PRIORITY: u8 = 2;
+pub const ADC_WATCH_PRIORITY: u8 = 2;
+
+/// Shell port, its interrupt is pended by every task that reports through the shell and
+/// runs the shell task once the port is serviced
+pub type ShellUsart = stm32::USART2;
+pub const SHELL_IRQ: Interrupt = Interrupt::USART2;
+/// Dispatcher of the shell software task, must match `dispatchers` of the app
+pub const SHELL_DISPATCHER: Interrupt = Interrupt::CEC;
+pub const SHELL_BAUD: u32 = 115_200;
+
+/// LED animation and system tick timers, any TIM16-like timer fits
+pub type BlinkTim = stm32::TIM16;
+pub const BLINK_IRQ: Interrupt = Interrupt::TIM16;
+pub type SysTim = stm32::TIM17;
+pub const SYS_TICK_IRQ: Interrupt = Interrupt::TIM17;
+
+/// System tick rate driving uptime and background jobs
+pub const TICK_HZ: u32 = 10;
+pub const DEFAULT_BLINK_FREQ: u8 = 2;
+
+pub const CMD_MAX_LEN: usize = 64;
+pub const HISTORY_LEN: usize = 4;
+pub const TRACE_LEN: usize = 128;
+/// Shell input DMA ring, a paste longer than this needs the UART interrupt to keep up
+pub const RX_RING_LEN: usize = 256;
+/// Received bytes waiting for the shell task, one slot of the queue stays unused
+pub const RX_QUEUE_LEN: usize = 512;
+/// Shell output queued for the TXE interrupt, a full queue makes writers wait
+pub const TX_QUEUE_LEN: usize = 256;
+
+/// Panics when a task attribute disagrees with the priorities above
+pub fn check_priorities() {
+    let tasks = [
+        (SHELL_IRQ, SERIAL_PRIORITY),
+        (SHELL_DISPATCHER, SHELL_PRIORITY),
+        (BLINK_IRQ, BLINK_PRIORITY),
+        (SYS_TICK_IRQ, SYS_TICK_PRIORITY),
+        (Interrupt::TIM3, WAVE_PRIORITY),
+        (Interrupt::EXTI4_15, PIN_EDGE_PRIORITY),
+        (Interrupt::TIM7_LPTIM2, LOAD_PRIORITY),
+        (Interrupt::PVD, POWER_PRIORITY),
+        (Interrupt::EXTI2_3, RX_EDGE_PRIORITY),
+        (Interrupt::DMA_CHANNEL4_5_6_7, RX_DMA_PRIORITY),
+        (Interrupt::TIM2, LED_PWM_PRIORITY),
+        (Interrupt::DMA_CHANNEL2_3, SPI_SLAVE_PRIORITY),
+        (Interrupt::TIM6_DAC_LPTIM1, ADC_WATCH_PRIORITY),
+    ];
+    let bits = stm32::NVIC_PRIO_BITS;
+    for (irq, priority) in tasks.iter() {
+        let hw = ((1 << bits) - priority) << (8 - bits);
+        assert!(
+            NVIC::get_priority(*irq) == hw,
+            "task priority differs from config"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+//! Crash record kept in its own flash page. A panic or HardFault only programs a blank
+//! page, so the first crash survives the resets after it until `coredump erase`.
+//! The Cortex-M0+ has no fault status registers, the stacked frame and the stack above
+//! it are what is left to go by.
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m_rt::{exception, ExceptionFrame};
+use heapless::String;
+
+use crate::flash::{self, FlashError, Page, PAGE_SIZE};
+use crate::led;
+
+const MAGIC: u32 = 0xc0de_0001;
+/// Magic, kind, stack pointer, stack bytes saved, the eight stacked registers, then the
+/// panic message NUL padded
+const HEADER_LEN: usize = 128;
+const TEXT_OFFSET: usize = 48;
+pub const TEXT_LEN: usize = HEADER_LEN - TEXT_OFFSET;
+/// Stack saved above the stack pointer, less near the top of RAM
+const MAX_STACK_LEN: usize = PAGE_SIZE - HEADER_LEN;
+const RAM_END: u32 = 0x2000_9000;
+
+/// Set once a dump started, a fault while saving must not start another
+static SAVING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    Panic = 1,
+    HardFault = 2,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Panic => "panic",
+            Kind::HardFault => "HardFault",
+        }
+    }
+}
+
+/// Registers the core stacked on exception entry, zero for a panic
+#[derive(Clone, Copy, Default)]
+pub struct Frame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+impl Frame {
+    fn words(&self) -> [u32; 8] {
+        [
+            self.r0, self.r1, self.r2, self.r3, self.r12, self.lr, self.pc, self.xpsr,
+        ]
+    }
+}
+
+/// Header of the saved crash, the stack follows it in the page
+pub struct Coredump {
+    pub kind: Kind,
+    pub sp: u32,
+    pub frame: Frame,
+    pub text: String<TEXT_LEN>,
+    stack_len: usize,
+}
+
+impl Coredump {
+    pub fn header(&self) -> &'static [u8] {
+        &flash::read(Page::Coredump)[..HEADER_LEN]
+    }
+
+    /// Stack bytes from `sp` up
+    pub fn stack(&self) -> &'static [u8] {
+        &flash::read(Page::Coredump)[HEADER_LEN..][..self.stack_len]
+    }
+}
+
+fn word(bytes: &[u8], idx: usize) -> u32 {
+    let at = idx * 4;
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Crash saved in flash, `None` while the page is blank
+pub fn load() -> Option<Coredump> {
+    let page = flash::read(Page::Coredump);
+    if word(page, 0) != MAGIC {
+        return None;
+    }
+    let kind = match word(page, 1) {
+        1 => Kind::Panic,
+        2 => Kind::HardFault,
+        _ => return None,
+    };
+    let words: [u32; 8] = core::array::from_fn(|idx| word(page, 4 + idx));
+    let text = &page[TEXT_OFFSET..HEADER_LEN];
+    let text_len = text.iter().position(|b| *b == 0).unwrap_or(TEXT_LEN);
+    Some(Coredump {
+        kind,
+        sp: word(page, 2),
+        frame: Frame {
+            r0: words[0],
+            r1: words[1],
+            r2: words[2],
+            r3: words[3],
+            r12: words[4],
+            lr: words[5],
+            pc: words[6],
+            xpsr: words[7],
+        },
+        text: core::str::from_utf8(&text[..text_len])
+            .map(String::from)
+            .unwrap_or_default(),
+        stack_len: (word(page, 3) as usize).min(MAX_STACK_LEN),
+    })
+}
+
+/// Blanks the page so the next crash is saved
+pub fn erase() -> Result<(), FlashError> {
+    flash::write(Page::Coredump, &[])
+}
+
+/// Programs the header and the stack above `sp` into a blank page, runs with
+/// interrupts off from the panic and fault handlers
+fn save(kind: Kind, sp: u32, frame: &Frame, text: &str) {
+    let blank = flash::read(Page::Coredump).iter().all(|b| *b == 0xff);
+    if SAVING.swap(true, Ordering::Relaxed) || !blank {
+        return;
+    }
+    // A crash in the middle of a dry run still has to reach the flash
+    flash::set_dry_run(false);
+    let stack_len = if (0x2000_0000..RAM_END).contains(&sp) {
+        ((RAM_END - sp) as usize).min(MAX_STACK_LEN)
+    } else {
+        0
+    };
+
+    let mut header = [0; HEADER_LEN];
+    let words = [MAGIC, kind as u32, sp, stack_len as u32];
+    for (idx, word) in words.iter().chain(frame.words().iter()).enumerate() {
+        header[idx * 4..][..4].copy_from_slice(&word.to_le_bytes());
+    }
+    let text = &text.as_bytes()[..text.len().min(TEXT_LEN)];
+    header[TEXT_OFFSET..][..text.len()].copy_from_slice(text);
+
+    // The stack first, the magic in the header marks a complete dump
+    let stack = unsafe { slice::from_raw_parts(sp as *const u8, stack_len) };
+    if flash::program(Page::Coredump, HEADER_LEN, stack).is_ok() {
+        flash::program(Page::Coredump, 0, &header).ok();
+    }
+}
+
+/// Called by the panic handler before it blinks SOS
+pub fn save_panic(info: &PanicInfo) {
+    let mut text: String<TEXT_LEN> = String::new();
+    // An overlong message keeps the part that fits
+    write!(text, "{}", info).ok();
+    save(
+        Kind::Panic,
+        cortex_m::register::msp::read(),
+        &Frame::default(),
+        &text,
+    );
+}
+
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    cortex_m::interrupt::disable();
+    let frame = Frame {
+        r0: ef.r0,
+        r1: ef.r1,
+        r2: ef.r2,
+        r3: ef.r3,
+        r12: ef.r12,
+        lr: ef.lr,
+        pc: ef.pc,
+        xpsr: ef.xpsr,
+    };
+    // The core stacked the eight registers right below the faulting stack pointer
+    let sp = ef as *const ExceptionFrame as u32 + 32;
+    save(Kind::HardFault, sp, &frame, "");
+    led::sos()
+}
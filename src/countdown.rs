@@ -0,0 +1,78 @@
+use heapless::String;
+
+use crate::config::CMD_MAX_LEN;
+
+/// Longest countdown, one day
+pub const MAX_SECS: u32 = 86_400;
+
+/// Countdown on the monotonic with an optional command to run when it reaches zero.
+/// Its task wakes on each whole second left so the shell can redraw the time.
+pub struct Countdown {
+    /// Bumped on each start, so a wakeup of a cancelled countdown is ignored
+    id: u16,
+    /// Monotonic milliseconds it reaches zero at
+    end_ms: Option<u64>,
+    line: Option<String<CMD_MAX_LEN>>,
+    redraw: bool,
+    expired: bool,
+}
+
+impl Countdown {
+    pub fn new() -> Self {
+        Self {
+            id: 0,
+            end_ms: None,
+            line: None,
+            redraw: false,
+            expired: false,
+        }
+    }
+
+    /// Returns the id the first wakeup has to carry
+    pub fn start(&mut self, secs: u32, now_ms: u64, line: Option<&str>) -> u16 {
+        self.id = self.id.wrapping_add(1);
+        self.end_ms = Some(now_ms + secs as u64 * 1000);
+        self.line = line.map(String::from);
+        self.redraw = false;
+        self.expired = false;
+        self.id
+    }
+
+    /// False if no countdown was running
+    pub fn cancel(&mut self) -> bool {
+        self.redraw = false;
+        self.end_ms.take().is_some()
+    }
+
+    pub fn remaining_ms(&self, now_ms: u64) -> Option<u64> {
+        self.end_ms.map(|end| end.saturating_sub(now_ms))
+    }
+
+    pub fn line(&self) -> Option<&str> {
+        self.line.as_deref()
+    }
+
+    /// Wakeup from the monotonic, returns when the next one is due
+    pub fn tick(&mut self, id: u16, now_ms: u64) -> Option<u64> {
+        if id != self.id {
+            return None;
+        }
+        let end = self.end_ms?;
+        if now_ms >= end {
+            self.end_ms = None;
+            self.redraw = false;
+            self.expired = true;
+            return None;
+        }
+        self.redraw = true;
+        Some(now_ms + (end - now_ms - 1) % 1000 + 1)
+    }
+
+    pub fn take_redraw(&mut self) -> bool {
+        core::mem::take(&mut self.redraw)
+    }
+
+    pub fn take_expired(&mut self) -> bool {
+        core::mem::take(&mut self.expired)
+    }
+}
@@ -0,0 +1,196 @@
+use hal::stm32;
+
+use crate::config::TICK_HZ;
+use crate::pins::{self, Owner, PinError, Port};
+
+/// Port A pins on EXTI4_15 that nothing else listens to: PA5 is the LED, PA8 the PIR,
+/// PA10 the sync line and PA13/PA14 SWD
+const COUNT_PINS: [u8; 7] = [4, 6, 7, 9, 11, 12, 15];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Edges {
+    Rising,
+    Falling,
+    Both,
+}
+
+pub const EDGES: [(&str, Edges); 3] = [
+    ("rise", Edges::Rising),
+    ("fall", Edges::Falling),
+    ("both", Edges::Both),
+];
+
+impl Edges {
+    pub fn from_name(name: &str) -> Option<Edges> {
+        EDGES
+            .iter()
+            .find(|(edges_name, _)| *edges_name == name)
+            .map(|(_, edges)| *edges)
+    }
+
+    pub fn name(self) -> &'static str {
+        EDGES[self as usize].0
+    }
+}
+
+/// Counts edges on a port A pin through EXTI, the rate comes from the count over a gate
+pub struct EdgeCounter {
+    pin: Option<u8>,
+    edges: Edges,
+    count: u32,
+    gate: Option<u32>,
+    elapsed: u32,
+    gate_start: u32,
+    rate: Option<u32>,
+}
+
+impl EdgeCounter {
+    pub const MIN_GATE_MS: u32 = 100;
+    pub const MAX_GATE_MS: u32 = 60_000;
+
+    pub fn new() -> Self {
+        Self {
+            pin: None,
+            edges: Edges::Both,
+            count: 0,
+            gate: None,
+            elapsed: 0,
+            gate_start: 0,
+            rate: None,
+        }
+    }
+
+    pub fn pin(&self) -> Option<u8> {
+        self.pin
+    }
+
+    pub fn edges(&self) -> Edges {
+        self.edges
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Switches counting to a pin as a pulled-down input, pins without a free EXTI line
+    /// are unsupported
+    pub fn start(&mut self, pin: u8, edges: Edges) -> Result<(), PinError> {
+        if !COUNT_PINS.contains(&pin) {
+            return Err(PinError::Unsupported);
+        }
+        pins::check(Port::A, pin, Owner::Counter)?;
+        self.stop();
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        let shift = pin * 2;
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << shift)) });
+        gpio.pupdr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b10 << shift)) });
+
+        let line = 1 << pin;
+        let cr_shift = (pin % 4) * 8;
+        let port_a = |r: u32| r & !(0xff << cr_shift);
+        match pin / 4 {
+            1 => exti
+                .exticr2
+                .modify(|r, w| unsafe { w.bits(port_a(r.bits())) }),
+            2 => exti
+                .exticr3
+                .modify(|r, w| unsafe { w.bits(port_a(r.bits())) }),
+            _ => exti
+                .exticr4
+                .modify(|r, w| unsafe { w.bits(port_a(r.bits())) }),
+        }
+        if edges != Edges::Falling {
+            exti.rtsr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+        }
+        if edges != Edges::Rising {
+            exti.ftsr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+        }
+        exti.rpr1.write(|w| unsafe { w.bits(line) });
+        exti.fpr1.write(|w| unsafe { w.bits(line) });
+        exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+
+        pins::claim(Port::A, pin, Owner::Counter)?;
+        self.pin = Some(pin);
+        self.edges = edges;
+        self.reset();
+        Ok(())
+    }
+
+    /// Stops listening, the pin stays an input
+    pub fn stop(&mut self) {
+        if let Some(pin) = self.pin.take() {
+            let exti = unsafe { &*stm32::EXTI::ptr() };
+            let line = 1 << pin;
+            exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() & !line) });
+            exti.rtsr1
+                .modify(|r, w| unsafe { w.bits(r.bits() & !line) });
+            exti.ftsr1
+                .modify(|r, w| unsafe { w.bits(r.bits() & !line) });
+            pins::release(Port::A, pin, Owner::Counter);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.elapsed = 0;
+        self.gate_start = 0;
+        self.rate = None;
+    }
+
+    /// Counts the pending edges of the pin and clears them, both edge flags can be set
+    /// when the interrupt was held off
+    pub fn on_edge(&mut self) {
+        let pin = match self.pin {
+            Some(pin) => pin,
+            None => return,
+        };
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        let line = 1 << pin;
+        let rising = exti.rpr1.read().bits() & line != 0;
+        let falling = exti.fpr1.read().bits() & line != 0;
+        exti.rpr1.write(|w| unsafe { w.bits(line) });
+        exti.fpr1.write(|w| unsafe { w.bits(line) });
+        self.count = self.count.wrapping_add(rising as u32 + falling as u32);
+    }
+
+    pub fn gate_ms(&self) -> Option<u32> {
+        self.gate.map(|ticks| ticks * 1000 / TICK_HZ)
+    }
+
+    /// Measures the rate over `gate_ms` windows, `None` stops measuring
+    pub fn set_gate_ms(&mut self, gate_ms: Option<u32>) {
+        self.gate = gate_ms.map(|ms| (ms * TICK_HZ / 1000).max(1));
+        self.elapsed = 0;
+        self.gate_start = self.count;
+        self.rate = None;
+    }
+
+    /// Edges per second over the last full gate
+    pub fn rate_hz(&self) -> Option<u32> {
+        self.rate
+    }
+
+    /// Advances the gate by one system tick
+    pub fn tick(&mut self) {
+        self.advance(1);
+    }
+
+    /// Advances the gate by a number of ticks slept through in Stop mode, EXTI keeps
+    /// counting while asleep
+    pub fn advance(&mut self, ticks: u32) {
+        let gate = match self.gate {
+            Some(gate) if self.pin.is_some() => gate,
+            _ => return,
+        };
+        self.elapsed += ticks;
+        if self.elapsed >= gate {
+            let edges = self.count.wrapping_sub(self.gate_start);
+            self.rate = Some(edges * TICK_HZ / self.elapsed);
+            self.elapsed = 0;
+            self.gate_start = self.count;
+        }
+    }
+}
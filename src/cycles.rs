@@ -0,0 +1,27 @@
+use hal::rcc::Rcc;
+use hal::stm32;
+use hal::timer::TimerExt;
+
+use crate::clocks;
+
+/// Cortex-M0+ has no DWT cycle counter, so TIM2 free-runs at the timer clock instead
+pub fn init(tim: stm32::TIM2, rcc: &mut Rcc) {
+    let tim = tim.timer(rcc).release();
+    tim.psc.write(|w| unsafe { w.bits(0) });
+    tim.arr.write(|w| unsafe { w.bits(u32::MAX) });
+    tim.egr.write(|w| w.ug().set_bit());
+    tim.cr1.modify(|_, w| w.cen().set_bit());
+}
+
+pub fn now() -> u32 {
+    unsafe { (*stm32::TIM2::ptr()).cnt.read().bits() }
+}
+
+pub fn since(start: u32) -> u32 {
+    now().wrapping_sub(start)
+}
+
+/// Counter frequency in Hertz, follows clock scaling
+pub fn freq() -> u32 {
+    clocks::timer_clk()
+}
@@ -0,0 +1,151 @@
+use core::fmt::Write;
+
+use crate::config::TICK_HZ;
+
+pub const HISTORY: usize = 40;
+const LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Readings taken once per second while the dashboard is open
+#[derive(Clone, Copy)]
+pub struct Sample {
+    pub freq: u8,
+    pub temp_c: i32,
+    pub vdda_mv: u32,
+    pub cpu: u32,
+}
+
+type Field = fn(&Sample) -> i32;
+
+const ROWS: [(&str, &str, Field); 4] = [
+    ("Freq", "Hz", |s| s.freq as i32),
+    ("Temp", "C", |s| s.temp_c),
+    ("VDD", "mV", |s| s.vdda_mv as i32),
+    ("CPU", "%", |s| s.cpu as i32),
+];
+
+/// Full-screen view of the monitoring readings with a sparkline per value
+#[derive(Clone)]
+pub struct Dashboard {
+    active: bool,
+    history: [Sample; HISTORY],
+    len: usize,
+    head: usize,
+    elapsed: u32,
+    due: bool,
+}
+
+impl Dashboard {
+    const INTERVAL: u32 = TICK_HZ;
+
+    pub fn new() -> Self {
+        Self {
+            active: false,
+            history: [Sample {
+                freq: 0,
+                temp_c: 0,
+                vdda_mv: 0,
+                cpu: 0,
+            }; HISTORY],
+            len: 0,
+            head: 0,
+            elapsed: 0,
+            due: false,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Opening starts a fresh history and draws on the next background pass
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+        self.len = 0;
+        self.head = 0;
+        self.elapsed = 0;
+        self.due = active;
+    }
+
+    pub fn push(&mut self, sample: Sample) {
+        self.history[self.head] = sample;
+        self.head = (self.head + 1) % HISTORY;
+        self.len = (self.len + 1).min(HISTORY);
+    }
+
+    /// Redraws in place: home, one cleared line per value, cursor hidden
+    pub fn render(&self, out: &mut dyn Write, uptime_s: u32) {
+        let last = match self.samples().last() {
+            Some(sample) => sample,
+            None => return,
+        };
+        write!(
+            out,
+            "\x1b[?25l\x1b[H ushell-rtic-example dashboard, any key exits\x1b[K\r\n\x1b[K\r\n \
+             Uptime  {}:{:02}:{:02}\x1b[K\r\n",
+            uptime_s / 3600,
+            uptime_s / 60 % 60,
+            uptime_s % 60
+        )
+        .ok();
+        for (name, unit, field) in ROWS.iter() {
+            write!(out, " {:<7} {:>5}{:<3} ", name, field(&last), unit).ok();
+            self.sparkline(out, *field);
+            out.write_str("\x1b[K\r\n").ok();
+        }
+        out.write_str("\x1b[J").ok();
+    }
+
+    /// Scales the history of one value between its own minimum and maximum
+    fn sparkline(&self, out: &mut dyn Write, field: Field) {
+        let (min, max) = self
+            .samples()
+            .map(|sample| field(&sample))
+            .fold((i32::MAX, i32::MIN), |(min, max), v| {
+                (min.min(v), max.max(v))
+            });
+        let span = (max - min).max(1);
+        for sample in self.samples() {
+            let level = (field(&sample) - min) * (LEVELS.len() as i32 - 1) / span;
+            out.write_char(LEVELS[level as usize]).ok();
+        }
+    }
+
+    /// Oldest first
+    fn samples(&self) -> impl Iterator<Item = Sample> + '_ {
+        let start = (self.head + HISTORY - self.len) % HISTORY;
+        (0..self.len).map(move |idx| self.history[(start + idx) % HISTORY])
+    }
+
+    /// Advances by one system tick, returns true when a refresh is due
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances by a number of ticks slept through in Stop mode
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        if !self.active {
+            return false;
+        }
+        self.elapsed += ticks;
+        if self.elapsed >= Self::INTERVAL {
+            self.elapsed = 0;
+            self.due = true;
+        }
+        self.due
+    }
+
+    /// Ticks left until the next refresh
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        if self.active {
+            Some(Self::INTERVAL.saturating_sub(self.elapsed))
+        } else {
+            None
+        }
+    }
+
+    pub fn take_due(&mut self) -> bool {
+        let due = self.due;
+        self.due = false;
+        due
+    }
+}
@@ -0,0 +1,132 @@
+use hal::gpio::{gpioa::PA5, Output, PushPull};
+use hal::stm32;
+
+use crate::clocks;
+use crate::cycles;
+use crate::pins::{self, Owner, Port};
+
+/// LED on PA5, TIM2_CH1 is alternate function 2
+const LED_PIN: u32 = 5;
+const LED_AF: u32 = 2;
+
+/// Output compare modes
+const OC_TOGGLE: u8 = 0b011;
+const OC_FORCE_LOW: u8 = 0b100;
+const OC_FORCE_HIGH: u8 = 0b101;
+
+/// LED brightness as PWM on TIM2_CH1. TIM2 free-runs as the cycle counter, so the
+/// channel toggles on compare matches and each match schedules the next edge.
+pub struct Dimmer {
+    _pin: PA5<Output<PushPull>>,
+    brightness: u8,
+    on: bool,
+    high: bool,
+    on_cycles: u32,
+    off_cycles: u32,
+}
+
+impl Dimmer {
+    pub const PWM_FREQ: u32 = 500;
+
+    /// Takes PA5 over from the GPIO, TIM2 must be running already
+    pub fn new(pin: PA5<Output<PushPull>>) -> Self {
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let tim = unsafe { &*stm32::TIM2::ptr() };
+        tim.ccmr1_output()
+            .modify(|_, w| unsafe { w.oc1m().bits(OC_FORCE_LOW) });
+        tim.ccer.modify(|_, w| w.cc1e().set_bit());
+
+        let shift = LED_PIN * 2;
+        let af_shift = LED_PIN * 4;
+        gpio.afrl.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0xf << af_shift)) | (LED_AF << af_shift))
+        });
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b10 << shift)) });
+        pins::reserve(Port::A, LED_PIN as u8, Owner::Led);
+
+        let mut dimmer = Self {
+            _pin: pin,
+            brightness: 100,
+            on: false,
+            high: false,
+            on_cycles: 0,
+            off_cycles: 0,
+        };
+        dimmer.retime();
+        dimmer
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    pub fn set(&mut self, on: bool) {
+        self.on = on;
+        self.apply();
+    }
+
+    pub fn toggle(&mut self) {
+        self.set(!self.on);
+    }
+
+    /// Brightness of the lit LED in percent
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    pub fn set_brightness(&mut self, brightness: u8) {
+        self.brightness = brightness;
+        self.retime();
+    }
+
+    /// Recomputes the PWM edges for the current timer clock
+    pub fn retime(&mut self) {
+        let period = clocks::timer_clk() / Self::PWM_FREQ;
+        self.on_cycles = period / 100 * self.brightness as u32;
+        self.off_cycles = period - self.on_cycles;
+        self.apply();
+    }
+
+    /// Full and zero brightness hold the pin, anything in between starts toggling high
+    fn apply(&mut self) {
+        let tim = unsafe { &*stm32::TIM2::ptr() };
+        tim.dier.modify(|_, w| w.cc1ie().clear_bit());
+        let mode = match self.brightness {
+            _ if !self.on => OC_FORCE_LOW,
+            0 => OC_FORCE_LOW,
+            100 => OC_FORCE_HIGH,
+            _ => {
+                tim.ccmr1_output()
+                    .modify(|_, w| unsafe { w.oc1m().bits(OC_FORCE_HIGH) });
+                self.high = true;
+                let next = cycles::now().wrapping_add(self.on_cycles);
+                tim.ccr1.write(|w| unsafe { w.bits(next) });
+                tim.sr.modify(|_, w| w.cc1if().clear_bit());
+                tim.dier.modify(|_, w| w.cc1ie().set_bit());
+                OC_TOGGLE
+            }
+        };
+        tim.ccmr1_output()
+            .modify(|_, w| unsafe { w.oc1m().bits(mode) });
+    }
+
+    /// The pin toggled on a compare match, schedules the following edge. A match handled
+    /// too late to be scheduled ahead counts from now instead, the level stays in step.
+    pub fn on_compare(&mut self) {
+        let tim = unsafe { &*stm32::TIM2::ptr() };
+        tim.sr.modify(|_, w| w.cc1if().clear_bit());
+        self.high = !self.high;
+        let wait = if self.high {
+            self.on_cycles
+        } else {
+            self.off_cycles
+        };
+        let mut next = tim.ccr1.read().bits().wrapping_add(wait);
+        let now = cycles::now();
+        if now.wrapping_sub(next) < u32::MAX / 2 {
+            next = now.wrapping_add(wait);
+        }
+        tim.ccr1.write(|w| unsafe { w.bits(next) });
+    }
+}
@@ -0,0 +1,82 @@
+use hal::dma::{self, Channel, Direction, Event, Priority, WordSize};
+use hal::stm32;
+
+use crate::mem;
+
+const RAM_START: u32 = 0x2000_0000;
+const RAM_END: u32 = 0x2000_9000;
+
+pub enum DmaError {
+    InvalidRange,
+    InvalidSource,
+    TransferError,
+}
+
+/// Memory-to-memory transfers on DMA channel 1
+pub struct MemDma {
+    ch: dma::C1,
+}
+
+impl MemDma {
+    pub const MAX_LEN: u32 = u16::MAX as u32;
+
+    pub fn new(mut ch: dma::C1) -> Self {
+        ch.set_priority_level(Priority::Low);
+        ch.set_direction(Direction::FromPeripheral);
+        unsafe {
+            (*stm32::DMA::ptr())
+                .ch1
+                .cr
+                .modify(|_, w| w.mem2mem().set_bit())
+        };
+        Self { ch }
+    }
+
+    /// Copies `len` bytes and blocks until the transfer completes. The source may be
+    /// any mapped region, the destination only RAM.
+    pub fn copy(&mut self, src: u32, dst: u32, len: u32) -> Result<(), DmaError> {
+        if len == 0
+            || len > Self::MAX_LEN
+            || !(RAM_START..RAM_END).contains(&dst)
+            || len > RAM_END - dst
+        {
+            return Err(DmaError::InvalidRange);
+        }
+        if !mem::is_valid_bytes(src, len) {
+            return Err(DmaError::InvalidSource);
+        }
+        self.transfer(src, dst, len as u16, WordSize::BITS8)
+    }
+
+    /// Copies word slices, used to compare DMA throughput against the CPU
+    pub fn copy_words(&mut self, src: &[u32], dst: &mut [u32]) -> Result<(), DmaError> {
+        let len = src.len().min(dst.len()) as u16;
+        self.transfer(
+            src.as_ptr() as u32,
+            dst.as_mut_ptr() as u32,
+            len,
+            WordSize::BITS32,
+        )
+    }
+
+    fn transfer(&mut self, src: u32, dst: u32, len: u16, size: WordSize) -> Result<(), DmaError> {
+        self.ch.disable();
+        self.ch.set_word_size::<u8>(size);
+        self.ch.set_peripheral_address(src, true);
+        self.ch.set_memory_address(dst, true);
+        self.ch.set_transfer_length(len);
+        self.ch.enable();
+
+        let res = loop {
+            if self.ch.event_occurred(Event::TransferError) {
+                break Err(DmaError::TransferError);
+            }
+            if self.ch.event_occurred(Event::TransferComplete) {
+                break Ok(());
+            }
+        };
+        self.ch.disable();
+        self.ch.clear_event(Event::Any);
+        res
+    }
+}
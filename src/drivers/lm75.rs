@@ -0,0 +1,65 @@
+use core::fmt::Write;
+
+use super::{write_i2c_error, Bus, BusKind, Driver};
+use crate::i2c::I2cError;
+use crate::shell::CR;
+
+const TEMP_REG: u8 = 0x00;
+
+/// LM75-compatible temperature sensor
+pub struct Lm75 {
+    addr: u8,
+}
+
+impl Lm75 {
+    pub fn new(addr: u8) -> Self {
+        Self { addr }
+    }
+
+    /// Temperature in tenths of a degree Celsius
+    fn read_temp(&self, bus: &mut Bus) -> Result<i32, I2cError> {
+        let i2c = bus.i2c.as_mut().ok_or(I2cError::Bus)?;
+        let mut buf = [0; 2];
+        i2c.write_read(self.addr, &[TEMP_REG], &mut buf)?;
+        // 9-bit two's complement, 0.5C per LSB
+        let half_degrees = i16::from_be_bytes(buf) >> 7;
+        Ok(half_degrees as i32 * 5)
+    }
+}
+
+impl Driver for Lm75 {
+    fn name(&self) -> &'static str {
+        "sensor"
+    }
+
+    fn description(&self) -> &'static str {
+        "LM75 temperature sensor at 0x48"
+    }
+
+    fn bus(&self) -> BusKind {
+        BusKind::I2c
+    }
+
+    fn init(&mut self, bus: &mut Bus) -> bool {
+        bus.i2c.as_mut().is_some_and(|i2c| i2c.probe(self.addr))
+    }
+
+    fn command(&mut self, bus: &mut Bus, out: &mut dyn Write, _args: &str) {
+        match self.read_temp(bus) {
+            Ok(temp) => {
+                let sign = if temp < 0 { "-" } else { "" };
+                let temp = temp.abs();
+                write!(
+                    out,
+                    "{0:}Temperature: {1:}{2:}.{3:}C{0:}",
+                    CR,
+                    sign,
+                    temp / 10,
+                    temp % 10
+                )
+                .ok();
+            }
+            Err(err) => write_i2c_error(out, err),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use core::fmt::Write;
+
+use crate::i2c::{I2c, I2cError};
+use crate::shell::CR;
+use crate::spi::Spi;
+
+mod lm75;
+mod spiflash;
+mod ssd1306;
+
+/// Buses shared by every driver
+pub struct Bus {
+    /// `None` when I2C1 has no pull-ups
+    pub i2c: Option<I2c>,
+    pub spi: Spi,
+}
+
+/// Bus a driver talks over, torn down once no enabled driver uses it
+#[derive(Clone, Copy, PartialEq)]
+pub enum BusKind {
+    I2c,
+    Spi,
+}
+
+impl BusKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            BusKind::I2c => "i2c",
+            BusKind::Spi => "spi",
+        }
+    }
+}
+
+/// Optional device plugged into the shell, one implementation per chip
+pub trait Driver: Send {
+    /// Command the driver answers to
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    fn bus(&self) -> BusKind;
+
+    /// Argument forms of the driver command, as in `catalog::CommandInfo`
+    fn forms(&self) -> &'static [&'static str] {
+        &[""]
+    }
+
+    /// Probes the device at boot and on `driver enable`, returns false when it does not
+    /// respond
+    fn init(&mut self, bus: &mut Bus) -> bool;
+
+    /// Leaves the device idle before `driver disable`, the bus may go down right after
+    fn deinit(&mut self, _bus: &mut Bus) {}
+
+    /// Runs on every pass of the shell background loop
+    fn poll(&mut self, _bus: &mut Bus) {}
+
+    /// Handles `<name> [args]`
+    fn command(&mut self, bus: &mut Bus, out: &mut dyn Write, args: &str);
+}
+
+pub const DRIVER_COUNT: usize = 3;
+
+/// Registration table, a fork adds its driver here and nowhere else
+pub fn registry() -> [&'static mut dyn Driver; DRIVER_COUNT] {
+    [
+        cortex_m::singleton!(: lm75::Lm75 = lm75::Lm75::new(0x48)).unwrap(),
+        cortex_m::singleton!(: ssd1306::Ssd1306 = ssd1306::Ssd1306::new(0x3c)).unwrap(),
+        cortex_m::singleton!(: spiflash::SpiFlash = spiflash::SpiFlash::new()).unwrap(),
+    ]
+}
+
+pub fn write_i2c_error(out: &mut dyn Write, err: I2cError) {
+    let msg = match err {
+        I2cError::Nack => "device not responding",
+        I2cError::Bus => "bus error",
+        I2cError::Timeout => "bus timeout",
+        I2cError::Off => "bus is off, enable a driver on it",
+    };
+    write!(out, "{0:}i2c: {1:}{0:}", CR, msg).ok();
+}
@@ -0,0 +1,65 @@
+use core::fmt::Write;
+
+use super::{Bus, BusKind, Driver};
+use crate::shell::CR;
+
+const READ_ID: u8 = 0x9f;
+const POWER_DOWN: u8 = 0xb9;
+const RELEASE_POWER_DOWN: u8 = 0xab;
+
+/// SPI NOR flash identified by its JEDEC ID
+pub struct SpiFlash {
+    id: [u8; 3],
+}
+
+impl SpiFlash {
+    pub fn new() -> Self {
+        Self { id: [0; 3] }
+    }
+
+    fn send(bus: &mut Bus, cmd: u8) {
+        bus.spi.select();
+        bus.spi.transfer(&mut [cmd]).ok();
+        bus.spi.deselect();
+    }
+}
+
+impl Driver for SpiFlash {
+    fn name(&self) -> &'static str {
+        "flash"
+    }
+
+    fn description(&self) -> &'static str {
+        "SPI NOR flash on SPI2, CS PB12"
+    }
+
+    fn bus(&self) -> BusKind {
+        BusKind::Spi
+    }
+
+    fn init(&mut self, bus: &mut Bus) -> bool {
+        // Wakes a chip left in deep power-down by `deinit`, a no-op on a running one
+        Self::send(bus, RELEASE_POWER_DOWN);
+        cortex_m::asm::delay(1_000);
+        let mut buf = [READ_ID, 0, 0, 0];
+        bus.spi.select();
+        let res = bus.spi.transfer(&mut buf);
+        bus.spi.deselect();
+        self.id.copy_from_slice(&buf[1..]);
+        // Floating or pulled-up MISO reads as all ones, a shorted one as zeros
+        res.is_ok() && buf[1] != 0x00 && buf[1] != 0xff
+    }
+
+    fn deinit(&mut self, bus: &mut Bus) {
+        Self::send(bus, POWER_DOWN);
+    }
+
+    fn command(&mut self, _bus: &mut Bus, out: &mut dyn Write, _args: &str) {
+        write!(
+            out,
+            "{0:}JEDEC ID: {1:02x} {2:02x} {3:02x}{0:}",
+            CR, self.id[0], self.id[1], self.id[2]
+        )
+        .ok();
+    }
+}
@@ -0,0 +1,69 @@
+use core::fmt::Write;
+
+use super::{write_i2c_error, Bus, BusKind, Driver};
+use crate::i2c::I2cError;
+use crate::shell::CR;
+
+const CMD: u8 = 0x00;
+const DISPLAY_ON: u8 = 0xaf;
+const DISPLAY_OFF: u8 = 0xae;
+
+/// SSD1306 OLED controller on I2C
+pub struct Ssd1306 {
+    addr: u8,
+}
+
+impl Ssd1306 {
+    pub fn new(addr: u8) -> Self {
+        Self { addr }
+    }
+
+    fn set_on(&self, bus: &mut Bus, on: bool) -> Result<(), I2cError> {
+        let i2c = bus.i2c.as_mut().ok_or(I2cError::Bus)?;
+        let cmd = if on { DISPLAY_ON } else { DISPLAY_OFF };
+        i2c.write(self.addr, &[CMD, cmd])
+    }
+}
+
+impl Driver for Ssd1306 {
+    fn name(&self) -> &'static str {
+        "display"
+    }
+
+    fn description(&self) -> &'static str {
+        "SSD1306 OLED at 0x3c"
+    }
+
+    fn bus(&self) -> BusKind {
+        BusKind::I2c
+    }
+
+    fn forms(&self) -> &'static [&'static str] {
+        &["on", "off"]
+    }
+
+    fn init(&mut self, bus: &mut Bus) -> bool {
+        bus.i2c.as_mut().is_some_and(|i2c| i2c.probe(self.addr))
+    }
+
+    fn deinit(&mut self, bus: &mut Bus) {
+        self.set_on(bus, false).ok();
+    }
+
+    fn command(&mut self, bus: &mut Bus, out: &mut dyn Write, args: &str) {
+        let on = match args {
+            "on" => true,
+            "off" => false,
+            _ => {
+                write!(out, "{0:}usage: display on|off{0:}", CR).ok();
+                return;
+            }
+        };
+        match self.set_on(bus, on) {
+            Ok(()) => {
+                out.write_str(CR).ok();
+            }
+            Err(err) => write_i2c_error(out, err),
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use crate::load::Usage;
+
+/// Typical STM32G071 supply currents at 3V and 25C, running from flash in range 1. The
+/// HSI16 oscillator and the regulator draw the base current whenever the core is clocked.
+const CLOCKED_BASE_UA: u32 = 150;
+const RUN_UA_PER_MHZ: u32 = 95;
+const SLEEP_UA_PER_MHZ: u32 = 30;
+/// Stop 1 with the RTC running from LSI, peripheral clocks are stopped too
+const STOP_UA: u32 = 90;
+
+/// Average supply current over the last CPU load window, split by power mode
+pub struct Estimate {
+    pub run_ua: u32,
+    pub sleep_ua: u32,
+    pub stop_ua: u32,
+    /// Added to run and sleep by the running peripheral clocks
+    pub periph_ua: u32,
+    pub average_ua: u32,
+}
+
+/// `periph_load` is the running clocks' draw in tenths of a microamp per MHz, as
+/// reported by `clkgate::running_load`
+pub fn estimate(usage: &Usage, sysclk_hz: u32, periph_load: u32) -> Estimate {
+    let per_mhz = |ua: u32| (ua as u64 * sysclk_hz as u64 / 1_000_000) as u32;
+    let periph_ua = per_mhz(periph_load) / 10;
+    let run_ua = CLOCKED_BASE_UA + per_mhz(RUN_UA_PER_MHZ) + periph_ua;
+    let sleep_ua = CLOCKED_BASE_UA + per_mhz(SLEEP_UA_PER_MHZ) + periph_ua;
+
+    let stop_us = usage.stop_us.min(usage.idle_us);
+    let sleep_us = usage.idle_us - stop_us;
+    let run_us = usage.total_us - usage.idle_us;
+    let charge = run_ua as u64 * run_us as u64
+        + sleep_ua as u64 * sleep_us as u64
+        + STOP_UA as u64 * stop_us as u64;
+    Estimate {
+        run_ua,
+        sleep_ua,
+        stop_ua: STOP_UA,
+        periph_ua,
+        average_ua: (charge / usage.total_us.max(1) as u64) as u32,
+    }
+}
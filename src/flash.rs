@@ -0,0 +1,198 @@
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::{ptr, slice};
+
+use cortex_m::interrupt::Mutex;
+use hal::stm32;
+use heapless::Vec;
+
+pub const PAGE_SIZE: usize = 2048;
+const FLASH_BASE: usize = 0x0800_0000;
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+/// OPERR, PROGERR, WRPERR, PGAERR, SIZERR, PGSERR, MISERR, FASTERR, RDERR, OPTVERR
+const SR_ERRORS: u32 = 0xc3fa;
+
+/// Writes one command can plan before the rest go unreported
+const MAX_PLANNED: usize = 4;
+
+/// Set by `--dry-run`: writes are compared against the page and noted, not programmed
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+static PLANNED: Mutex<RefCell<Vec<Planned, MAX_PLANNED>>> = Mutex::new(RefCell::new(Vec::new()));
+
+/// Internal flash pages kept out of the firmware image by memory.x
+#[derive(Clone, Copy)]
+pub enum Page {
+    /// Written by the panic and HardFault handlers
+    Coredump = 58,
+    /// Shell history, saved next to the settings
+    History = 59,
+    Scripts = 60,
+    Bundles = 61,
+    Settings = 62,
+    /// Per-board calibration, never touched by settings resets
+    Cal = 63,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FlashError {
+    Program,
+    Verify,
+}
+
+impl FlashError {
+    pub fn message(self) -> &'static str {
+        match self {
+            FlashError::Program => "flash programming failed",
+            FlashError::Verify => "flash verify failed",
+        }
+    }
+}
+
+impl Page {
+    fn addr(self) -> usize {
+        FLASH_BASE + self as usize * PAGE_SIZE
+    }
+}
+
+/// Page write a dry run skipped, with the span of bytes it would have changed
+#[derive(Clone, Copy)]
+pub struct Planned {
+    pub page: u8,
+    pub addr: usize,
+    pub changed: usize,
+    pub first: usize,
+    pub last: usize,
+}
+
+/// Starts or ends a dry run, starting one forgets the writes planned before
+pub fn set_dry_run(on: bool) {
+    if on {
+        cortex_m::interrupt::free(|cs| PLANNED.borrow(cs).borrow_mut().clear());
+    }
+    DRY_RUN.store(on, Ordering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Writes skipped since the dry run started
+pub fn take_planned() -> Vec<Planned, MAX_PLANNED> {
+    cortex_m::interrupt::free(|cs| core::mem::take(&mut *PLANNED.borrow(cs).borrow_mut()))
+}
+
+/// Notes what programming `data` at `offset` would change, an erase blanks the rest
+fn plan(page: Page, offset: usize, data: &[u8], erase: bool) {
+    let mut planned = Planned {
+        page: page as u8,
+        addr: page.addr(),
+        changed: 0,
+        first: 0,
+        last: 0,
+    };
+    for (idx, old) in read(page).iter().enumerate() {
+        let kept = if erase { 0xff } else { *old };
+        let new = idx
+            .checked_sub(offset)
+            .and_then(|idx| data.get(idx))
+            .map_or(kept, |byte| *byte);
+        if *old != new {
+            if planned.changed == 0 {
+                planned.first = idx;
+            }
+            planned.changed += 1;
+            planned.last = idx;
+        }
+    }
+    cortex_m::interrupt::free(|cs| PLANNED.borrow(cs).borrow_mut().push(planned).ok());
+}
+
+/// Page contents, erased bytes read as 0xff
+pub fn read(page: Page) -> &'static [u8] {
+    unsafe { slice::from_raw_parts(page.addr() as *const u8, PAGE_SIZE) }
+}
+
+/// Erases the page and programs `data` from its start. The core stalls on flash fetches
+/// while the page is busy, interrupts are held off for the whole write (about 40ms).
+/// A dry run only notes the write.
+pub fn write(page: Page, data: &[u8]) -> Result<(), FlashError> {
+    program_at(page, 0, data, true)
+}
+
+/// Programs `data` at a double word aligned `offset` without erasing, for records
+/// appended to a page. The bytes there must still be blank.
+pub fn program(page: Page, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+    program_at(page, offset, data, false)
+}
+
+fn program_at(page: Page, offset: usize, data: &[u8], erase: bool) -> Result<(), FlashError> {
+    let len = data.len().min(PAGE_SIZE - offset);
+    if is_dry_run() {
+        plan(page, offset, &data[..len], erase);
+        return Ok(());
+    }
+    let flash = unsafe { &*stm32::FLASH::ptr() };
+    let res = cortex_m::interrupt::free(|_| {
+        while flash.sr.read().bsy().bit_is_set() {}
+        flash.keyr.write(|w| unsafe { w.bits(KEY1) });
+        flash.keyr.write(|w| unsafe { w.bits(KEY2) });
+        flash.sr.write(|w| unsafe { w.bits(SR_ERRORS) });
+
+        let mut res = Ok(());
+        if erase {
+            flash
+                .cr
+                .modify(|_, w| unsafe { w.per().set_bit().pnb().bits(page as u8) });
+            flash.cr.modify(|_, w| w.strt().set_bit());
+            res = wait(flash);
+            flash.cr.modify(|_, w| w.per().clear_bit());
+        }
+
+        flash.cr.modify(|_, w| w.pg().set_bit());
+        for start in (0..len).step_by(8) {
+            if res.is_err() {
+                break;
+            }
+            let mut dword = [0xff; 8];
+            let end = (start + 8).min(len);
+            dword[..end - start].copy_from_slice(&data[start..end]);
+            let addr = (page.addr() + offset + start) as *mut u32;
+            // Double word programming, the second write starts it
+            unsafe {
+                ptr::write_volatile(
+                    addr,
+                    u32::from_le_bytes([dword[0], dword[1], dword[2], dword[3]]),
+                );
+                ptr::write_volatile(
+                    addr.add(1),
+                    u32::from_le_bytes([dword[4], dword[5], dword[6], dword[7]]),
+                );
+            }
+            res = wait(flash);
+        }
+        flash.cr.modify(|_, w| w.pg().clear_bit().lock().set_bit());
+        res
+    });
+    res?;
+    if read(page)[offset..][..len] != data[..len] {
+        return Err(FlashError::Verify);
+    }
+    Ok(())
+}
+
+/// Rewrites the page with `data` at `offset`, the rest of the page is kept
+pub fn update(page: Page, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+    let mut buf = [0xff; PAGE_SIZE];
+    buf.copy_from_slice(read(page));
+    buf[offset..][..data.len()].copy_from_slice(data);
+    write(page, &buf)
+}
+
+fn wait(flash: &stm32::flash::RegisterBlock) -> Result<(), FlashError> {
+    while flash.sr.read().bsy().bit_is_set() {}
+    if flash.sr.read().bits() & SR_ERRORS != 0 {
+        return Err(FlashError::Program);
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use hal::stm32;
+
+use crate::pins::{self, Owner, PinError, Port};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PinMode {
+    Input,
+    PullUp,
+    PullDown,
+    Output,
+    Analog,
+}
+
+pub const PIN_MODES: [(&str, PinMode); 5] = [
+    ("input", PinMode::Input),
+    ("pullup", PinMode::PullUp),
+    ("pulldown", PinMode::PullDown),
+    ("output", PinMode::Output),
+    ("analog", PinMode::Analog),
+];
+
+impl PinMode {
+    pub fn from_name(name: &str) -> Option<PinMode> {
+        PIN_MODES
+            .iter()
+            .find(|(mode_name, _)| *mode_name == name)
+            .map(|(_, mode)| *mode)
+    }
+
+    pub fn name(self) -> &'static str {
+        PIN_MODES[self as usize].0
+    }
+
+    /// MODER and PUPDR field values
+    fn bits(self) -> (u32, u32) {
+        match self {
+            PinMode::Input => (0b00, 0b00),
+            PinMode::PullUp => (0b00, 0b01),
+            PinMode::PullDown => (0b00, 0b10),
+            PinMode::Output => (0b01, 0b00),
+            PinMode::Analog => (0b11, 0b00),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum GpioError {
+    Pin(PinError),
+    NotOutput,
+}
+
+impl GpioError {
+    pub fn message(self) -> &'static str {
+        match self {
+            GpioError::Pin(_) => "pin is used by another subsystem",
+            GpioError::NotOutput => "pin is not a gpio output",
+        }
+    }
+}
+
+/// Port A pins handed to the shell at runtime. Only pins configured here are driven,
+/// so the command never fights a peripheral that owns a pin.
+pub struct Gpio {
+    modes: [Option<PinMode>; 16],
+}
+
+impl Gpio {
+    pub fn new() -> Self {
+        Self { modes: [None; 16] }
+    }
+
+    /// Mode set with `set_mode`, `None` for pins the shell does not own
+    pub fn mode(&self, pin: u8) -> Option<PinMode> {
+        self.modes[pin as usize]
+    }
+
+    pub fn set_mode(&mut self, pin: u8, mode: PinMode) -> Result<(), GpioError> {
+        pins::claim(Port::A, pin, Owner::Gpio).map_err(GpioError::Pin)?;
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let shift = pin * 2;
+        let (moder, pupdr) = mode.bits();
+        gpio.pupdr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (pupdr << shift)) });
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (moder << shift)) });
+        self.modes[pin as usize] = Some(mode);
+        Ok(())
+    }
+
+    pub fn write(&mut self, pin: u8, high: bool) -> Result<(), GpioError> {
+        if self.mode(pin) != Some(PinMode::Output) {
+            return Err(GpioError::NotOutput);
+        }
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let bit = if high { pin } else { pin + 16 };
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << bit) });
+        Ok(())
+    }
+}
+
+/// Input level of any port A pin, whoever owns it
+pub fn read(pin: u8) -> bool {
+    let gpio = unsafe { &*stm32::GPIOA::ptr() };
+    gpio.idr.read().bits() & (1 << pin) != 0
+}
+
+/// MODER field of any port A pin: input, output, alternate or analog
+pub fn moder_name(pin: u8) -> &'static str {
+    let gpio = unsafe { &*stm32::GPIOA::ptr() };
+    match gpio.moder.read().bits() >> (pin * 2) & 0b11 {
+        0b00 => "input",
+        0b01 => "output",
+        0b10 => "alternate",
+        _ => "analog",
+    }
+}
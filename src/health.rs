@@ -0,0 +1,393 @@
+use core::ptr;
+
+use hal::analog::adc::{Adc, AdcExt, SampleTime, VRef, VTemp};
+use hal::dma::{self, Channel, Direction, WordSize};
+use hal::dmamux::DmaMuxIndex;
+use hal::gpio::{gpioa::PA0, Analog};
+use hal::hal::adc::OneShot;
+use hal::rcc::Rcc;
+use hal::{nb, stm32};
+
+use crate::cal;
+use crate::config::TICK_HZ;
+use crate::pins::{self, Owner, PinError, Port};
+
+/// Factory calibration in system memory, taken at VDDA = 3.0V
+const VREFINT_CAL: *const u16 = 0x1fff_75aa as *const u16;
+const TS_CAL1: *const u16 = 0x1fff_75a8 as *const u16;
+const TS_CAL2: *const u16 = 0x1fff_75ca as *const u16;
+pub const CAL_VDDA_MV: u32 = 3000;
+pub const TS_CAL1_TEMP: i32 = 30;
+pub const TS_CAL2_TEMP: i32 = 130;
+
+/// Streamed or sampled input on PA0 (ADC_IN0)
+const STREAM_PIN: u32 = 0;
+/// PA0..PA7 are ADC_IN0..ADC_IN7, PA2/PA3 carry the shell and PA5 the LED
+const ADC_PINS: [u8; 5] = [0, 1, 4, 6, 7];
+const STREAM_LEN: usize = 64;
+/// Filled by DMA in circular mode while streaming
+static mut STREAM: [u16; STREAM_LEN] = [0; STREAM_LEN];
+
+/// Internal temperature sensor and VREFINT channels of the ADC, plus the PA0 input
+pub struct Sensors {
+    adc: Adc,
+    input: PA0<Analog>,
+    vtemp: VTemp,
+    vref: VRef,
+    dma: dma::C2,
+    streaming: bool,
+    /// Readings taken before streaming started, the ADC is busy until it stops
+    cached: (u32, i32),
+}
+
+impl Sensors {
+    pub fn new(adc: stm32::ADC, input: PA0<Analog>, dma: dma::C2, rcc: &mut Rcc) -> Self {
+        let mut adc = adc.constrain(rcc);
+        // Wait for the ADC regulator before calibration, tADCVREG_SETUP is 20us
+        cortex_m::asm::delay(rcc.clocks.sys_clk.0 / 50_000);
+        adc.calibrate();
+        // Temperature sensor needs at least 5us of sampling
+        adc.set_sample_time(SampleTime::T_160);
+
+        let mut vtemp = VTemp::new();
+        let mut vref = VRef::new();
+        vtemp.enable(&mut adc);
+        vref.enable(&mut adc);
+        pins::reserve(Port::A, STREAM_PIN as u8, Owner::Adc);
+        Self {
+            adc,
+            input,
+            vtemp,
+            vref,
+            dma,
+            streaming: false,
+            cached: (0, 0),
+        }
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+
+    /// Converts PA0 continuously into a circular DMA buffer
+    pub fn start_stream(&mut self) {
+        if self.streaming {
+            return;
+        }
+        self.cached = (self.vdda_mv(), self.temp_c());
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits(r.bits() | (0b11 << (STREAM_PIN * 2))) });
+
+        let rb = unsafe { &*stm32::ADC::ptr() };
+        self.dma.disable();
+        self.dma.select_peripheral(DmaMuxIndex::ADC);
+        self.dma.set_direction(Direction::FromPeripheral);
+        self.dma.set_word_size::<u16>(WordSize::BITS16);
+        self.dma
+            .set_peripheral_address(&rb.dr as *const _ as u32, false);
+        self.dma
+            .set_memory_address(ptr::addr_of!(STREAM) as u32, true);
+        self.dma.set_transfer_length(STREAM_LEN as u16);
+        self.dma.set_circular_mode(true);
+        self.dma.enable();
+
+        rb.chselr()
+            .write(|w| unsafe { w.chsel().bits(1 << STREAM_PIN) });
+        rb.cfgr1
+            .modify(|_, w| w.cont().set_bit().dmaen().set_bit().dmacfg().set_bit());
+        rb.isr.modify(|_, w| w.adrdy().set_bit());
+        rb.cr.modify(|_, w| w.aden().set_bit());
+        while rb.isr.read().adrdy().bit_is_clear() {}
+        rb.cr.modify(|_, w| w.adstart().set_bit());
+        self.streaming = true;
+    }
+
+    /// Returns the ADC to the one-shot reads of the internal channels
+    pub fn stop_stream(&mut self) {
+        if !self.streaming {
+            return;
+        }
+        let rb = unsafe { &*stm32::ADC::ptr() };
+        rb.cr.modify(|_, w| w.adstp().set_bit());
+        while rb.cr.read().adstp().bit_is_set() {}
+        rb.cfgr1.modify(|_, w| {
+            w.cont()
+                .clear_bit()
+                .dmaen()
+                .clear_bit()
+                .dmacfg()
+                .clear_bit()
+        });
+        rb.cr.modify(|_, w| w.addis().set_bit());
+        while rb.cr.read().aden().bit_is_set() {}
+        self.dma.disable();
+        self.streaming = false;
+    }
+
+    /// Half the peak-to-peak span of the last buffer of streamed samples
+    pub fn stream_amplitude(&self) -> Option<u16> {
+        if !self.streaming {
+            return None;
+        }
+        let samples = ptr::addr_of!(STREAM) as *const u16;
+        let (min, max) = (0..STREAM_LEN)
+            .map(|idx| unsafe { samples.add(idx).read_volatile() })
+            .fold((u16::MAX, 0), |(min, max), sample| {
+                (min.min(sample), max.max(sample))
+            });
+        Some(max.saturating_sub(min) / 2)
+    }
+
+    /// VREFINT conversion, `None` while the ADC streams
+    pub fn vref_raw(&mut self) -> Option<u16> {
+        if self.streaming {
+            return None;
+        }
+        Some(nb::block!(self.adc.read(&mut self.vref)).unwrap_or(0))
+    }
+
+    /// Supply voltage in millivolts, derived from the internal reference
+    pub fn vdda_mv(&mut self) -> u32 {
+        match self.vref_raw() {
+            Some(raw) => CAL_VDDA_MV * vref_cal() as u32 / (raw as u32).max(1),
+            None => self.cached.0,
+        }
+    }
+
+    /// PA0 voltage in millivolts with the board offset applied, `None` while the ADC streams it
+    pub fn input_mv(&mut self) -> Option<u32> {
+        if self.streaming {
+            return None;
+        }
+        let vdda = self.vdda_mv();
+        let raw: u16 = nb::block!(self.adc.read(&mut self.input)).unwrap_or(0);
+        let mv = (raw as u32 * vdda / 4095) as i32 + cal::get(cal::ADC_OFFSET);
+        Some(mv.max(0) as u32)
+    }
+
+    /// Raw counts and millivolts of a port A pin, the pin is left in analog mode.
+    /// Pins without a usable ADC channel are unsupported, as is any pin while the ADC streams.
+    pub fn read_pin(&mut self, pin: u8) -> Result<(u16, u32), PinError> {
+        if self.streaming || !ADC_PINS.contains(&pin) {
+            return Err(PinError::Unsupported);
+        }
+        pins::claim(Port::A, pin, Owner::Adc)?;
+        let vdda = self.vdda_mv();
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits(r.bits() | (0b11 << (pin * 2))) });
+
+        let rb = unsafe { &*stm32::ADC::ptr() };
+        rb.isr.write(|w| w.ccrdy().set_bit());
+        rb.chselr().write(|w| unsafe { w.chsel().bits(1 << pin) });
+        while rb.isr.read().ccrdy().bit_is_clear() {}
+        if rb.cr.read().aden().bit_is_clear() {
+            rb.isr.modify(|_, w| w.adrdy().set_bit());
+            rb.cr.modify(|_, w| w.aden().set_bit());
+            while rb.isr.read().adrdy().bit_is_clear() {}
+        }
+        rb.isr.write(|w| w.eoc().set_bit());
+        rb.cr.modify(|_, w| w.adstart().set_bit());
+        while rb.isr.read().eoc().bit_is_clear() {}
+        let raw = rb.dr.read().bits() as u16;
+        Ok((raw, raw as u32 * vdda / 4095))
+    }
+
+    /// Die temperature in tenths of a degree Celsius and the sensor conversion scaled to
+    /// the 3.0V of the factory calibration, `None` while the ADC streams
+    pub fn temp_dc(&mut self) -> Option<(i32, u16)> {
+        if self.streaming {
+            return None;
+        }
+        let vdda = self.vdda_mv();
+        let raw: u16 = nb::block!(self.adc.read(&mut self.vtemp)).unwrap_or(0);
+        let raw = (raw as u32 * vdda / CAL_VDDA_MV) as i32;
+        let (cal1, cal2) = ts_cal();
+        let (cal1, cal2) = (cal1 as i32, cal2 as i32);
+        let span = (TS_CAL2_TEMP - TS_CAL1_TEMP) * 10;
+        Some((
+            span * (raw - cal1) / (cal2 - cal1) + TS_CAL1_TEMP * 10,
+            raw as u16,
+        ))
+    }
+
+    /// Die temperature in degrees Celsius
+    pub fn temp_c(&mut self) -> i32 {
+        self.temp_dc().map_or(self.cached.1, |(dc, _)| dc / 10)
+    }
+}
+
+/// VREFINT reading at 3.0V, the board calibration overrides the factory value
+pub fn vref_cal() -> u16 {
+    match cal::get(cal::VREF_CAL) {
+        0 => unsafe { VREFINT_CAL.read_volatile() },
+        board => board as u16,
+    }
+}
+
+/// Factory temperature sensor readings at `TS_CAL1_TEMP` and `TS_CAL2_TEMP`
+pub fn ts_cal() -> (u16, u16) {
+    unsafe { (TS_CAL1.read_volatile(), TS_CAL2.read_volatile()) }
+}
+
+#[derive(Clone, Copy)]
+pub enum Alarm {
+    OverTemp,
+    UnderVoltage,
+    OverVoltage,
+}
+
+pub const ALARMS: [(&str, Alarm); 3] = [
+    ("temperature high", Alarm::OverTemp),
+    ("supply low", Alarm::UnderVoltage),
+    ("supply high", Alarm::OverVoltage),
+];
+
+impl Alarm {
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Safe operating bounds
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub temp_max: i32,
+    pub vdd_min: u32,
+    pub vdd_max: u32,
+}
+
+/// Derating supervisor, checks temperature and supply once per second
+pub struct Health {
+    enabled: bool,
+    throttle: bool,
+    limits: Limits,
+    alarms: u8,
+    elapsed: u32,
+    due: bool,
+}
+
+impl Health {
+    pub const TEMP_HYST: i32 = 3;
+    pub const VDD_HYST_MV: u32 = 50;
+    const INTERVAL: u32 = TICK_HZ;
+
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            throttle: false,
+            limits: Limits {
+                temp_max: 85,
+                vdd_min: 2700,
+                vdd_max: 3600,
+            },
+            alarms: 0,
+            elapsed: 0,
+            due: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.alarms = 0;
+        self.elapsed = 0;
+        self.due = enabled;
+    }
+
+    pub fn throttle(&self) -> bool {
+        self.throttle
+    }
+
+    pub fn set_throttle(&mut self, throttle: bool) {
+        self.throttle = throttle;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn is_raised(&self, alarm: Alarm) -> bool {
+        self.alarms & alarm.mask() != 0
+    }
+
+    /// True while throttling is enabled and any alarm is raised
+    pub fn is_throttling(&self) -> bool {
+        self.throttle && self.alarms != 0
+    }
+
+    /// Advances supervisor by one system tick, returns true when a check is due
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances supervisor by a number of ticks slept through in Stop mode
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        self.elapsed += ticks;
+        if self.elapsed >= Self::INTERVAL {
+            self.elapsed = 0;
+            self.due = true;
+        }
+        self.due
+    }
+
+    /// Ticks left until the next check
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        if self.enabled {
+            Some(Self::INTERVAL.saturating_sub(self.elapsed))
+        } else {
+            None
+        }
+    }
+
+    pub fn take_due(&mut self) -> bool {
+        let due = self.due;
+        self.due = false;
+        due
+    }
+
+    /// Applies readings with hysteresis, returns the alarms that changed state
+    pub fn update(&mut self, temp: i32, vdd: u32) -> u8 {
+        let limits = self.limits;
+        let prev = self.alarms;
+        self.set_alarm(
+            Alarm::OverTemp,
+            temp > limits.temp_max,
+            temp < limits.temp_max - Self::TEMP_HYST,
+        );
+        self.set_alarm(
+            Alarm::UnderVoltage,
+            vdd < limits.vdd_min,
+            vdd > limits.vdd_min + Self::VDD_HYST_MV,
+        );
+        self.set_alarm(
+            Alarm::OverVoltage,
+            vdd > limits.vdd_max,
+            vdd + Self::VDD_HYST_MV < limits.vdd_max,
+        );
+        prev ^ self.alarms
+    }
+
+    pub fn changed(changes: u8, alarm: Alarm) -> bool {
+        changes & alarm.mask() != 0
+    }
+
+    fn set_alarm(&mut self, alarm: Alarm, raise: bool, clear: bool) {
+        if raise {
+            self.alarms |= alarm.mask();
+        } else if clear {
+            self.alarms &= !alarm.mask();
+        }
+    }
+}
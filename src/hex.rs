@@ -0,0 +1,82 @@
+use core::fmt::Write;
+
+use heapless::Vec;
+
+use crate::shell::CR;
+
+/// Bytes per hexdump line
+pub const LINE_LEN: usize = 16;
+
+/// A hex byte, with or without the `0x` prefix
+pub fn parse_byte(arg: &str) -> Option<u8> {
+    let digits = arg.strip_prefix("0x").unwrap_or(arg);
+    if digits.is_empty() || digits.len() > 2 {
+        return None;
+    }
+    u8::from_str_radix(digits, 16).ok()
+}
+
+/// Hex bytes, one per argument or runs like `deadbeef`. `None` on a bad digit or when
+/// the bytes do not fit.
+pub fn parse_bytes<'a, const N: usize>(args: impl Iterator<Item = &'a str>) -> Option<Vec<u8, N>> {
+    let mut bytes = Vec::new();
+    for arg in args {
+        let digits = arg.strip_prefix("0x").unwrap_or(arg);
+        if digits.len() <= 2 {
+            bytes.push(parse_byte(digits)?).ok()?;
+            continue;
+        }
+        if !digits.is_ascii() || digits.len() % 2 != 0 {
+            return None;
+        }
+        for idx in (0..digits.len()).step_by(2) {
+            let byte = u8::from_str_radix(&digits[idx..idx + 2], 16).ok()?;
+            bytes.push(byte).ok()?;
+        }
+    }
+    Some(bytes)
+}
+
+/// Bytes as space separated hex pairs, each with a leading space
+pub fn write_bytes(out: &mut dyn Write, bytes: &[u8]) {
+    for byte in bytes {
+        write!(out, " {:02x}", byte).ok();
+    }
+}
+
+/// Printable characters between bars, dots for the rest, padded to a full line
+pub fn write_ascii(out: &mut dyn Write, bytes: &[u8]) {
+    for _ in bytes.len()..LINE_LEN {
+        out.write_str("   ").ok();
+    }
+    out.write_str("  |").ok();
+    for byte in bytes {
+        let ch = if byte.is_ascii_graphic() || *byte == b' ' {
+            *byte as char
+        } else {
+            '.'
+        };
+        out.write_char(ch).ok();
+    }
+    out.write_char('|').ok();
+}
+
+/// Offset, hex and ASCII columns, one line per 16 bytes
+pub fn dump(out: &mut dyn Write, bytes: &[u8]) {
+    for (idx, line) in bytes.chunks(LINE_LEN).enumerate() {
+        write!(out, "{:04x}:", idx * LINE_LEN).ok();
+        write_bytes(out, line);
+        write_ascii(out, line);
+        out.write_str(CR).ok();
+    }
+}
+
+/// Like `dump` with the absolute address of `bytes` in the first column
+pub fn dump_at(out: &mut dyn Write, addr: u32, bytes: &[u8]) {
+    for (idx, line) in bytes.chunks(LINE_LEN).enumerate() {
+        write!(out, "{:08x}:", addr as usize + idx * LINE_LEN).ok();
+        write_bytes(out, line);
+        write_ascii(out, line);
+        out.write_str(CR).ok();
+    }
+}
@@ -0,0 +1,158 @@
+use hal::stm32;
+
+use crate::budget;
+use crate::cal::{self, CalError};
+use crate::config::ShellUsart;
+use crate::cycles;
+use crate::pins::{self, Owner, PinError, Port};
+
+/// HSITRIM after reset, the factory calibration sits in HSICAL underneath
+pub const TRIM_DEFAULT: u8 = 64;
+pub const TRIM_MAX: u8 = 127;
+/// Typical frequency change per trim step
+const TRIM_STEP_PPM: i32 = 2_800;
+/// Port A pins a 1 PPS reference can come in on
+const PPS_PINS: [u8; 4] = [0, 1, 4, 8];
+/// How long to wait for a PPS edge or the autobaud character
+const PPS_TIMEOUT_S: u32 = 2;
+const UART_TIMEOUT_S: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum HsiError {
+    Range,
+    Timeout,
+    /// The USART flagged the character as unusable for a baud measurement
+    Autobaud,
+    Pin(PinError),
+    Cal(CalError),
+}
+
+impl HsiError {
+    pub fn message(self) -> &'static str {
+        match self {
+            HsiError::Range => "trim must be 0-127",
+            HsiError::Timeout => "no reference edge in time",
+            HsiError::Autobaud => "autobaud failed, send U",
+            HsiError::Pin(_) => "pin is used by another subsystem",
+            HsiError::Cal(err) => err.message(),
+        }
+    }
+}
+
+pub fn trim() -> u8 {
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    rcc.icscr.read().hsitrim().bits()
+}
+
+pub fn set_trim(trim: u8) -> Result<(), HsiError> {
+    if trim > TRIM_MAX {
+        return Err(HsiError::Range);
+    }
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    rcc.icscr.modify(|_, w| unsafe { w.hsitrim().bits(trim) });
+    Ok(())
+}
+
+/// Loads the trim saved with `save`, called once at boot
+pub fn restore() {
+    set_trim(cal::get(cal::HSI_TRIM) as u8).ok();
+}
+
+/// Keeps the current trim across resets, the calibration page has to be unlocked
+pub fn save() -> Result<(), HsiError> {
+    cal::write(cal::HSI_TRIM, trim() as i32).map_err(HsiError::Cal)
+}
+
+/// Moves the trim against a measured error, returns the new trim
+pub fn correct(error_ppm: i32) -> u8 {
+    let steps = (error_ppm + error_ppm.signum() * TRIM_STEP_PPM / 2) / TRIM_STEP_PPM;
+    let trim = (trim() as i32 - steps).clamp(0, TRIM_MAX as i32) as u8;
+    set_trim(trim).ok();
+    trim
+}
+
+fn error_ppm(measured: u32, nominal: u32) -> i32 {
+    ((measured as i64 - nominal as i64) * 1_000_000 / nominal as i64) as i32
+}
+
+/// Counts timer cycles over one second of a 1 PPS signal on a port A pin and returns
+/// how fast HSI16 runs in ppm. Busy-waits up to two periods, the shell is blocked.
+pub fn measure_pps(pin: u8) -> Result<i32, HsiError> {
+    if !PPS_PINS.contains(&pin) {
+        return Err(HsiError::Pin(PinError::Unsupported));
+    }
+    pins::claim(Port::A, pin, Owner::Hsical).map_err(HsiError::Pin)?;
+    let gpio = unsafe { &*stm32::GPIOA::ptr() };
+    let shift = pin * 2;
+    gpio.moder
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << shift)) });
+
+    let level = || gpio.idr.read().bits() & (1 << pin) != 0;
+    let timeout = cycles::freq() * PPS_TIMEOUT_S;
+    let rising = || {
+        let start = cycles::now();
+        while level() {
+            if cycles::since(start) > timeout || budget::expired() {
+                return None;
+            }
+        }
+        while !level() {
+            if cycles::since(start) > timeout || budget::expired() {
+                return None;
+            }
+        }
+        Some(cycles::now())
+    };
+    let res = rising()
+        .and_then(|first| rising().map(|second| second.wrapping_sub(first)))
+        .map(|measured| error_ppm(measured, cycles::freq()))
+        .ok_or(HsiError::Timeout);
+
+    gpio.moder
+        .modify(|r, w| unsafe { w.bits(r.bits() | (0b11 << shift)) });
+    pins::release(Port::A, pin, Owner::Hsical);
+    res
+}
+
+/// Lets the shell USART time the host's next character, a `U`, and returns how fast
+/// HSI16 runs in ppm. BRR resolution limits this to a coarse step or so. The receive DMA
+/// is paused meanwhile, so the `U` never reaches the shell.
+pub fn measure_uart() -> Result<i32, HsiError> {
+    let usart = unsafe { &*ShellUsart::ptr() };
+    while usart.isr.read().tc().bit_is_clear() {}
+    let nominal = usart.brr.read().bits();
+
+    // ABREN only changes with the USART disabled, 0x55 frame mode times all of `U`
+    usart.cr3.modify(|_, w| w.dmar().clear_bit());
+    usart.cr1.modify(|_, w| w.ue().clear_bit());
+    usart
+        .cr2
+        .modify(|_, w| unsafe { w.abren().set_bit().abrmod().bits(0b11) });
+    usart.cr1.modify(|_, w| w.ue().set_bit());
+
+    let timeout = cycles::freq() * UART_TIMEOUT_S;
+    let start = cycles::now();
+    let res = loop {
+        let isr = usart.isr.read();
+        if isr.abre().bit_is_set() {
+            break Err(HsiError::Autobaud);
+        }
+        if isr.abrf().bit_is_set() {
+            break Ok(error_ppm(usart.brr.read().bits(), nominal));
+        }
+        if cycles::since(start) > timeout || budget::expired() {
+            break Err(HsiError::Timeout);
+        }
+    };
+
+    usart.cr1.modify(|_, w| w.ue().clear_bit());
+    usart.cr2.modify(|_, w| w.abren().clear_bit());
+    usart.brr.write(|w| unsafe { w.bits(nominal) });
+    usart.cr1.modify(|_, w| w.ue().set_bit());
+    // Drop the autobaud character and anything that overran behind it before the ring
+    // takes over again
+    usart.rqr.write(|w| w.rxfrq().set_bit());
+    usart.icr.write(|w| w.orecf().set_bit());
+    usart.cr3.modify(|_, w| w.dmar().set_bit());
+    res
+}
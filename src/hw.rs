@@ -0,0 +1,203 @@
+use core::fmt::Write;
+
+use hal::stm32;
+
+use crate::drivers::{self, Bus, BusKind, Driver, DRIVER_COUNT};
+use crate::i2c::I2c;
+use crate::pins::PinError;
+use crate::spi::Spi;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum State {
+    /// The device did not respond
+    Absent,
+    On,
+    /// Turned off with `driver disable`
+    Off,
+}
+
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Absent => "absent",
+            State::On => "detected",
+            State::Off => "disabled",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum DriverError {
+    Unknown,
+    /// I2C1 has no pull-ups
+    NoBus,
+    NoDevice,
+    /// Another subsystem took a bus pin while the bus was down
+    Pin(PinError),
+}
+
+impl DriverError {
+    pub fn message(self) -> &'static str {
+        match self {
+            DriverError::Unknown => "unknown driver",
+            DriverError::NoBus => "bus not detected",
+            DriverError::NoDevice => "device not responding",
+            DriverError::Pin(_) => "bus pin is used by another subsystem",
+        }
+    }
+}
+
+/// Registered drivers and their state. A bus stays up while an enabled driver uses it.
+pub struct Hw {
+    bus: Bus,
+    drivers: [&'static mut dyn Driver; DRIVER_COUNT],
+    state: [State; DRIVER_COUNT],
+}
+
+impl Hw {
+    pub fn probe(i2c: stm32::I2C1, spi: stm32::SPI2) -> Self {
+        let mut hw = Self::unprobed(i2c, spi);
+        for (driver, state) in hw.drivers.iter_mut().zip(hw.state.iter_mut()) {
+            *state = if driver.init(&mut hw.bus) {
+                State::On
+            } else {
+                State::Absent
+            };
+        }
+        hw
+    }
+
+    /// Every driver starts disabled without touching its device, for safe mode
+    pub fn unprobed(i2c: stm32::I2C1, spi: stm32::SPI2) -> Self {
+        Self {
+            bus: Bus {
+                i2c: I2c::new(i2c),
+                spi: Spi::new(spi),
+            },
+            drivers: drivers::registry(),
+            state: [State::Off; DRIVER_COUNT],
+        }
+    }
+
+    pub fn has_i2c(&self) -> bool {
+        self.bus.i2c.is_some()
+    }
+
+    /// Raw bus access for shell tools, drivers keep their own device state
+    pub fn i2c(&mut self) -> Option<&mut I2c> {
+        self.bus.i2c.as_mut()
+    }
+
+    pub fn spi(&mut self) -> &mut Spi {
+        &mut self.bus.spi
+    }
+
+    /// True while the bus is powered, a missing I2C bus counts as down
+    pub fn bus_on(&self, kind: BusKind) -> bool {
+        match kind {
+            BusKind::I2c => self.bus.i2c.as_ref().is_some_and(|i2c| i2c.is_on()),
+            BusKind::Spi => self.bus.spi.is_on(),
+        }
+    }
+
+    /// Name, description, bus and state of every registered driver
+    pub fn devices(
+        &self,
+    ) -> impl Iterator<Item = (&'static str, &'static str, BusKind, State)> + '_ {
+        self.drivers
+            .iter()
+            .zip(self.state.iter())
+            .map(|(driver, state)| (driver.name(), driver.description(), driver.bus(), *state))
+    }
+
+    /// Every registered driver and whether its command is available
+    pub fn drivers(&self) -> impl Iterator<Item = (&dyn Driver, bool)> + '_ {
+        self.drivers
+            .iter()
+            .zip(self.state.iter())
+            .map(|(driver, state)| (&**driver, *state == State::On))
+    }
+
+    /// Brings the driver's bus up if needed and probes the device again. A bus brought
+    /// up here goes back down when the device does not answer.
+    pub fn enable(&mut self, name: &str) -> Result<(), DriverError> {
+        let idx = self.find(name)?;
+        if self.state[idx] == State::On {
+            return Ok(());
+        }
+        let kind = self.drivers[idx].bus();
+        let resumed = !self.bus_on(kind);
+        if resumed {
+            match kind {
+                BusKind::I2c => {
+                    let i2c = self.bus.i2c.as_mut().ok_or(DriverError::NoBus)?;
+                    i2c.resume().map_err(DriverError::Pin)?;
+                }
+                BusKind::Spi => self.bus.spi.resume().map_err(DriverError::Pin)?,
+            }
+        }
+        if self.drivers[idx].init(&mut self.bus) {
+            self.state[idx] = State::On;
+            Ok(())
+        } else {
+            self.state[idx] = State::Absent;
+            if resumed {
+                self.release_bus(kind);
+            }
+            Err(DriverError::NoDevice)
+        }
+    }
+
+    /// Leaves the device idle and tears its bus down once no enabled driver uses it,
+    /// releasing the pins and the peripheral clock
+    pub fn disable(&mut self, name: &str) -> Result<(), DriverError> {
+        let idx = self.find(name)?;
+        if self.state[idx] == State::On {
+            self.drivers[idx].deinit(&mut self.bus);
+        }
+        self.state[idx] = State::Off;
+        self.release_bus(self.drivers[idx].bus());
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Result<usize, DriverError> {
+        self.drivers
+            .iter()
+            .position(|driver| driver.name() == name)
+            .ok_or(DriverError::Unknown)
+    }
+
+    fn release_bus(&mut self, kind: BusKind) {
+        let used = self
+            .drivers
+            .iter()
+            .zip(self.state.iter())
+            .any(|(driver, state)| driver.bus() == kind && *state == State::On);
+        if used || !self.bus_on(kind) {
+            return;
+        }
+        match kind {
+            BusKind::I2c => self.bus.i2c.iter_mut().for_each(|i2c| i2c.suspend()),
+            BusKind::Spi => self.bus.spi.suspend(),
+        }
+    }
+
+    pub fn poll(&mut self) {
+        for (driver, state) in self.drivers.iter_mut().zip(self.state.iter()) {
+            if *state == State::On {
+                driver.poll(&mut self.bus);
+            }
+        }
+    }
+
+    /// Runs a driver command, returns false when no enabled driver owns `cmd`
+    pub fn command(&mut self, out: &mut dyn Write, cmd: &str, args: &str) -> bool {
+        for (driver, state) in self.drivers.iter_mut().zip(self.state.iter()) {
+            if *state == State::On && driver.name() == cmd {
+                driver.command(&mut self.bus, out, args);
+                return true;
+            }
+        }
+        false
+    }
+}
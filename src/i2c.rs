@@ -0,0 +1,237 @@
+use hal::stm32;
+
+use crate::pins::{self, Owner, PinError, Port};
+
+/// I2C1 on PB8 (SCL) and PB9 (SDA), alternate function 6
+pub const SCL_PIN: u32 = 8;
+pub const SDA_PIN: u32 = 9;
+const PINS_AF: u32 = 6;
+/// 100kHz standard mode from the 16MHz HSI kernel clock
+const TIMINGR_100K: u32 = 0x0050_3d58;
+const I2C1SEL_HSI16: u8 = 0b10;
+/// Status polls before a transfer is abandoned
+const TIMEOUT: u32 = 20_000;
+/// NBYTES limit of a single transfer, reload is not used
+pub const MAX_LEN: usize = 255;
+/// 7-bit addresses outside this range are reserved
+pub const SCAN_FIRST: u8 = 0x08;
+pub const SCAN_LAST: u8 = 0x77;
+
+const CR2_RD_WRN: u32 = 1 << 10;
+const CR2_START: u32 = 1 << 13;
+const CR2_STOP: u32 = 1 << 14;
+const CR2_AUTOEND: u32 = 1 << 25;
+const ISR_TXIS: u32 = 1 << 1;
+const ISR_RXNE: u32 = 1 << 2;
+const ISR_NACKF: u32 = 1 << 4;
+const ISR_STOPF: u32 = 1 << 5;
+const ISR_TC: u32 = 1 << 6;
+const ISR_BERR: u32 = 1 << 8;
+const ISR_ARLO: u32 = 1 << 9;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum I2cError {
+    Nack,
+    Bus,
+    Timeout,
+    /// The bus is suspended
+    Off,
+}
+
+/// Blocking I2C master that never waits forever on a missing or stuck bus
+pub struct I2c {
+    rb: stm32::I2C1,
+    on: bool,
+}
+
+impl I2c {
+    /// Brings up the bus, `None` when SDA or SCL lack pull-ups and nothing can be attached
+    pub fn new(rb: stm32::I2C1) -> Option<Self> {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        rcc.iopenr.modify(|_, w| w.iopben().set_bit());
+
+        // Weak internal pull-downs lose against external pull-ups
+        for pin in [SCL_PIN, SDA_PIN].iter() {
+            let shift = pin * 2;
+            gpio.pupdr
+                .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b10 << shift)) });
+            gpio.moder
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << shift)) });
+        }
+        cortex_m::asm::delay(1_000);
+        let idle = (1 << SCL_PIN) | (1 << SDA_PIN);
+        let pulled_up = gpio.idr.read().bits() & idle == idle;
+
+        for pin in [SCL_PIN, SDA_PIN].iter() {
+            let shift = pin * 2;
+            gpio.pupdr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << shift)) });
+        }
+        if !pulled_up {
+            return None;
+        }
+
+        let mut i2c = Self { rb, on: false };
+        i2c.configure();
+        for pin in [SCL_PIN, SDA_PIN].iter() {
+            pins::reserve(Port::B, *pin as u8, Owner::I2c);
+        }
+        Some(i2c)
+    }
+
+    /// False while suspended, transfers then fail with `I2cError::Off`
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /// Stops the peripheral and its clock and hands the pins back as analog inputs
+    pub fn suspend(&mut self) {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        self.rb.cr1.modify(|_, w| w.pe().clear_bit());
+        rcc.apbenr1.modify(|_, w| w.i2c1en().clear_bit());
+        for pin in [SCL_PIN, SDA_PIN].iter() {
+            gpio.otyper
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << pin)) });
+            gpio.moder
+                .modify(|r, w| unsafe { w.bits(r.bits() | (0b11 << (pin * 2))) });
+            pins::release(Port::B, *pin as u8, Owner::I2c);
+        }
+        self.on = false;
+    }
+
+    /// Brings the bus back as `new` left it, unless another subsystem took a pin meanwhile
+    pub fn resume(&mut self) -> Result<(), PinError> {
+        for pin in [SCL_PIN, SDA_PIN].iter() {
+            pins::check(Port::B, *pin as u8, Owner::I2c)?;
+        }
+        self.configure();
+        for pin in [SCL_PIN, SDA_PIN].iter() {
+            pins::claim(Port::B, *pin as u8, Owner::I2c)?;
+        }
+        Ok(())
+    }
+
+    fn configure(&mut self) {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        for pin in [SCL_PIN, SDA_PIN].iter() {
+            let shift = pin * 2;
+            let af_shift = (pin - 8) * 4;
+            gpio.otyper
+                .modify(|r, w| unsafe { w.bits(r.bits() | (1 << pin)) });
+            gpio.afrh.modify(|r, w| unsafe {
+                w.bits((r.bits() & !(0xf << af_shift)) | (PINS_AF << af_shift))
+            });
+            gpio.moder
+                .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b10 << shift)) });
+        }
+
+        // HSI16 kernel clock keeps the bus timing fixed under clock scaling
+        rcc.ccipr
+            .modify(|_, w| unsafe { w.i2c1sel().bits(I2C1SEL_HSI16) });
+        rcc.apbenr1.modify(|_, w| w.i2c1en().set_bit());
+        self.rb.cr1.modify(|_, w| w.pe().clear_bit());
+        self.rb.timingr.write(|w| unsafe { w.bits(TIMINGR_100K) });
+        self.rb.cr1.modify(|_, w| w.pe().set_bit());
+        self.on = true;
+    }
+
+    /// True when a device acknowledges its address
+    pub fn probe(&mut self, addr: u8) -> bool {
+        self.write(addr, &[]).is_ok()
+    }
+
+    pub fn write(&mut self, addr: u8, bytes: &[u8]) -> Result<(), I2cError> {
+        self.powered()?;
+        self.start(addr, bytes.len(), false, true);
+        for byte in bytes.iter() {
+            self.wait(ISR_TXIS)?;
+            self.rb.txdr.write(|w| unsafe { w.bits(*byte as u32) });
+        }
+        self.wait(ISR_STOPF)?;
+        self.rb.icr.write(|w| unsafe { w.bits(ISR_STOPF) });
+        Ok(())
+    }
+
+    pub fn read(&mut self, addr: u8, buf: &mut [u8]) -> Result<(), I2cError> {
+        self.powered()?;
+        self.start(addr, buf.len(), true, true);
+        for byte in buf.iter_mut() {
+            self.wait(ISR_RXNE)?;
+            *byte = self.rb.rxdr.read().bits() as u8;
+        }
+        self.wait(ISR_STOPF)?;
+        self.rb.icr.write(|w| unsafe { w.bits(ISR_STOPF) });
+        Ok(())
+    }
+
+    /// Writes `bytes` then reads into `buf` after a repeated start
+    pub fn write_read(&mut self, addr: u8, bytes: &[u8], buf: &mut [u8]) -> Result<(), I2cError> {
+        self.powered()?;
+        self.start(addr, bytes.len(), false, false);
+        for byte in bytes.iter() {
+            self.wait(ISR_TXIS)?;
+            self.rb.txdr.write(|w| unsafe { w.bits(*byte as u32) });
+        }
+        self.wait(ISR_TC)?;
+        self.read(addr, buf)
+    }
+
+    fn powered(&self) -> Result<(), I2cError> {
+        if self.on {
+            Ok(())
+        } else {
+            Err(I2cError::Off)
+        }
+    }
+
+    fn start(&mut self, addr: u8, len: usize, read: bool, autoend: bool) {
+        let mut cr2 = (addr as u32) << 1 | (len as u32 & 0xff) << 16 | CR2_START;
+        if read {
+            cr2 |= CR2_RD_WRN;
+        }
+        if autoend {
+            cr2 |= CR2_AUTOEND;
+        }
+        self.rb.cr2.write(|w| unsafe { w.bits(cr2) });
+    }
+
+    fn wait(&mut self, flag: u32) -> Result<(), I2cError> {
+        for _ in 0..TIMEOUT {
+            let isr = self.rb.isr.read().bits();
+            if isr & ISR_NACKF != 0 {
+                // Hardware only sends the stop after a NACK with AUTOEND
+                if self.rb.cr2.read().bits() & CR2_AUTOEND == 0 {
+                    self.rb
+                        .cr2
+                        .modify(|r, w| unsafe { w.bits(r.bits() | CR2_STOP) });
+                }
+                self.rb.icr.write(|w| unsafe { w.bits(ISR_NACKF) });
+                self.wait(ISR_STOPF).ok();
+                self.rb.icr.write(|w| unsafe { w.bits(ISR_STOPF) });
+                return Err(I2cError::Nack);
+            }
+            if isr & (ISR_BERR | ISR_ARLO) != 0 {
+                self.rb
+                    .icr
+                    .write(|w| unsafe { w.bits(ISR_BERR | ISR_ARLO) });
+                self.reset();
+                return Err(I2cError::Bus);
+            }
+            if isr & flag != 0 {
+                return Ok(());
+            }
+        }
+        self.reset();
+        Err(I2cError::Timeout)
+    }
+
+    /// Software reset releases a stuck peripheral state machine
+    fn reset(&mut self) {
+        self.rb.cr1.modify(|_, w| w.pe().clear_bit());
+        while self.rb.cr1.read().pe().bit_is_set() {}
+        self.rb.cr1.modify(|_, w| w.pe().set_bit());
+    }
+}
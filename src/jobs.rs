@@ -0,0 +1,162 @@
+use heapless::String;
+
+use crate::config::CMD_MAX_LEN;
+
+/// Commands waiting at the same time
+pub const MAX_JOBS: usize = 4;
+/// Longest delay or period, one day
+pub const MAX_DELAY_S: u32 = 86_400;
+/// Due jobs wait this long after the last keystroke of a half typed line
+const TYPING_HOLD_MS: u64 = 3_000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum JobError {
+    Full,
+    LineTooLong,
+}
+
+impl JobError {
+    pub fn message(self) -> &'static str {
+        match self {
+            JobError::Full => "no free job slot",
+            JobError::LineTooLong => "command line too long",
+        }
+    }
+}
+
+struct Job {
+    /// Stays unique while the job lives, so a message for a killed job never hits the
+    /// job that took its slot
+    id: u16,
+    line: String<CMD_MAX_LEN>,
+    /// Monotonic milliseconds the job runs at next
+    at_ms: u64,
+    /// Period of an `every` job in seconds
+    every: Option<u32>,
+    due: bool,
+}
+
+/// Shell commands scheduled with `after` and `every`. The monotonic marks a job due from
+/// its own task, the shell task runs it through the usual command dispatch.
+pub struct Jobs {
+    slots: [Option<Job>; MAX_JOBS],
+    next_id: u16,
+    /// Last keystroke of the line being typed, `None` at an empty prompt
+    typed_at: Option<u64>,
+}
+
+/// One row of the job table
+pub struct JobInfo<'a> {
+    pub id: u16,
+    pub at_ms: u64,
+    pub every: Option<u32>,
+    pub line: &'a str,
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Self {
+            slots: Default::default(),
+            next_id: 1,
+            typed_at: None,
+        }
+    }
+
+    fn find(&mut self, id: u16) -> Option<&mut Option<Job>> {
+        self.slots
+            .iter_mut()
+            .find(|slot| slot.as_ref().is_some_and(|job| job.id == id))
+    }
+
+    fn fresh_id(&mut self) -> u16 {
+        loop {
+            let id = self.next_id;
+            self.next_id = self.next_id.wrapping_add(1).max(1);
+            if self.find(id).is_none() {
+                return id;
+            }
+        }
+    }
+
+    /// Takes a free slot for `line`, the caller schedules the returned job id
+    pub fn add(&mut self, line: &str, at_ms: u64, every: Option<u32>) -> Result<u16, JobError> {
+        if line.len() > CMD_MAX_LEN {
+            return Err(JobError::LineTooLong);
+        }
+        let idx = self
+            .slots
+            .iter()
+            .position(Option::is_none)
+            .ok_or(JobError::Full)?;
+        let id = self.fresh_id();
+        self.slots[idx] = Some(Job {
+            id,
+            line: line.into(),
+            at_ms,
+            every,
+            due: false,
+        });
+        Ok(id)
+    }
+
+    /// Drops a job, false when there is no job with that id
+    pub fn remove(&mut self, id: u16) -> bool {
+        self.find(id).and_then(Option::take).is_some()
+    }
+
+    /// Flags the job for the shell task. A repeating job moves on by one period and
+    /// returns the instant to schedule it at, a run still waiting is not doubled.
+    pub fn mark_due(&mut self, id: u16) -> Option<u64> {
+        let job = self.find(id)?.as_mut()?;
+        job.due = true;
+        let every = job.every?;
+        job.at_ms += every as u64 * 1000;
+        Some(job.at_ms)
+    }
+
+    /// First due job, a one-shot job is freed, a repeating one waits for its next run
+    pub fn take_due(&mut self) -> Option<(u16, String<CMD_MAX_LEN>)> {
+        let slot = self
+            .slots
+            .iter_mut()
+            .find(|slot| slot.as_ref().is_some_and(|job| job.due))?;
+        let job = slot.as_mut()?;
+        job.due = false;
+        if job.every.is_some() {
+            Some((job.id, job.line.clone()))
+        } else {
+            slot.take().map(|job| (job.id, job.line))
+        }
+    }
+
+    /// Every waiting job in slot order
+    pub fn pending(&self) -> impl Iterator<Item = JobInfo<'_>> + '_ {
+        self.slots.iter().flatten().map(|job| JobInfo {
+            id: job.id,
+            at_ms: job.at_ms,
+            every: job.every,
+            line: job.line.as_str(),
+        })
+    }
+
+    /// A key went into the input line at `now_ms`
+    pub fn typing(&mut self, now_ms: u64) {
+        self.typed_at = Some(now_ms);
+    }
+
+    /// The input line was submitted or dropped
+    pub fn line_done(&mut self) {
+        self.typed_at = None;
+    }
+
+    /// A line is being typed, a redraw of the prompt would wipe it
+    pub fn is_typing(&self) -> bool {
+        self.typed_at.is_some()
+    }
+
+    /// Job output would tear a half typed line, hold it while the user keeps typing
+    pub fn held(&self, now_ms: u64) -> bool {
+        self.typed_at
+            .is_some_and(|at| now_ms.saturating_sub(at) < TYPING_HOLD_MS)
+    }
+}
@@ -0,0 +1,205 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use hal::stm32;
+
+use crate::config::ShellUsart;
+use crate::cycles;
+
+/// USART2 RX is PA3, its falling edges are captured on EXTI line 3
+const RX_EXTI_LINE: u32 = 3;
+/// USART2 kernel clock, BRR counts it per bit
+const USART_CLK: u32 = 16_000_000;
+
+/// Last and worst latency of a path in timer cycles
+pub struct Stat {
+    last: AtomicU32,
+    worst: AtomicU32,
+    count: AtomicU32,
+}
+
+impl Stat {
+    const fn new() -> Self {
+        Self {
+            last: AtomicU32::new(0),
+            worst: AtomicU32::new(0),
+            count: AtomicU32::new(0),
+        }
+    }
+
+    pub fn last(&self) -> u32 {
+        self.last.load(Ordering::Relaxed)
+    }
+
+    pub fn worst(&self) -> u32 {
+        self.worst.load(Ordering::Relaxed)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.last.store(0, Ordering::Relaxed);
+        self.worst.store(0, Ordering::Relaxed);
+        self.count.store(0, Ordering::Relaxed);
+    }
+
+    // Each path is recorded from a single task, plain load/store is enough on Cortex-M0+
+    fn record(&self, cycles: u32) {
+        self.last.store(cycles, Ordering::Relaxed);
+        if cycles > self.worst() {
+            self.worst.store(cycles, Ordering::Relaxed);
+        }
+        self.count
+            .store(self.count().wrapping_add(1), Ordering::Relaxed);
+    }
+}
+
+/// UART RX flag to shell byte processing
+pub static RX: Stat = Stat::new();
+/// Blink timer expiry to LED toggle
+pub static BLINK: Stat = Stat::new();
+
+static RX_START: AtomicU32 = AtomicU32::new(0);
+
+/// Captures the start bit of each received byte through EXTI, the pin stays in AF mode
+pub fn init() {
+    let exti = unsafe { &*stm32::EXTI::ptr() };
+    let line = 1 << RX_EXTI_LINE;
+    exti.exticr1
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(0xff << (RX_EXTI_LINE * 8))) });
+    exti.ftsr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+    exti.fpr1.write(|w| unsafe { w.bits(line) });
+    exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+}
+
+/// Start bit edge, masks the line until the byte is processed so data bits are ignored
+pub fn on_rx_edge() {
+    RX_START.store(cycles::now(), Ordering::Relaxed);
+    let exti = unsafe { &*stm32::EXTI::ptr() };
+    let line = 1 << RX_EXTI_LINE;
+    exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() & !line) });
+    exti.fpr1.write(|w| unsafe { w.bits(line) });
+}
+
+/// Shell consumed a byte, RXNE was raised half way through the stop bit
+pub fn on_rx_processed() {
+    let exti = unsafe { &*stm32::EXTI::ptr() };
+    let line = 1 << RX_EXTI_LINE;
+    if exti.imr1.read().bits() & line != 0 {
+        return;
+    }
+    let brr = unsafe { (*ShellUsart::ptr()).brr.read().bits() };
+    let bit = (brr as u64 * cycles::freq() as u64 / USART_CLK as u64) as u32;
+    let since_start = cycles::since(RX_START.load(Ordering::Relaxed));
+    RX.record(since_start.saturating_sub(bit * 19 / 2));
+    exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+}
+
+/// Timer has counted `elapsed` cycles since its update event
+pub fn on_blink(elapsed: u32) {
+    BLINK.record(elapsed);
+}
+
+/// Activations of a periodic task checked against its period on the cycle counter
+pub struct Deadline {
+    last_at: AtomicU32,
+    period: AtomicU32,
+    count: AtomicU32,
+    late: AtomicU32,
+    worst_late: AtomicU32,
+    missed: AtomicU32,
+    unreported: AtomicU32,
+}
+
+impl Deadline {
+    const fn new() -> Self {
+        Self {
+            last_at: AtomicU32::new(0),
+            period: AtomicU32::new(0),
+            count: AtomicU32::new(0),
+            late: AtomicU32::new(0),
+            worst_late: AtomicU32::new(0),
+            missed: AtomicU32::new(0),
+            unreported: AtomicU32::new(0),
+        }
+    }
+
+    /// Expected period in timer cycles
+    pub fn period(&self) -> u32 {
+        self.period.load(Ordering::Relaxed)
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Activations more than a tenth of the period behind schedule
+    pub fn late(&self) -> u32 {
+        self.late.load(Ordering::Relaxed)
+    }
+
+    pub fn worst_late(&self) -> u32 {
+        self.worst_late.load(Ordering::Relaxed)
+    }
+
+    /// Whole periods that passed without an activation
+    pub fn missed(&self) -> u32 {
+        self.missed.load(Ordering::Relaxed)
+    }
+
+    /// Misses since the last call
+    pub fn take_unreported(&self) -> u32 {
+        let missed = self.unreported.load(Ordering::Relaxed);
+        self.unreported.store(0, Ordering::Relaxed);
+        missed
+    }
+
+    pub fn reset(&self) {
+        self.period.store(0, Ordering::Relaxed);
+        self.count.store(0, Ordering::Relaxed);
+        self.late.store(0, Ordering::Relaxed);
+        self.worst_late.store(0, Ordering::Relaxed);
+        self.missed.store(0, Ordering::Relaxed);
+        self.unreported.store(0, Ordering::Relaxed);
+    }
+
+    /// Records an activation of a task expected every `period` cycles, returns true on
+    /// a miss. A changed period, after a restart or clock switch, only resynchronizes.
+    fn record(&self, period: u32) -> bool {
+        let now = cycles::now();
+        let delta = now.wrapping_sub(self.last_at.load(Ordering::Relaxed));
+        self.last_at.store(now, Ordering::Relaxed);
+        if period != self.period() {
+            self.period.store(period, Ordering::Relaxed);
+            return false;
+        }
+        self.count
+            .store(self.count().wrapping_add(1), Ordering::Relaxed);
+
+        let misses = (delta + period / 2) / period.max(1);
+        if misses >= 2 {
+            let missed = self.missed() + misses - 1;
+            self.missed.store(missed, Ordering::Relaxed);
+            let unreported = self.unreported.load(Ordering::Relaxed) + misses - 1;
+            self.unreported.store(unreported, Ordering::Relaxed);
+            return true;
+        }
+        let behind = delta.saturating_sub(period);
+        if behind > period / 10 {
+            self.late.store(self.late() + 1, Ordering::Relaxed);
+            if behind > self.worst_late() {
+                self.worst_late.store(behind, Ordering::Relaxed);
+            }
+        }
+        false
+    }
+}
+
+/// Blink timer activations
+pub static BLINK_DEADLINE: Deadline = Deadline::new();
+
+/// Blink timer fired, its period is `period` timer cycles. Returns true on a miss.
+pub fn on_blink_activation(period: u32) -> bool {
+    BLINK_DEADLINE.record(period)
+}
@@ -0,0 +1,146 @@
+use core::panic::PanicInfo;
+
+use hal::stm32;
+
+use crate::coredump;
+
+/// Who drives the LED, a higher owner overrides the lower ones until it lets go
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Owner {
+    Animation,
+    Alarm,
+    Panic,
+}
+
+impl Owner {
+    pub fn name(self) -> &'static str {
+        match self {
+            Owner::Animation => "animation",
+            Owner::Alarm => "alarm",
+            Owner::Panic => "sos",
+        }
+    }
+}
+
+/// On/off pattern in system ticks, least significant bit first
+struct Pattern {
+    bits: u64,
+    len: u32,
+}
+
+/// Double flash once per second
+const ALARM: Pattern = Pattern {
+    bits: 0b101,
+    len: 10,
+};
+
+/// `... --- ...` with one tick per dot, letters from the low bits up, then a word gap
+const SOS: Pattern = Pattern {
+    bits: 0b101_0100_0111_0111_0111_0001_0101,
+    len: 34,
+};
+
+/// LED on PA5
+const LED_PIN: u32 = 5;
+
+/// Arbitrates the LED between the blink animation and overriding alerts
+pub struct LedOwner {
+    claims: u8,
+    step: u32,
+    handover: bool,
+}
+
+impl LedOwner {
+    pub fn new() -> Self {
+        Self {
+            claims: 0,
+            step: 0,
+            handover: false,
+        }
+    }
+
+    pub fn owner(&self) -> Owner {
+        if self.claims & 1 << Owner::Panic as u8 != 0 {
+            Owner::Panic
+        } else if self.claims & 1 << Owner::Alarm as u8 != 0 {
+            Owner::Alarm
+        } else {
+            Owner::Animation
+        }
+    }
+
+    pub fn is_claimed(&self, owner: Owner) -> bool {
+        self.claims & 1 << owner as u8 != 0
+    }
+
+    pub fn claim(&mut self, owner: Owner) {
+        self.set(owner, true);
+    }
+
+    /// Lets go of the LED, the animation takes it back once no override is left
+    pub fn release(&mut self, owner: Owner) {
+        self.set(owner, false);
+    }
+
+    fn set(&mut self, owner: Owner, claimed: bool) {
+        if owner == Owner::Animation {
+            return;
+        }
+        let before = self.owner();
+        if claimed {
+            self.claims |= 1 << owner as u8;
+        } else {
+            self.claims &= !(1 << owner as u8);
+        }
+        if self.owner() != before {
+            self.step = 0;
+            self.handover = self.owner() == Owner::Animation;
+        }
+    }
+
+    /// Advances the pattern by one system tick, returns the LED level while an
+    /// override owns it. The tick that hands back to the animation switches it off.
+    pub fn tick(&mut self) -> Option<bool> {
+        let pattern = match self.owner() {
+            Owner::Animation if self.handover => {
+                self.handover = false;
+                return Some(false);
+            }
+            Owner::Animation => return None,
+            Owner::Alarm => &ALARM,
+            Owner::Panic => &SOS,
+        };
+        let on = pattern.bits >> self.step & 1 != 0;
+        self.step = (self.step + 1) % pattern.len;
+        Some(on)
+    }
+}
+
+/// Saves a coredump, then blinks SOS until reset
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    coredump::save_panic(info);
+    sos()
+}
+
+/// Blinks SOS with interrupts off until reset. Not knowing the clock, the timing
+/// assumes 16MHz and runs four times faster on 64MHz.
+pub fn sos() -> ! {
+    let gpio = unsafe { &*stm32::GPIOA::ptr() };
+    // The dimmer leaves PA5 on TIM2, take it back as a plain output
+    let shift = LED_PIN * 2;
+    gpio.moder
+        .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b01 << shift)) });
+    loop {
+        for step in 0..SOS.len {
+            let bit = if SOS.bits >> step & 1 != 0 {
+                LED_PIN
+            } else {
+                LED_PIN + 16
+            };
+            gpio.bsrr.write(|w| unsafe { w.bits(1 << bit) });
+            cortex_m::asm::delay(1_600_000);
+        }
+    }
+}
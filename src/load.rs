@@ -0,0 +1,155 @@
+use hal::rcc::Rcc;
+use hal::stm32;
+use hal::timer::TimerExt;
+
+use crate::clocks;
+use crate::config::TICK_HZ;
+use crate::cycles;
+
+/// Burn slices per second
+const RATE_HZ: u32 = 100;
+
+/// Busy loop run from TIM7 at the shell priority, so it delays the shell but not the
+/// higher priority blink and system tick tasks
+pub struct LoadGen {
+    tim: stm32::TIM7,
+    percent: u8,
+}
+
+impl LoadGen {
+    /// Leaves the shell a slice now and then, above this it would never run again
+    pub const MAX_PERCENT: u8 = 90;
+
+    pub fn new(tim: stm32::TIM7, rcc: &mut Rcc) -> Self {
+        Self {
+            tim: tim.timer(rcc).release(),
+            percent: 0,
+        }
+    }
+
+    pub fn percent(&self) -> u8 {
+        self.percent
+    }
+
+    pub fn set_percent(&mut self, percent: u8) {
+        self.percent = percent;
+        if percent == 0 {
+            self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+            self.tim.dier.modify(|_, w| w.uie().clear_bit());
+        } else {
+            self.retime();
+            self.tim.dier.modify(|_, w| w.uie().set_bit());
+        }
+    }
+
+    /// Recomputes prescaler for the current timer clock
+    pub fn retime(&mut self) {
+        if self.percent == 0 {
+            return;
+        }
+        let cycles = clocks::timer_clk() / RATE_HZ;
+        let psc = (cycles - 1) / 0xffff;
+        let arr = cycles / (psc + 1) - 1;
+        let tim = &self.tim;
+        tim.cr1.modify(|_, w| w.cen().clear_bit());
+        tim.cnt.reset();
+        tim.psc.write(|w| unsafe { w.psc().bits(psc as u16) });
+        tim.arr.write(|w| unsafe { w.bits(arr) });
+        tim.cr1.modify(|_, w| w.cen().set_bit().urs().set_bit());
+    }
+
+    /// Spins for the configured share of one slice, returns the cycles burned
+    pub fn burn(&self) -> u32 {
+        let slice = cycles::freq() / RATE_HZ * self.percent as u32 / 100;
+        let start = cycles::now();
+        while cycles::since(start) < slice {}
+        cycles::since(start)
+    }
+
+    pub fn clear_irq(&mut self) {
+        self.tim.sr.modify(|_, w| w.uif().clear_bit());
+    }
+}
+
+/// Time split over the last second
+#[derive(Clone, Copy, Default)]
+pub struct Usage {
+    pub idle_us: u32,
+    /// Part of the idle time spent in Stop mode
+    pub stop_us: u32,
+    pub load_us: u32,
+    pub total_us: u32,
+}
+
+impl Usage {
+    pub fn percent(us: u32, total_us: u32) -> u32 {
+        (us as u64 * 100 / total_us.max(1) as u64) as u32
+    }
+}
+
+/// CPU time accounting, idle counts its sleep and the load generator its busy loop
+pub struct CpuLoad {
+    current: Usage,
+    window: u32,
+    last: Option<Usage>,
+}
+
+impl CpuLoad {
+    pub fn new() -> Self {
+        Self {
+            current: Usage::default(),
+            window: 0,
+            last: None,
+        }
+    }
+
+    pub fn add_idle_cycles(&mut self, cycles: u32) {
+        self.add_idle_us(Self::cycles_to_us(cycles));
+    }
+
+    pub fn add_idle_us(&mut self, us: u32) {
+        self.current.idle_us = self.current.idle_us.saturating_add(us);
+    }
+
+    /// Idle time with the clocks stopped, measured by the RTC
+    pub fn add_stop_us(&mut self, us: u32) {
+        self.add_idle_us(us);
+        self.current.stop_us = self.current.stop_us.saturating_add(us);
+    }
+
+    pub fn add_load_cycles(&mut self, cycles: u32) {
+        let us = Self::cycles_to_us(cycles);
+        self.current.load_us = self.current.load_us.saturating_add(us);
+    }
+
+    /// Usage over the last full second
+    pub fn last(&self) -> Option<Usage> {
+        self.last
+    }
+
+    /// Advances by one system tick
+    pub fn tick(&mut self) {
+        self.advance(1);
+    }
+
+    /// Advances by a number of ticks, closes the window once a second has passed
+    pub fn advance(&mut self, ticks: u32) {
+        self.window += ticks;
+        if self.window < TICK_HZ {
+            return;
+        }
+        let total_us = self.window * (1_000_000 / TICK_HZ);
+        self.last = Some(Usage {
+            idle_us: self.current.idle_us.min(total_us),
+            stop_us: self.current.stop_us.min(total_us),
+            load_us: self.current.load_us.min(total_us),
+            total_us,
+        });
+        self.current = Usage::default();
+        self.window = 0;
+    }
+
+    fn cycles_to_us(cycles: u32) -> u32 {
+        (cycles as u64 * 1_000_000 / cycles::freq() as u64) as u32
+    }
+}
@@ -4,200 +4,918 @@
 
 extern crate cortex_m;
 extern crate cortex_m_rt as rt;
-extern crate panic_halt;
 extern crate rtic;
 extern crate stm32g0xx_hal as hal;
 extern crate ushell;
 
+mod adcwatch;
+mod alarm;
+mod aliases;
+mod audio;
+mod backup;
+mod bitbang;
+mod boot;
+mod budget;
+mod build_info;
+mod bundle;
+mod burst;
+mod cal;
+mod calc;
+mod capture;
+mod catalog;
+mod clkgate;
+mod clocks;
+mod cobs;
+mod config;
+mod coredump;
+mod countdown;
+mod counter;
+mod cycles;
+mod dashboard;
+mod dim;
+mod dma;
+mod drivers;
+mod energy;
+mod flash;
+mod gpio;
+mod health;
+mod hex;
+mod hsical;
+mod hw;
+mod i2c;
+mod jobs;
+mod latency;
+mod led;
+mod load;
+mod mco;
+mod mem;
+mod metrics;
+mod monitor;
+mod mono;
+mod morse;
+mod motion;
+mod output;
+mod pattern;
+mod pid;
+mod pins;
+mod port;
+mod power;
+mod provision;
+mod pwmout;
+mod ranger;
+mod resources;
+mod rollback;
+mod rtc;
+mod safemode;
+mod scripts;
+mod settings;
+mod sha256;
+mod shell;
+mod signing;
+mod slave;
+mod slider;
+mod sniff;
+mod spi;
+mod standby;
+mod statusbar;
+mod stopwatch;
+mod sweep;
+mod switch;
+mod sync;
+mod sysinfo;
+mod telemetry;
+mod thermostat;
+mod tickless;
+mod timesync;
+mod touch;
+mod trace;
+mod trigger;
+mod verbosity;
+mod wave;
+
 use core::fmt::Write;
-use core::usize;
-
-use hal::{gpio::*, prelude::*, serial, stm32, timer::*};
-use ushell::{
-    autocomplete::StaticAutocomplete, control, history::LRUHistory, Input, ShellError, UShell,
-};
-
-const SHELL_PROMPT: &str = "#> ";
-const CR: &str = "\r\n";
-const HELP: &str = "\r\n\
-\x1b[31mL\x1b[32mE\x1b[34mD \x1b[33mBlinky Shell \x1b[0mv.1\r\n\r\n\
-USAGE:\r\n\
-\tcommand [arg]\r\n\r\n\
-COMMANDS:\r\n\
-\ton        Start animation\r\n\
-\toff       Stop animation\r\n\
-\tstatus    Get animation status\r\n\
-\tset <Hz>  Set animation frequency in Hertz [1-100]\r\n\
-\tclear     Clear screen\r\n\
-\thelp      Print this message\r\n\r\n
-CONTROL KEYS:\r\n\
-\tCtrl+D    Start animation\r\n\
-\tCtrl+C    Stop animation\r\n\
-\tCtrl+S    Increment animation frequency\r\n\
-\tCtrl+X    Decrement animation frequency\r\n\
-";
-
-#[rtic::app(device = hal::stm32, peripherals = true)]
+
+use adcwatch::AdcWatch;
+use alarm::Alarm;
+use audio::Envelope;
+use bitbang::Bitbang;
+use bundle::{RingStep, Ringer};
+use burst::Burst;
+use clocks::{ClockPolicy, PeriodicTimer};
+use config::*;
+use countdown::Countdown;
+use counter::EdgeCounter;
+use dashboard::Dashboard;
+use dim::Dimmer;
+use dma::MemDma;
+use gpio::Gpio;
+use hal::{prelude::*, serial};
+use health::{Health, Sensors};
+use heapless::{spsc::Queue, String};
+use hw::Hw;
+use jobs::Jobs;
+use led::{LedOwner, Owner};
+use load::{CpuLoad, LoadGen};
+use monitor::Monitor;
+use morse::Morse;
+use motion::Motion;
+use pattern::BlinkPattern;
+use pid::Pid;
+use port::{ShellPort, UartLink};
+use power::PowerMonitor;
+use pwmout::PwmOut;
+use ranger::Ranger;
+use rollback::Rollback;
+use rtic::time::Instant;
+use scripts::Scripts;
+use settings::Settings;
+use shell::*;
+use slave::SpiSlave;
+use slider::Slider;
+use statusbar::StatusBar;
+use stopwatch::Stopwatch;
+use sweep::Sweep;
+use switch::Switches;
+use sync::{BlinkSync, Mode};
+use telemetry::Telemetry;
+use thermostat::Thermostat;
+use touch::Touch;
+use trace::Traced;
+use trigger::{Event, Trigger};
+use ushell::history::History as _;
+use ushell::{Input, ShellError, UShell};
+use wave::{Step, Wave};
+
+#[rtic::app(device = hal::stm32, peripherals = true, dispatchers = [CEC])]
 mod ushell_demo {
     use super::*;
 
-    type Serial = serial::Serial<stm32::USART2, serial::FullConfig>;
-    type BlinkTimer = Timer<stm32::TIM16>;
-    type Led = gpioa::PA5<Output<PushPull>>;
-    type Shell = UShell<Serial, StaticAutocomplete<6>, LRUHistory<32, 4>, 32>;
+    type BlinkTimer = PeriodicTimer<BlinkTim>;
+    type SysTimer = PeriodicTimer<SysTim>;
+
+    #[monotonic(binds = SysTick, default = true)]
+    type Mono = mono::SysMono;
+
+    resources::track! {
+        adc_watch => AdcWatch,
+        alarm => Alarm,
+        audio => Audio,
+        bitbang => Bitbang,
+        blink_enabled => BlinkEnabled,
+        blink_freq => BlinkFreq,
+        blink_sync => BlinkSync,
+        blink_timer => BlinkTimer,
+        burst => Burst,
+        clock => Clock,
+        countdown => Countdown,
+        counter => Counter,
+        cpu => Cpu,
+        dashboard => Dashboard,
+        gpio => Gpio,
+        health => Health,
+        hw => Hw,
+        jobs => Jobs,
+        led => Led,
+        led_owner => LedOwner,
+        loadgen => Loadgen,
+        mem_dma => MemDma,
+        monitor => Monitor,
+        morse => Morse,
+        motion => Motion,
+        pattern => Pattern,
+        pid => Pid,
+        power => Power,
+        pwmout => Pwmout,
+        ranger => Ranger,
+        ringer => Ringer,
+        rollback => Rollback,
+        scripts => Scripts,
+        sensors => Sensors,
+        slave => Slave,
+        slider => Slider,
+        statusbar => StatusBar,
+        stopwatch => Stopwatch,
+        sweep => Sweep,
+        switches => Switches,
+        sys_timer => SysTimer,
+        telemetry => Telemetry,
+        thermostat => Thermostat,
+        ticks => Ticks,
+        touch => Touch,
+        trigger => Trigger,
+        wave => Wave,
+    }
 
     #[shared]
     struct Shared {
+        adc_watch: AdcWatch,
+        alarm: Alarm,
+        audio: Envelope,
+        bitbang: Bitbang,
         blink_enabled: bool,
+        blink_freq: u8,
+        blink_sync: BlinkSync,
         blink_timer: BlinkTimer,
+        burst: Burst,
+        clock: ClockPolicy,
+        countdown: Countdown,
+        counter: EdgeCounter,
+        cpu: CpuLoad,
+        dashboard: Dashboard,
+        gpio: Gpio,
+        health: Health,
+        hw: Hw,
+        jobs: Jobs,
+        led: Dimmer,
+        led_owner: LedOwner,
+        loadgen: LoadGen,
+        mem_dma: MemDma,
+        monitor: Monitor,
+        morse: Morse,
+        motion: Motion,
+        pattern: BlinkPattern,
+        pid: Pid,
+        power: PowerMonitor,
+        pwmout: PwmOut,
+        ranger: Ranger,
+        ringer: Ringer,
+        rollback: Rollback,
+        scripts: Scripts,
+        sensors: Sensors,
+        slave: SpiSlave,
+        slider: Slider,
+        statusbar: StatusBar,
+        stopwatch: Stopwatch,
+        sweep: Sweep,
+        switches: Switches,
+        sys_timer: SysTimer,
+        telemetry: Telemetry,
+        thermostat: Thermostat,
+        ticks: u32,
+        touch: Touch,
+        trigger: Trigger,
+        wave: Wave,
     }
 
     #[local]
     struct Local {
-        blink_freq: u8,
-        led: Led,
+        /// The `rc` script or the safe mode notice is still due, a standby wakeup skips both
+        boot_script: bool,
         shell: Shell,
+        uart: UartLink,
     }
 
-    #[init]
+    #[init(local = [
+        rx_queue: Queue<u8, RX_QUEUE_LEN> = Queue::new(),
+        tx_queue: Queue<u8, TX_QUEUE_LEN> = Queue::new(),
+    ])]
     fn init(ctx: init::Context) -> (Shared, Local, init::Monotonics) {
+        config::check_priorities();
         let mut rcc = ctx.device.RCC.constrain();
         let port_a = ctx.device.GPIOA.split(&mut rcc);
-        let led = port_a.pa5.into_push_pull_output();
 
-        let mut blink_timer = ctx.device.TIM16.timer(&mut rcc);
-        blink_timer.start(4.hz());
+        backup::init();
+        pins::init();
+        let power = PowerMonitor::new();
+        // A standby wakeup resumes where it left off, a reset restores the saved settings
+        let resume = standby::resume();
+        let saved = settings::load().unwrap_or_else(Settings::defaults);
+        settings::set_prompt(&saved.prompt).ok();
+        let blink_enabled = resume.map_or(saved.blink_enabled, |state| state.blink_enabled);
+        let blink_freq = resume.map_or(saved.blink_freq, |state| state.blink_freq);
+        rtc::init();
+
+        hsical::restore();
+        clocks::init(&mut rcc);
+        let mut blink_timer = PeriodicTimer::new(ctx.device.TIM16.timer(&mut rcc).release());
+        blink_timer.start(blink_freq as u32 * 2);
         blink_timer.listen();
 
+        let mut sys_timer = PeriodicTimer::new(ctx.device.TIM17.timer(&mut rcc).release());
+        sys_timer.start(TICK_HZ);
+        sys_timer.listen();
+
+        let pwmout = PwmOut::new(ctx.device.TIM3, &mut rcc);
+        let burst = Burst::new(ctx.device.TIM1, &mut rcc);
+        let ranger = Ranger::new(ctx.device.TIM14, &mut rcc);
+        let loadgen = LoadGen::new(ctx.device.TIM7, &mut rcc);
+        let adc_watch = AdcWatch::new(ctx.device.TIM6, &mut rcc);
+
+        let dma = ctx.device.DMA.split(&mut rcc, ctx.device.DMAMUX);
+        let mem_dma = MemDma::new(dma.ch1);
+        let sensors = Sensors::new(ctx.device.ADC, port_a.pa0.into_analog(), dma.ch2, &mut rcc);
+        let slave = SpiSlave::new(ctx.device.SPI1, dma.ch3, dma.ch4);
+        cycles::init(ctx.device.TIM2, &mut rcc);
+        let led = Dimmer::new(port_a.pa5.into_push_pull_output());
+
         let mut serial = ctx
             .device
             .USART2
             .usart(
                 port_a.pa2,
                 port_a.pa3,
-                serial::FullConfig::default(),
+                serial::FullConfig::default().baudrate(SHELL_BAUD.bps()),
                 &mut rcc,
             )
             .expect("Failed to init serial port");
-        serial.listen(serial::Event::Rxne);
+        // `reboot safe` or a key held through the first second, a standby wakeup never
+        if resume.is_none() && (safemode::take_request() || safemode::key_held(&mut serial)) {
+            safemode::enter();
+        }
+        let hw = if safemode::is_active() {
+            Hw::unprobed(ctx.device.I2C1, ctx.device.SPI2)
+        } else {
+            Hw::probe(ctx.device.I2C1, ctx.device.SPI2)
+        };
+        tickless::init();
+        latency::init();
 
-        let autocomplete = StaticAutocomplete(["clear", "help", "off", "on", "set ", "status"]);
-        let history = LRUHistory::default();
-        let shell = UShell::new(serial, autocomplete, history);
+        let (rx_producer, rx_consumer) = ctx.local.rx_queue.split();
+        let (tx_producer, tx_consumer) = ctx.local.tx_queue.split();
+        let uart = UartLink::new(serial, dma.ch5, rx_producer, tx_consumer);
+
+        let mut history = History::default();
+        for line in settings::load_history().iter() {
+            history.push(line).ok();
+        }
+        let shell = UShell::new(
+            output::Output::new(Traced::new(ShellPort::new(rx_consumer, tx_producer))),
+            autocomplete(),
+            history,
+        );
+        // The shell task runs the `rc` script, or says it is in safe mode, as soon as
+        // init returns
+        let boot_script = resume.is_none();
+        if boot_script {
+            shell_poll::spawn().ok();
+        }
 
         (
             Shared {
+                adc_watch,
+                alarm: Alarm::new(),
+                audio: Envelope::new(),
+                bitbang: Bitbang::new(),
+                blink_enabled,
+                blink_freq,
+                blink_sync: BlinkSync::new(),
                 blink_timer,
-                blink_enabled: false,
+                burst,
+                clock: ClockPolicy::new(),
+                countdown: Countdown::new(),
+                counter: EdgeCounter::new(),
+                cpu: CpuLoad::new(),
+                dashboard: Dashboard::new(),
+                gpio: Gpio::new(),
+                health: Health::new(),
+                hw,
+                jobs: Jobs::new(),
+                led,
+                led_owner: LedOwner::new(),
+                loadgen,
+                mem_dma,
+                monitor: Monitor::new(),
+                morse: Morse::new(),
+                motion: Motion::new(),
+                pattern: BlinkPattern::new(),
+                pid: Pid::new(),
+                power,
+                pwmout,
+                ranger,
+                ringer: Ringer::new(),
+                rollback: Rollback::new(),
+                scripts: Scripts::new(),
+                sensors,
+                slave,
+                slider: Slider::new(),
+                statusbar: StatusBar::new(),
+                stopwatch: Stopwatch::new(),
+                sweep: Sweep::new(),
+                switches: Switches::new(),
+                sys_timer,
+                telemetry: Telemetry::new(),
+                thermostat: Thermostat::new(),
+                ticks: 0,
+                touch: Touch::new(),
+                trigger: Trigger::new(),
+                wave: Wave::new(),
             },
             Local {
+                boot_script,
                 shell,
-                blink_freq: 2,
-                led,
+                uart,
             },
-            init::Monotonics(),
+            init::Monotonics(mono::SysMono::new(ctx.core.SYST)),
         )
     }
 
-    #[task(binds = TIM16, priority = 2, shared = [blink_timer, blink_enabled], local = [led])]
+    /// Tickless idle: with nothing driven by the timers, the core sleeps in Stop mode
+    /// and catches up on the system ticks it missed from the RTC
+    #[idle(shared = [adc_watch, blink_enabled, blink_sync, burst, clock, counter, cpu, dashboard, health, led_owner, loadgen, monitor, motion, pid, pwmout, ranger, slave, statusbar, sweep, switches, telemetry, thermostat, ticks, touch])]
+    fn idle(ctx: idle::Context) -> ! {
+        let idle::SharedResources {
+            mut adc_watch,
+            mut blink_enabled,
+            mut blink_sync,
+            mut burst,
+            mut clock,
+            mut counter,
+            mut cpu,
+            mut dashboard,
+            mut health,
+            mut led_owner,
+            mut loadgen,
+            mut monitor,
+            mut motion,
+            mut pid,
+            mut pwmout,
+            mut ranger,
+            mut slave,
+            mut statusbar,
+            mut sweep,
+            mut switches,
+            mut telemetry,
+            mut thermostat,
+            mut ticks,
+            mut touch,
+        } = ctx.shared;
+        let tick_ms = 1000 / TICK_HZ;
+        let mut carry_ms = 0;
+
+        loop {
+            cortex_m::interrupt::disable();
+            let busy = blink_enabled.lock(|e| *e)
+                || led_owner.lock(|l| l.owner() != Owner::Animation)
+                || blink_sync.lock(|s| s.mode() != Mode::Off)
+                || pwmout.lock(|p| p.channel().is_some())
+                || burst.lock(|b| b.is_running())
+                || slave.lock(|s| s.is_enabled())
+                || loadgen.lock(|l| l.percent() > 0)
+                || adc_watch.lock(|w| w.is_active());
+            if busy || !tickless::is_quiet() {
+                let start = cycles::now();
+                cortex_m::asm::wfi();
+                cpu.lock(|c| c.add_idle_cycles(cycles::since(start)));
+            } else {
+                let limit = [
+                    health.lock(|h| h.ticks_until_due()),
+                    monitor.lock(|m| m.ticks_until_due()),
+                    sweep.lock(|s| s.ticks_until_due()),
+                    telemetry.lock(|t| t.ticks_until_due()),
+                    touch.lock(|t| t.ticks_until_due()),
+                    ranger.lock(|r| r.ticks_until_due()),
+                    motion.lock(|m| m.ticks_until_due()),
+                    switches.lock(|s| s.ticks_until_due()),
+                    thermostat.lock(|t| t.ticks_until_due()),
+                    pid.lock(|p| p.ticks_until_due()),
+                    statusbar.lock(|b| b.ticks_until_due()),
+                    dashboard.lock(|d| d.ticks_until_due()),
+                ]
+                .iter()
+                .flatten()
+                .min()
+                .copied();
+                let limit_ms = limit
+                    .map(|ticks| ticks * tick_ms)
+                    .into_iter()
+                    .chain(mono::ms_until_due())
+                    .min();
+                let stopped_ms = tickless::stop(limit_ms);
+                mono::advance(stopped_ms);
+                let slept_ms = carry_ms + stopped_ms;
+                let slept = slept_ms / tick_ms;
+                carry_ms = slept_ms % tick_ms;
+
+                ticks.lock(|t| *t = t.wrapping_add(slept));
+                cpu.lock(|c| {
+                    c.add_stop_us(slept * tick_ms * 1000);
+                    c.advance(slept);
+                });
+                counter.lock(|c| c.advance(slept));
+                let idle_due = clock.lock(|c| c.advance(slept));
+                let health_due = health.lock(|h| h.advance(slept));
+                let monitor_due = monitor.lock(|m| m.advance(slept));
+                let sweep_due = sweep.lock(|s| s.advance(slept));
+                let telemetry_due = telemetry.lock(|t| t.advance(slept));
+                let touch_due = touch.lock(|t| t.advance(slept));
+                let ranger_due = ranger.lock(|r| r.advance(slept));
+                let motion_due = motion.lock(|m| m.advance(slept));
+                let switch_due = switches.lock(|s| s.advance(slept));
+                let thermostat_due = thermostat.lock(|t| t.advance(slept));
+                let pid_due = pid.lock(|p| p.advance(slept));
+                let statusbar_due = statusbar.lock(|b| b.advance(slept));
+                let dashboard_due = dashboard.lock(|d| d.advance(slept));
+                if idle_due
+                    || health_due
+                    || monitor_due
+                    || sweep_due
+                    || telemetry_due
+                    || touch_due
+                    || ranger_due
+                    || motion_due
+                    || switch_due
+                    || thermostat_due
+                    || pid_due
+                    || statusbar_due
+                    || dashboard_due
+                {
+                    rtic::pend(SHELL_IRQ);
+                }
+            }
+            unsafe { cortex_m::interrupt::enable() };
+        }
+    }
+
+    #[task(binds = TIM16, priority = 2, shared = [blink_timer, blink_enabled, led, led_owner, morse, pattern, trigger])]
     fn blink_timer_tick(ctx: blink_timer_tick::Context) {
-        let led = ctx.local.led;
         let blink_timer_tick::SharedResources {
             mut blink_enabled,
             mut blink_timer,
+            mut led,
+            mut led_owner,
+            mut morse,
+            mut pattern,
+            mut trigger,
         } = ctx.shared;
 
-        if blink_enabled.lock(|e| *e) {
-            led.toggle().expect("Failed to blink o_0");
-        } else {
-            led.set_low().expect("Failed to switch led off");
+        trigger.lock(|t| t.fire_on(Event::Tick));
+        // An alarm or panic pattern owns the LED, the animation resumes when it ends
+        if led_owner.lock(|l| l.owner() == Owner::Animation) {
+            // A message plays over the animation, a stopped animation starts its
+            // pattern over when it comes back
+            let on = match morse.lock(|m| m.next()) {
+                Some(on) => on,
+                None if blink_enabled.lock(|e| *e) => pattern.lock(|p| p.next()),
+                None => {
+                    pattern.lock(|p| p.restart());
+                    false
+                }
+            };
+            led.lock(|led| led.set(on));
+        }
+        let missed = blink_timer.lock(|t| {
+            latency::on_blink(t.elapsed());
+            t.clear_irq();
+            latency::on_blink_activation(t.period())
+        });
+        if missed {
+            rtic::pend(SHELL_IRQ);
         }
-        blink_timer.lock(|t| t.clear_irq());
     }
 
-    #[task(binds = USART2, priority = 1, shared = [blink_timer, blink_enabled], local = [blink_freq, shell])]
-    fn serial_data(ctx: serial_data::Context) {
-        let serial_data::SharedResources {
+    #[task(binds = TIM17, priority = 2, shared = [blink_enabled, blink_sync, blink_timer, clock, counter, cpu, dashboard, health, led, led_owner, monitor, motion, pid, ranger, slave, statusbar, sweep, switches, sys_timer, telemetry, thermostat, ticks, touch])]
+    fn sys_tick(ctx: sys_tick::Context) {
+        let sys_tick::SharedResources {
             mut blink_enabled,
+            mut blink_sync,
             mut blink_timer,
+            mut clock,
+            mut counter,
+            mut cpu,
+            mut dashboard,
+            mut health,
+            mut led,
+            mut led_owner,
+            mut monitor,
+            mut motion,
+            mut pid,
+            mut ranger,
+            mut slave,
+            mut statusbar,
+            mut sweep,
+            mut switches,
+            mut sys_timer,
+            mut telemetry,
+            mut thermostat,
+            mut ticks,
+            mut touch,
+        } = ctx.shared;
+
+        let now = ticks.lock(|t| {
+            *t = t.wrapping_add(1);
+            *t
+        });
+        cpu.lock(|c| c.tick());
+        // The master aligns its own animation on the pulse it sends
+        if blink_sync.lock(|s| s.tick(now)) {
+            let period = blink_timer.lock(|t| t.period());
+            let (on, next) = blink_sync.lock(|s| s.align(period));
+            blink_timer.lock(|t| t.align(next));
+            if blink_enabled.lock(|e| *e) && led_owner.lock(|l| l.owner() == Owner::Animation) {
+                led.lock(|led| led.set(on));
+            }
+        }
+        if let Some(on) = led_owner.lock(|l| l.tick()) {
+            led.lock(|led| led.set(on));
+        }
+        counter.lock(|c| c.tick());
+        slave.lock(|s| s.tick());
+        let idle_due = clock.lock(|c| c.tick());
+        let health_due = health.lock(|h| h.tick());
+        let monitor_due = monitor.lock(|m| m.tick());
+        let sweep_due = sweep.lock(|s| s.tick());
+        let telemetry_due = telemetry.lock(|t| t.tick());
+        let touch_due = touch.lock(|t| t.tick());
+        let ranger_due = ranger.lock(|r| r.tick());
+        let motion_due = motion.lock(|m| m.tick());
+        let switch_due = switches.lock(|s| s.tick());
+        let thermostat_due = thermostat.lock(|t| t.tick());
+        let pid_due = pid.lock(|p| p.tick());
+        let statusbar_due = statusbar.lock(|b| b.tick());
+        let dashboard_due = dashboard.lock(|d| d.tick());
+        if idle_due
+            || health_due
+            || monitor_due
+            || sweep_due
+            || telemetry_due
+            || touch_due
+            || ranger_due
+            || motion_due
+            || switch_due
+            || thermostat_due
+            || pid_due
+            || statusbar_due
+            || dashboard_due
+        {
+            rtic::pend(SHELL_IRQ);
+        }
+        sys_timer.lock(|t| t.clear_irq());
+    }
+
+    /// Takes a sample for `adc watch`, the shell reports once a second
+    #[task(binds = TIM6_DAC_LPTIM1, priority = 2, shared = [adc_watch, sensors])]
+    fn adc_sample(ctx: adc_sample::Context) {
+        let adc_sample::SharedResources {
+            mut adc_watch,
+            mut sensors,
+        } = ctx.shared;
+        adc_watch.lock(|w| w.clear_irq());
+        let pin = match adc_watch.lock(|w| w.pin()) {
+            Some(pin) => pin,
+            None => return,
+        };
+        if let Ok((_, mv)) = sensors.lock(|s| s.read_pin(pin)) {
+            if adc_watch.lock(|w| w.record(mv)) {
+                rtic::pend(SHELL_IRQ);
+            }
+        }
+    }
+
+    /// LED brightness PWM edge on a TIM2 compare match
+    #[task(binds = TIM2, priority = 2, shared = [led])]
+    fn led_pwm(mut ctx: led_pwm::Context) {
+        ctx.shared.led.lock(|l| l.on_compare());
+    }
+
+    /// SPI slave transaction done, the register map gets fresh values for the next one
+    #[task(binds = DMA_CHANNEL2_3, priority = 2, shared = [blink_enabled, blink_freq, counter, led, slave, ticks])]
+    fn spi_slave(ctx: spi_slave::Context) {
+        let spi_slave::SharedResources {
+            mut blink_enabled,
+            mut blink_freq,
+            mut counter,
+            mut led,
+            mut slave,
+            mut ticks,
+        } = ctx.shared;
+        let status = slave::Status {
+            animation: blink_enabled.lock(|e| *e),
+            led: led.lock(|l| l.is_on()),
+            freq: blink_freq.lock(|f| *f),
+            ticks: ticks.lock(|t| *t),
+            edges: counter.lock(|c| c.count()),
+        };
+        slave.lock(|s| s.on_dma(&status));
+    }
+
+    /// PWM LED period: follows the audio envelope or steps through the wave table
+    #[task(binds = TIM3, priority = 2, shared = [audio, pwmout, sensors, wave])]
+    fn wave_tick(ctx: wave_tick::Context) {
+        let wave_tick::SharedResources {
+            mut audio,
+            mut pwmout,
+            mut sensors,
+            mut wave,
         } = ctx.shared;
-        let serial_data::LocalResources { blink_freq, shell } = ctx.local;
+
+        if let Some(amplitude) = sensors.lock(|s| s.stream_amplitude()) {
+            let duty = audio.lock(|a| a.step(amplitude));
+            pwmout.lock(|p| {
+                p.set_duty(duty);
+                p.clear_irq();
+            });
+            return;
+        }
+        match wave.lock(|w| w.step()) {
+            Step::Hold => {}
+            Step::Duty(duty) => pwmout.lock(|p| p.set_duty(duty)),
+            Step::Done => pwmout.lock(|p| p.stop()),
+        }
+        pwmout.lock(|p| p.clear_irq());
+    }
+
+    /// Load generator slice, shares the shell priority so it only delays the shell
+    #[task(binds = TIM7_LPTIM2, priority = 1, shared = [cpu, loadgen])]
+    fn load_tick(ctx: load_tick::Context) {
+        let load_tick::SharedResources {
+            mut cpu,
+            mut loadgen,
+        } = ctx.shared;
+
+        let burned = loadgen.lock(|l| {
+            l.clear_irq();
+            l.burn()
+        });
+        cpu.lock(|c| c.add_load_cycles(burned));
+    }
+
+    #[task(binds = PVD, priority = 3, shared = [power, ticks])]
+    fn power_fail(ctx: power_fail::Context) {
+        let power_fail::SharedResources {
+            mut power,
+            mut ticks,
+        } = ctx.shared;
+
+        let now = ticks.lock(|t| *t);
+        power.lock(|p| p.on_interrupt(now));
+        rtic::pend(SHELL_IRQ);
+    }
+
+    #[task(binds = EXTI2_3, priority = 3)]
+    fn uart_rx_edge(_: uart_rx_edge::Context) {
+        latency::on_rx_edge();
+    }
+
+    #[task(binds = DMA_CHANNEL4_5_6_7, priority = 3)]
+    fn uart_rx_dma(_: uart_rx_dma::Context) {
+        port::on_rx_dma();
+        rtic::pend(SHELL_IRQ);
+    }
+
+    /// PIR rising edge, the motion rule starts the animation right away. A sync pulse
+    /// from another board realigns the animation to its phase, counted pin edges add up.
+    #[task(binds = EXTI4_15, priority = 2, shared = [blink_enabled, blink_sync, blink_timer, counter, led, led_owner, motion, ticks])]
+    fn pin_edge(ctx: pin_edge::Context) {
+        let pin_edge::SharedResources {
+            mut blink_enabled,
+            mut blink_sync,
+            mut blink_timer,
+            mut counter,
+            mut led,
+            mut led_owner,
+            mut motion,
+            mut ticks,
+        } = ctx.shared;
+
+        let now = ticks.lock(|t| *t);
+        if BlinkSync::is_pending() {
+            BlinkSync::clear_irq();
+            let period = blink_timer.lock(|t| t.period());
+            let (on, next) = blink_sync.lock(|s| {
+                s.on_pulse(now);
+                s.align(period)
+            });
+            blink_timer.lock(|t| t.align(next));
+            if blink_enabled.lock(|e| *e) && led_owner.lock(|l| l.owner() == Owner::Animation) {
+                led.lock(|led| led.set(on));
+            }
+        }
+        counter.lock(|c| c.on_edge());
+        if Motion::is_pending() {
+            Motion::clear_irq();
+            if motion.lock(|m| m.on_motion(now)) {
+                blink_enabled.lock(|e| *e = true);
+            }
+        }
+    }
+
+    /// Services the shell UART, commands run in `shell_poll` below it so a long command
+    /// never holds up reception
+    #[task(binds = USART2, priority = 2, local = [uart])]
+    fn serial_data(ctx: serial_data::Context) {
+        if ctx.local.uart.service() {
+            shell_poll::spawn().ok();
+        }
+    }
+
+    #[task(priority = 1, shared = [adc_watch, alarm, audio, bitbang, blink_enabled, blink_freq, blink_sync, blink_timer, burst, clock, countdown, counter, cpu, dashboard, gpio, health, hw, jobs, led, led_owner, loadgen, mem_dma, monitor, morse, motion, pattern, pid, power, pwmout, ranger, ringer, rollback, scripts, sensors, slave, slider, statusbar, stopwatch, sweep, switches, sys_timer, telemetry, thermostat, ticks, touch, trigger, wave], local = [boot_script, shell])]
+    fn shell_poll(ctx: shell_poll::Context) {
+        let shell = ctx.local.shell;
+        let mut env = ctx.shared;
+        if core::mem::take(ctx.local.boot_script) {
+            env.boot(shell);
+        }
+        let ticks = env.ticks.lock(|t| *t);
+        shell.serial().set_uptime(ticks);
+        if env.dashboard_input(shell) || env.adc_watch_input(shell) || env.slider_input(shell) {
+            env.background(shell);
+            return;
+        }
 
         loop {
-            match shell.poll() {
+            let input = shell.poll();
+            if input.is_ok() {
+                latency::on_rx_processed();
+                env.clock.lock(|c| c.activity());
+            }
+            match input {
                 Ok(Some(Input::Command((cmd, args)))) => {
-                    match cmd {
-                        "help" => {
-                            shell.write_str(HELP).ok();
-                        }
-                        "clear" => {
-                            shell.clear().ok();
-                        }
-                        "on" => {
-                            blink_enabled.lock(|e| *e = true);
-                            shell.write_str(CR).ok();
-                        }
-                        "off" => {
-                            blink_enabled.lock(|e| *e = false);
-                            shell.write_str(CR).ok();
-                        }
-                        "status" => {
-                            let on = blink_enabled.lock(|e| *e);
-                            let status = if on { "On" } else { "Off" };
-                            write!(
-                                shell,
-                                "{0:}Animation: {1:}{0:}Frequency: {2:}Hz{0:}",
-                                CR, status, blink_freq
-                            )
-                            .ok();
-                        }
-                        "set" => match btoi::btoi(args.as_bytes()) {
-                            Ok(freq) if freq > 0 && freq <= 100 => {
-                                *blink_freq = freq;
-                                blink_timer.lock(|t| {
-                                    t.start((freq as u32 * 2).hz());
-                                });
-                                shell.write_str(CR).ok();
-                            }
-                            _ => {
-                                write!(shell, "{0:}unsupported frequency{0:}", CR).ok();
-                            }
-                        },
-                        "" => {
-                            shell.write_str(CR).ok();
-                        }
-                        _ => {
-                            write!(shell, "{0:}unsupported command{0:}", CR).ok();
-                        }
-                    }
-                    shell.write_str(SHELL_PROMPT).ok();
+                    env.jobs.lock(|j| j.line_done());
+                    let cmd: String<CMD_MAX_LEN> = cmd.into();
+                    let args: String<CMD_MAX_LEN> = args.into();
+                    env.dispatch(shell, &cmd, &args);
+                    shell.write_str(&settings::prompt()).ok();
                 }
-                Ok(Some(Input::Control(control::CTRL_D))) => {
-                    blink_enabled.lock(|e| *e = true);
+                Ok(Some(Input::Control(code))) => {
+                    env.jobs.lock(|j| j.line_done());
+                    env.control(shell, code);
                 }
-                Ok(Some(Input::Control(control::CTRL_C))) => {
-                    blink_enabled.lock(|e| *e = false);
+                Ok(None) => {
+                    let now = mono::now_ms();
+                    env.jobs.lock(|j| j.typing(now));
                 }
-                Ok(Some(Input::Control(control::CTRL_S))) => {
-                    if *blink_freq == 100 {
-                        return;
-                    }
-                    *blink_freq += 1;
-                    blink_timer.lock(|t| {
-                        t.start((*blink_freq as u32 * 2).hz());
-                    });
+                Err(ShellError::WouldBlock) => break,
+                Err(ShellError::ReadError(_)) | Err(ShellError::WriteError(_)) => {
+                    metrics::UART_ERRORS.inc();
                 }
-                Ok(Some(Input::Control(control::CTRL_X))) => {
-                    if *blink_freq == 1 {
-                        return;
+                _ => {}
+            }
+        }
+        env.provision(shell);
+        env.background(shell);
+    }
+
+    /// A command scheduled with `after` or `every` is due, the shell task runs it.
+    /// Killed jobs keep their message queued until it fires, hence twice `MAX_JOBS`.
+    #[task(priority = 1, capacity = 8, shared = [jobs])]
+    fn job_due(mut ctx: job_due::Context, id: u16) {
+        let next = ctx.shared.jobs.lock(|j| j.mark_due(id));
+        if let Some(at_ms) = next {
+            if job_due::spawn_at(Instant::new(at_ms), id).is_err() {
+                ctx.shared.jobs.lock(|j| j.remove(id));
+            }
+        }
+        shell_poll::spawn().ok();
+    }
+
+    /// The RTC alarm fired, the shell task runs its command. The wakeup timer shares the
+    /// interrupt, the tickless idle clears it before it gets here.
+    #[task(binds = RTC_STAMP, priority = 1, shared = [alarm])]
+    fn rtc_alarm(mut ctx: rtc_alarm::Context) {
+        if rtc::take_alarm() {
+            ctx.shared.alarm.lock(|a| a.mark_due());
+            shell_poll::spawn().ok();
+        }
+    }
+
+    /// A whole second of the countdown went by or it reached zero, the shell task
+    /// redraws the time or runs its command. A restart leaves the stale wakeups queued.
+    #[task(priority = 1, capacity = 4, shared = [countdown])]
+    fn countdown_tick(mut ctx: countdown_tick::Context, id: u16) {
+        let next = ctx.shared.countdown.lock(|c| c.tick(id, mono::now_ms()));
+        if let Some(at_ms) = next {
+            if countdown_tick::spawn_at(Instant::new(at_ms), id).is_err() {
+                ctx.shared.countdown.lock(|c| c.cancel());
+            }
+        }
+        shell_poll::spawn().ok();
+    }
+
+    /// End of the confirm window of a risky change, the shell task rolls it back unless
+    /// it was confirmed. A newer change leaves the stale wakeup queued.
+    #[task(priority = 1, capacity = 4, shared = [rollback])]
+    fn rollback_due(mut ctx: rollback_due::Context, id: u16) {
+        ctx.shared.rollback.lock(|r| r.expire(id));
+        shell_poll::spawn().ok();
+    }
+
+    /// Next note of a ringing bundle. At the timeout the shell task puts the LED pattern
+    /// back, a restart leaves the stale wakeups queued.
+    #[task(priority = 1, capacity = 4, shared = [pwmout, ringer])]
+    fn ring_step(ctx: ring_step::Context, id: u16) {
+        let ring_step::SharedResources {
+            mut pwmout,
+            mut ringer,
+        } = ctx.shared;
+        match ringer.lock(|r| r.step(id, mono::now_ms())) {
+            RingStep::Stale => {}
+            RingStep::Note(channel, freq, next_ms) => {
+                match channel {
+                    Some(channel) if freq > 0 => {
+                        pwmout.lock(|p| p.start(channel, freq, bundle::TONE_DUTY).ok());
                     }
-                    *blink_freq -= 1;
-                    blink_timer.lock(|t| {
-                        t.start((*blink_freq as u32 * 2).hz());
-                    });
+                    Some(_) => pwmout.lock(|p| p.stop()),
+                    None => {}
                 }
-                Err(ShellError::WouldBlock) => break,
-                _ => {}
+                if ring_step::spawn_at(Instant::new(next_ms), id).is_err() {
+                    pwmout.lock(|p| p.stop());
+                }
+            }
+            RingStep::Done(channel) => {
+                if channel.is_some() {
+                    pwmout.lock(|p| p.stop());
+                }
+                shell_poll::spawn().ok();
             }
         }
     }
@@ -0,0 +1,153 @@
+use hal::stm32;
+
+use crate::clocks;
+use crate::pins::{self, Owner, PinError, Port};
+
+/// MCO on PA9, alternate function 0
+const MCO_PIN: u8 = 9;
+const LSI_FREQ: u32 = 32_000;
+const LSE_FREQ: u32 = 32_768;
+const HSI_FREQ: u32 = 16_000_000;
+/// Largest MCOPRE divider
+pub const MAX_DIV: u32 = 128;
+/// CFGR fields, the PAC leaves MCOPRE out
+const MCOSEL_SHIFT: u32 = 24;
+const MCOPRE_SHIFT: u32 = 28;
+const MCO_MASK: u32 = (0b111 << MCOSEL_SHIFT) | (0b111 << MCOPRE_SHIFT);
+
+/// MCOSEL choices of RM0444, 0b010 only exists on parts with HSI48
+#[derive(Clone, Copy, PartialEq)]
+pub enum Source {
+    Sysclk,
+    Hsi16,
+    Hse,
+    Pllr,
+    Lsi,
+    Lse,
+}
+
+pub const SOURCES: [(&str, Source); 6] = [
+    ("sysclk", Source::Sysclk),
+    ("hsi16", Source::Hsi16),
+    ("hse", Source::Hse),
+    ("pllr", Source::Pllr),
+    ("lsi", Source::Lsi),
+    ("lse", Source::Lse),
+];
+
+impl Source {
+    pub fn from_name(name: &str) -> Option<Source> {
+        SOURCES
+            .iter()
+            .find(|(source_name, _)| *source_name == name)
+            .map(|(_, source)| *source)
+    }
+
+    pub fn name(self) -> &'static str {
+        SOURCES[self as usize].0
+    }
+
+    fn mcosel(self) -> u8 {
+        match self {
+            Source::Sysclk => 0b001,
+            Source::Hsi16 => 0b011,
+            Source::Hse => 0b100,
+            Source::Pllr => 0b101,
+            Source::Lsi => 0b110,
+            Source::Lse => 0b111,
+        }
+    }
+
+    /// The system clock and HSI16 always run, the others only once their ready flag is set
+    fn is_running(self) -> bool {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        match self {
+            Source::Sysclk | Source::Hsi16 => true,
+            Source::Hse => rcc.cr.read().hserdy().bit_is_set(),
+            Source::Pllr => rcc.cr.read().pllrdy().bit_is_set(),
+            Source::Lsi => rcc.csr.read().lsirdy().bit_is_set(),
+            Source::Lse => rcc.bdcr.read().lserdy().bit_is_set(),
+        }
+    }
+
+    /// Nominal frequency, `None` for the board dependent HSE and PLL
+    pub fn freq(self) -> Option<u32> {
+        match self {
+            Source::Sysclk => Some(clocks::timer_clk()),
+            Source::Hsi16 => Some(HSI_FREQ),
+            Source::Lsi => Some(LSI_FREQ),
+            Source::Lse => Some(LSE_FREQ),
+            Source::Hse | Source::Pllr => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum McoError {
+    /// Not a power of two up to `MAX_DIV`
+    Div,
+    NotRunning,
+    Pin(PinError),
+}
+
+impl McoError {
+    pub fn message(self) -> &'static str {
+        match self {
+            McoError::Div => "divider must be 1, 2, 4 ... 128",
+            McoError::NotRunning => "clock source is not running",
+            McoError::Pin(_) => "PA9 is used by another subsystem",
+        }
+    }
+}
+
+/// Routes `source` divided by `div` to PA9 for a frequency counter or a scope
+pub fn start(source: Source, div: u32) -> Result<(), McoError> {
+    if !div.is_power_of_two() || div > MAX_DIV {
+        return Err(McoError::Div);
+    }
+    if !source.is_running() {
+        return Err(McoError::NotRunning);
+    }
+    pins::claim(Port::A, MCO_PIN, Owner::Mco).map_err(McoError::Pin)?;
+
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    let fields = (source.mcosel() as u32) << MCOSEL_SHIFT | div.trailing_zeros() << MCOPRE_SHIFT;
+    rcc.cfgr
+        .modify(|r, w| unsafe { w.bits((r.bits() & !MCO_MASK) | fields) });
+    let gpio = unsafe { &*stm32::GPIOA::ptr() };
+    let shift = MCO_PIN * 2;
+    let af_shift = (MCO_PIN - 8) * 4;
+    gpio.afrh
+        .modify(|r, w| unsafe { w.bits(r.bits() & !(0xf << af_shift)) });
+    // Very high speed keeps the edges of a 16MHz output square
+    gpio.ospeedr
+        .modify(|r, w| unsafe { w.bits(r.bits() | (0b11 << shift)) });
+    gpio.moder
+        .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b10 << shift)) });
+    Ok(())
+}
+
+/// Stops the output and hands PA9 back as an analog input
+pub fn stop() {
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    rcc.cfgr
+        .modify(|r, w| unsafe { w.bits(r.bits() & !MCO_MASK) });
+    if pins::owner(Port::A, MCO_PIN) == Some(Owner::Mco) {
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits(r.bits() | (0b11 << (MCO_PIN * 2))) });
+        pins::release(Port::A, MCO_PIN, Owner::Mco);
+    }
+}
+
+/// Source and divider currently routed to the pin
+pub fn current() -> Option<(Source, u32)> {
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    let cfgr = rcc.cfgr.read().bits();
+    let sel = ((cfgr >> MCOSEL_SHIFT) & 0b111) as u8;
+    let source = SOURCES
+        .iter()
+        .map(|(_, source)| *source)
+        .find(|source| source.mcosel() == sel)?;
+    Some((source, 1 << ((cfgr >> MCOPRE_SHIFT) & 0b111)))
+}
@@ -0,0 +1,40 @@
+/// Address ranges of the STM32G071 that can be accessed without faulting
+const REGIONS: [(u32, u32); 5] = [
+    (0x0800_0000, 0x0802_0000), // Flash
+    (0x2000_0000, 0x2000_9000), // SRAM
+    (0x4000_0000, 0x4001_5c00), // APB peripherals
+    (0x4002_0000, 0x4002_6400), // AHB peripherals
+    (0x5000_0000, 0x5000_1800), // IOPORT
+];
+
+/// Longest `md` dump
+pub const MAX_DUMP_LEN: u32 = 256;
+
+/// Checks that a word access at `addr` stays within a known memory region
+pub fn is_valid_word(addr: u32) -> bool {
+    addr & 0b11 == 0
+        && REGIONS
+            .iter()
+            .any(|(start, end)| addr >= *start && addr < end - 3)
+}
+
+/// Checks that word accesses over `len` bytes from `addr` stay within one region
+pub fn is_valid_range(addr: u32, len: u32) -> bool {
+    addr & 0b11 == 0 && is_valid_bytes(addr, len)
+}
+
+/// Checks that `len` bytes from `addr` stay within one region, for byte accesses
+pub fn is_valid_bytes(addr: u32, len: u32) -> bool {
+    len > 0
+        && REGIONS
+            .iter()
+            .any(|(start, end)| addr >= *start && addr < *end && end - addr >= len)
+}
+
+pub fn read_word(addr: u32) -> u32 {
+    unsafe { core::ptr::read_volatile(addr as *const u32) }
+}
+
+pub fn write_word(addr: u32, value: u32) {
+    unsafe { core::ptr::write_volatile(addr as *mut u32, value) }
+}
@@ -0,0 +1,57 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// Monotonic event count, exported by the `metrics` command
+pub struct Counter(AtomicU32);
+
+impl Counter {
+    const fn new() -> Self {
+        Self(AtomicU32::new(0))
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    // Every counter has a single writing task, plain load/store is enough on Cortex-M0+
+    pub fn inc(&self) {
+        self.0.store(self.get().wrapping_add(1), Ordering::Relaxed);
+    }
+}
+
+/// Commands dispatched by the shell
+pub static COMMANDS: Counter = Counter::new();
+/// Commands that ran over the budget set with `budget`
+pub static COMMAND_TIMEOUTS: Counter = Counter::new();
+/// Scripts stopped by the command count or run time limit
+pub static SCRIPT_ABORTS: Counter = Counter::new();
+/// Shell UART read and write failures
+pub static UART_ERRORS: Counter = Counter::new();
+/// Shell writes that found the output queue full
+pub static UART_TX_STALLS: Counter = Counter::new();
+/// Received bytes dropped because the shell task fell behind, counted by the UART interrupt
+pub static UART_RX_DROPS: Counter = Counter::new();
+
+/// Shell command outcome, commands start at 0, unknown commands, `assert` and `run` set it
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExitStatus {
+    Ok = 0,
+    Fail = 1,
+    Error = 2,
+    /// Bad arguments, the dispatcher answers with the command's usage
+    Usage = 3,
+}
+
+static EXIT_STATUS: AtomicU32 = AtomicU32::new(0);
+
+pub fn exit_status() -> ExitStatus {
+    match EXIT_STATUS.load(Ordering::Relaxed) {
+        0 => ExitStatus::Ok,
+        1 => ExitStatus::Fail,
+        3 => ExitStatus::Usage,
+        _ => ExitStatus::Error,
+    }
+}
+
+pub fn set_exit_status(status: ExitStatus) {
+    EXIT_STATUS.store(status as u32, Ordering::Relaxed);
+}
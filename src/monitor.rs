@@ -0,0 +1,118 @@
+use crate::config::TICK_HZ;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Watch {
+    Animation,
+    BlinkFreq,
+    Uptime,
+    Current,
+}
+
+pub const WATCHES: [(&str, Watch); 4] = [
+    ("animation", Watch::Animation),
+    ("blink_freq", Watch::BlinkFreq),
+    ("uptime", Watch::Uptime),
+    ("current_ua", Watch::Current),
+];
+
+impl Watch {
+    pub fn from_name(name: &str) -> Option<Watch> {
+        WATCHES
+            .iter()
+            .find(|(watch_name, _)| *watch_name == name)
+            .map(|(_, watch)| *watch)
+    }
+
+    pub fn name(self) -> &'static str {
+        WATCHES[self as usize].0
+    }
+
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+pub struct Monitor {
+    watches: u8,
+    interval: u32,
+    elapsed: u32,
+    due: bool,
+}
+
+impl Monitor {
+    /// Reports come on system ticks, so the interval is a whole number of them
+    pub const MIN_INTERVAL_MS: u32 = 1000 / TICK_HZ;
+    pub const MAX_INTERVAL_MS: u32 = 60_000;
+
+    pub fn new() -> Self {
+        Self {
+            watches: 0,
+            interval: TICK_HZ,
+            elapsed: 0,
+            due: false,
+        }
+    }
+
+    pub fn add(&mut self, watch: Watch) {
+        self.watches |= watch.mask();
+    }
+
+    pub fn remove(&mut self, watch: Watch) {
+        self.watches &= !watch.mask();
+    }
+
+    pub fn clear(&mut self) {
+        self.watches = 0;
+    }
+
+    pub fn is_watched(&self, watch: Watch) -> bool {
+        self.watches & watch.mask() != 0
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.watches != 0
+    }
+
+    pub fn interval_ms(&self) -> u32 {
+        self.interval * 1000 / TICK_HZ
+    }
+
+    pub fn set_interval_ms(&mut self, interval_ms: u32) {
+        self.interval = interval_ms * TICK_HZ / 1000;
+        self.elapsed = 0;
+    }
+
+    /// Advances monitor by one system tick, returns true when a report is due
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances monitor by a number of ticks slept through in Stop mode
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        if !self.is_active() {
+            self.elapsed = 0;
+            return false;
+        }
+        self.elapsed += ticks;
+        if self.elapsed >= self.interval {
+            self.elapsed = 0;
+            self.due = true;
+        }
+        self.due
+    }
+
+    /// Ticks left until the next report
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        if self.is_active() {
+            Some(self.interval.saturating_sub(self.elapsed))
+        } else {
+            None
+        }
+    }
+
+    pub fn take_due(&mut self) -> bool {
+        let due = self.due;
+        self.due = false;
+        due
+    }
+}
@@ -0,0 +1,106 @@
+use core::cell::Cell;
+
+use cortex_m::interrupt::{self, Mutex};
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::{SCB, SYST};
+use rtic::time::{self, fraction::Fraction, Clock, Instant};
+use rtic::Monotonic;
+
+use crate::clocks;
+
+/// Tick rate, one tick per millisecond
+const MONO_HZ: u32 = 1_000;
+
+/// Milliseconds since boot, the SysTick handler and the tickless catch-up both count
+static NOW: Mutex<Cell<u64>> = Mutex::new(Cell::new(0));
+/// Earliest instant the timer queue waits for, `u64::MAX` before anything is scheduled
+static DEADLINE: Mutex<Cell<u64>> = Mutex::new(Cell::new(u64::MAX));
+
+/// RTIC monotonic counting SysTick wraps. SysTick halts in Stop mode, so the tickless
+/// idle adds the time it slept with `advance` and never sleeps past a scheduled task.
+pub struct SysMono {
+    syst: SYST,
+}
+
+impl SysMono {
+    pub fn new(mut syst: SYST) -> Self {
+        syst.disable_counter();
+        syst.set_clock_source(SystClkSource::Core);
+        Self { syst }
+    }
+}
+
+/// Follows the core clock, call after every speed change like the timers' `retime`
+pub fn retime() {
+    let syst = unsafe { &*SYST::ptr() };
+    let reload = clocks::timer_clk() / MONO_HZ - 1;
+    unsafe { syst.rvr.write(reload) };
+}
+
+pub fn now_ms() -> u64 {
+    interrupt::free(|cs| NOW.borrow(cs).get())
+}
+
+/// Adds time the core spent in Stop mode, wakes the timer queue if a task came due
+pub fn advance(ms: u32) {
+    let due = interrupt::free(|cs| {
+        let now = NOW.borrow(cs).get() + ms as u64;
+        NOW.borrow(cs).set(now);
+        DEADLINE.borrow(cs).get() <= now
+    });
+    if due {
+        SCB::set_pendst();
+    }
+}
+
+/// Time left to the next scheduled task, `None` when nothing is waiting
+pub fn ms_until_due() -> Option<u32> {
+    interrupt::free(|cs| {
+        let now = NOW.borrow(cs).get();
+        let deadline = DEADLINE.borrow(cs).get();
+        if deadline > now && deadline != u64::MAX {
+            Some((deadline - now).min(u32::MAX as u64) as u32)
+        } else {
+            None
+        }
+    })
+}
+
+impl Clock for SysMono {
+    type T = u64;
+
+    const SCALING_FACTOR: Fraction = Fraction::new(1, MONO_HZ);
+
+    fn try_now(&self) -> Result<Instant<Self>, time::clock::Error> {
+        Ok(Instant::new(now_ms()))
+    }
+}
+
+impl Monotonic for SysMono {
+    // Every wrap counts, the interrupt stays on with an empty queue
+    const DISABLE_INTERRUPT_ON_EMPTY_QUEUE: bool = false;
+
+    unsafe fn reset(&mut self) {
+        retime();
+        self.syst.clear_current();
+        self.syst.enable_interrupt();
+        self.syst.enable_counter();
+        interrupt::free(|cs| NOW.borrow(cs).set(0));
+    }
+
+    fn set_compare(&mut self, instant: &Instant<Self>) {
+        let ms = instant.duration_since_epoch().integer();
+        interrupt::free(|cs| DEADLINE.borrow(cs).set(ms));
+    }
+
+    fn clear_compare_flag(&mut self) {}
+
+    /// Runs after the queue was checked, so a wrap that makes a task due pends the
+    /// handler once more instead of leaving it to the next wrap
+    fn on_interrupt(&mut self) {
+        if !self.syst.has_wrapped() {
+            return;
+        }
+        advance(1);
+    }
+}
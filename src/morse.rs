@@ -0,0 +1,195 @@
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::config::CMD_MAX_LEN;
+use crate::shell::CR;
+
+/// International Morse code of the characters `morse` accepts
+const CODES: [(u8, &str); 41] = [
+    (b'A', ".-"),
+    (b'B', "-..."),
+    (b'C', "-.-."),
+    (b'D', "-.."),
+    (b'E', "."),
+    (b'F', "..-."),
+    (b'G', "--."),
+    (b'H', "...."),
+    (b'I', ".."),
+    (b'J', ".---"),
+    (b'K', "-.-"),
+    (b'L', ".-.."),
+    (b'M', "--"),
+    (b'N', "-."),
+    (b'O', "---"),
+    (b'P', ".--."),
+    (b'Q', "--.-"),
+    (b'R', ".-."),
+    (b'S', "..."),
+    (b'T', "-"),
+    (b'U', "..-"),
+    (b'V', "...-"),
+    (b'W', ".--"),
+    (b'X', "-..-"),
+    (b'Y', "-.--"),
+    (b'Z', "--.."),
+    (b'0', "-----"),
+    (b'1', ".----"),
+    (b'2', "..---"),
+    (b'3', "...--"),
+    (b'4', "....-"),
+    (b'5', "....."),
+    (b'6', "-...."),
+    (b'7', "--..."),
+    (b'8', "---.."),
+    (b'9', "----."),
+    (b'.', ".-.-.-"),
+    (b',', "--..--"),
+    (b'?', "..--.."),
+    (b'/', "-..-."),
+    (b'=', "-...-"),
+];
+
+/// Units of the standard timing, one unit is one blink timer tick
+const DAH_UNITS: u8 = 3;
+const SYMBOL_GAP_UNITS: u8 = 1;
+const LETTER_GAP_UNITS: u8 = 3;
+/// Added to the letter gap before a space, seven units in all
+const WORD_GAP_EXTRA_UNITS: u8 = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MorseError {
+    Empty,
+    /// No code for the character
+    Unsupported(char),
+}
+
+pub fn write_error(out: &mut dyn Write, err: MorseError) {
+    match err {
+        MorseError::Empty => write!(out, "{0:}usage: morse <text>{0:}", CR),
+        MorseError::Unsupported(c) => write!(out, "{0:}no Morse code for '{1:}'{0:}", CR, c),
+    }
+    .ok();
+}
+
+fn code(c: u8) -> Option<&'static [u8]> {
+    let c = c.to_ascii_uppercase();
+    CODES
+        .iter()
+        .find(|(code_char, _)| *code_char == c)
+        .map(|(_, code)| code.as_bytes())
+}
+
+/// Plays text on the LED from the blink timer, overriding the animation until the
+/// message ends or is aborted
+pub struct Morse {
+    text: String<CMD_MAX_LEN>,
+    /// Character being sent
+    pos: usize,
+    /// Symbol of that character being sent
+    symbol: usize,
+    /// The current symbol's mark went out, its gap comes next
+    marked: bool,
+    on: bool,
+    /// Ticks left of the current mark or gap
+    left: u8,
+    active: bool,
+}
+
+impl Morse {
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            pos: 0,
+            symbol: 0,
+            marked: false,
+            on: false,
+            left: 0,
+            active: false,
+        }
+    }
+
+    pub fn start(&mut self, text: &str) -> Result<(), MorseError> {
+        let text = text.trim();
+        if text.is_empty() {
+            return Err(MorseError::Empty);
+        }
+        let unsupported = |c: char| c != ' ' && (!c.is_ascii() || code(c as u8).is_none());
+        if let Some(c) = text.chars().find(|&c| unsupported(c)) {
+            return Err(MorseError::Unsupported(c));
+        }
+        *self = Self::new();
+        self.text.push_str(text).ok();
+        self.active = true;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.active = false;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// LED level for this blink tick, `None` once the message is done
+    pub fn next(&mut self) -> Option<bool> {
+        if !self.active {
+            return None;
+        }
+        while self.left == 0 {
+            if !self.load_next() {
+                self.active = false;
+                return None;
+            }
+        }
+        self.left -= 1;
+        Some(self.on)
+    }
+
+    /// Moves on to the next mark or gap, false at the end of the text
+    fn load_next(&mut self) -> bool {
+        let text = self.text.as_bytes();
+        let c = match text.get(self.pos) {
+            Some(c) => *c,
+            None => return false,
+        };
+        if c == b' ' {
+            self.pos += 1;
+            self.set(false, WORD_GAP_EXTRA_UNITS);
+            return true;
+        }
+        let code = match code(c) {
+            Some(code) => code,
+            None => {
+                self.pos += 1;
+                return true;
+            }
+        };
+        if !self.marked {
+            let units = if code[self.symbol] == b'-' {
+                DAH_UNITS
+            } else {
+                1
+            };
+            self.marked = true;
+            self.set(true, units);
+        } else {
+            self.marked = false;
+            self.symbol += 1;
+            if self.symbol < code.len() {
+                self.set(false, SYMBOL_GAP_UNITS);
+            } else {
+                self.symbol = 0;
+                self.pos += 1;
+                self.set(false, LETTER_GAP_UNITS);
+            }
+        }
+        true
+    }
+
+    fn set(&mut self, on: bool, units: u8) {
+        self.on = on;
+        self.left = units;
+    }
+}
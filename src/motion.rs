@@ -0,0 +1,138 @@
+use hal::stm32;
+
+use crate::config::TICK_HZ;
+use crate::pins::{self, Owner, Port};
+
+/// PIR output on PA8, rising edges on EXTI line 8
+const PIR_PIN: u32 = 8;
+const PIR_EXTI_LINE: u32 = 8;
+
+/// PIR motion events and the rule holding the animation on after each of them
+pub struct Motion {
+    count: u32,
+    last: Option<u32>,
+    hold: Option<u32>,
+    remaining: u32,
+    expired: bool,
+}
+
+impl Motion {
+    pub const MAX_HOLD_S: u32 = 3600;
+
+    pub fn new() -> Self {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        rcc.iopenr.modify(|_, w| w.iopaen().set_bit());
+        // Input with pull-down, an unplugged sensor reads idle
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << (PIR_PIN * 2))) });
+        gpio.pupdr.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << (PIR_PIN * 2))) | (0b10 << (PIR_PIN * 2)))
+        });
+        pins::reserve(Port::A, PIR_PIN as u8, Owner::Motion);
+
+        let line = 1 << PIR_EXTI_LINE;
+        let cr_shift = (PIR_EXTI_LINE % 4) * 8;
+        exti.exticr3
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0xff << cr_shift)) });
+        exti.rtsr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+        exti.rpr1.write(|w| unsafe { w.bits(line) });
+        exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+
+        Self {
+            count: 0,
+            last: None,
+            hold: None,
+            remaining: 0,
+            expired: false,
+        }
+    }
+
+    pub fn is_pending() -> bool {
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        exti.rpr1.read().bits() & (1 << PIR_EXTI_LINE) != 0
+    }
+
+    pub fn clear_irq() {
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        exti.rpr1.write(|w| unsafe { w.bits(1 << PIR_EXTI_LINE) });
+    }
+
+    /// Sensor output is high while it sees motion
+    pub fn is_active() -> bool {
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        gpio.idr.read().bits() & (1 << PIR_PIN) != 0
+    }
+
+    /// Records a rising edge, returns true when the rule turns the animation on
+    pub fn on_motion(&mut self, now: u32) -> bool {
+        self.count = self.count.wrapping_add(1);
+        self.last = Some(now);
+        match self.hold {
+            Some(hold) => {
+                self.remaining = hold * TICK_HZ;
+                self.expired = false;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Tick of the latest event
+    pub fn last(&self) -> Option<u32> {
+        self.last
+    }
+
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.last = None;
+    }
+
+    pub fn hold_s(&self) -> Option<u32> {
+        self.hold
+    }
+
+    /// Keeps the animation on for `hold` seconds after motion, `None` disables the rule
+    pub fn set_hold_s(&mut self, hold: Option<u32>) {
+        self.hold = hold;
+        self.remaining = 0;
+        self.expired = false;
+    }
+
+    /// Advances by one system tick, returns true when the hold expires
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances by a number of ticks slept through in Stop mode
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+        self.remaining = self.remaining.saturating_sub(ticks);
+        if self.remaining == 0 {
+            self.expired = true;
+        }
+        self.expired
+    }
+
+    /// Ticks left until the hold expires
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        if self.remaining > 0 {
+            Some(self.remaining)
+        } else {
+            None
+        }
+    }
+
+    pub fn take_expired(&mut self) -> bool {
+        let expired = self.expired;
+        self.expired = false;
+        expired
+    }
+}
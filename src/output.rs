@@ -0,0 +1,263 @@
+use core::fmt::Write as _;
+
+use hal::hal::serial::{Read, Write};
+use hal::nb;
+use heapless::String;
+
+use crate::config::TICK_HZ;
+use crate::provision::FrameReader;
+use crate::rtc;
+use crate::telemetry::{crc16_step, CRC16_INIT};
+
+const ESC: u8 = 0x1b;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stamp {
+    Off,
+    Uptime,
+    Rtc,
+    RtcMs,
+}
+
+pub const STAMPS: [(&str, Stamp); 4] = [
+    ("off", Stamp::Off),
+    ("uptime", Stamp::Uptime),
+    ("rtc", Stamp::Rtc),
+    ("rtc-ms", Stamp::RtcMs),
+];
+
+impl Stamp {
+    pub fn from_name(name: &str) -> Option<Stamp> {
+        STAMPS
+            .iter()
+            .find(|(stamp_name, _)| *stamp_name == name)
+            .map(|(_, stamp)| *stamp)
+    }
+
+    pub fn name(self) -> &'static str {
+        STAMPS[self as usize].0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Esc,
+    Csi,
+}
+
+/// Output arbiter every shell byte passes through, decorates lines on the way out and
+/// pulls binary provisioning frames out of the input
+pub struct Output<S> {
+    serial: S,
+    frames: FrameReader,
+    stamp: Stamp,
+    nmea: bool,
+    uptime: u32,
+    line_start: bool,
+    line_open: bool,
+    checksum: u8,
+    escape: Escape,
+    staged: bool,
+    pending: String<24>,
+    pending_pos: usize,
+    digest: Option<u16>,
+}
+
+impl<S> Output<S> {
+    pub fn new(serial: S) -> Self {
+        Self {
+            serial,
+            frames: FrameReader::new(),
+            stamp: Stamp::Off,
+            nmea: false,
+            uptime: 0,
+            line_start: true,
+            line_open: false,
+            checksum: 0,
+            escape: Escape::None,
+            staged: false,
+            pending: String::new(),
+            pending_pos: 0,
+            digest: None,
+        }
+    }
+
+    pub fn inner(&mut self) -> &mut S {
+        &mut self.serial
+    }
+
+    /// Starts a CRC-16 over the visible characters written from now on. Spaces, line
+    /// ends and escape sequences are skipped, so a copy from the terminal still matches
+    pub fn start_digest(&mut self) {
+        self.digest = Some(CRC16_INIT);
+    }
+
+    pub fn take_digest(&mut self) -> Option<u16> {
+        self.digest.take()
+    }
+
+    fn add_digest(&mut self, byte: u8) {
+        if let Some(crc) = self.digest {
+            if self.escape == Escape::None && byte > 0x20 && byte < 0x7f {
+                self.digest = Some(crc16_step(crc, byte));
+            }
+        }
+    }
+
+    /// Provisioning frame received since the last call
+    pub fn take_frame(&mut self) -> Option<&[u8]> {
+        self.frames.take()
+    }
+
+    pub fn stamp(&self) -> Stamp {
+        self.stamp
+    }
+
+    pub fn set_stamp(&mut self, stamp: Stamp) {
+        self.stamp = stamp;
+    }
+
+    pub fn nmea(&self) -> bool {
+        self.nmea
+    }
+
+    /// Wraps lines as `$<line>*<XOR of line bytes in hex>`
+    pub fn set_nmea(&mut self, nmea: bool) {
+        self.nmea = nmea;
+        self.line_open = false;
+    }
+
+    /// System ticks used for uptime stamps
+    pub fn set_uptime(&mut self, ticks: u32) {
+        self.uptime = ticks;
+    }
+
+    /// Queues whatever has to go out ahead of `byte`, runs once per byte
+    fn stage(&mut self, byte: u8) {
+        self.pending.clear();
+        self.pending_pos = 0;
+
+        let mut visible = false;
+        let mut line_end = false;
+        match (self.escape, byte) {
+            (Escape::None, ESC) => self.escape = Escape::Esc,
+            (Escape::Esc, b'[') => self.escape = Escape::Csi,
+            (Escape::Esc, _) => self.escape = Escape::None,
+            (Escape::Csi, 0x40..=0x7e) => self.escape = Escape::None,
+            (Escape::Csi, _) => {}
+            (Escape::None, b'\r') | (Escape::None, b'\n') => {
+                self.line_start = true;
+                line_end = true;
+            }
+            (Escape::None, _) if self.line_start && byte >= 0x20 => {
+                self.line_start = false;
+                visible = true;
+            }
+            (Escape::None, _) => {}
+        }
+
+        if line_end && self.line_open {
+            self.line_open = false;
+            write!(self.pending, "*{:02X}", self.checksum).ok();
+        }
+        if visible {
+            if self.nmea {
+                self.pending.push('$').ok();
+                self.line_open = true;
+                self.checksum = 0;
+            }
+            self.format_stamp();
+        }
+        if self.line_open {
+            self.checksum ^= byte;
+        }
+    }
+
+    fn format_stamp(&mut self) {
+        let start = self.pending.len();
+        let ticks = self.uptime;
+        let millis = rtc::millis();
+        let now = millis / 1000;
+        match self.stamp {
+            Stamp::Off => Ok(()),
+            Stamp::Uptime => write!(
+                self.pending,
+                "[{}.{}] ",
+                ticks / TICK_HZ,
+                ticks % TICK_HZ * 10 / TICK_HZ
+            ),
+            Stamp::Rtc => write!(
+                self.pending,
+                "[{:02}:{:02}:{:02}] ",
+                now / 3600,
+                now / 60 % 60,
+                now % 60
+            ),
+            Stamp::RtcMs => write!(
+                self.pending,
+                "[{:02}:{:02}:{:02}.{:03}] ",
+                now / 3600,
+                now / 60 % 60,
+                now % 60,
+                millis % 1000
+            ),
+        }
+        .ok();
+        if self.line_open {
+            for byte in self.pending.as_bytes()[start..].iter() {
+                self.checksum ^= byte;
+            }
+        }
+    }
+}
+
+impl<S: Write<u8>> Output<S> {
+    /// Sends queued decoration, resuming where a `WouldBlock` left it
+    fn write_pending(&mut self) -> nb::Result<(), S::Error> {
+        while let Some(byte) = self.pending.as_bytes().get(self.pending_pos).copied() {
+            self.serial.write(byte)?;
+            self.add_digest(byte);
+            self.pending_pos += 1;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read<u8>> Read<u8> for Output<S> {
+    type Error = S::Error;
+
+    /// Frame bytes never reach the shell, a completed frame blocks input until taken
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        loop {
+            if self.frames.is_ready() {
+                return Err(nb::Error::WouldBlock);
+            }
+            if let Some(byte) = self.frames.feed(self.serial.read()?) {
+                return Ok(byte);
+            }
+        }
+    }
+}
+
+impl<S: Write<u8>> Write<u8> for Output<S> {
+    type Error = S::Error;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        // A `WouldBlock` retry hands in the same byte again
+        if !self.staged {
+            self.stage(byte);
+            self.staged = true;
+        }
+        self.write_pending()?;
+        self.serial.write(byte)?;
+        self.add_digest(byte);
+        self.staged = false;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.write_pending()?;
+        self.serial.flush()
+    }
+}
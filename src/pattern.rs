@@ -0,0 +1,100 @@
+use heapless::String;
+
+/// Longest sequence, one bit per blink timer tick
+pub const MAX_STEPS: usize = 32;
+
+/// Built-in sequences written like the `pattern` argument, first step first
+pub const BUILTINS: [(&str, &str); 5] = [
+    ("blink", "10"),
+    ("heartbeat", "1010000000"),
+    ("sos", "10101000111011101110001010100000"),
+    ("strobe", "10000000"),
+    ("wink", "1111111110"),
+];
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PatternError {
+    Unknown,
+    TooLong,
+}
+
+impl PatternError {
+    pub fn message(self) -> &'static str {
+        match self {
+            PatternError::Unknown => "expected 0s and 1s or a built-in name",
+            PatternError::TooLong => "at most 32 steps",
+        }
+    }
+}
+
+/// On/off sequence the blink timer steps through, it replaces the plain toggle. The
+/// timer runs at twice the animation frequency, so `10` is the classic 50% blink.
+pub struct BlinkPattern {
+    /// Step `n` is bit `n`
+    bits: u32,
+    len: u8,
+    step: u8,
+    /// Built-in the sequence came from
+    name: Option<&'static str>,
+}
+
+impl BlinkPattern {
+    pub fn new() -> Self {
+        Self {
+            bits: 0b01,
+            len: 2,
+            step: 0,
+            name: Some(BUILTINS[0].0),
+        }
+    }
+
+    /// Takes a bit string like `10011010` or the name of a built-in
+    pub fn set(&mut self, arg: &str) -> Result<(), PatternError> {
+        let (name, seq) = match BUILTINS.iter().find(|(name, _)| *name == arg) {
+            Some((name, seq)) => (Some(*name), *seq),
+            None => (None, arg),
+        };
+        if seq.is_empty() || !seq.bytes().all(|b| b == b'0' || b == b'1') {
+            return Err(PatternError::Unknown);
+        }
+        if seq.len() > MAX_STEPS {
+            return Err(PatternError::TooLong);
+        }
+        self.bits = seq
+            .bytes()
+            .enumerate()
+            .filter(|(_, b)| *b == b'1')
+            .fold(0, |bits, (n, _)| bits | 1 << n);
+        self.len = seq.len() as u8;
+        self.step = 0;
+        self.name = name;
+        Ok(())
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// Sequence as a bit string, first step first
+    pub fn steps(&self) -> String<MAX_STEPS> {
+        let mut steps = String::new();
+        for n in 0..self.len {
+            steps
+                .push(if self.bits >> n & 1 != 0 { '1' } else { '0' })
+                .ok();
+        }
+        steps
+    }
+
+    /// LED level for this tick, then moves on to the next step
+    pub fn next(&mut self) -> bool {
+        let on = self.bits >> self.step & 1 != 0;
+        self.step = (self.step + 1) % self.len;
+        on
+    }
+
+    /// Starts over at the first step
+    pub fn restart(&mut self) {
+        self.step = 0;
+    }
+}
@@ -0,0 +1,182 @@
+use crate::backup::{self, Slot};
+use crate::config::TICK_HZ;
+
+/// Marks backup registers holding saved gains
+const GAINS_MAGIC: u32 = 0x91d0;
+/// Gains are hundredths, the output term is percent duty per volt of error
+const GAIN_SCALE: i64 = 100 * 1000;
+const MAX_OUTPUT: i64 = 100;
+
+/// Kp in %/V, Ki in %/(V*s), Kd in %*s/V, all in hundredths
+#[derive(Clone, Copy)]
+pub struct Gains {
+    pub kp: u16,
+    pub ki: u16,
+    pub kd: u16,
+}
+
+/// PID loop from an ADC input in millivolts to a PWM duty, stepped on every system tick
+pub struct Pid {
+    gains: Gains,
+    target: u32,
+    integral: i64,
+    prev_error: Option<i32>,
+    output: u8,
+    enabled: bool,
+    csv: bool,
+    due: bool,
+}
+
+impl Pid {
+    pub const MAX_TARGET_MV: u32 = 3300;
+    pub const PWM_FREQ: u32 = 1000;
+
+    /// Restores gains saved before the last reset
+    pub fn new() -> Self {
+        let gains = Self::load().unwrap_or(Gains {
+            kp: 100,
+            ki: 0,
+            kd: 0,
+        });
+        Self {
+            gains,
+            target: 0,
+            integral: 0,
+            prev_error: None,
+            output: 0,
+            enabled: false,
+            csv: false,
+            due: false,
+        }
+    }
+
+    pub fn gains(&self) -> Gains {
+        self.gains
+    }
+
+    /// Changes the gains and saves them in the backup domain
+    pub fn set_gains(&mut self, gains: Gains) {
+        self.gains = gains;
+        backup::write(Slot::PidGains, (gains.kp as u32) << 16 | gains.ki as u32);
+        backup::write(Slot::PidKd, GAINS_MAGIC << 16 | gains.kd as u32);
+    }
+
+    pub fn target_mv(&self) -> u32 {
+        self.target
+    }
+
+    pub fn set_target_mv(&mut self, target: u32) {
+        self.target = target;
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.integral = 0;
+        self.prev_error = None;
+        self.output = 0;
+        self.due = false;
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.csv
+    }
+
+    /// Prints every step as CSV for tuning plots
+    pub fn set_streaming(&mut self, csv: bool) {
+        self.csv = csv;
+    }
+
+    /// Computes the duty in percent for an input reading
+    pub fn step(&mut self, input_mv: u32) -> u8 {
+        let error = self.target as i32 - input_mv as i32;
+        let Gains { kp, ki, kd } = self.gains;
+        let derivative = error - self.prev_error.unwrap_or(error);
+        self.prev_error = Some(error);
+
+        // Integrate only while it does not push an already saturated output further
+        let integral = self.integral + error as i64;
+        let unclamped = Self::output_of(kp, ki, kd, error, integral, derivative);
+        if (0..=MAX_OUTPUT).contains(&unclamped) || (unclamped > MAX_OUTPUT) != (error > 0) {
+            self.integral = integral;
+        }
+        let output = Self::output_of(kp, ki, kd, error, self.integral, derivative);
+        self.output = output.clamp(0, MAX_OUTPUT) as u8;
+        self.output
+    }
+
+    /// Advances by one system tick, returns true when a step is due
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances by a number of ticks slept through in Stop mode, a wakeup short of a
+    /// whole tick leaves the loop where it was
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        if ticks > 0 {
+            self.due = self.enabled;
+        }
+        self.due
+    }
+
+    /// A step is due on the next tick
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        if self.enabled {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    pub fn take_due(&mut self) -> bool {
+        let due = self.due;
+        self.due = false;
+        due
+    }
+
+    fn output_of(kp: u16, ki: u16, kd: u16, error: i32, integral: i64, derivative: i32) -> i64 {
+        let p = kp as i64 * error as i64;
+        let i = ki as i64 * integral / TICK_HZ as i64;
+        let d = kd as i64 * derivative as i64 * TICK_HZ as i64;
+        (p + i + d) / GAIN_SCALE
+    }
+
+    fn load() -> Option<Gains> {
+        let kd = backup::read(Slot::PidKd);
+        if kd >> 16 != GAINS_MAGIC {
+            return None;
+        }
+        let kpi = backup::read(Slot::PidGains);
+        Some(Gains {
+            kp: (kpi >> 16) as u16,
+            ki: kpi as u16,
+            kd: kd as u16,
+        })
+    }
+}
+
+/// Parses a gain like `2`, `0.5` or `1.25` into hundredths
+pub fn parse_gain(arg: &str) -> Option<u16> {
+    let (int, frac) = arg.split_once('.').unwrap_or((arg, ""));
+    if frac.len() > 2 || (int.is_empty() && frac.is_empty()) {
+        return None;
+    }
+    let int = if int.is_empty() {
+        0
+    } else {
+        btoi::btoi::<u16>(int.as_bytes()).ok()?
+    };
+    let frac = match frac.len() {
+        0 => 0,
+        1 => btoi::btoi::<u16>(frac.as_bytes()).ok()? * 10,
+        _ => btoi::btoi::<u16>(frac.as_bytes()).ok()?,
+    };
+    int.checked_mul(100)?.checked_add(frac)
+}
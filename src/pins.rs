@@ -0,0 +1,221 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use hal::stm32;
+
+use crate::metrics::{self, ExitStatus};
+use crate::shell::CR;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Port {
+    A,
+    B,
+    C,
+    D,
+    F,
+}
+
+/// Ports of the LQFP64 package with their bonded pins
+pub const PORTS: [(Port, char, u16); 5] = [
+    (Port::A, 'A', 0xffff),
+    (Port::B, 'B', 0xffff),
+    (Port::C, 'C', 0xffff),
+    (Port::D, 'D', 0x037f),
+    (Port::F, 'F', 0x0007),
+];
+
+/// Subsystems that configure pins
+#[derive(Clone, Copy, PartialEq)]
+pub enum Owner {
+    Adc,
+    Burst,
+    Counter,
+    Gpio,
+    Hsical,
+    I2c,
+    Led,
+    Mco,
+    Motion,
+    Pwmout,
+    Ranger,
+    Slave,
+    Spi,
+    Swd,
+    Switches,
+    Sync,
+    Touch,
+    Trigger,
+    Uart,
+}
+
+pub const OWNERS: [(&str, Owner); 19] = [
+    ("adc", Owner::Adc),
+    ("burst", Owner::Burst),
+    ("counter", Owner::Counter),
+    ("gpio", Owner::Gpio),
+    ("hsical", Owner::Hsical),
+    ("i2c", Owner::I2c),
+    ("led", Owner::Led),
+    ("mco", Owner::Mco),
+    ("motion", Owner::Motion),
+    ("pwmout", Owner::Pwmout),
+    ("ranger", Owner::Ranger),
+    ("slave", Owner::Slave),
+    ("spi", Owner::Spi),
+    ("swd", Owner::Swd),
+    ("switches", Owner::Switches),
+    ("sync", Owner::Sync),
+    ("touch", Owner::Touch),
+    ("trigger", Owner::Trigger),
+    ("uart", Owner::Uart),
+];
+
+impl Owner {
+    pub fn name(self) -> &'static str {
+        OWNERS[self as usize].0
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PinError {
+    /// The subsystem has no function on the pin
+    Unsupported,
+    /// Another subsystem configured the pin
+    Claimed(Port, u8, Owner),
+}
+
+pub fn write_error(out: &mut dyn Write, err: PinError) {
+    metrics::set_exit_status(ExitStatus::Error);
+    match err {
+        PinError::Unsupported => write!(out, "{0:}unsupported pin{0:}", CR),
+        PinError::Claimed(port, pin, owner) => write!(
+            out,
+            "{0:}P{1:}{2:} is used by {3:}{0:}",
+            CR,
+            PORTS[port as usize].1,
+            pin,
+            owner.name()
+        ),
+    }
+    .ok();
+}
+
+const FREE: u8 = 0xff;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE_INIT: AtomicU8 = AtomicU8::new(FREE);
+/// Owner of every pin, indexed by port then pin. Drivers claim from several tasks,
+/// each pin is a single byte so no lock is needed.
+static CLAIMS: [AtomicU8; PORTS.len() * 16] = [FREE_INIT; PORTS.len() * 16];
+
+fn slot(port: Port, pin: u8) -> &'static AtomicU8 {
+    &CLAIMS[port as usize * 16 + pin as usize]
+}
+
+/// Pins the board wiring dedicates before any driver runs
+pub fn init() {
+    reserve(Port::A, 2, Owner::Uart);
+    reserve(Port::A, 3, Owner::Uart);
+    reserve(Port::A, 13, Owner::Swd);
+    reserve(Port::A, 14, Owner::Swd);
+}
+
+/// Records the owner of a pin wired to one subsystem, drivers brought up at boot
+pub fn reserve(port: Port, pin: u8, owner: Owner) {
+    slot(port, pin).store(owner as u8, Ordering::Relaxed);
+}
+
+/// Fails when another subsystem holds the pin, drivers check every pin before they
+/// touch a register so a refused start leaves the hardware as it was
+pub fn check(port: Port, pin: u8, owner: Owner) -> Result<(), PinError> {
+    match self::owner(port, pin) {
+        Some(held) if held != owner => Err(PinError::Claimed(port, pin, held)),
+        _ => Ok(()),
+    }
+}
+
+/// Records `owner` as the subsystem driving the pin unless another one holds it
+pub fn claim(port: Port, pin: u8, owner: Owner) -> Result<(), PinError> {
+    check(port, pin, owner)?;
+    slot(port, pin).store(owner as u8, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Frees the pin if `owner` still holds it
+pub fn release(port: Port, pin: u8, owner: Owner) {
+    let slot = slot(port, pin);
+    if slot.load(Ordering::Relaxed) == owner as u8 {
+        slot.store(FREE, Ordering::Relaxed);
+    }
+}
+
+pub fn owner(port: Port, pin: u8) -> Option<Owner> {
+    let idx = slot(port, pin).load(Ordering::Relaxed) as usize;
+    OWNERS.get(idx).map(|(_, owner)| *owner)
+}
+
+/// MODER, PUPDR and both AFR words of a port, `None` while its clock is off
+fn registers(port: Port) -> Option<(u32, u32, u64)> {
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    if rcc.iopenr.read().bits() & (1 << port_bit(port)) == 0 {
+        return None;
+    }
+    if port == Port::A {
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let afr = (gpio.afrh.read().bits() as u64) << 32 | gpio.afrl.read().bits() as u64;
+        return Some((gpio.moder.read().bits(), gpio.pupdr.read().bits(), afr));
+    }
+    let gpio = unsafe {
+        match port {
+            Port::B => &*stm32::GPIOB::ptr(),
+            Port::C => &*stm32::GPIOC::ptr(),
+            Port::D => &*stm32::GPIOD::ptr(),
+            _ => &*stm32::GPIOF::ptr(),
+        }
+    };
+    let afr = (gpio.afrh.read().bits() as u64) << 32 | gpio.afrl.read().bits() as u64;
+    Some((gpio.moder.read().bits(), gpio.pupdr.read().bits(), afr))
+}
+
+/// IOPENR bit of a port, F sits after the unbonded port E
+fn port_bit(port: Port) -> u32 {
+    match port {
+        Port::F => 5,
+        _ => port as u32,
+    }
+}
+
+/// One line per package pin: mode, pull and the subsystem that claimed it
+pub fn write_report(out: &mut dyn Write) {
+    write!(out, "{0:}Pin   Mode     Pull  Owner{0:}", CR).ok();
+    for (port, name, bonded) in PORTS.iter() {
+        let regs = registers(*port);
+        for pin in (0..16).filter(|pin| bonded & (1 << pin) != 0) {
+            write!(out, "P{}{:<4} ", name, pin).ok();
+            match regs {
+                Some((moder, pupdr, afr)) => {
+                    let shift = pin * 2;
+                    match moder >> shift & 0b11 {
+                        0b00 => write!(out, "{:<8} ", "input"),
+                        0b01 => write!(out, "{:<8} ", "output"),
+                        0b10 => write!(out, "af{:<6} ", afr >> (pin * 4) & 0xf),
+                        _ => write!(out, "{:<8} ", "analog"),
+                    }
+                    .ok();
+                    let pull = match pupdr >> shift & 0b11 {
+                        0b00 => "none",
+                        0b01 => "up",
+                        0b10 => "down",
+                        _ => "?",
+                    };
+                    write!(out, "{:<5} ", pull).ok();
+                }
+                None => {
+                    out.write_str("off      -     ").ok();
+                }
+            }
+            let owner = owner(*port, pin).map_or("-", |owner| owner.name());
+            write!(out, "{}{}", owner, CR).ok();
+        }
+    }
+}
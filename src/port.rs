@@ -0,0 +1,193 @@
+use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use hal::dma::{self, Channel, Direction, Event as DmaEvent, Priority, WordSize};
+use hal::dmamux::DmaMuxIndex;
+use hal::hal::serial::{Read, Write};
+use hal::nb;
+use hal::serial::{self, Event, Serial};
+use hal::stm32;
+use heapless::spsc::{Consumer, Producer};
+
+use crate::config::{ShellUsart, RX_QUEUE_LEN, RX_RING_LEN, SHELL_BAUD, SHELL_IRQ, TX_QUEUE_LEN};
+use crate::metrics;
+
+type Usart = Serial<ShellUsart, serial::FullConfig>;
+
+/// USART2 kernel clock, `clocks::init` feeds it from HSI16
+const USART_CLK: u32 = 16_000_000;
+pub const MIN_BAUD: u32 = 1200;
+pub const MAX_BAUD: u32 = 921_600;
+
+static BAUD: AtomicU32 = AtomicU32::new(SHELL_BAUD);
+
+/// Shell UART as seen from its interrupt. DMA fills a circular receive ring and the idle
+/// line interrupt moves it into the input queue, so a pasted script arrives whole. The
+/// TXE interrupt moves the output queue to the transmitter.
+pub struct UartLink {
+    serial: Usart,
+    /// Kept so nothing else reprograms the channel, the ring position is read from NDTR
+    _rx: dma::C5,
+    ring: &'static mut [u8; RX_RING_LEN],
+    tail: usize,
+    input: Producer<'static, u8, RX_QUEUE_LEN>,
+    output: Consumer<'static, u8, TX_QUEUE_LEN>,
+}
+
+impl UartLink {
+    pub fn new(
+        mut serial: Usart,
+        mut rx: dma::C5,
+        input: Producer<'static, u8, RX_QUEUE_LEN>,
+        output: Consumer<'static, u8, TX_QUEUE_LEN>,
+    ) -> Self {
+        let usart = unsafe { &*ShellUsart::ptr() };
+        let ring = cortex_m::singleton!(: [u8; RX_RING_LEN] = [0; RX_RING_LEN]).unwrap();
+
+        rx.disable();
+        rx.select_peripheral(DmaMuxIndex::USART2_RX);
+        rx.set_direction(Direction::FromPeripheral);
+        rx.set_priority_level(Priority::High);
+        rx.set_word_size::<u8>(WordSize::BITS8);
+        rx.set_peripheral_address(&usart.rdr as *const _ as u32, false);
+        rx.set_memory_address(ring.as_ptr() as u32, true);
+        rx.set_transfer_length(RX_RING_LEN as u16);
+        rx.set_circular_mode(true);
+        // Half and full ring wake the link during a paste longer than the ring
+        rx.listen(DmaEvent::HalfTransfer);
+        rx.listen(DmaEvent::TransferComplete);
+        rx.enable();
+        usart.cr3.modify(|_, w| w.dmar().set_bit());
+        serial.listen(Event::Idle);
+
+        Self {
+            serial,
+            _rx: rx,
+            ring,
+            tail: 0,
+            input,
+            output,
+        }
+    }
+
+    /// Moves received bytes to the shell and queued bytes to the transmitter. Returns true
+    /// when the shell should run: input arrived or the output queue is empty, so reports
+    /// pended while a long output drains are picked up once it is done.
+    pub fn service(&mut self) -> bool {
+        self.serial.unpend(Event::Idle);
+
+        let head = self.head();
+        let received = head != self.tail;
+        while self.tail != head {
+            let byte = unsafe { ptr::read_volatile(&self.ring[self.tail]) };
+            if self.input.enqueue(byte).is_err() {
+                metrics::UART_RX_DROPS.inc();
+            }
+            self.tail = (self.tail + 1) % RX_RING_LEN;
+        }
+
+        while let Some(byte) = self.output.peek() {
+            if self.serial.write(*byte).is_err() {
+                break;
+            }
+            self.output.dequeue();
+        }
+        let drained = !self.output.ready();
+        if drained {
+            self.serial.unlisten(Event::Txe);
+        } else {
+            self.serial.listen(Event::Txe);
+        }
+        received || drained
+    }
+
+    /// Ring offset the DMA writes next
+    fn head(&self) -> usize {
+        let dma = unsafe { &*stm32::DMA::ptr() };
+        (RX_RING_LEN - dma.ch5.ndtr.read().bits() as usize) % RX_RING_LEN
+    }
+}
+
+/// Ring half or ring full, only wakes the link. Bytes are lost when the link falls a
+/// whole ring behind.
+pub fn on_rx_dma() {
+    let dma = unsafe { &*stm32::DMA::ptr() };
+    dma.ifcr.write(|w| w.cgif5().set_bit());
+}
+
+pub fn baud() -> u32 {
+    BAUD.load(Ordering::Relaxed)
+}
+
+/// Switches the shell USART to a new rate, false when out of range. Whatever is still
+/// in the shift register goes out garbled, flush the shell first.
+pub fn set_baud(baud: u32) -> bool {
+    if !(MIN_BAUD..=MAX_BAUD).contains(&baud) {
+        return false;
+    }
+    let usart = unsafe { &*ShellUsart::ptr() };
+    // BRR only changes with the USART disabled, the receive DMA picks up where it was
+    usart.cr1.modify(|_, w| w.ue().clear_bit());
+    usart
+        .brr
+        .write(|w| unsafe { w.bits((USART_CLK + baud / 2) / baud) });
+    usart.cr1.modify(|_, w| w.ue().set_bit());
+    BAUD.store(baud, Ordering::Relaxed);
+    true
+}
+
+/// Shell side of the UART, both directions go through queues shared with `UartLink`.
+/// Writes return as soon as the byte is queued.
+pub struct ShellPort {
+    input: Consumer<'static, u8, RX_QUEUE_LEN>,
+    output: Producer<'static, u8, TX_QUEUE_LEN>,
+    stalled: bool,
+}
+
+impl ShellPort {
+    pub fn new(
+        input: Consumer<'static, u8, RX_QUEUE_LEN>,
+        output: Producer<'static, u8, TX_QUEUE_LEN>,
+    ) -> Self {
+        Self {
+            input,
+            output,
+            stalled: false,
+        }
+    }
+}
+
+impl Read<u8> for ShellPort {
+    type Error = serial::Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        self.input.dequeue().ok_or(nb::Error::WouldBlock)
+    }
+}
+
+impl Write<u8> for ShellPort {
+    type Error = serial::Error;
+
+    /// Blocks only on a full queue, the link interrupt preempts the shell and drains it
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        if self.output.enqueue(byte).is_err() {
+            if !self.stalled {
+                self.stalled = true;
+                metrics::UART_TX_STALLS.inc();
+            }
+            return Err(nb::Error::WouldBlock);
+        }
+        self.stalled = false;
+        rtic::pend(SHELL_IRQ);
+        Ok(())
+    }
+
+    /// Done once the queue is empty and the last byte left the shift register
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        let usart = unsafe { &*ShellUsart::ptr() };
+        if self.output.ready() || usart.isr.read().tc().bit_is_clear() {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use hal::stm32;
+
+use crate::backup::{self, Slot};
+
+/// Falling edge PVD thresholds in millivolts, indexed by PVDFT level
+pub const PVD_LEVELS_MV: [u16; 7] = [2050, 2200, 2360, 2520, 2640, 2810, 2910];
+
+const PVD_EXTI_LINE: u32 = 16;
+const POWER_FAIL_MARKER: u32 = 0xdead_0bad;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PowerEvent {
+    Fail,
+    Restore,
+}
+
+/// Programmable voltage detector supervision
+pub struct PowerMonitor {
+    level: Option<u8>,
+    failures: u32,
+    last_failure: Option<u32>,
+    failed_before_reset: bool,
+    event: Option<PowerEvent>,
+}
+
+impl PowerMonitor {
+    /// Picks up a power failure recorded before the last reset
+    pub fn new() -> Self {
+        let failed_before_reset = backup::read(Slot::PowerFail) == POWER_FAIL_MARKER;
+        backup::write(Slot::PowerFail, 0);
+        Self {
+            level: None,
+            failures: 0,
+            last_failure: None,
+            failed_before_reset,
+            event: None,
+        }
+    }
+
+    pub fn level(&self) -> Option<u8> {
+        self.level
+    }
+
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn last_failure(&self) -> Option<u32> {
+        self.last_failure
+    }
+
+    pub fn failed_before_reset(&self) -> bool {
+        self.failed_before_reset
+    }
+
+    pub fn is_low(&self) -> bool {
+        let pwr = unsafe { &*stm32::PWR::ptr() };
+        self.level.is_some() && pwr.sr2.read().pvdo().bit_is_set()
+    }
+
+    pub fn enable(&mut self, level: u8) {
+        let pwr = unsafe { &*stm32::PWR::ptr() };
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        let line = 1 << PVD_EXTI_LINE;
+        pwr.cr2
+            .modify(|_, w| unsafe { w.pvdft().bits(level).pvdrt().bits(level).pvde().set_bit() });
+        exti.rtsr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+        exti.ftsr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+        exti.rpr1.write(|w| unsafe { w.bits(line) });
+        exti.fpr1.write(|w| unsafe { w.bits(line) });
+        // IMR1 is shared with the RX edge capture of a higher priority task
+        cortex_m::interrupt::free(|_| {
+            exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+        });
+        self.level = Some(level);
+    }
+
+    pub fn disable(&mut self) {
+        let pwr = unsafe { &*stm32::PWR::ptr() };
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        cortex_m::interrupt::free(|_| {
+            exti.imr1
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << PVD_EXTI_LINE)) });
+        });
+        pwr.cr2.modify(|_, w| w.pvde().clear_bit());
+        self.level = None;
+    }
+
+    /// Handles the PVD interrupt, the failure marker goes to a backup register first
+    pub fn on_interrupt(&mut self, ticks: u32) {
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        let line = 1 << PVD_EXTI_LINE;
+        if exti.fpr1.read().bits() & line != 0 {
+            exti.fpr1.write(|w| unsafe { w.bits(line) });
+            backup::write(Slot::PowerFail, POWER_FAIL_MARKER);
+            self.failures += 1;
+            self.last_failure = Some(ticks);
+            self.event = Some(PowerEvent::Fail);
+        }
+        if exti.rpr1.read().bits() & line != 0 {
+            exti.rpr1.write(|w| unsafe { w.bits(line) });
+            backup::write(Slot::PowerFail, 0);
+            self.event = Some(PowerEvent::Restore);
+        }
+    }
+
+    pub fn take_event(&mut self) -> Option<PowerEvent> {
+        self.event.take()
+    }
+}
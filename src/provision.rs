@@ -0,0 +1,261 @@
+//! Board identity and the binary provisioning messages a manufacturing fixture sends.
+//! A message is the COBS frame of `type, seq, data, CRC-16` sent between zero bytes,
+//! every one is answered with an acknowledgement frame of the same layout. Besides the
+//! fixture messages a host can set the RTC with TIME_SYNC.
+//!
+//! The link is not authenticated, so the fixture messages are only taken until a key is
+//! set. A provisioned board refuses them with `Status::Locked`, otherwise anyone on the
+//! UART could swap the key and sign whatever they like.
+
+use crate::cal;
+use crate::cobs;
+use crate::flash::{self, FlashError, Page};
+use crate::telemetry::crc16;
+use crate::timesync;
+
+pub const SERIAL_LEN: usize = 16;
+pub const KEY_LEN: usize = 16;
+/// SET_KEY is the longest message
+pub const MESSAGE_LEN: usize = 2 + KEY_LEN + 2;
+pub const FRAME_LEN: usize = cobs::max_encoded_len(MESSAGE_LEN);
+pub const ACK_LEN: usize = 6;
+
+const SET_SERIAL: u8 = 0x10;
+const WRITE_CAL: u8 = 0x11;
+const SET_KEY: u8 = 0x12;
+/// Unix milliseconds as a little endian u64
+const TIME_SYNC: u8 = 0x13;
+const ACK: u8 = 0x02;
+
+/// Identity record in the calibration page, clear of the calibration record
+const IDENTITY_OFFSET: usize = 1024;
+const IDENTITY_MAGIC: u32 = 0x1d3e_0001;
+const HAS_SERIAL: u8 = 1;
+const HAS_KEY: u8 = 2;
+const SIGNING: u8 = 4;
+/// Magic, flags, serial number, key, CRC-16 of the preceding bytes
+const IDENTITY_LEN: usize = 4 + 1 + SERIAL_LEN + KEY_LEN + 2;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Ok = 0,
+    BadCrc = 1,
+    BadMessage = 2,
+    Range = 3,
+    Flash = 4,
+    /// The board holds a key, identity and calibration are fixed
+    Locked = 5,
+}
+
+#[derive(Clone, Copy)]
+struct Identity {
+    flags: u8,
+    serial: [u8; SERIAL_LEN],
+    key: [u8; KEY_LEN],
+}
+
+impl Identity {
+    fn load() -> Self {
+        let record = &flash::read(Page::Cal)[IDENTITY_OFFSET..][..IDENTITY_LEN];
+        let magic = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+        let crc = u16::from_le_bytes([record[IDENTITY_LEN - 2], record[IDENTITY_LEN - 1]]);
+        let mut identity = Identity {
+            flags: 0,
+            serial: [0; SERIAL_LEN],
+            key: [0; KEY_LEN],
+        };
+        if magic == IDENTITY_MAGIC && crc == crc16(&record[..IDENTITY_LEN - 2]) {
+            identity.flags = record[4];
+            identity.serial.copy_from_slice(&record[5..][..SERIAL_LEN]);
+            identity
+                .key
+                .copy_from_slice(&record[5 + SERIAL_LEN..][..KEY_LEN]);
+        }
+        identity
+    }
+
+    fn save(&self) -> Result<(), FlashError> {
+        let mut record = [0; IDENTITY_LEN];
+        record[..4].copy_from_slice(&IDENTITY_MAGIC.to_le_bytes());
+        record[4] = self.flags;
+        record[5..][..SERIAL_LEN].copy_from_slice(&self.serial);
+        record[5 + SERIAL_LEN..][..KEY_LEN].copy_from_slice(&self.key);
+        let crc = crc16(&record[..IDENTITY_LEN - 2]);
+        record[IDENTITY_LEN - 2..].copy_from_slice(&crc.to_le_bytes());
+        flash::update(Page::Cal, IDENTITY_OFFSET, &record)
+    }
+}
+
+/// Serial number programmed by the fixture
+pub fn serial() -> Option<&'static str> {
+    if Identity::load().flags & HAS_SERIAL == 0 {
+        return None;
+    }
+    let serial = &flash::read(Page::Cal)[IDENTITY_OFFSET + 5..][..SERIAL_LEN];
+    let len = serial.iter().position(|c| *c == 0).unwrap_or(SERIAL_LEN);
+    core::str::from_utf8(&serial[..len]).ok()
+}
+
+/// Shared secret programmed by the fixture
+pub fn key() -> Option<[u8; KEY_LEN]> {
+    let identity = Identity::load();
+    if identity.flags & HAS_KEY == 0 {
+        return None;
+    }
+    Some(identity.key)
+}
+
+/// Dangerous commands need a signature, kept in flash so a reset does not clear it
+pub fn signing() -> bool {
+    Identity::load().flags & SIGNING != 0
+}
+
+pub fn set_signing(on: bool) -> Result<(), FlashError> {
+    let mut identity = Identity::load();
+    if on {
+        identity.flags |= SIGNING;
+    } else {
+        identity.flags &= !SIGNING;
+    }
+    identity.save()
+}
+
+/// Collects bytes between zero delimiters once a zero starts a binary frame
+pub struct FrameReader {
+    frame: [u8; FRAME_LEN],
+    len: usize,
+    receiving: bool,
+    overflow: bool,
+    ready: bool,
+}
+
+impl FrameReader {
+    pub fn new() -> Self {
+        Self {
+            frame: [0; FRAME_LEN],
+            len: 0,
+            receiving: false,
+            overflow: false,
+            ready: false,
+        }
+    }
+
+    /// Takes a received byte, hands back the ones meant for the shell. The shell never
+    /// sees a zero, so a zero always opens or closes a frame.
+    pub fn feed(&mut self, byte: u8) -> Option<u8> {
+        match (self.receiving, byte) {
+            (false, 0) => {
+                self.receiving = true;
+                self.len = 0;
+                self.overflow = false;
+                None
+            }
+            (false, _) => Some(byte),
+            // Back to back delimiters carry no frame
+            (true, 0) if self.len == 0 => None,
+            (true, 0) => {
+                self.receiving = false;
+                self.ready = !self.overflow;
+                None
+            }
+            (true, _) => {
+                match self.frame.get_mut(self.len) {
+                    Some(slot) => *slot = byte,
+                    None => self.overflow = true,
+                }
+                self.len += 1;
+                None
+            }
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready
+    }
+
+    /// Completed frame, without its delimiters
+    pub fn take(&mut self) -> Option<&[u8]> {
+        if !self.ready {
+            return None;
+        }
+        self.ready = false;
+        Some(&self.frame[..self.len])
+    }
+}
+
+/// Decodes and applies one frame, returns the acknowledgement payload
+pub fn handle(frame: &[u8]) -> [u8; ACK_LEN] {
+    let mut msg = [0; MESSAGE_LEN];
+    let (kind, seq, status) = match cobs::decode(frame, &mut msg) {
+        Ok(len) if len >= 4 => {
+            let (body, crc) = msg[..len].split_at(len - 2);
+            if crc16(body) != u16::from_le_bytes([crc[0], crc[1]]) {
+                (body[0], body[1], Status::BadCrc)
+            } else {
+                (body[0], body[1], apply(body[0], &body[2..]))
+            }
+        }
+        _ => (0, 0, Status::BadMessage),
+    };
+    let mut ack = [ACK, seq, kind, status as u8, 0, 0];
+    let crc = crc16(&ack[..4]);
+    ack[4..].copy_from_slice(&crc.to_le_bytes());
+    ack
+}
+
+fn apply(kind: u8, data: &[u8]) -> Status {
+    if kind == TIME_SYNC {
+        if data.len() != 8 {
+            return Status::BadMessage;
+        }
+        let mut ms = [0; 8];
+        ms.copy_from_slice(data);
+        return match timesync::sync(u64::from_le_bytes(ms)) {
+            Ok(_) => Status::Ok,
+            Err(_) => Status::Range,
+        };
+    }
+    let mut identity = Identity::load();
+    if identity.flags & HAS_KEY != 0 {
+        return match kind {
+            SET_SERIAL | SET_KEY | WRITE_CAL => Status::Locked,
+            _ => Status::BadMessage,
+        };
+    }
+    match kind {
+        SET_SERIAL => {
+            if data.is_empty()
+                || data.len() > SERIAL_LEN
+                || !data.iter().all(|c| c.is_ascii_graphic())
+            {
+                return Status::Range;
+            }
+            identity.serial = [0; SERIAL_LEN];
+            identity.serial[..data.len()].copy_from_slice(data);
+            identity.flags |= HAS_SERIAL;
+        }
+        SET_KEY => {
+            if data.len() != KEY_LEN {
+                return Status::BadMessage;
+            }
+            identity.key.copy_from_slice(data);
+            identity.flags |= HAS_KEY;
+        }
+        WRITE_CAL => {
+            if data.len() != 5 {
+                return Status::BadMessage;
+            }
+            let value = i32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+            return match cal::store(data[0] as usize, value) {
+                Ok(()) => Status::Ok,
+                Err(cal::CalError::Flash(_)) => Status::Flash,
+                Err(_) => Status::Range,
+            };
+        }
+        _ => return Status::BadMessage,
+    }
+    match identity.save() {
+        Ok(()) => Status::Ok,
+        Err(_) => Status::Flash,
+    }
+}
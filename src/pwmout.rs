@@ -0,0 +1,154 @@
+use hal::rcc::Rcc;
+use hal::stm32;
+use hal::timer::TimerExt;
+
+use crate::clocks;
+use crate::pins::{self, Owner, PinError, Port};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Channel {
+    Ch1,
+    Ch2,
+}
+
+impl Channel {
+    /// Maps port A pins to TIM3 channels
+    pub fn from_pin(pin: u8) -> Option<Channel> {
+        match pin {
+            6 => Some(Channel::Ch1),
+            7 => Some(Channel::Ch2),
+            _ => None,
+        }
+    }
+
+    pub fn pin(self) -> u8 {
+        match self {
+            Channel::Ch1 => 6,
+            Channel::Ch2 => 7,
+        }
+    }
+}
+
+/// Test PWM output on a spare TIM3 channel, independent from the LED
+pub struct PwmOut {
+    tim: stm32::TIM3,
+    channel: Option<Channel>,
+    freq: u32,
+    duty: u8,
+}
+
+impl PwmOut {
+    pub const MAX_FREQ: u32 = 100_000;
+
+    pub fn new(tim: stm32::TIM3, rcc: &mut Rcc) -> Self {
+        Self {
+            tim: tim.timer(rcc).release(),
+            channel: None,
+            freq: 0,
+            duty: 0,
+        }
+    }
+
+    pub fn channel(&self) -> Option<Channel> {
+        self.channel
+    }
+
+    pub fn freq(&self) -> u32 {
+        self.freq
+    }
+
+    pub fn duty(&self) -> u8 {
+        self.duty
+    }
+
+    /// Starts PWM on the given channel, frequency in Hertz and duty in percent
+    pub fn start(&mut self, channel: Channel, freq: u32, duty: u8) -> Result<(), PinError> {
+        pins::check(Port::A, channel.pin(), Owner::Pwmout)?;
+        if self.channel.is_some_and(|active| active != channel) {
+            self.stop();
+        }
+
+        let ratio = clocks::timer_clk() / freq;
+        let psc = (ratio - 1) / 0xffff;
+        let arr = ratio / (psc + 1) - 1;
+        let ccr = (arr + 1) * duty as u32 / 100;
+
+        let tim = &self.tim;
+        tim.cr1.modify(|_, w| w.cen().clear_bit());
+        tim.psc.write(|w| unsafe { w.bits(psc) });
+        tim.arr.write(|w| unsafe { w.bits(arr) });
+        match channel {
+            Channel::Ch1 => {
+                tim.ccr1.write(|w| unsafe { w.bits(ccr) });
+                tim.ccmr1_output()
+                    .modify(|_, w| unsafe { w.oc1pe().set_bit().oc1m().bits(6) });
+                tim.ccer.modify(|_, w| w.cc1e().set_bit());
+            }
+            Channel::Ch2 => {
+                tim.ccr2.write(|w| unsafe { w.bits(ccr) });
+                tim.ccmr1_output()
+                    .modify(|_, w| unsafe { w.oc2pe().set_bit().oc2m().bits(6) });
+                tim.ccer.modify(|_, w| w.cc2e().set_bit());
+            }
+        }
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.cr1.modify(|_, w| w.arpe().set_bit().cen().set_bit());
+
+        Self::set_pin_mode(channel.pin(), 0b10);
+        pins::claim(Port::A, channel.pin(), Owner::Pwmout)?;
+        self.channel = Some(channel);
+        self.freq = freq;
+        self.duty = duty;
+        Ok(())
+    }
+
+    /// Changes duty in percent, takes effect at the next period
+    pub fn set_duty(&mut self, duty: u8) {
+        let ccr = (self.tim.arr.read().bits() + 1) * duty as u32 / 100;
+        match self.channel {
+            Some(Channel::Ch1) => self.tim.ccr1.write(|w| unsafe { w.bits(ccr) }),
+            Some(Channel::Ch2) => self.tim.ccr2.write(|w| unsafe { w.bits(ccr) }),
+            None => return,
+        }
+        self.duty = duty;
+    }
+
+    /// Interrupts on every period
+    pub fn listen(&mut self) {
+        self.tim.dier.modify(|_, w| w.uie().set_bit());
+    }
+
+    pub fn clear_irq(&mut self) {
+        self.tim.sr.modify(|_, w| w.uif().clear_bit());
+    }
+
+    /// Recomputes prescaler for the current timer clock
+    pub fn retime(&mut self) {
+        if let Some(channel) = self.channel {
+            self.start(channel, self.freq, self.duty).ok();
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(channel) = self.channel.take() {
+            self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+            self.tim.dier.modify(|_, w| w.uie().clear_bit());
+            self.tim
+                .ccer
+                .modify(|_, w| w.cc1e().clear_bit().cc2e().clear_bit());
+            Self::set_pin_mode(channel.pin(), 0b11);
+            pins::release(Port::A, channel.pin(), Owner::Pwmout);
+        }
+    }
+
+    fn set_pin_mode(pin: u8, mode: u32) {
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let shift = pin * 2;
+        let af_shift = pin * 4;
+        // TIM3_CH1/CH2 on PA6/PA7 are AF1
+        gpio.afrl
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0xf << af_shift)) | (1 << af_shift)) });
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (mode << shift)) });
+    }
+}
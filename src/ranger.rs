@@ -0,0 +1,148 @@
+use hal::rcc::Rcc;
+use hal::stm32;
+use hal::timer::TimerExt;
+
+use crate::clocks;
+use crate::pins::{self, Owner, Port};
+
+/// HC-SR04 trigger on PA1, echo on PA4 captured by TIM14_CH1 (alternate function 4)
+const TRIG_PIN: u32 = 1;
+const ECHO_PIN: u32 = 4;
+const ECHO_AF: u32 = 4;
+/// Echo pulses this long mean nothing is in range
+const MAX_ECHO_US: u32 = 30_000;
+/// Capture counter values, 1us per count
+const RISE_TIMEOUT_US: u32 = 20_000;
+const FALL_TIMEOUT_US: u32 = 60_000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RangeError {
+    NoSensor,
+    OutOfRange,
+}
+
+/// Ultrasonic ranger, echo width measured by input capture on both edges
+pub struct Ranger {
+    tim: stm32::TIM14,
+    map: bool,
+    due: bool,
+}
+
+impl Ranger {
+    /// Shortest and longest distance mapped onto blink frequency
+    pub const MAP_MIN_CM: u32 = 5;
+    pub const MAP_MAX_CM: u32 = 100;
+
+    pub fn new(tim: stm32::TIM14, rcc: &mut Rcc) -> Self {
+        let tim = tim.timer(rcc).release();
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << (TRIG_PIN + 16)) });
+        gpio.moder.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << (TRIG_PIN * 2))) | (0b01 << (TRIG_PIN * 2)))
+        });
+        gpio.afrl.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0xf << (ECHO_PIN * 4))) | (ECHO_AF << (ECHO_PIN * 4)))
+        });
+        gpio.moder.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << (ECHO_PIN * 2))) | (0b10 << (ECHO_PIN * 2)))
+        });
+        pins::reserve(Port::A, TRIG_PIN as u8, Owner::Ranger);
+        pins::reserve(Port::A, ECHO_PIN as u8, Owner::Ranger);
+
+        tim.ccmr1_input()
+            .write(|w| unsafe { w.cc1s().bits(0b01).ic1f().bits(0b0011) });
+        tim.ccer
+            .write(|w| w.cc1e().set_bit().cc1p().set_bit().cc1np().set_bit());
+        tim.arr.write(|w| unsafe { w.bits(0xffff) });
+        Self {
+            tim,
+            map: false,
+            due: false,
+        }
+    }
+
+    /// Triggers a ping and returns the echo width in microseconds, blocks up to 60ms
+    pub fn echo_us(&mut self) -> Result<u32, RangeError> {
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let tim = &self.tim;
+        let clk = clocks::timer_clk();
+        tim.psc.write(|w| unsafe { w.bits(clk / 1_000_000 - 1) });
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.write(|w| unsafe { w.bits(0) });
+        tim.cr1.modify(|_, w| w.cen().set_bit());
+
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << TRIG_PIN) });
+        cortex_m::asm::delay(clk / 100_000);
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << (TRIG_PIN + 16)) });
+
+        let res = self.capture(RISE_TIMEOUT_US).and_then(|rise| {
+            self.capture(FALL_TIMEOUT_US)
+                .map(|fall| fall.wrapping_sub(rise) & 0xffff)
+        });
+        self.tim.cr1.modify(|_, w| w.cen().clear_bit());
+        match res {
+            Some(width) if width < MAX_ECHO_US => Ok(width),
+            Some(_) => Err(RangeError::OutOfRange),
+            None => Err(RangeError::NoSensor),
+        }
+    }
+
+    /// Distance in millimeters, sound travels 1mm forth and back in about 5.8us
+    pub fn distance_mm(&mut self) -> Result<u32, RangeError> {
+        self.echo_us().map(|us| us * 10 / 58)
+    }
+
+    pub fn is_mapping(&self) -> bool {
+        self.map
+    }
+
+    pub fn set_mapping(&mut self, map: bool) {
+        self.map = map;
+        self.due = false;
+    }
+
+    /// Advances by one system tick, returns true when a mapped ping is due
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances by a number of ticks slept through in Stop mode, a wakeup short of a
+    /// whole tick leaves the loop where it was
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        if ticks > 0 {
+            self.due = self.map;
+        }
+        self.due
+    }
+
+    /// A mapped ping is due on the next tick
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        if self.map {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    pub fn take_due(&mut self) -> bool {
+        let due = self.due;
+        self.due = false;
+        due
+    }
+
+    /// Maps distance to blink frequency, closer blinks faster
+    pub fn blink_freq(distance_mm: u32) -> u8 {
+        let cm = (distance_mm / 10).clamp(Self::MAP_MIN_CM, Self::MAP_MAX_CM);
+        let span = Self::MAP_MAX_CM - Self::MAP_MIN_CM;
+        (1 + (Self::MAP_MAX_CM - cm) * 99 / span) as u8
+    }
+
+    fn capture(&self, timeout: u32) -> Option<u32> {
+        while self.tim.sr.read().cc1if().bit_is_clear() {
+            if self.tim.cnt.read().bits() > timeout {
+                return None;
+            }
+        }
+        Some(self.tim.ccr1.read().bits())
+    }
+}
@@ -0,0 +1,291 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::peripheral::NVIC;
+use cortex_m::register::primask;
+use rtic::Mutex;
+
+use crate::config::{
+    ADC_WATCH_PRIORITY, BLINK_PRIORITY, LED_PWM_PRIORITY, LOAD_PRIORITY, PIN_EDGE_PRIORITY,
+    POWER_PRIORITY, SHELL_PRIORITY, SPI_SLAVE_PRIORITY, SYS_TICK_PRIORITY, WAVE_PRIORITY,
+};
+use crate::cycles;
+
+#[derive(Clone, Copy)]
+pub enum Res {
+    AdcWatch,
+    Alarm,
+    Audio,
+    Bitbang,
+    BlinkEnabled,
+    BlinkFreq,
+    BlinkSync,
+    BlinkTimer,
+    Burst,
+    Clock,
+    Countdown,
+    Counter,
+    Cpu,
+    Dashboard,
+    Gpio,
+    Health,
+    Hw,
+    Jobs,
+    Led,
+    LedOwner,
+    Loadgen,
+    MemDma,
+    Monitor,
+    Morse,
+    Motion,
+    Pattern,
+    Pid,
+    Power,
+    Pwmout,
+    Ranger,
+    Ringer,
+    Rollback,
+    Scripts,
+    Sensors,
+    Slave,
+    Slider,
+    StatusBar,
+    Stopwatch,
+    Sweep,
+    Switches,
+    SysTimer,
+    Telemetry,
+    Thermostat,
+    Ticks,
+    Touch,
+    Trigger,
+    Wave,
+}
+
+/// Tasks of the app with their priorities
+pub const TASKS: [(&str, u8); 16] = [
+    ("idle", 0),
+    ("shell_poll", SHELL_PRIORITY),
+    ("blink_timer_tick", BLINK_PRIORITY),
+    ("sys_tick", SYS_TICK_PRIORITY),
+    ("power_fail", POWER_PRIORITY),
+    ("wave_tick", WAVE_PRIORITY),
+    ("pin_edge", PIN_EDGE_PRIORITY),
+    ("load_tick", LOAD_PRIORITY),
+    ("led_pwm", LED_PWM_PRIORITY),
+    ("spi_slave", SPI_SLAVE_PRIORITY),
+    ("job_due", SHELL_PRIORITY),
+    ("rtc_alarm", SHELL_PRIORITY),
+    ("countdown_tick", SHELL_PRIORITY),
+    ("ring_step", SHELL_PRIORITY),
+    ("adc_sample", ADC_WATCH_PRIORITY),
+    ("rollback_due", SHELL_PRIORITY),
+];
+
+/// Shared resources and indices into `TASKS` of the tasks using them. `track!` fails the
+/// build when this list and `Res` disagree with the resources of the app, the task lists
+/// are still kept by hand.
+pub const RESOURCES: [(&str, &[usize]); 47] = [
+    ("adc_watch", &[0, 1, 14]),
+    ("alarm", &[1, 11]),
+    ("audio", &[1, 5]),
+    ("bitbang", &[1]),
+    ("blink_enabled", &[0, 1, 2, 3, 6, 9]),
+    ("blink_freq", &[1, 9]),
+    ("blink_sync", &[0, 1, 3, 6]),
+    ("blink_timer", &[1, 2, 3, 6]),
+    ("burst", &[0, 1]),
+    ("clock", &[0, 1, 3]),
+    ("countdown", &[1, 12]),
+    ("counter", &[0, 1, 3, 6, 9]),
+    ("cpu", &[0, 1, 3, 7]),
+    ("dashboard", &[0, 1, 3]),
+    ("gpio", &[1]),
+    ("health", &[0, 1, 3]),
+    ("hw", &[1]),
+    ("jobs", &[1, 10]),
+    ("led", &[1, 2, 3, 6, 8, 9]),
+    ("led_owner", &[0, 1, 2, 3, 6]),
+    ("loadgen", &[0, 1, 7]),
+    ("mem_dma", &[1]),
+    ("monitor", &[0, 1, 3]),
+    ("morse", &[1, 2]),
+    ("motion", &[0, 1, 3, 6]),
+    ("pattern", &[1, 2]),
+    ("pid", &[0, 1, 3]),
+    ("power", &[1, 4]),
+    ("pwmout", &[0, 1, 5, 13]),
+    ("ranger", &[0, 1, 3]),
+    ("ringer", &[1, 13]),
+    ("rollback", &[1, 15]),
+    ("scripts", &[1]),
+    ("sensors", &[1, 5, 14]),
+    ("slave", &[0, 1, 3, 9]),
+    ("slider", &[1]),
+    ("statusbar", &[0, 1, 3]),
+    ("stopwatch", &[1]),
+    ("sweep", &[0, 1, 3]),
+    ("switches", &[0, 1, 3]),
+    ("sys_timer", &[1, 3]),
+    ("telemetry", &[0, 1, 3]),
+    ("thermostat", &[0, 1, 3]),
+    ("ticks", &[0, 1, 3, 4, 6, 9]),
+    ("touch", &[0, 1, 3]),
+    ("trigger", &[1, 2]),
+    ("wave", &[1, 5]),
+];
+
+// Every task index points into `TASKS`
+const _: () = {
+    let mut res = 0;
+    while res < RESOURCES.len() {
+        let tasks = RESOURCES[res].1;
+        let mut idx = 0;
+        while idx < tasks.len() {
+            assert!(tasks[idx] < TASKS.len(), "resource used by an unknown task");
+            idx += 1;
+        }
+        res += 1;
+    }
+};
+
+/// Whether `RESOURCES` lists `name` at the index of `res`
+pub const fn is_listed(res: Res, name: &str) -> bool {
+    let listed = RESOURCES[res as usize].0.as_bytes();
+    let name = name.as_bytes();
+    if listed.len() != name.len() {
+        return false;
+    }
+    let mut idx = 0;
+    while idx < name.len() {
+        if listed[idx] != name[idx] {
+            return false;
+        }
+        idx += 1;
+    }
+    true
+}
+
+/// Lock counters of a resource
+pub struct Stats {
+    locks: AtomicU32,
+    contended: AtomicU32,
+    max_hold: AtomicU32,
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Self {
+            locks: AtomicU32::new(0),
+            contended: AtomicU32::new(0),
+            max_hold: AtomicU32::new(0),
+        }
+    }
+
+    pub fn locks(&self) -> u32 {
+        self.locks.load(Ordering::Relaxed)
+    }
+
+    /// Locks during which another task became ready and had to wait
+    pub fn contended(&self) -> u32 {
+        self.contended.load(Ordering::Relaxed)
+    }
+
+    /// Longest lock in timer cycles
+    pub fn max_hold(&self) -> u32 {
+        self.max_hold.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.locks.store(0, Ordering::Relaxed);
+        self.contended.store(0, Ordering::Relaxed);
+        self.max_hold.store(0, Ordering::Relaxed);
+    }
+
+    // Only called while holding the resource, so plain load/store is race free
+    fn record(&self, hold: u32, contended: bool) {
+        self.locks
+            .store(self.locks().wrapping_add(1), Ordering::Relaxed);
+        if contended {
+            self.contended
+                .store(self.contended().wrapping_add(1), Ordering::Relaxed);
+        }
+        if hold > self.max_hold() {
+            self.max_hold.store(hold, Ordering::Relaxed);
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const STATS_INIT: Stats = Stats::new();
+static STATS: [Stats; RESOURCES.len()] = [STATS_INIT; RESOURCES.len()];
+
+/// Lock counters of the resource at `idx` in `RESOURCES`
+pub fn stats(idx: usize) -> &'static Stats {
+    &STATS[idx]
+}
+
+/// Ceiling priority of a resource, the highest priority among the tasks using it
+pub fn ceiling(tasks: &[usize]) -> u8 {
+    tasks.iter().map(|task| TASKS[*task].1).max().unwrap_or(0)
+}
+
+/// Resource proxy with a known entry in `RESOURCES`
+pub trait Tracked {
+    const RES: Res;
+}
+
+/// Instrumented replacement for `rtic::Mutex::lock`, bring into scope instead of `Mutex`
+pub trait Lock {
+    type T;
+
+    fn lock<R>(&mut self, f: impl FnOnce(&mut Self::T) -> R) -> R;
+}
+
+impl<M: Mutex + Tracked> Lock for M {
+    type T = <M as Mutex>::T;
+
+    fn lock<R>(&mut self, f: impl FnOnce(&mut Self::T) -> R) -> R {
+        Mutex::lock(self, |res| {
+            let start = cycles::now();
+            let pending = pending_irqs();
+            let r = f(res);
+            // On Cortex-M0+ a raised lock masks every interrupt, new pending ones were delayed by it
+            let contended = primask::read().is_active() && pending_irqs() & !pending != 0;
+            stats(M::RES as usize).record(cycles::since(start), contended);
+            r
+        })
+    }
+}
+
+fn pending_irqs() -> u32 {
+    unsafe { (*NVIC::PTR).ispr[0].read() }
+}
+
+/// Maps RTIC resource proxies to `Res`, expanded inside the app module. A shared
+/// resource left out can't be locked through `Lock`, one missing from `RESOURCES` or
+/// listed at another index stops the build.
+macro_rules! track {
+    ($($name:ident => $res:ident),* $(,)?) => {
+        $(
+            impl crate::resources::Tracked for shared_resources::$name<'_> {
+                const RES: crate::resources::Res = crate::resources::Res::$res;
+            }
+        )*
+
+        const _: () = {
+            $(
+                assert!(
+                    crate::resources::is_listed(crate::resources::Res::$res, stringify!($name)),
+                    concat!("RESOURCES does not list ", stringify!($name), " at its Res index")
+                );
+            )*
+            let tracked = [$(crate::resources::Res::$res),*];
+            assert!(
+                tracked.len() == crate::resources::RESOURCES.len(),
+                "RESOURCES lists a resource the app does not track"
+            );
+        };
+    };
+}
+
+pub(crate) use track;
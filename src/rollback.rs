@@ -0,0 +1,115 @@
+//! Provisional changes for settings that can cut off a remote link. The change applies
+//! at once and goes back by itself unless `confirm` arrives within `CONFIRM_MS`.
+
+use crate::clocks::Profile;
+
+pub const CONFIRM_MS: u64 = 10_000;
+
+/// Value a provisional change goes back to
+#[derive(Clone, Copy)]
+pub enum Previous {
+    Baud(u32),
+    Profile(Profile),
+}
+
+impl Previous {
+    /// Command that made the change
+    pub fn command(self) -> &'static str {
+        match self {
+            Previous::Baud(_) => "baud",
+            Previous::Profile(_) => "powerprofile",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RollbackError {
+    /// Another setting waits for its confirm
+    Pending,
+}
+
+impl RollbackError {
+    pub fn message(self) -> &'static str {
+        match self {
+            RollbackError::Pending => "confirm or revert the pending change first",
+        }
+    }
+}
+
+pub struct Rollback {
+    /// Bumped on each change, so the wakeup of a confirmed change is ignored
+    id: u16,
+    pending: Option<Previous>,
+    deadline_ms: u64,
+    due: bool,
+}
+
+impl Rollback {
+    pub fn new() -> Self {
+        Self {
+            id: 0,
+            pending: None,
+            deadline_ms: 0,
+            due: false,
+        }
+    }
+
+    /// Opens the confirm window and returns the id and time of the wakeup to schedule.
+    /// Changing the same setting again inside the window needs no wakeup: the value
+    /// from before the first change and its deadline stay, so a rollback always lands
+    /// on the last confirmed state.
+    pub fn arm(
+        &mut self,
+        previous: Previous,
+        now_ms: u64,
+    ) -> Result<Option<(u16, u64)>, RollbackError> {
+        match self.pending {
+            Some(pending) if pending.command() != previous.command() => Err(RollbackError::Pending),
+            Some(_) => Ok(None),
+            None => {
+                self.id = self.id.wrapping_add(1);
+                self.pending = Some(previous);
+                self.deadline_ms = now_ms + CONFIRM_MS;
+                self.due = false;
+                Ok(Some((self.id, self.deadline_ms)))
+            }
+        }
+    }
+
+    pub fn pending(&self) -> Option<Previous> {
+        self.pending
+    }
+
+    pub fn remaining_ms(&self, now_ms: u64) -> Option<u64> {
+        self.pending
+            .map(|_| self.deadline_ms.saturating_sub(now_ms))
+    }
+
+    /// Keeps the change, false with nothing pending
+    pub fn confirm(&mut self) -> bool {
+        self.due = false;
+        self.pending.take().is_some()
+    }
+
+    /// Value to restore right away
+    pub fn revert(&mut self) -> Option<Previous> {
+        self.due = false;
+        self.pending.take()
+    }
+
+    /// Wakeup at the end of the window
+    pub fn expire(&mut self, id: u16) {
+        if id == self.id && self.pending.is_some() {
+            self.due = true;
+        }
+    }
+
+    /// Value to restore once the window closed unconfirmed
+    pub fn take_due(&mut self) -> Option<Previous> {
+        if core::mem::take(&mut self.due) {
+            self.pending.take()
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use core::cell::Cell;
+
+use cortex_m::interrupt::{self, Mutex};
+use hal::stm32;
+
+const RTCSEL_LSI: u8 = 0b10;
+/// Prescalers for the 32kHz LSI: 125Hz ck_apre, 1Hz ck_spre
+const PREDIV_A: u8 = 127;
+const PREDIV_S: u16 = 249;
+/// Wakeup timer clock in Hertz with RTC/16 selected
+const WAKEUP_HZ: u32 = 32_000 / 16;
+
+pub const DAY_MS: u32 = 24 * 60 * 60 * 1000;
+/// Longest wakeup timer period
+pub const MAX_WAKEUP_MS: u32 = 0xffff * 1000 / WAKEUP_HZ;
+/// Smooth calibration masks up to 511 of 2^20 clock pulses or adds 512 minus that
+const CAL_CYCLE: i64 = 1 << 20;
+const CALP_PULSES: i32 = 512;
+/// Correction range of the smooth calibration, rounded in
+pub const MAX_CAL_PPM: i32 = 487;
+
+/// Reference and RTC milliseconds at the start of a drift measurement
+static DRIFT_START: Mutex<Cell<Option<(u64, u32)>>> = Mutex::new(Cell::new(None));
+
+/// Starts the RTC from LSI, keeps the calendar running if it was already configured
+pub fn init() {
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+
+    rcc.csr.modify(|_, w| w.lsion().set_bit());
+    while rcc.csr.read().lsirdy().bit_is_clear() {}
+    if rcc.bdcr.read().rtcsel().bits() == 0 {
+        rcc.bdcr
+            .modify(|_, w| unsafe { w.rtcsel().bits(RTCSEL_LSI).rtcen().set_bit() });
+    }
+
+    unlock();
+    if rtc.prer.read().prediv_s().bits() != PREDIV_S {
+        rtc.icsr.modify(|_, w| w.init().set_bit());
+        while rtc.icsr.read().initf().bit_is_clear() {}
+        rtc.prer
+            .write(|w| unsafe { w.prediv_a().bits(PREDIV_A).prediv_s().bits(PREDIV_S) });
+        rtc.icsr.modify(|_, w| w.init().clear_bit());
+    }
+    // Read counters directly, shadow registers are stale right after Stop mode
+    rtc.cr.modify(|_, w| w.bypshad().set_bit());
+    lock();
+}
+
+/// Calendar date and time of the RTC, years 2000 to 2099
+#[derive(Clone, Copy, PartialEq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Parses `YYYY-MM-DD` and `HH:MM:SS`
+    pub fn parse(date: &str, time: &str) -> Option<Self> {
+        let mut date = date
+            .splitn(3, '-')
+            .map(|part| btoi::btou::<u16>(part.as_bytes()).ok());
+        let mut time = time
+            .splitn(3, ':')
+            .map(|part| btoi::btou::<u8>(part.as_bytes()).ok());
+        let dt = Self {
+            year: date.next()??,
+            month: date.next()?? as u8,
+            day: date.next()?? as u8,
+            hour: time.next()??,
+            minute: time.next()??,
+            second: time.next()??,
+        };
+        let valid = (2000..=2099).contains(&dt.year)
+            && (1..=12).contains(&dt.month)
+            && (1..=dt.days_in_month()).contains(&dt.day)
+            && dt.hour < 24
+            && dt.minute < 60
+            && dt.second < 60;
+        valid.then_some(dt)
+    }
+
+    fn days_in_month(&self) -> u8 {
+        match self.month {
+            2 if self.year.is_multiple_of(4) => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    /// ISO weekday, 1 is Monday, by Sakamoto's method
+    fn weekday(&self) -> u8 {
+        const OFFSETS: [u16; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+        let year = self.year - (self.month < 3) as u16;
+        let day = (year + year / 4 - year / 100
+            + year / 400
+            + OFFSETS[self.month as usize - 1]
+            + self.day as u16)
+            % 7;
+        if day == 0 {
+            7
+        } else {
+            day as u8
+        }
+    }
+}
+
+fn from_bcd(tens: u32, units: u32) -> u8 {
+    (tens * 10 + units) as u8
+}
+
+fn to_bcd(value: u8) -> u32 {
+    (((value / 10) << 4) | (value % 10)) as u32
+}
+
+/// False until the calendar was set once, it then keeps running across resets and
+/// Standby as long as the backup domain has power
+pub fn is_set() -> bool {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    rtc.icsr.read().inits().bit_is_set()
+}
+
+pub fn now() -> DateTime {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    // Shadow registers are bypassed, read twice so midnight cannot tear date and time
+    let (tr, dr) = loop {
+        let tr = rtc.tr.read().bits();
+        let dr = rtc.dr.read().bits();
+        if rtc.tr.read().bits() == tr && rtc.dr.read().bits() == dr {
+            break (tr, dr);
+        }
+    };
+    let field = |reg: u32, shift: u32, mask: u32| reg >> shift & mask;
+    DateTime {
+        year: 2000 + from_bcd(field(dr, 20, 0xf), field(dr, 16, 0xf)) as u16,
+        month: from_bcd(field(dr, 12, 0x1), field(dr, 8, 0xf)),
+        day: from_bcd(field(dr, 4, 0x3), field(dr, 0, 0xf)),
+        hour: from_bcd(field(tr, 20, 0x3), field(tr, 16, 0xf)),
+        minute: from_bcd(field(tr, 12, 0x7), field(tr, 8, 0xf)),
+        second: from_bcd(field(tr, 4, 0x7), field(tr, 0, 0xf)),
+    }
+}
+
+/// Sets the calendar, a running drift measurement starts over
+pub fn set(dt: &DateTime) {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    let tr = to_bcd(dt.hour) << 16 | to_bcd(dt.minute) << 8 | to_bcd(dt.second);
+    let dr = to_bcd((dt.year - 2000) as u8) << 16
+        | (dt.weekday() as u32) << 13
+        | to_bcd(dt.month) << 8
+        | to_bcd(dt.day);
+
+    unlock();
+    rtc.icsr.modify(|_, w| w.init().set_bit());
+    while rtc.icsr.read().initf().bit_is_clear() {}
+    rtc.tr.write(|w| unsafe { w.bits(tr) });
+    rtc.dr.write(|w| unsafe { w.bits(dr) });
+    rtc.icsr.modify(|_, w| w.init().clear_bit());
+    lock();
+    drift_reset();
+}
+
+/// ALRMAR MSK4, the alarm ignores the date and fires every day
+const ALRMAR_MSK4: u32 = 1 << 31;
+
+/// Arms alarm A for `hour:minute:00` every day
+pub fn set_alarm(hour: u8, minute: u8) {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    let alrmar = ALRMAR_MSK4 | to_bcd(hour) << 16 | to_bcd(minute) << 8;
+
+    unlock();
+    rtc.cr
+        .modify(|_, w| w.alrae().clear_bit().alraie().clear_bit());
+    while rtc.icsr.read().alrawf().bit_is_clear() {}
+    rtc.alrmar.write(|w| unsafe { w.bits(alrmar) });
+    rtc.scr.write(|w| w.calraf().set_bit());
+    rtc.cr.modify(|_, w| w.alraie().set_bit().alrae().set_bit());
+    lock();
+}
+
+pub fn clear_alarm() {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    unlock();
+    rtc.cr
+        .modify(|_, w| w.alrae().clear_bit().alraie().clear_bit());
+    rtc.scr.write(|w| w.calraf().set_bit());
+    lock();
+}
+
+/// Hour and minute alarm A is armed for
+pub fn alarm() -> Option<(u8, u8)> {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    if rtc.cr.read().alrae().bit_is_clear() {
+        return None;
+    }
+    let alrmar = rtc.alrmar.read().bits();
+    let field = |shift: u32, mask: u32| alrmar >> shift & mask;
+    Some((
+        from_bcd(field(20, 0x3), field(16, 0xf)),
+        from_bcd(field(12, 0x7), field(8, 0xf)),
+    ))
+}
+
+pub fn alarm_fired() -> bool {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    rtc.sr.read().alraf().bit_is_set()
+}
+
+/// Clears a fired alarm, false when the interrupt came from the wakeup timer
+pub fn take_alarm() -> bool {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    if !alarm_fired() {
+        return false;
+    }
+    rtc.scr.write(|w| w.calraf().set_bit());
+    true
+}
+
+/// Milliseconds since midnight
+pub fn millis() -> u32 {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    let (tr, ss) = loop {
+        let ss = rtc.ssr.read().ss().bits() as u32;
+        let tr = rtc.tr.read();
+        if rtc.ssr.read().ss().bits() as u32 == ss && rtc.tr.read().bits() == tr.bits() {
+            break (tr, ss);
+        }
+    };
+    let hours = tr.ht().bits() as u32 * 10 + tr.hu().bits() as u32;
+    let minutes = tr.mnt().bits() as u32 * 10 + tr.mnu().bits() as u32;
+    let seconds = tr.st().bits() as u32 * 10 + tr.su().bits() as u32;
+    let sub_ms = (PREDIV_S as u32 - ss) * 1000 / (PREDIV_S as u32 + 1);
+    ((hours * 60 + minutes) * 60 + seconds) * 1000 + sub_ms
+}
+
+/// Arms the wakeup timer interrupt to fire after `ms`
+pub fn set_wakeup(ms: u32) {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    let reload = (ms.min(MAX_WAKEUP_MS) * WAKEUP_HZ / 1000).max(1) - 1;
+
+    unlock();
+    rtc.cr.modify(|_, w| w.wute().clear_bit());
+    while rtc.icsr.read().wutwf().bit_is_clear() {}
+    rtc.wutr.write(|w| unsafe { w.bits(reload) });
+    rtc.scr.write(|w| w.cwutf().set_bit());
+    rtc.cr
+        .modify(|_, w| unsafe { w.wucksel().bits(0b000).wutie().set_bit().wute().set_bit() });
+    lock();
+}
+
+pub fn clear_wakeup() {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    unlock();
+    rtc.cr
+        .modify(|_, w| w.wute().clear_bit().wutie().clear_bit());
+    rtc.scr.write(|w| w.cwutf().set_bit());
+    lock();
+}
+
+/// Correction of the smooth calibration in ppm, positive speeds the RTC up
+pub fn calibration_ppm() -> i32 {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    let calr = rtc.calr.read();
+    let pulses = calr.calp().bit_is_set() as i32 * CALP_PULSES - calr.calm().bits() as i32;
+    (pulses as i64 * 1_000_000 / CAL_CYCLE) as i32
+}
+
+/// Applies a correction in ppm over the 32s calibration cycle, clamped to `MAX_CAL_PPM`
+pub fn set_calibration(ppm: i32) {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    let ppm = ppm.clamp(-MAX_CAL_PPM, MAX_CAL_PPM) as i64;
+    let pulses = ((ppm * CAL_CYCLE + ppm.signum() * 500_000) / 1_000_000) as i32;
+    let (calp, calm) = if pulses > 0 {
+        (true, CALP_PULSES - pulses)
+    } else {
+        (false, -pulses)
+    };
+
+    unlock();
+    while rtc.icsr.read().recalpf().bit_is_set() {}
+    rtc.calr
+        .write(|w| unsafe { w.calp().bit(calp).calm().bits(calm as u16) });
+    lock();
+}
+
+/// Notes the time of a trusted reference, a GPS PPS or the host clock, in milliseconds.
+/// The first mark starts the measurement, later ones return how far the RTC ran ahead
+/// of the reference since then in ppm. Marks must stay within a day of the first.
+pub fn drift_mark(ref_ms: u64) -> Option<i32> {
+    let now = millis();
+    interrupt::free(|cs| {
+        let start = DRIFT_START.borrow(cs);
+        let (ref_start, rtc_start) = match start.get() {
+            Some(start) => start,
+            None => {
+                start.set(Some((ref_ms, now)));
+                return None;
+            }
+        };
+        let ref_elapsed = ref_ms.checked_sub(ref_start).filter(|ms| *ms > 0)?;
+        let rtc_elapsed = ((now + DAY_MS - rtc_start) % DAY_MS) as i64;
+        Some(((rtc_elapsed - ref_elapsed as i64) * 1_000_000 / ref_elapsed as i64) as i32)
+    })
+}
+
+/// RTC time since the drift measurement started
+pub fn drift_span_ms() -> Option<u32> {
+    let now = millis();
+    interrupt::free(|cs| DRIFT_START.borrow(cs).get())
+        .map(|(_, rtc_start)| (now + DAY_MS - rtc_start) % DAY_MS)
+}
+
+pub fn drift_reset() {
+    interrupt::free(|cs| DRIFT_START.borrow(cs).set(None));
+}
+
+fn unlock() {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    rtc.wpr.write(|w| unsafe { w.bits(0xca) });
+    rtc.wpr.write(|w| unsafe { w.bits(0x53) });
+}
+
+fn lock() {
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+    rtc.wpr.write(|w| unsafe { w.bits(0xff) });
+}
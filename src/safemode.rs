@@ -0,0 +1,63 @@
+//! Escape hatch for a board a stored script or driver keeps breaking. Safe mode skips
+//! the `rc` script and the driver probe and only takes the commands needed to look
+//! around and clean up, until the next reset.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use hal::hal::serial::Read;
+
+use crate::backup::{self, Slot};
+use crate::cycles;
+
+/// Left in the Standby slot by `reboot safe`, resume rejects it as a Standby state
+const SAFE_MARKER: u32 = 0x5afe_0000;
+/// Holding this key during the first second after reset enters safe mode
+pub const KEY: u8 = b's';
+const WINDOW_MS: u32 = 1000;
+/// Commands safe mode keeps
+pub const COMMANDS: [&str; 13] = [
+    "clear", "coredump", "defaults", "dfu", "help", "info", "metrics", "rc", "reboot", "record",
+    "save", "uptime", "version",
+];
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Makes the next reset start in safe mode
+pub fn request() {
+    backup::write(Slot::Standby, SAFE_MARKER);
+}
+
+/// True once after `request`
+pub fn take_request() -> bool {
+    let requested = backup::read(Slot::Standby) == SAFE_MARKER;
+    if requested {
+        backup::write(Slot::Standby, 0);
+    }
+    requested
+}
+
+/// Watches the shell USART for `KEY` over the first second, a held key repeats. Runs
+/// from init before the UART is handed to DMA.
+pub fn key_held<S: Read<u8>>(serial: &mut S) -> bool {
+    let window = cycles::freq() / 1000 * WINDOW_MS;
+    let start = cycles::now();
+    while cycles::since(start) < window {
+        if let Ok(KEY) = serial.read() {
+            return true;
+        }
+    }
+    false
+}
+
+pub fn enter() {
+    ACTIVE.store(true, Ordering::Relaxed);
+}
+
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Whether `cmd` runs, every command does outside safe mode
+pub fn allows(cmd: &str) -> bool {
+    !is_active() || COMMANDS.contains(&cmd)
+}
@@ -0,0 +1,253 @@
+use core::fmt::Write;
+
+use heapless::String;
+
+use crate::config::CMD_MAX_LEN;
+use crate::flash::{self, FlashError, Page, PAGE_SIZE};
+use crate::mono;
+
+pub const MAX_SCRIPTS: usize = 4;
+pub const NAME_LEN: usize = 16;
+const SLOT_SIZE: usize = PAGE_SIZE / MAX_SCRIPTS;
+/// Slot header: NUL padded name, then the text length as u16
+const HEADER_LEN: usize = NAME_LEN + 2;
+pub const SCRIPT_LEN: usize = SLOT_SIZE - HEADER_LEN;
+/// Script replayed after a reset, `rc` edits it a line at a time
+pub const RC_SCRIPT: &str = "rc";
+/// Scripts may run other scripts this deep, which also stops a script running itself
+pub const MAX_DEPTH: u8 = 4;
+/// Lines one `run` may execute and how long it may take, the scripts it runs included.
+/// Nesting alone lets a few short scripts fan out to thousands of lines.
+pub const MAX_COMMANDS: u16 = 200;
+pub const MAX_RUN_MS: u32 = 10_000;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScriptError {
+    BadName,
+    Full,
+    NoSlot,
+    NotFound,
+    TooDeep,
+    TooManyCommands,
+    TooSlow,
+    MissingArg,
+    LineTooLong,
+    Flash(FlashError),
+}
+
+impl ScriptError {
+    pub fn message(self) -> &'static str {
+        match self {
+            ScriptError::BadName => "script name must be 1 to 16 characters",
+            ScriptError::Full => "script is full",
+            ScriptError::NoSlot => "no free script slot",
+            ScriptError::NotFound => "no such script",
+            ScriptError::TooDeep => "scripts nested too deep",
+            ScriptError::TooManyCommands => "script ran too many commands",
+            ScriptError::TooSlow => "script ran too long",
+            ScriptError::MissingArg => "missing script argument",
+            ScriptError::LineTooLong => "expanded line too long",
+            ScriptError::Flash(err) => err.message(),
+        }
+    }
+
+    /// The limit behind a `TooManyCommands` or `TooSlow` error, for the diagnostic
+    pub fn write_limit(self, out: &mut dyn Write) {
+        match self {
+            ScriptError::TooManyCommands => write!(out, " ({} max)", MAX_COMMANDS),
+            ScriptError::TooSlow => write!(out, " ({}ms max)", MAX_RUN_MS),
+            _ => Ok(()),
+        }
+        .ok();
+    }
+}
+
+/// Named command scripts in a flash page, one newline separated script per slot
+pub struct Scripts {
+    recording: Option<(String<NAME_LEN>, String<SCRIPT_LEN>)>,
+    depth: u8,
+    /// Lines run and monotonic start of the outermost script, the core clock may change
+    /// speed while it runs
+    commands: u16,
+    start_ms: u64,
+}
+
+impl Scripts {
+    pub fn new() -> Self {
+        Self {
+            recording: None,
+            depth: 0,
+            commands: 0,
+            start_ms: 0,
+        }
+    }
+
+    /// Name of the script being recorded
+    pub fn recording(&self) -> Option<&str> {
+        self.recording.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    pub fn start(&mut self, name: &str) -> Result<(), ScriptError> {
+        check_name(name)?;
+        self.recording = Some((name.into(), String::new()));
+        Ok(())
+    }
+
+    /// Appends a command line to the recording
+    pub fn record(&mut self, line: &str) -> Result<(), ScriptError> {
+        let (_, text) = self.recording.as_mut().ok_or(ScriptError::NotFound)?;
+        if text.len() + line.len() + 1 > SCRIPT_LEN {
+            return Err(ScriptError::Full);
+        }
+        text.push_str(line).ok();
+        text.push('\n').ok();
+        Ok(())
+    }
+
+    /// Ends the recording and saves it, replacing a script of the same name
+    pub fn stop(&mut self) -> Result<usize, ScriptError> {
+        let (name, text) = self.recording.take().ok_or(ScriptError::NotFound)?;
+        Self::save(Self::slot_for(&name)?, &name, &text)?;
+        Ok(text.lines().count())
+    }
+
+    /// Adds a line to the end of a saved script, which is created when missing, and
+    /// returns its line count
+    pub fn append(name: &str, line: &str) -> Result<usize, ScriptError> {
+        check_name(name)?;
+        let slot = Self::slot_for(name)?;
+        let mut text: String<SCRIPT_LEN> = String::new();
+        if Self::name(slot).is_some() {
+            text.push_str(Self::text(slot)).ok();
+        }
+        if text.len() + line.len() + 1 > SCRIPT_LEN {
+            return Err(ScriptError::Full);
+        }
+        text.push_str(line).ok();
+        text.push('\n').ok();
+        Self::save(slot, name, &text)?;
+        Ok(text.lines().count())
+    }
+
+    /// Marks a script as running, fails once scripts nest `MAX_DEPTH` deep
+    pub fn enter(&mut self) -> Result<(), ScriptError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(ScriptError::TooDeep);
+        }
+        if self.depth == 0 {
+            self.commands = 0;
+            self.start_ms = mono::now_ms();
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// Counts a line about to run. Fails once the outermost script is over
+    /// `MAX_COMMANDS` or `MAX_RUN_MS`, and keeps failing for every script it runs, so
+    /// the whole nest stops.
+    pub fn step(&mut self) -> Result<(), ScriptError> {
+        if self.commands >= MAX_COMMANDS {
+            return Err(ScriptError::TooManyCommands);
+        }
+        if mono::now_ms() - self.start_ms > MAX_RUN_MS as u64 {
+            return Err(ScriptError::TooSlow);
+        }
+        self.commands += 1;
+        Ok(())
+    }
+
+    pub fn leave(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    pub fn delete(name: &str) -> Result<(), ScriptError> {
+        let slot = Self::slot_of(name).ok_or(ScriptError::NotFound)?;
+        Self::save(slot, "", "")
+    }
+
+    /// Saved script text
+    pub fn find(name: &str) -> Option<&'static str> {
+        Self::slot_of(name).map(Self::text)
+    }
+
+    /// Names and sizes of the saved scripts
+    pub fn list() -> impl Iterator<Item = (&'static str, usize)> {
+        (0..MAX_SCRIPTS)
+            .filter_map(|slot| Self::name(slot).map(|name| (name, Self::text(slot).len())))
+    }
+
+    fn slot_of(name: &str) -> Option<usize> {
+        (0..MAX_SCRIPTS).find(|slot| Self::name(*slot) == Some(name))
+    }
+
+    /// Slot of the script, or a free one for a new script
+    fn slot_for(name: &str) -> Result<usize, ScriptError> {
+        Self::slot_of(name)
+            .or_else(|| (0..MAX_SCRIPTS).find(|slot| Self::name(*slot).is_none()))
+            .ok_or(ScriptError::NoSlot)
+    }
+
+    fn name(slot: usize) -> Option<&'static str> {
+        let header = &flash::read(Page::Scripts)[slot * SLOT_SIZE..][..NAME_LEN];
+        if header[0] == 0xff || header[0] == 0 {
+            return None;
+        }
+        let len = header.iter().position(|c| *c == 0).unwrap_or(NAME_LEN);
+        core::str::from_utf8(&header[..len]).ok()
+    }
+
+    fn text(slot: usize) -> &'static str {
+        let slot = &flash::read(Page::Scripts)[slot * SLOT_SIZE..][..SLOT_SIZE];
+        let len = u16::from_le_bytes([slot[NAME_LEN], slot[NAME_LEN + 1]]) as usize;
+        core::str::from_utf8(&slot[HEADER_LEN..][..len.min(SCRIPT_LEN)]).unwrap_or("")
+    }
+
+    /// Rewrites the page with one slot changed, an empty name frees the slot
+    fn save(slot: usize, name: &str, text: &str) -> Result<(), ScriptError> {
+        let mut page = [0xff; PAGE_SIZE];
+        page.copy_from_slice(flash::read(Page::Scripts));
+        let dst = &mut page[slot * SLOT_SIZE..][..SLOT_SIZE];
+        dst.fill(0xff);
+        if !name.is_empty() {
+            dst[..NAME_LEN].fill(0);
+            dst[..name.len()].copy_from_slice(name.as_bytes());
+            dst[NAME_LEN..HEADER_LEN].copy_from_slice(&(text.len() as u16).to_le_bytes());
+            dst[HEADER_LEN..][..text.len()].copy_from_slice(text.as_bytes());
+        }
+        flash::write(Page::Scripts, &page).map_err(ScriptError::Flash)
+    }
+}
+
+fn check_name(name: &str) -> Result<(), ScriptError> {
+    if name.is_empty() || name.len() > NAME_LEN || name.contains(' ') {
+        return Err(ScriptError::BadName);
+    }
+    Ok(())
+}
+
+/// Replaces `$1`..`$9` in a script line with space separated arguments, `$$` is a
+/// literal `$`
+pub fn expand(line: &str, args: &str) -> Result<String<CMD_MAX_LEN>, ScriptError> {
+    let mut out = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        let push = match c {
+            '$' => match chars.next() {
+                Some('$') => out.push('$'),
+                Some(n @ '1'..='9') => {
+                    let idx = n as usize - '1' as usize;
+                    let arg = args
+                        .split_whitespace()
+                        .nth(idx)
+                        .ok_or(ScriptError::MissingArg)?;
+                    out.push_str(arg)
+                }
+                Some(other) => out.push('$').and_then(|_| out.push(other)),
+                None => out.push('$'),
+            },
+            _ => out.push(c),
+        };
+        push.map_err(|_| ScriptError::LineTooLong)?;
+    }
+    Ok(out)
+}
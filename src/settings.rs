@@ -0,0 +1,264 @@
+//! Board settings kept across resets in their own flash page. Every save appends a
+//! record after the previous one and only a full page is erased, so the page wears
+//! once per `SLOTS` saves. The last intact record wins. Shell history is saved the same
+//! way in a page of its own. The settings page also holds the signing nonce reservation,
+//! an erase puts the latest record of the other kind back first.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{self, Mutex};
+use heapless::{String, Vec};
+
+use crate::config::{CMD_MAX_LEN, DEFAULT_BLINK_FREQ, HISTORY_LEN};
+use crate::flash::{self, FlashError, Page, PAGE_SIZE};
+use crate::telemetry::crc16;
+
+/// Prompt text, the shell adds a space after it
+pub const PROMPT_LEN: usize = 15;
+const DEFAULT_PROMPT: &str = "#>";
+pub const MAX_BLINK_FREQ: u8 = 100;
+/// Marks a settings record, the low byte is the layout version
+const MAGIC: u32 = 0x5e77_0001;
+/// Magic, blink frequency, enabled flag, prompt length and NUL padded prompt, CRC-16 of
+/// the preceding bytes, padded to whole double words for programming
+const DATA_LEN: usize = 4 + 3 + PROMPT_LEN;
+const RECORD_LEN: usize = 32;
+pub const SLOTS: usize = PAGE_SIZE / RECORD_LEN;
+/// Marks a nonce reservation record: magic, highest reserved nonce, CRC-16
+const NONCE_MAGIC: u32 = 0x0ce5_0001;
+const NONCE_DATA_LEN: usize = 8;
+const HISTORY_MAGIC: u32 = 0x4157_0001;
+/// Magic, line count, then a length byte and NUL padded text per line, oldest first
+const HISTORY_DATA_LEN: usize = 4 + 1 + HISTORY_LEN * (1 + CMD_MAX_LEN);
+const HISTORY_RECORD_LEN: usize = (HISTORY_DATA_LEN + 2).div_ceil(8) * 8;
+
+/// Shell history lines, oldest first
+pub type HistoryLines = Vec<String<CMD_MAX_LEN>, HISTORY_LEN>;
+
+static PROMPT: Mutex<RefCell<String<PROMPT_LEN>>> = Mutex::new(RefCell::new(String::new()));
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SettingsError {
+    Prompt,
+    Flash(FlashError),
+}
+
+impl SettingsError {
+    pub fn message(self) -> &'static str {
+        match self {
+            SettingsError::Prompt => "prompt must be 1-15 printable characters",
+            SettingsError::Flash(err) => err.message(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Settings {
+    pub blink_freq: u8,
+    pub blink_enabled: bool,
+    pub prompt: String<PROMPT_LEN>,
+}
+
+impl Settings {
+    pub fn defaults() -> Self {
+        Self {
+            blink_freq: DEFAULT_BLINK_FREQ,
+            blink_enabled: false,
+            prompt: String::from(DEFAULT_PROMPT),
+        }
+    }
+
+    fn pack(&self) -> [u8; RECORD_LEN] {
+        let mut record = [0; RECORD_LEN];
+        record[..4].copy_from_slice(&MAGIC.to_le_bytes());
+        record[4] = self.blink_freq;
+        record[5] = self.blink_enabled as u8;
+        record[6] = self.prompt.len() as u8;
+        record[7..][..self.prompt.len()].copy_from_slice(self.prompt.as_bytes());
+        let crc = crc16(&record[..DATA_LEN]);
+        record[DATA_LEN..][..2].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    fn unpack(record: &[u8]) -> Option<Self> {
+        let magic = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+        let crc = u16::from_le_bytes([record[DATA_LEN], record[DATA_LEN + 1]]);
+        if magic != MAGIC || crc != crc16(&record[..DATA_LEN]) {
+            return None;
+        }
+        let prompt = core::str::from_utf8(record[7..].get(..record[6] as usize)?).ok()?;
+        if !is_valid_prompt(prompt) || !(1..=MAX_BLINK_FREQ).contains(&record[4]) {
+            return None;
+        }
+        Some(Self {
+            blink_freq: record[4],
+            blink_enabled: record[5] != 0,
+            prompt: String::from(prompt),
+        })
+    }
+}
+
+fn is_valid_prompt(prompt: &str) -> bool {
+    (1..=PROMPT_LEN).contains(&prompt.len())
+        && prompt.bytes().all(|b| b.is_ascii_graphic() || b == b' ')
+}
+
+fn is_blank(record: &[u8]) -> bool {
+    record.iter().all(|b| *b == 0xff)
+}
+
+/// Records of a page written since it was last erased
+fn records(page: Page, len: usize) -> impl Iterator<Item = &'static [u8]> {
+    flash::read(page)
+        .chunks_exact(len)
+        .take_while(|record| !is_blank(record))
+}
+
+/// Appends a record after the last one. A full page is erased first and `keep` goes back
+/// in front of the record.
+fn append(page: Page, record: &[u8], keep: &[u8]) -> Result<(), SettingsError> {
+    let res = match records(page, record.len()).count() {
+        slot if slot < PAGE_SIZE / record.len() => {
+            flash::program(page, slot * record.len(), record)
+        }
+        _ => flash::write(page, keep).and_then(|_| flash::program(page, keep.len(), record)),
+    };
+    res.map_err(SettingsError::Flash)
+}
+
+/// Copy of the last record on the settings page that `valid` accepts, the page may be
+/// erased while it is held
+fn last_record(valid: fn(&[u8]) -> bool) -> Option<[u8; RECORD_LEN]> {
+    let record = records(Page::Settings, RECORD_LEN)
+        .filter(|record| valid(record))
+        .last()?;
+    let mut copy = [0; RECORD_LEN];
+    copy.copy_from_slice(record);
+    Some(copy)
+}
+
+/// Records written since the page was last erased
+pub fn used_slots() -> usize {
+    records(Page::Settings, RECORD_LEN).count()
+}
+
+/// Last intact record, `None` while nothing was saved
+pub fn load() -> Option<Settings> {
+    records(Page::Settings, RECORD_LEN)
+        .filter_map(Settings::unpack)
+        .last()
+}
+
+pub fn save(settings: &Settings) -> Result<(), SettingsError> {
+    let nonce = last_record(|record| unpack_nonce(record).is_some());
+    append(
+        Page::Settings,
+        &settings.pack(),
+        nonce.as_ref().map_or(&[], |r| &r[..]),
+    )
+}
+
+fn unpack_nonce(record: &[u8]) -> Option<u32> {
+    let magic = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+    let crc = u16::from_le_bytes([record[NONCE_DATA_LEN], record[NONCE_DATA_LEN + 1]]);
+    if magic != NONCE_MAGIC || crc != crc16(&record[..NONCE_DATA_LEN]) {
+        return None;
+    }
+    Some(u32::from_le_bytes([
+        record[4], record[5], record[6], record[7],
+    ]))
+}
+
+/// Highest signing nonce reserved so far, 0 before the first signed command
+pub fn nonce_ceiling() -> u32 {
+    records(Page::Settings, RECORD_LEN)
+        .filter_map(unpack_nonce)
+        .last()
+        .unwrap_or(0)
+}
+
+/// Notes that nonces up to `ceiling` may be accepted, before the first of them is
+pub fn reserve_nonces(ceiling: u32) -> Result<(), SettingsError> {
+    let mut record = [0; RECORD_LEN];
+    record[..4].copy_from_slice(&NONCE_MAGIC.to_le_bytes());
+    record[4..8].copy_from_slice(&ceiling.to_le_bytes());
+    let crc = crc16(&record[..NONCE_DATA_LEN]);
+    record[NONCE_DATA_LEN..][..2].copy_from_slice(&crc.to_le_bytes());
+    let settings = last_record(|record| Settings::unpack(record).is_some());
+    append(
+        Page::Settings,
+        &record,
+        settings.as_ref().map_or(&[], |r| &r[..]),
+    )
+}
+
+fn pack_history(lines: &[String<CMD_MAX_LEN>]) -> [u8; HISTORY_RECORD_LEN] {
+    let mut record = [0; HISTORY_RECORD_LEN];
+    record[..4].copy_from_slice(&HISTORY_MAGIC.to_le_bytes());
+    let lines = &lines[lines.len().saturating_sub(HISTORY_LEN)..];
+    record[4] = lines.len() as u8;
+    for (line, slot) in lines.iter().zip(record[5..].chunks_mut(1 + CMD_MAX_LEN)) {
+        slot[0] = line.len() as u8;
+        slot[1..][..line.len()].copy_from_slice(line.as_bytes());
+    }
+    let crc = crc16(&record[..HISTORY_DATA_LEN]);
+    record[HISTORY_DATA_LEN..][..2].copy_from_slice(&crc.to_le_bytes());
+    record
+}
+
+fn unpack_history(record: &[u8]) -> Option<HistoryLines> {
+    let magic = u32::from_le_bytes([record[0], record[1], record[2], record[3]]);
+    let crc = u16::from_le_bytes([record[HISTORY_DATA_LEN], record[HISTORY_DATA_LEN + 1]]);
+    if magic != HISTORY_MAGIC || crc != crc16(&record[..HISTORY_DATA_LEN]) {
+        return None;
+    }
+    let mut lines = Vec::new();
+    for slot in record[5..].chunks(1 + CMD_MAX_LEN).take(record[4] as usize) {
+        let line = core::str::from_utf8(slot[1..].get(..slot[0] as usize)?).ok()?;
+        lines.push(String::from(line)).ok()?;
+    }
+    Some(lines)
+}
+
+/// Last saved shell history, empty while nothing was saved
+pub fn load_history() -> HistoryLines {
+    records(Page::History, HISTORY_RECORD_LEN)
+        .filter_map(unpack_history)
+        .last()
+        .unwrap_or_default()
+}
+
+/// Keeps the shell history lines, oldest first, for the next boot
+pub fn save_history(lines: &[String<CMD_MAX_LEN>]) -> Result<(), SettingsError> {
+    append(Page::History, &pack_history(lines), &[])
+}
+
+/// Prompt text, the default until `set_prompt`
+pub fn prompt_text() -> String<PROMPT_LEN> {
+    let text = interrupt::free(|cs| PROMPT.borrow(cs).borrow().clone());
+    if text.is_empty() {
+        String::from(DEFAULT_PROMPT)
+    } else {
+        text
+    }
+}
+
+/// Prompt the shell prints, with the space after it
+pub fn prompt() -> String<{ PROMPT_LEN + 1 }> {
+    let mut prompt = String::new();
+    prompt.push_str(&prompt_text()).ok();
+    prompt.push(' ').ok();
+    prompt
+}
+
+pub fn set_prompt(text: &str) -> Result<(), SettingsError> {
+    if !is_valid_prompt(text) {
+        return Err(SettingsError::Prompt);
+    }
+    interrupt::free(|cs| {
+        let mut prompt = PROMPT.borrow(cs).borrow_mut();
+        prompt.clear();
+        prompt.push_str(text).ok();
+    });
+    Ok(())
+}
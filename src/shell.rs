@@ -0,0 +1,5344 @@
+use core::fmt::Write;
+
+use hal::hal::serial::{Read as _, Write as _};
+use hal::nb;
+use heapless::String;
+use rtic::time::Instant;
+use ushell::history::{History as _, LRUHistory};
+use ushell::{control, UShell};
+
+use crate::adcwatch::{self, Stats};
+use crate::aliases;
+use crate::audio::Envelope;
+use crate::bitbang::{self, BitbangError, Op};
+use crate::boot::{self, BootConfig, BootTarget};
+use crate::budget;
+use crate::build_info::BUILD_INFO;
+use crate::bundle::{self, BundleError};
+use crate::burst::Burst;
+use crate::cal::{self, CalError};
+use crate::calc;
+use crate::capture::{self, Capture};
+use crate::catalog;
+use crate::clkgate;
+use crate::clocks::{self, Profile, Speed, PROFILES};
+use crate::cobs;
+use crate::config::{CMD_MAX_LEN, HISTORY_LEN, TICK_HZ};
+use crate::coredump;
+use crate::countdown;
+use crate::counter::{EdgeCounter, Edges};
+use crate::cycles;
+use crate::dashboard;
+use crate::dma::DmaError;
+use crate::drivers::{self, BusKind};
+use crate::energy::{self, Estimate};
+use crate::flash;
+use crate::gpio::{self, GpioError, PinMode, PIN_MODES};
+use crate::health::{self, Health, ALARMS};
+use crate::hex;
+use crate::hsical::{self, HsiError};
+use crate::hw::{DriverError, State};
+use crate::i2c::{self, I2cError};
+use crate::jobs::{self, JobError};
+use crate::latency::{self, Stat};
+use crate::led::Owner;
+use crate::load::{LoadGen, Usage};
+use crate::mco::{self, McoError, Source};
+use crate::mem;
+use crate::metrics::{self, ExitStatus};
+use crate::monitor::{Monitor, Watch, WATCHES};
+use crate::mono;
+use crate::morse::{self, MorseError};
+use crate::motion::Motion;
+use crate::output::{Output, Stamp, STAMPS};
+use crate::pattern;
+use crate::pid::{self, Pid};
+use crate::pins::{self, PinError};
+use crate::port::{self, ShellPort};
+use crate::power::{PowerEvent, PVD_LEVELS_MV};
+use crate::provision;
+use crate::pwmout::{Channel, PwmOut};
+use crate::ranger::{RangeError, Ranger};
+use crate::resources::{self, Lock, RESOURCES, TASKS};
+use crate::rollback::{Previous, CONFIRM_MS};
+use crate::rtc::{self, DateTime};
+use crate::safemode;
+use crate::scripts::{self, ScriptError, Scripts, RC_SCRIPT};
+use crate::settings::{self, HistoryLines, Settings};
+use crate::signing::{self, SignError};
+use crate::slave;
+use crate::slider::{Key, Spec};
+use crate::sniff::{self, Sniff};
+use crate::spi::{self, SpiError};
+use crate::standby::{self, ResumeState};
+use crate::statusbar::{Edge, StatusBar};
+use crate::stopwatch;
+use crate::sweep::Target;
+use crate::switch::{self, Switches};
+use crate::sync::{BlinkSync, Mode, MODES};
+use crate::sysinfo;
+use crate::telemetry::{Sample, Telemetry, PACKET_LEN};
+use crate::thermostat::Thermostat;
+use crate::timesync;
+use crate::trace::{Direction, Traced};
+use crate::trigger::{self, Event, Trigger, EVENTS};
+use crate::ushell_demo::{
+    countdown_tick, job_due, monotonics, ring_step, rollback_due, shell_poll,
+};
+use crate::verbosity::{self, detail, say, Level, LEVELS};
+use crate::wave::{self, MAX_SAMPLES};
+
+pub type History = LRUHistory<{ CMD_MAX_LEN }, { HISTORY_LEN }>;
+pub type Uart = Output<Traced<ShellPort>>;
+pub type Shell = UShell<Uart, Autocomplete, History, { CMD_MAX_LEN }>;
+pub type Env<'a> = shell_poll::SharedResources<'a>;
+
+type SliderGet = fn(&mut Env) -> u32;
+type SliderSet = fn(&mut Env, u32);
+
+/// Values `adjust` can slide, each step is applied live
+const SLIDERS: [(Spec, SliderGet, SliderSet); 4] = [
+    (
+        Spec {
+            name: "brightness",
+            unit: "%",
+            min: 0,
+            max: 100,
+            step: 5,
+        },
+        |env| env.led.lock(|l| l.brightness()) as u32,
+        |env, value| env.led.lock(|l| l.set_brightness(value as u8)),
+    ),
+    (
+        Spec {
+            name: "duty",
+            unit: "%",
+            min: 0,
+            max: 100,
+            step: 1,
+        },
+        |env| env.pwmout.lock(|p| p.duty()) as u32,
+        |env, value| env.pwmout.lock(|p| p.set_duty(value as u8)),
+    ),
+    (
+        Spec {
+            name: "freq",
+            unit: "Hz",
+            min: 1,
+            max: 100,
+            step: 1,
+        },
+        |env| env.blink_freq.lock(|f| *f) as u32,
+        |env, value| env.set_blink_freq(value as u8),
+    ),
+    (
+        Spec {
+            name: "phase",
+            unit: "ms",
+            min: 0,
+            max: BlinkSync::MAX_PHASE_MS,
+            step: 10,
+        },
+        |env| env.blink_sync.lock(|s| s.phase_ms()),
+        |env, value| env.blink_sync.lock(|s| s.set_phase_ms(value)),
+    ),
+];
+
+/// How long `dfu` waits for the y/N answer
+const DFU_CONFIRM_S: u32 = 10;
+/// Command and subcommand pairs that take `--dry-run`, the ones whose only hardware
+/// effect is a flash write
+const DRY_RUN: [(&str, &str); 11] = [
+    ("bundle", "del"),
+    ("bundle", "set"),
+    ("cal", "write"),
+    ("coredump", "erase"),
+    ("hsical", "save"),
+    ("rc", "add"),
+    ("rc", "clear"),
+    ("record", "delete"),
+    ("save", ""),
+    ("sign", "off"),
+    ("sign", "on"),
+];
+
+pub const CR: &str = "\r\n";
+/// Banner and usage above the command list `help` builds from the catalog
+pub const HELP_HEADER: &str = "\r\n\
+\x1b[31mL\x1b[32mE\x1b[34mD \x1b[33mBlinky Shell \x1b[0mv.1\r\n\r\n\
+USAGE:\r\n\
+\tcommand [arg]\r\n\r\n\
+COMMANDS:\r\n\
+";
+pub const HELP_KEYS: &str = "\r\n\
+CONTROL KEYS:\r\n\
+\tCtrl+D    Start animation\r\n\
+\tCtrl+C    Stop animation\r\n\
+\tCtrl+S    Increment animation frequency\r\n\
+\tCtrl+X    Decrement animation frequency\r\n\
+";
+
+/// Completes from the command catalog first, then the aliases defined at runtime
+pub struct Autocomplete;
+
+impl ushell::autocomplete::Autocomplete<{ CMD_MAX_LEN }> for Autocomplete {
+    fn suggest(&self, prefix: &str) -> Option<String<CMD_MAX_LEN>> {
+        catalog::complete(prefix).or_else(|| aliases::suggest(prefix))
+    }
+}
+
+pub fn autocomplete() -> Autocomplete {
+    Autocomplete
+}
+
+/// Where a command line came from
+#[derive(Clone, Copy, PartialEq)]
+enum Origin {
+    /// Typed on the link, a dangerous command carries its signature
+    Typed,
+    /// Replayed from a script, job, alarm or countdown. A script runs signed with `run`,
+    /// the others check the signature when they store the line.
+    Stored,
+}
+
+/// Formatted output below the output arbiter, for cursor-addressed screens
+struct Raw<'a>(&'a mut Shell);
+
+impl Write for Raw<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        Env::write_raw(self.0, s.as_bytes());
+        Ok(())
+    }
+}
+
+impl Env<'_> {
+    /// Runs a command with flash writes held back, then lists the pages it would have
+    /// changed
+    fn dry_run(&mut self, shell: &mut Shell, cmd: &str, args: &str) {
+        let sub = args.split_whitespace().next().unwrap_or("");
+        if !DRY_RUN.contains(&(cmd, sub)) {
+            write!(shell, "{0:}{1:}: --dry-run is not supported{0:}", CR, cmd).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return;
+        }
+        flash::set_dry_run(true);
+        self.command(shell, cmd, args);
+        flash::set_dry_run(false);
+
+        let planned = flash::take_planned();
+        if planned.is_empty() {
+            write!(shell, "dry run: flash left as is{}", CR).ok();
+        }
+        for write in planned.iter() {
+            if write.changed == 0 {
+                write!(shell, "dry run: page {} unchanged{}", write.page, CR).ok();
+                continue;
+            }
+            write!(
+                shell,
+                "dry run: page {} at 0x{:08x}, {} bytes would change in 0x{:03x}..0x{:03x}{}",
+                write.page,
+                write.addr,
+                write.changed,
+                write.first,
+                write.last + 1,
+                CR
+            )
+            .ok();
+        }
+    }
+
+    /// Runs a typed command line, checking its signature in signed mode
+    pub fn dispatch(&mut self, shell: &mut Shell, cmd: &str, args: &str) {
+        self.run_line(shell, cmd, args, Origin::Typed);
+    }
+
+    /// Every command line goes through here, typed or replayed: safe mode, alias
+    /// expansion and the signature check
+    fn run_line(&mut self, shell: &mut Shell, cmd: &str, args: &str, origin: Origin) {
+        if !safemode::allows(cmd) {
+            write!(shell, "{0:}{1:}: not available in safe mode{0:}", CR, cmd).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return;
+        }
+        // Aliases expand before the signature check, so one can't hide a dangerous command
+        let expanded = match aliases::expand(cmd, args) {
+            Ok(expanded) => expanded,
+            Err(err) => {
+                write!(shell, "{0:}{1:}: {2:}{0:}", CR, cmd, err.message()).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+                return;
+            }
+        };
+        let (cmd, args) = match &expanded {
+            Some(line) => line.split_once(" ").unwrap_or((line, "")),
+            None => (cmd, args),
+        };
+        if !signing::is_dangerous(cmd) || !provision::signing() {
+            self.command(shell, cmd, args);
+            return;
+        }
+        let verified = match origin {
+            Origin::Typed => {
+                let (args, signature) = signing::split(args);
+                signing::verify(cmd, args, signature).map(|_| args)
+            }
+            // The line was checked when it was stored, but an alias can change since
+            Origin::Stored if expanded.is_some() => Err(SignError::Alias),
+            Origin::Stored => Ok(args),
+        };
+        match verified {
+            Ok(args) => self.command(shell, cmd, args),
+            Err(err) => {
+                write!(shell, "{0:}{1:}: {2:}{0:}", CR, cmd, err.message()).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+        }
+    }
+
+    /// Runs a line stored by a script, job, alarm or countdown
+    fn run_stored(&mut self, shell: &mut Shell, line: &str) {
+        let (cmd, args) = line.split_once(" ").unwrap_or((line, ""));
+        self.run_line(shell, cmd, args, Origin::Stored);
+    }
+
+    /// Runs a command against the budget set with `budget`. A handler can't be
+    /// preempted, busy-waits give up early and the overrun is reported once it returns.
+    pub fn command(&mut self, shell: &mut Shell, cmd: &str, args: &str) {
+        budget::enter();
+        self.execute(shell, cmd, args);
+        if let Some(elapsed_ms) = budget::leave(cmd) {
+            write!(shell, "{0:}error: command timed out{0:}", CR).ok();
+            detail!(shell, "{} took {}ms{}", cmd, elapsed_ms, CR);
+            metrics::set_exit_status(ExitStatus::Error);
+        }
+    }
+
+    fn execute(&mut self, shell: &mut Shell, cmd: &str, args: &str) {
+        if self.wave.lock(|w| w.is_uploading()) {
+            self.wave_upload(shell, cmd, args);
+            return;
+        }
+        if let Some(args) = strip_dry_run(args) {
+            self.dry_run(shell, cmd, &args);
+            return;
+        }
+        // An unlock only covers the calibration writes typed right after it
+        if cmd != "cal" && (cmd, args) != ("hsical", "save") {
+            cal::lock();
+        }
+        let recording = self.scripts.lock(|s| s.recording().is_some());
+        if cmd != "record" && recording && !flash::is_dry_run() {
+            self.record_line(shell, cmd, args);
+        }
+        self.trigger.lock(|t| t.fire_on(Event::Dispatch));
+        metrics::COMMANDS.inc();
+        metrics::set_exit_status(ExitStatus::Ok);
+        match catalog::find(cmd) {
+            Some(info) => (info.run)(self, shell, args),
+            None if cmd.is_empty() => {
+                shell.write_str(CR).ok();
+            }
+            None => {
+                if !self.hw.lock(|h| h.command(shell, cmd, args)) {
+                    write!(shell, "{0:}unsupported command{0:}", CR).ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            }
+        }
+        // Commands only flag bad arguments, the usage comes from the catalog
+        if metrics::exit_status() == ExitStatus::Usage {
+            if let Some(info) = catalog::find(cmd) {
+                catalog::write_usage(shell, info);
+            }
+        }
+    }
+
+    pub fn help_command(shell: &mut Shell) {
+        shell.write_str(HELP_HEADER).ok();
+        catalog::write_help(shell);
+        shell.write_str(HELP_KEYS).ok();
+    }
+
+    pub fn animation_command(&mut self, shell: &mut Shell, on: bool) {
+        self.blink_enabled.lock(|e| *e = on);
+        shell.write_str(CR).ok();
+        detail!(shell, "Animation: {}{}", if on { "On" } else { "Off" }, CR);
+    }
+
+    pub fn status_command(&mut self, shell: &mut Shell) {
+        let on = self.blink_enabled.lock(|e| *e);
+        let status = if on { "On" } else { "Off" };
+        let freq = self.blink_freq.lock(|f| *f);
+        write!(
+            shell,
+            "{0:}Animation: {1:}{0:}Frequency: {2:}Hz{0:}",
+            CR, status, freq
+        )
+        .ok();
+    }
+
+    pub fn set_command(&mut self, shell: &mut Shell, args: &str) {
+        match btoi::btoi(args.as_bytes()) {
+            Ok(freq) if freq > 0 && freq <= 100 => {
+                self.set_blink_freq(freq);
+                shell.write_str(CR).ok();
+                detail!(shell, "Frequency: {}Hz{}", freq, CR);
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn standby_command(&mut self, shell: &mut Shell, args: &str) {
+        match btoi::btoi::<u16>(args.as_bytes()) {
+            Ok(seconds) if seconds > 0 => {
+                let state = ResumeState {
+                    blink_enabled: self.blink_enabled.lock(|e| *e),
+                    blink_freq: self.blink_freq.lock(|f| *f),
+                };
+                write!(shell, "{0:}entering standby for {1:}s{0:}", CR, seconds).ok();
+                nb::block!(shell.serial().flush()).ok();
+                standby::enter(seconds, state);
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn cobs_command(shell: &mut Shell, args: &str) {
+        match args {
+            "selftest" => match cobs::selftest() {
+                Ok(cases) => {
+                    shell.write_str(CR).ok();
+                    say!(shell, "COBS selftest: {} cases passed{}", cases, CR);
+                    detail!(
+                        shell,
+                        "Vectors: {1:}{0:}Block boundary: 1{0:}Round trips: 0..={2:} bytes{0:}\
+                         Damaged frame: 1{0:}",
+                        CR,
+                        cobs::VECTORS.len(),
+                        cobs::SELFTEST_LEN
+                    );
+                }
+                Err(case) => {
+                    write!(shell, "{0:}COBS selftest failed at case {1:}{0:}", CR, case).ok();
+                }
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn describe_command(&mut self, shell: &mut Shell) {
+        shell.write_str(CR).ok();
+        self.hw.lock(|hw| catalog::describe(shell, hw));
+        shell.write_str(CR).ok();
+    }
+
+    pub fn nmea_command(shell: &mut Shell, args: &str) {
+        match args {
+            "" => {
+                let state = if shell.serial().nmea() { "on" } else { "off" };
+                write!(shell, "{0:}NMEA framing: {1:}{0:}", CR, state).ok();
+            }
+            "on" | "off" => {
+                shell.serial().set_nmea(args == "on");
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn trace_command(&mut self, shell: &mut Shell, args: &str) {
+        match args {
+            "" => {
+                let len = shell.serial().inner().trace().len();
+                write!(shell, "{0:}Captured: {1:} bytes{0:}", CR, len).ok();
+            }
+            "dump" => self.trace_dump(shell),
+            "clear" => {
+                shell.serial().inner().trace().clear();
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn version_command(shell: &mut Shell) {
+        let info = &BUILD_INFO;
+        let features = if info.features.is_empty() {
+            "none"
+        } else {
+            info.features
+        };
+        write!(
+            shell,
+            "{0:}Version:  {1:}{0:}Git:      {2:}{0:}Built:    {3:}{0:}\
+             Rustc:    {4:}{0:}Features: {5:}{0:}HAL:      stm32g0xx-hal {6:}{0:}\
+             Serial:   {7:}{0:}",
+            CR,
+            info.version,
+            info.git_hash,
+            info.timestamp,
+            info.rustc,
+            features,
+            info.hal_version,
+            provision::serial().unwrap_or("unprovisioned")
+        )
+        .ok();
+    }
+
+    pub fn control(&mut self, _shell: &mut Shell, code: u8) {
+        match code {
+            control::CTRL_D => {
+                self.blink_enabled.lock(|e| *e = true);
+            }
+            // Silences a ringing bundle or aborts a Morse message first, the animation
+            // stops on the next press
+            control::CTRL_C => {
+                if self.ring_stop() {
+                    return;
+                }
+                if self.morse.lock(|m| m.is_active()) {
+                    self.morse.lock(|m| m.stop());
+                } else {
+                    self.blink_enabled.lock(|e| *e = false);
+                }
+            }
+            control::CTRL_S => {
+                let freq = self.blink_freq.lock(|f| *f);
+                if freq < 100 {
+                    self.set_blink_freq(freq + 1);
+                }
+            }
+            control::CTRL_X => {
+                let freq = self.blink_freq.lock(|f| *f);
+                if freq > 1 {
+                    self.set_blink_freq(freq - 1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Runs background jobs signalled by the system tick
+    pub fn background(&mut self, shell: &mut Shell) {
+        self.hw.lock(|h| h.poll());
+        self.jobs_run(shell);
+        self.alarm_run(shell);
+        self.countdown_run(shell);
+        self.ring_run();
+        self.rollback_run(shell);
+        Self::deadline_check(shell);
+        self.health_check(shell);
+        self.apply_clock_policy();
+        self.power_report(shell);
+        self.monitor_report(shell);
+        self.sweep_step(shell);
+        self.telemetry_push(shell);
+        self.touch_check(shell);
+        self.dist_map(shell);
+        self.motion_check(shell);
+        self.pid_step(shell);
+        self.thermostat_control(shell);
+        self.switch_check(shell);
+        self.statusbar_draw(shell);
+        self.dashboard_refresh(shell);
+        self.adc_watch_report(shell);
+    }
+
+    /// Runs commands scheduled with `after` or `every` once the monotonic marked them
+    /// due. Each run is tagged with its job id and the prompt comes back once after the
+    /// batch, a job leaves the exit status of the typed commands alone.
+    fn jobs_run(&mut self, shell: &mut Shell) {
+        if self.jobs.lock(|j| j.held(mono::now_ms())) {
+            return;
+        }
+        let status = metrics::exit_status();
+        let mut ran = false;
+        while let Some((id, line)) = self.jobs.lock(|j| j.take_due()) {
+            write!(shell, "\r\x1b[Kjob {}> {}", id, line).ok();
+            self.run_stored(shell, &line);
+            ran = true;
+        }
+        if ran {
+            metrics::set_exit_status(status);
+            shell.write_str(&settings::prompt()).ok();
+        }
+    }
+
+    /// Runs the command of a fired RTC alarm with a notification line above the prompt
+    fn alarm_run(&mut self, shell: &mut Shell) {
+        if self.jobs.lock(|j| j.held(mono::now_ms())) {
+            return;
+        }
+        let line = match self.alarm.lock(|a| a.take_due()) {
+            Some(line) => line,
+            None => return,
+        };
+        let (hour, minute) = rtc::alarm().unwrap_or((0, 0));
+        let status = metrics::exit_status();
+        write!(shell, "\r\x1b[Kalarm {:02}:{:02}> {}", hour, minute, line).ok();
+        self.run_stored(shell, &line);
+        metrics::set_exit_status(status);
+        shell.write_str(&settings::prompt()).ok();
+    }
+
+    /// Keeps the time left in front of the prompt while the countdown runs, a half typed
+    /// line is left alone. At zero it runs the command or just says so.
+    fn countdown_run(&mut self, shell: &mut Shell) {
+        if self.jobs.lock(|j| j.held(mono::now_ms())) {
+            return;
+        }
+        if self.countdown.lock(|c| c.take_expired()) {
+            let line = self
+                .countdown
+                .lock(|c| c.line().map(String::<CMD_MAX_LEN>::from));
+            match line {
+                Some(line) => {
+                    let status = metrics::exit_status();
+                    write!(shell, "\r\x1b[Kcountdown> {}", line).ok();
+                    self.run_stored(shell, &line);
+                    metrics::set_exit_status(status);
+                    shell.write_str(&settings::prompt()).ok();
+                }
+                None => {
+                    write!(shell, "\r\x1b[KCountdown done{}{}", CR, settings::prompt()).ok();
+                }
+            }
+            return;
+        }
+        let now = mono::now_ms();
+        let left = self.countdown.lock(|c| {
+            let redraw = c.take_redraw();
+            c.remaining_ms(now).filter(|_| redraw)
+        });
+        if let Some(left) = left {
+            if !self.jobs.lock(|j| j.is_typing()) {
+                let secs = left.div_ceil(1000);
+                write!(
+                    shell,
+                    "\r\x1b[K[{}:{:02}] {}",
+                    secs / 60,
+                    secs % 60,
+                    settings::prompt()
+                )
+                .ok();
+            }
+        }
+    }
+
+    /// Puts the LED pattern back once a ring timed out
+    fn ring_run(&mut self) {
+        if self.ringer.lock(|r| r.take_finished()) {
+            self.ring_stop();
+        }
+    }
+
+    fn apply_clock_policy(&mut self) {
+        let pwm_active = self.pwmout.lock(|p| p.channel().is_some());
+        let (speed, target) = self.clock.lock(|c| (c.speed(), c.target(pwm_active)));
+        if speed == target {
+            return;
+        }
+        self.clock.lock(|c| c.set_speed(target));
+        let freq = self.blink_freq.lock(|f| *f);
+        self.blink_timer.lock(|t| t.start(freq as u32 * 2));
+        self.sys_timer.lock(|t| t.start(TICK_HZ));
+        mono::retime();
+        self.pwmout.lock(|p| p.retime());
+        self.loadgen.lock(|l| l.retime());
+        self.adc_watch.lock(|w| w.retime());
+        self.led.lock(|l| l.retime());
+    }
+
+    fn health_check(&mut self, shell: &mut Shell) {
+        if !self.health.lock(|h| h.take_due()) {
+            return;
+        }
+        let (temp, vdd) = self.sensors.lock(|s| (s.temp_c(), s.vdda_mv()));
+        let changes = self.health.lock(|h| h.update(temp, vdd));
+        for (name, alarm) in ALARMS.iter() {
+            if !Health::changed(changes, *alarm) {
+                continue;
+            }
+            let state = if self.health.lock(|h| h.is_raised(*alarm)) {
+                "warning"
+            } else {
+                "ok"
+            };
+            write!(
+                shell,
+                "\r\x1b[Khealth: {} {} ({}C, {}mV){}{}",
+                name,
+                state,
+                temp,
+                vdd,
+                CR,
+                settings::prompt()
+            )
+            .ok();
+        }
+        // Any raised alarm takes the LED over, the animation comes back once all clear
+        if changes != 0 {
+            let raised = self
+                .health
+                .lock(|h| ALARMS.iter().any(|(_, alarm)| h.is_raised(*alarm)));
+            self.led_owner.lock(|l| {
+                if raised {
+                    l.claim(Owner::Alarm)
+                } else {
+                    l.release(Owner::Alarm)
+                }
+            });
+        }
+
+        let throttling = self.health.lock(|h| h.is_throttling());
+        self.clock.lock(|c| c.set_throttled(throttling));
+        if throttling && self.pwmout.lock(|p| p.channel().is_some()) {
+            self.pwmout.lock(|p| p.stop());
+            write!(
+                shell,
+                "\r\x1b[Khealth: PWM output stopped{}{}",
+                CR,
+                settings::prompt()
+            )
+            .ok();
+        }
+    }
+
+    fn power_report(&mut self, shell: &mut Shell) {
+        let msg = match self.power.lock(|p| p.take_event()) {
+            Some(PowerEvent::Fail) => "power: supply below PVD threshold",
+            Some(PowerEvent::Restore) => "power: supply restored",
+            None => return,
+        };
+        write!(shell, "\r\x1b[K{}{}{}", msg, CR, settings::prompt()).ok();
+    }
+
+    fn monitor_report(&mut self, shell: &mut Shell) {
+        if !self.monitor.lock(|m| m.take_due()) {
+            return;
+        }
+        shell.write_str("\r\x1b[K").ok();
+        for (name, watch) in WATCHES.iter() {
+            if !self.monitor.lock(|m| m.is_watched(*watch)) {
+                continue;
+            }
+            write!(shell, "{}=", name).ok();
+            self.write_watch(shell, *watch);
+            shell.write_str(" ").ok();
+        }
+        write!(shell, "{}{}", CR, settings::prompt()).ok();
+    }
+
+    /// Sends a due packet as a COBS frame. Shell text never contains a zero byte, the
+    /// leading and trailing delimiters let a host split frames out of the text stream
+    fn telemetry_push(&mut self, shell: &mut Shell) {
+        let seq = match self.telemetry.lock(|t| t.take_due()) {
+            Some(seq) => seq,
+            None => return,
+        };
+        let sample = Sample {
+            animation: self.blink_enabled.lock(|e| *e),
+            pwm: self.pwmout.lock(|p| p.channel().is_some()),
+            throttling: self.health.lock(|h| h.is_throttling()),
+            blink_freq: self.blink_freq.lock(|f| *f),
+            vdda_mv: self.sensors.lock(|s| s.vdda_mv()) as u16,
+            temp_c: self.sensors.lock(|s| s.temp_c()) as i16,
+            ticks: self.ticks.lock(|t| *t),
+        };
+        Self::send_frame::<{ cobs::max_encoded_len(PACKET_LEN) }>(shell, &sample.encode(seq));
+    }
+
+    /// Answers provisioning frames pulled out of the shell input
+    pub fn provision(&mut self, shell: &mut Shell) {
+        while let Some(frame) = shell.serial().take_frame() {
+            let ack = provision::handle(frame);
+            Self::send_frame::<{ cobs::max_encoded_len(provision::ACK_LEN) }>(shell, &ack);
+        }
+    }
+
+    /// Sends a COBS frame between zero delimiters
+    fn send_frame<const N: usize>(shell: &mut Shell, payload: &[u8]) {
+        let mut frame = [0; N];
+        let len = match cobs::encode(payload, &mut frame) {
+            Ok(len) => len,
+            Err(_) => return,
+        };
+        Self::write_raw(shell, &[0]);
+        Self::write_raw(shell, &frame[..len]);
+    }
+
+    /// Writes below the output arbiter, stamps and NMEA framing would corrupt frames and
+    /// cursor control sequences
+    fn write_raw(shell: &mut Shell, bytes: &[u8]) {
+        let serial = shell.serial().inner();
+        for byte in bytes {
+            nb::block!(serial.write(*byte)).ok();
+        }
+    }
+
+    /// Any key closes the dashboard, returns true while it owns the input
+    pub fn dashboard_input(&mut self, shell: &mut Shell) -> bool {
+        if !self.dashboard.lock(|d| d.is_active()) {
+            return false;
+        }
+        let mut pressed = false;
+        while shell.serial().read().is_ok() {
+            pressed = true;
+        }
+        if pressed {
+            self.dashboard.lock(|d| d.set_active(false));
+            Self::write_raw(shell, b"\x1b[?25h\x1b[H\x1b[2J");
+            self.statusbar.lock(|b| b.redraw());
+            shell.write_str(&settings::prompt()).ok();
+        }
+        true
+    }
+
+    fn dashboard_refresh(&mut self, shell: &mut Shell) {
+        if !self.dashboard.lock(|d| d.take_due()) {
+            return;
+        }
+        let cpu = self.cpu.lock(|c| c.last()).map_or(0, |usage| {
+            Usage::percent(usage.total_us - usage.idle_us, usage.total_us)
+        });
+        let sample = dashboard::Sample {
+            freq: self.blink_freq.lock(|f| *f),
+            temp_c: self.sensors.lock(|s| s.temp_c()),
+            vdda_mv: self.sensors.lock(|s| s.vdda_mv()),
+            cpu,
+        };
+        // Rendered from a copy, the UART is too slow to hold the lock for
+        let view = self.dashboard.lock(|d| {
+            d.push(sample);
+            d.clone()
+        });
+        let uptime_s = self.ticks.lock(|t| *t) / TICK_HZ;
+        view.render(&mut Raw(shell), uptime_s);
+    }
+
+    pub fn dashboard_command(&mut self, shell: &mut Shell, args: &str) {
+        if !args.is_empty() {
+            metrics::set_exit_status(ExitStatus::Usage);
+            return;
+        }
+        // The bar's scroll region would cut off the bottom of the view
+        Self::write_raw(shell, b"\x1b[r\x1b[2J");
+        self.dashboard.lock(|d| d.set_active(true));
+    }
+
+    pub fn led_command(&mut self, shell: &mut Shell, args: &str) {
+        let (name, state) = args.split_once(" ").unwrap_or((args, ""));
+        let owner = match name {
+            "" => {
+                let (owner, alarm, sos) = self.led_owner.lock(|l| {
+                    (
+                        l.owner(),
+                        l.is_claimed(Owner::Alarm),
+                        l.is_claimed(Owner::Panic),
+                    )
+                });
+                write!(
+                    shell,
+                    "{0:}LED owner: {1:}{0:}Alarm: {2:}{0:}SOS: {3:}{0:}",
+                    CR,
+                    owner.name(),
+                    if alarm { "On" } else { "Off" },
+                    if sos { "On" } else { "Off" }
+                )
+                .ok();
+                return;
+            }
+            "alarm" => Owner::Alarm,
+            "sos" => Owner::Panic,
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        match state {
+            "on" => self.led_owner.lock(|l| l.claim(owner)),
+            "off" => self.led_owner.lock(|l| l.release(owner)),
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        }
+        let owner = self.led_owner.lock(|l| l.owner());
+        say!(shell, "{0:}LED owner: {1:}{0:}", CR, owner.name());
+    }
+
+    pub fn dim_command(&mut self, shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            let brightness = self.led.lock(|l| l.brightness());
+            write!(shell, "{0:}Brightness: {1:}%{0:}", CR, brightness).ok();
+            return;
+        }
+        match btoi::btoi::<u8>(args.as_bytes()) {
+            Ok(brightness) if brightness <= 100 => {
+                self.led.lock(|l| l.set_brightness(brightness));
+                shell.write_str(CR).ok();
+                detail!(shell, "Brightness: {}%{}", brightness, CR);
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    fn statusbar_draw(&mut self, shell: &mut Shell) {
+        if !self.statusbar.lock(|b| b.take_due()) || self.dashboard.lock(|d| d.is_active()) {
+            return;
+        }
+        let secs = self.ticks.lock(|t| *t) / TICK_HZ;
+        let on = self.blink_enabled.lock(|e| *e);
+        let freq = self.blink_freq.lock(|f| *f);
+        let mut text: String<48> = String::new();
+        write!(
+            text,
+            " Up {}:{:02}:{:02} | Animation: {} | {}Hz ",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            if on { "On" } else { "Off" },
+            freq
+        )
+        .ok();
+        let mut seq: String<96> = String::new();
+        self.statusbar.lock(|b| b.draw(&mut seq, &text));
+        Self::write_raw(shell, seq.as_bytes());
+    }
+
+    pub fn statusbar_command(&mut self, shell: &mut Shell, args: &str) {
+        let (enabled, edge, rows) = self
+            .statusbar
+            .lock(|b| (b.is_enabled(), b.edge(), b.rows()));
+        let mut words = args.split_whitespace();
+        let enable = match words.next() {
+            None => {
+                write!(
+                    shell,
+                    "{0:}Status bar: {1:}{0:}Edge: {2:}{0:}Rows: {3:}{0:}",
+                    CR,
+                    if enabled { "On" } else { "Off" },
+                    if edge == Edge::Top { "top" } else { "bottom" },
+                    rows
+                )
+                .ok();
+                return;
+            }
+            Some("on") => true,
+            Some("off") => false,
+            Some(_) => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        let (mut edge, mut rows) = (edge, rows);
+        for word in words {
+            match (word, btoi::btoi::<u16>(word.as_bytes())) {
+                ("top", _) => edge = Edge::Top,
+                ("bottom", _) => edge = Edge::Bottom,
+                (_, Ok(n)) if (StatusBar::MIN_ROWS..=StatusBar::MAX_ROWS).contains(&n) => rows = n,
+                _ => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                    return;
+                }
+            }
+        }
+        // Release the old position first, a moved bar would leave a stale row behind
+        let mut seq: String<24> = String::new();
+        self.statusbar.lock(|b| {
+            if b.is_enabled() {
+                b.release(&mut seq);
+            }
+            b.set(enable, edge, rows);
+        });
+        Self::write_raw(shell, seq.as_bytes());
+        shell.write_str(CR).ok();
+        if enable {
+            self.statusbar.lock(|b| b.redraw());
+            self.statusbar_draw(shell);
+        }
+    }
+
+    pub fn audio_command(&mut self, shell: &mut Shell, args: &str) {
+        let (subcmd, arg) = args.split_once(" ").unwrap_or((args, ""));
+        let value = btoi::btoi::<u32>(arg.as_bytes()).ok();
+        match (subcmd, value) {
+            ("", _) => {
+                let on = self.sensors.lock(|s| s.is_streaming());
+                let (attack, decay, gain) =
+                    self.audio.lock(|a| (a.attack_ms(), a.decay_ms(), a.gain()));
+                write!(
+                    shell,
+                    "{0:}Audio: {1:}{0:}Attack: {2:}ms{0:}Decay: {3:}ms{0:}Gain: {4:}{0:}",
+                    CR,
+                    if on { "On" } else { "Off" },
+                    attack,
+                    decay,
+                    gain
+                )
+                .ok();
+            }
+            ("on", _) => {
+                self.wave.lock(|w| w.stop());
+                if let Err(err) = self.start_carrier() {
+                    pins::write_error(shell, err);
+                    return;
+                }
+                self.audio.lock(|a| a.reset());
+                self.sensors.lock(|s| s.start_stream());
+                shell.write_str(CR).ok();
+            }
+            ("off", _) => {
+                self.sensors.lock(|s| s.stop_stream());
+                self.pwmout.lock(|p| p.stop());
+                shell.write_str(CR).ok();
+            }
+            ("attack", Some(ms)) if (1..=Envelope::MAX_TIME_MS).contains(&ms) => {
+                self.audio.lock(|a| a.set_attack_ms(ms));
+                shell.write_str(CR).ok();
+            }
+            ("decay", Some(ms)) if (1..=Envelope::MAX_TIME_MS).contains(&ms) => {
+                self.audio.lock(|a| a.set_decay_ms(ms));
+                shell.write_str(CR).ok();
+            }
+            ("gain", Some(gain)) if (1..=Envelope::MAX_GAIN).contains(&gain) => {
+                self.audio.lock(|a| a.set_gain(gain));
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    /// PWM carrier on PA6 for audio and wave playback, its period interrupt feeds samples
+    fn start_carrier(&mut self) -> Result<(), PinError> {
+        self.pwmout.lock(|p| {
+            p.start(Channel::Ch1, wave::CARRIER_HZ, 0)?;
+            p.listen();
+            Ok(())
+        })
+    }
+
+    pub fn adc_command(&mut self, shell: &mut Shell, args: &str) {
+        if let Some(args) = args.strip_prefix("watch ") {
+            return self.adc_watch_command(shell, args);
+        }
+        let pin = match trigger::parse_pin(args) {
+            Some(pin) => pin,
+            None => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        if self.sensors.lock(|s| s.is_streaming()) {
+            metrics::set_exit_status(ExitStatus::Error);
+            write!(shell, "{0:}ADC is streaming, turn audio off first{0:}", CR).ok();
+            return;
+        }
+        match self.sensors.lock(|s| s.read_pin(pin)) {
+            Ok((raw, mv)) => {
+                write!(
+                    shell,
+                    "{0:}PA{1:}: {2:} counts, {3:}mV{0:}",
+                    CR, pin, raw, mv
+                )
+                .ok();
+            }
+            Err(err) => pins::write_error(shell, err),
+        }
+    }
+
+    pub fn temp_command(&mut self, shell: &mut Shell) {
+        let (dc, raw) = match self.sensors.lock(|s| s.temp_dc()) {
+            Some(reading) => reading,
+            None => {
+                write!(shell, "{0:}ADC is streaming, turn audio off first{0:}", CR).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+                return;
+            }
+        };
+        let sign = if dc < 0 { "-" } else { "" };
+        write!(
+            shell,
+            "{0:}Temperature: {1:}{2:}.{3:}C{0:}",
+            CR,
+            sign,
+            dc.abs() / 10,
+            dc.abs() % 10
+        )
+        .ok();
+        let (cal1, cal2) = health::ts_cal();
+        detail!(
+            shell,
+            "Sensor: {} counts at 3.0V, TS_CAL1 {} at {}C, TS_CAL2 {} at {}C{}",
+            raw,
+            cal1,
+            health::TS_CAL1_TEMP,
+            cal2,
+            health::TS_CAL2_TEMP,
+            CR
+        );
+    }
+
+    pub fn vdda_command(&mut self, shell: &mut Shell) {
+        let raw = match self.sensors.lock(|s| s.vref_raw()) {
+            Some(raw) => raw,
+            None => {
+                write!(shell, "{0:}ADC is streaming, turn audio off first{0:}", CR).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+                return;
+            }
+        };
+        let cal = health::vref_cal();
+        let mv = health::CAL_VDDA_MV * cal as u32 / (raw as u32).max(1);
+        write!(shell, "{0:}VDDA: {1:}mV{0:}", CR, mv).ok();
+        let source = if cal::get(cal::VREF_CAL) == 0 {
+            "factory"
+        } else {
+            "board"
+        };
+        detail!(
+            shell,
+            "VREFINT: {} counts, {} calibration {} at 3.0V{}",
+            raw,
+            source,
+            cal,
+            CR
+        );
+    }
+
+    /// Samples a pin from TIM6 and keeps a line of statistics updated until a key is pressed
+    fn adc_watch_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        let pin = args.next().and_then(trigger::parse_pin);
+        let rate = args.next().map_or(Ok(adcwatch::DEFAULT_RATE_HZ), |rate| {
+            btoi::btoi::<u32>(rate.as_bytes())
+        });
+        let (pin, rate) = match (pin, rate, args.next()) {
+            (Some(pin), Ok(rate), None) if (1..=adcwatch::MAX_RATE_HZ).contains(&rate) => {
+                (pin, rate)
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        if self.sensors.lock(|s| s.is_streaming()) {
+            metrics::set_exit_status(ExitStatus::Error);
+            write!(shell, "{0:}ADC is streaming, turn audio off first{0:}", CR).ok();
+            return;
+        }
+        // One read up front catches a busy or unsupported pin
+        if let Err(err) = self.sensors.lock(|s| s.read_pin(pin)) {
+            pins::write_error(shell, err);
+            return;
+        }
+        self.adc_watch.lock(|w| w.start(pin, rate));
+        write!(
+            shell,
+            "{0:}Watching PA{1:} at {2:}Hz, press any key to stop{0:}",
+            CR, pin, rate
+        )
+        .ok();
+    }
+
+    /// Any key ends `adc watch`, returns true while it owns the input
+    pub fn adc_watch_input(&mut self, shell: &mut Shell) -> bool {
+        if !self.adc_watch.lock(|w| w.is_active()) {
+            return false;
+        }
+        let mut pressed = false;
+        while shell.serial().read().is_ok() {
+            pressed = true;
+        }
+        if pressed {
+            if let Some(stats) = self.adc_watch.lock(|w| w.stop()) {
+                Self::write_adc_stats(shell, &stats);
+            }
+            write!(shell, "{}{}", CR, settings::prompt()).ok();
+        }
+        true
+    }
+
+    pub fn adjust_command(&mut self, shell: &mut Shell, args: &str) {
+        let idx = match SLIDERS.iter().position(|(spec, ..)| spec.name == args) {
+            Some(idx) => idx,
+            None => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        if SLIDERS[idx].0.name == "duty" && self.pwmout.lock(|p| p.channel().is_none()) {
+            write!(
+                shell,
+                "{0:}adjust: PWM output is off, start it with pwmout{0:}",
+                CR
+            )
+            .ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return;
+        }
+        self.slider.lock(|s| s.start(idx));
+        write!(shell, "{0:}Up/Down adjusts, Esc exits{0:}", CR).ok();
+        self.slider_draw(shell, idx);
+    }
+
+    /// Arrow keys of `adjust`, returns true while the slider owns the input
+    pub fn slider_input(&mut self, shell: &mut Shell) -> bool {
+        let idx = match self.slider.lock(|s| s.active()) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        while let Ok(byte) = shell.serial().read() {
+            if let Some(key) = self.slider.lock(|s| s.feed(byte)) {
+                if !self.slider_key(shell, idx, key) {
+                    return true;
+                }
+            }
+        }
+        if let Some(key) = self.slider.lock(|s| s.end_of_input()) {
+            self.slider_key(shell, idx, key);
+        }
+        true
+    }
+
+    /// Applies one step and redraws the value, false once the slider closed
+    fn slider_key(&mut self, shell: &mut Shell, idx: usize, key: Key) -> bool {
+        if key == Key::Exit {
+            self.slider.lock(|s| s.stop());
+            write!(shell, "{}{}", CR, settings::prompt()).ok();
+            return false;
+        }
+        let (spec, get, set) = SLIDERS[idx];
+        let value = spec.next(get(self), key);
+        set(self, value);
+        self.slider_draw(shell, idx);
+        true
+    }
+
+    fn slider_draw(&mut self, shell: &mut Shell, idx: usize) {
+        let (spec, get, _) = SLIDERS[idx];
+        let value = get(self);
+        write!(
+            shell,
+            "\r\x1b[K{}: {}{}  [{}-{}]",
+            spec.name, value, spec.unit, spec.min, spec.max
+        )
+        .ok();
+    }
+
+    fn adc_watch_report(&mut self, shell: &mut Shell) {
+        if let Some(stats) = self.adc_watch.lock(|w| w.take_due()) {
+            Self::write_adc_stats(shell, &stats);
+        }
+    }
+
+    /// Redraws the statistics line in place
+    fn write_adc_stats(shell: &mut Shell, stats: &Stats) {
+        if stats.count == 0 {
+            return;
+        }
+        write!(
+            shell,
+            "\r\x1b[KPA{}: {}mV  min {}mV  max {}mV  avg {}mV  {} samples",
+            stats.pin,
+            stats.last,
+            stats.min,
+            stats.max,
+            stats.avg(),
+            stats.count
+        )
+        .ok();
+    }
+
+    pub fn wave_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next(), args.next()) {
+            (None, _, _) => {
+                let (len, playing) = self.wave.lock(|w| (w.len(), w.is_playing()));
+                let state = if playing { "playing" } else { "stopped" };
+                write!(shell, "{0:}Wave: {1:} samples, {2:}{0:}", CR, len, state).ok();
+            }
+            (Some("upload"), None, _) => {
+                self.wave.lock(|w| w.begin_upload());
+                write!(
+                    shell,
+                    "{0:}Paste samples 0-100, up to {1:}, finish with an empty line{0:}",
+                    CR, MAX_SAMPLES
+                )
+                .ok();
+            }
+            (Some("play"), Some(rate), looped) if looped.is_none() || looped == Some("loop") => {
+                match btoi::btoi::<u32>(rate.as_bytes()) {
+                    Ok(rate) if (1..=wave::MAX_RATE).contains(&rate) => {
+                        if self.wave.lock(|w| w.play(rate, looped.is_some())) {
+                            self.sensors.lock(|s| s.stop_stream());
+                            match self.start_carrier() {
+                                Ok(()) => {
+                                    shell.write_str(CR).ok();
+                                }
+                                Err(err) => {
+                                    self.wave.lock(|w| w.stop());
+                                    pins::write_error(shell, err);
+                                }
+                            }
+                        } else {
+                            write!(shell, "{0:}wave table is empty{0:}", CR).ok();
+                        }
+                    }
+                    _ => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                }
+            }
+            (Some("stop"), None, _) => {
+                self.wave.lock(|w| w.stop());
+                self.pwmout.lock(|p| p.stop());
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    /// Takes a pasted line of samples separated by spaces or commas
+    fn wave_upload(&mut self, shell: &mut Shell, cmd: &str, args: &str) {
+        if cmd.is_empty() || cmd == "end" {
+            let len = self.wave.lock(|w| {
+                w.end_upload();
+                w.len()
+            });
+            write!(shell, "{0:}Uploaded {1:} samples{0:}", CR, len).ok();
+            return;
+        }
+        let samples = cmd
+            .split(',')
+            .chain(args.split([' ', ',']))
+            .filter(|sample| !sample.is_empty());
+        for sample in samples {
+            match btoi::btoi::<u8>(sample.as_bytes()) {
+                Ok(sample) if sample <= 100 => {
+                    if !self.wave.lock(|w| w.push(sample)) {
+                        self.wave.lock(|w| w.end_upload());
+                        write!(shell, "{0:}wave table full, upload finished{0:}", CR).ok();
+                        return;
+                    }
+                }
+                _ => {
+                    write!(shell, "{0:}invalid sample {1:}{0:}", CR, sample).ok();
+                    return;
+                }
+            }
+        }
+        shell.write_str(CR).ok();
+    }
+
+    pub fn capture_command(shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        let duration = args
+            .next()
+            .and_then(|ms| btoi::btoi::<u32>(ms.as_bytes()).ok());
+        let mut pins = [0; capture::MAX_PINS];
+        let mut count = 0;
+        for name in args {
+            match trigger::parse_pin(name) {
+                Some(pin) if count < pins.len() => {
+                    pins[count] = pin;
+                    count += 1;
+                }
+                _ => count = pins.len() + 1,
+            }
+        }
+        match duration {
+            Some(ms)
+                if (1..=capture::MAX_DURATION_MS).contains(&ms)
+                    && (1..=pins.len()).contains(&count) =>
+            {
+                let capture = Capture::run(&pins[..count], ms);
+                shell.write_str(CR).ok();
+                capture.write_vcd(shell);
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn count_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next(), args.next()) {
+            (None, ..) => {
+                let (pin, edges, gate) = self.counter.lock(|c| (c.pin(), c.edges(), c.gate_ms()));
+                match pin {
+                    Some(pin) => write!(shell, "{}Pin: PA{} ({})", CR, pin, edges.name()),
+                    None => write!(shell, "{}Pin: none", CR),
+                }
+                .ok();
+                match gate {
+                    Some(ms) => write!(shell, "{0:}Gate: {1:}ms{0:}", CR, ms),
+                    None => write!(shell, "{0:}Gate: off{0:}", CR),
+                }
+                .ok();
+                self.count_read(shell);
+            }
+            (Some("read"), None, _) => {
+                shell.write_str(CR).ok();
+                self.count_read(shell);
+            }
+            (Some("reset"), None, _) => {
+                self.counter.lock(|c| c.reset());
+                shell.write_str(CR).ok();
+            }
+            (Some("off"), None, _) => {
+                self.counter.lock(|c| c.stop());
+                shell.write_str(CR).ok();
+            }
+            (Some("gate"), Some("off"), None) => {
+                self.counter.lock(|c| c.set_gate_ms(None));
+                shell.write_str(CR).ok();
+            }
+            (Some("gate"), Some(ms), None) => match btoi::btoi::<u32>(ms.as_bytes()) {
+                Ok(ms) if (EdgeCounter::MIN_GATE_MS..=EdgeCounter::MAX_GATE_MS).contains(&ms) => {
+                    self.counter.lock(|c| c.set_gate_ms(Some(ms)));
+                    shell.write_str(CR).ok();
+                }
+                _ => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                }
+            },
+            (Some(pin), edges, None) => {
+                let edges = match edges {
+                    Some(name) => Edges::from_name(name),
+                    None => Some(Edges::Both),
+                };
+                let pin = trigger::parse_pin(pin);
+                match (pin, edges) {
+                    (Some(pin), Some(edges)) => match self.counter.lock(|c| c.start(pin, edges)) {
+                        Ok(()) => {
+                            shell.write_str(CR).ok();
+                        }
+                        Err(err) => pins::write_error(shell, err),
+                    },
+                    (_, None) => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                    _ => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                }
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    fn count_read(&mut self, shell: &mut Shell) {
+        let (count, rate) = self.counter.lock(|c| (c.count(), c.rate_hz()));
+        write!(shell, "Count: {}{}", count, CR).ok();
+        if let Some(rate) = rate {
+            write!(shell, "Rate: {}Hz{}", rate, CR).ok();
+        }
+    }
+
+    pub fn dist_command(&mut self, shell: &mut Shell, args: &str) {
+        match args {
+            "" => {
+                let (res, map) = self.ranger.lock(|r| (r.distance_mm(), r.is_mapping()));
+                shell.write_str(CR).ok();
+                match res {
+                    Ok(mm) => {
+                        write!(shell, "Distance: {}.{}cm", mm / 10, mm % 10).ok();
+                    }
+                    Err(err) => Self::write_range_error(shell, err),
+                }
+                let map = if map { "on" } else { "off" };
+                write!(shell, "{0:}Blink mapping: {1:}{0:}", CR, map).ok();
+            }
+            "map on" | "map off" => {
+                self.ranger.lock(|r| r.set_mapping(args == "map on"));
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    /// Follows the measured distance with the blink frequency
+    fn dist_map(&mut self, shell: &mut Shell) {
+        if !self.ranger.lock(|r| r.take_due()) {
+            return;
+        }
+        match self.ranger.lock(|r| r.distance_mm()) {
+            Ok(mm) => {
+                let freq = Ranger::blink_freq(mm);
+                if self.blink_freq.lock(|f| *f) != freq {
+                    self.set_blink_freq(freq);
+                }
+            }
+            Err(RangeError::OutOfRange) => {}
+            Err(err) => {
+                self.ranger.lock(|r| r.set_mapping(false));
+                shell.write_str("\r\x1b[K").ok();
+                Self::write_range_error(shell, err);
+                write!(shell, ", mapping stopped{}{}", CR, settings::prompt()).ok();
+            }
+        }
+    }
+
+    fn write_range_error(shell: &mut Shell, err: RangeError) {
+        let msg = match err {
+            RangeError::NoSensor => "dist: no echo, sensor missing",
+            RangeError::OutOfRange => "dist: out of range",
+        };
+        shell.write_str(msg).ok();
+    }
+
+    pub fn motion_command(&mut self, shell: &mut Shell, args: &str) {
+        let (subcmd, arg) = args.split_once(" ").unwrap_or((args, ""));
+        match subcmd {
+            "" => {
+                let now = self.ticks.lock(|t| *t);
+                let (count, last, hold) = self.motion.lock(|m| (m.count(), m.last(), m.hold_s()));
+                let state = if Motion::is_active() {
+                    "detected"
+                } else {
+                    "none"
+                };
+                write!(shell, "{0:}Motion: {1:}{0:}Events: {2:}", CR, state, count).ok();
+                if let Some(ticks) = last {
+                    write!(shell, ", last {}s ago", now.wrapping_sub(ticks) / TICK_HZ).ok();
+                }
+                match hold {
+                    Some(hold) => write!(shell, "{0:}Rule: animation for {1:}s{0:}", CR, hold),
+                    None => write!(shell, "{0:}Rule: off{0:}", CR),
+                }
+                .ok();
+            }
+            "reset" => {
+                self.motion.lock(|m| m.reset());
+                shell.write_str(CR).ok();
+            }
+            "rule" if arg == "off" => {
+                self.motion.lock(|m| m.set_hold_s(None));
+                shell.write_str(CR).ok();
+            }
+            "rule" => match btoi::btoi::<u32>(arg.as_bytes()) {
+                Ok(hold) if (1..=Motion::MAX_HOLD_S).contains(&hold) => {
+                    self.motion.lock(|m| m.set_hold_s(Some(hold)));
+                    shell.write_str(CR).ok();
+                }
+                _ => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                }
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    /// Stops the animation the motion rule started once the hold runs out
+    fn motion_check(&mut self, shell: &mut Shell) {
+        if !self.motion.lock(|m| m.take_expired()) {
+            return;
+        }
+        self.blink_enabled.lock(|e| *e = false);
+        write!(
+            shell,
+            "\r\x1b[Kmotion: no movement, animation off{}{}",
+            CR,
+            settings::prompt()
+        )
+        .ok();
+    }
+
+    pub fn out_command(&mut self, shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            shell.write_str(CR).ok();
+            for n in 0..switch::CHANNELS {
+                let (on_for, max_on) = self.switches.lock(|s| (s.on_for_s(n), s.max_on_ms(n)));
+                match on_for {
+                    Some(secs) => write!(shell, "{}: on for {}s", n, secs),
+                    None => write!(shell, "{}: off", n),
+                }
+                .ok();
+                if let Some(ms) = max_on {
+                    write!(shell, ", max {}ms", ms).ok();
+                }
+                shell.write_str(CR).ok();
+            }
+            return;
+        }
+        let (n, args) = args.split_once(" ").unwrap_or((args, ""));
+        let (subcmd, arg) = args.split_once(" ").unwrap_or((args, ""));
+        let n = match btoi::btoi::<usize>(n.as_bytes()) {
+            Ok(n) if n < switch::CHANNELS => n,
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        let ms = btoi::btoi::<u32>(arg.as_bytes())
+            .ok()
+            .filter(|ms| (1..=Switches::MAX_PULSE_MS).contains(ms));
+        match (subcmd, arg, ms) {
+            ("on", "", _) => self.switches.lock(|s| s.on(n)),
+            ("off", "", _) => self.switches.lock(|s| s.off(n)),
+            ("pulse", _, Some(ms)) => self.switches.lock(|s| s.pulse(n, ms)),
+            ("max", "off", _) => self.switches.lock(|s| s.set_max_on_ms(n, None)),
+            ("max", _, Some(ms)) => self.switches.lock(|s| s.set_max_on_ms(n, Some(ms))),
+            ("pulse", _, None) | ("max", _, None) => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        }
+        shell.write_str(CR).ok();
+    }
+
+    /// Reports channels the max-on watchdog switched off
+    fn switch_check(&mut self, shell: &mut Shell) {
+        while let Some(n) = self.switches.lock(|s| s.take_tripped()) {
+            write!(
+                shell,
+                "\r\x1b[Kout {}: max on time reached, switched off{}{}",
+                n,
+                CR,
+                settings::prompt()
+            )
+            .ok();
+        }
+    }
+
+    fn pid_step(&mut self, shell: &mut Shell) {
+        if !self.pid.lock(|p| p.take_due()) {
+            return;
+        }
+        let input = self.sensors.lock(|s| s.input_mv());
+        let pwm_owned = self.pwmout.lock(|p| p.channel()) == Some(Channel::Ch2);
+        let input = match input {
+            Some(input) if pwm_owned => input,
+            _ => {
+                self.pid.lock(|p| p.set_enabled(false));
+                write!(
+                    shell,
+                    "\r\x1b[Kpid: PA0 or PA7 taken over, loop stopped{}{}",
+                    CR,
+                    settings::prompt()
+                )
+                .ok();
+                return;
+            }
+        };
+        let (duty, target, csv) = self
+            .pid
+            .lock(|p| (p.step(input), p.target_mv(), p.is_streaming()));
+        self.pwmout.lock(|p| p.set_duty(duty));
+        if csv {
+            let ticks = self.ticks.lock(|t| *t);
+            write!(
+                shell,
+                "\r\x1b[K{},{},{},{}{}{}",
+                ticks * 1000 / TICK_HZ,
+                target,
+                input,
+                duty,
+                CR,
+                settings::prompt()
+            )
+            .ok();
+        }
+    }
+
+    pub fn pid_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next(), args.next(), args.next()) {
+            (None, ..) | (Some("status"), None, ..) => {
+                let input = self.sensors.lock(|s| s.input_mv());
+                let (gains, target, output, enabled, csv) = self.pid.lock(|p| {
+                    (
+                        p.gains(),
+                        p.target_mv(),
+                        p.output(),
+                        p.is_enabled(),
+                        p.is_streaming(),
+                    )
+                });
+                write!(
+                    shell,
+                    "{0:}Loop: {1:}{0:}Target: {2:}mV{0:}Input: ",
+                    CR,
+                    if enabled { "On" } else { "Off" },
+                    target
+                )
+                .ok();
+                match input {
+                    Some(mv) => write!(shell, "{}mV", mv),
+                    None => write!(shell, "busy"),
+                }
+                .ok();
+                write!(shell, "{0:}Output: {1:}%{0:}Gains:", CR, output).ok();
+                for (name, gain) in [("kp", gains.kp), ("ki", gains.ki), ("kd", gains.kd)] {
+                    write!(shell, " {}={}.{:02}", name, gain / 100, gain % 100).ok();
+                }
+                write!(
+                    shell,
+                    "{0:}CSV: {1:}{0:}",
+                    CR,
+                    if csv { "On" } else { "Off" }
+                )
+                .ok();
+            }
+            (Some("on"), None, ..) => {
+                if self.sensors.lock(|s| s.is_streaming()) {
+                    metrics::set_exit_status(ExitStatus::Error);
+                    write!(shell, "{0:}PA0 is streaming, turn audio off first{0:}", CR).ok();
+                    return;
+                }
+                if let Err(err) = self
+                    .pwmout
+                    .lock(|p| p.start(Channel::Ch2, Pid::PWM_FREQ, 0))
+                {
+                    pins::write_error(shell, err);
+                    return;
+                }
+                self.pid.lock(|p| p.set_enabled(true));
+                shell.write_str(CR).ok();
+            }
+            (Some("off"), None, ..) => {
+                if self.pid.lock(|p| p.is_enabled()) {
+                    self.pid.lock(|p| p.set_enabled(false));
+                    self.pwmout.lock(|p| p.stop());
+                }
+                shell.write_str(CR).ok();
+            }
+            (Some("set"), Some(name @ ("kp" | "ki" | "kd")), Some(value), None) => {
+                let gain = match pid::parse_gain(value) {
+                    Some(gain) => gain,
+                    None => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                        return;
+                    }
+                };
+                let mut gains = self.pid.lock(|p| p.gains());
+                match name {
+                    "kp" => gains.kp = gain,
+                    "ki" => gains.ki = gain,
+                    _ => gains.kd = gain,
+                }
+                self.pid.lock(|p| p.set_gains(gains));
+                shell.write_str(CR).ok();
+            }
+            (Some("target"), Some(value), None, _) => match btoi::btoi::<u32>(value.as_bytes()) {
+                Ok(target) if target <= Pid::MAX_TARGET_MV => {
+                    self.pid.lock(|p| p.set_target_mv(target));
+                    shell.write_str(CR).ok();
+                }
+                _ => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                }
+            },
+            (Some("csv"), Some(mode @ ("on" | "off")), None, _) => {
+                self.pid.lock(|p| p.set_streaming(mode == "on"));
+                if mode == "on" {
+                    write!(shell, "{0:}ms,target_mv,input_mv,duty{0:}", CR).ok();
+                } else {
+                    shell.write_str(CR).ok();
+                }
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    /// Switches the heater only when the thermostat changes state, a channel the
+    /// max-on watchdog switched off stays off until the next heating cycle
+    fn thermostat_control(&mut self, shell: &mut Shell) {
+        if !self.thermostat.lock(|t| t.take_due()) {
+            return;
+        }
+        let temp = self.sensors.lock(|s| s.temp_c());
+        let (channel, heating) = self.thermostat.lock(|t| (t.channel(), t.update(temp)));
+        let heating = match heating {
+            Some(heating) => heating,
+            None => return,
+        };
+        self.switches.lock(|s| {
+            if heating {
+                s.on(channel)
+            } else {
+                s.off(channel)
+            }
+        });
+        write!(
+            shell,
+            "\r\x1b[Kthermostat: heater {} ({}C){}{}",
+            if heating { "on" } else { "off" },
+            temp,
+            CR,
+            settings::prompt()
+        )
+        .ok();
+    }
+
+    pub fn thermostat_command(&mut self, shell: &mut Shell, args: &str) {
+        let (subcmd, arg) = args.split_once(" ").unwrap_or((args, ""));
+        let value = btoi::btoi::<i32>(arg.as_bytes()).ok();
+        match (subcmd, value) {
+            ("", _) => {
+                let temp = self.sensors.lock(|s| s.temp_c());
+                let (enabled, setpoint, hyst, channel, heating) = self.thermostat.lock(|t| {
+                    (
+                        t.is_enabled(),
+                        t.setpoint(),
+                        t.hysteresis(),
+                        t.channel(),
+                        t.is_heating(),
+                    )
+                });
+                write!(
+                    shell,
+                    "{0:}Thermostat: {1:}{0:}Temperature: {2:}C{0:}Setpoint: {3:}C (-{4:}C){0:}\
+                     Heater: out {5:}, {6:}{0:}",
+                    CR,
+                    if enabled { "On" } else { "Off" },
+                    temp,
+                    setpoint,
+                    hyst,
+                    channel,
+                    if heating { "on" } else { "off" }
+                )
+                .ok();
+            }
+            ("on", _) | ("off", _) if arg.is_empty() => {
+                let (was_heating, channel) =
+                    self.thermostat.lock(|t| (t.is_heating(), t.channel()));
+                if was_heating {
+                    self.switches.lock(|s| s.off(channel));
+                }
+                self.thermostat.lock(|t| t.set_enabled(subcmd == "on"));
+                shell.write_str(CR).ok();
+            }
+            ("setpoint", Some(setpoint))
+                if (Thermostat::MIN_SETPOINT..=Thermostat::MAX_SETPOINT).contains(&setpoint) =>
+            {
+                self.thermostat.lock(|t| t.set_setpoint(setpoint));
+                shell.write_str(CR).ok();
+            }
+            ("hyst", Some(hyst)) if (0..=Thermostat::MAX_HYSTERESIS).contains(&hyst) => {
+                self.thermostat.lock(|t| t.set_hysteresis(hyst));
+                shell.write_str(CR).ok();
+            }
+            ("out", Some(channel)) if (0..switch::CHANNELS as i32).contains(&channel) => {
+                if self.thermostat.lock(|t| t.is_enabled()) {
+                    write!(shell, "{0:}turn thermostat off first{0:}", CR).ok();
+                    return;
+                }
+                self.thermostat.lock(|t| t.set_channel(channel as usize));
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    fn touch_check(&mut self, shell: &mut Shell) {
+        if !self.touch.lock(|t| t.take_due()) {
+            return;
+        }
+        let reading = self.touch.lock(|t| t.measure());
+        if self.touch.lock(|t| t.update(reading)) {
+            let on = self.blink_enabled.lock(|e| {
+                *e = !*e;
+                *e
+            });
+            let state = if on { "on" } else { "off" };
+            write!(
+                shell,
+                "\r\x1b[Ktouch: animation {}{}{}",
+                state,
+                CR,
+                settings::prompt()
+            )
+            .ok();
+        }
+    }
+
+    pub fn touch_command(&mut self, shell: &mut Shell, args: &str) {
+        let (subcmd, arg) = args.split_once(" ").unwrap_or((args, ""));
+        match subcmd {
+            "cal" => {
+                let baseline = self.touch.lock(|t| t.calibrate());
+                write!(shell, "{0:}Baseline: {1:} cycles{0:}", CR, baseline).ok();
+            }
+            "read" => {
+                let (reading, baseline, threshold, touched) = self.touch.lock(|t| {
+                    let reading = t.measure();
+                    t.update(reading);
+                    (reading, t.baseline(), t.threshold(), t.is_touched())
+                });
+                write!(
+                    shell,
+                    "{0:}Reading: {1:} cycles{0:}Baseline: {2:} cycles{0:}Threshold: {3:}%{0:}Touched: {4:}{0:}",
+                    CR,
+                    reading,
+                    baseline,
+                    threshold,
+                    if touched { "yes" } else { "no" }
+                )
+                .ok();
+            }
+            "on" | "off" => {
+                if subcmd == "on" && self.touch.lock(|t| t.baseline()) == 0 {
+                    write!(shell, "{0:}run touch cal first{0:}", CR).ok();
+                    return;
+                }
+                self.touch.lock(|t| t.set_enabled(subcmd == "on"));
+                shell.write_str(CR).ok();
+            }
+            "threshold" => match btoi::btoi::<u32>(arg.as_bytes()) {
+                Ok(threshold) if (1..=1000).contains(&threshold) => {
+                    self.touch.lock(|t| t.set_threshold(threshold));
+                    shell.write_str(CR).ok();
+                }
+                _ => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                }
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn telemetry_command(&mut self, shell: &mut Shell, args: &str) {
+        let (subcmd, arg) = args.split_once(" ").unwrap_or((args, ""));
+        match subcmd {
+            "" => {
+                let (enabled, interval) =
+                    self.telemetry.lock(|t| (t.is_enabled(), t.interval_ms()));
+                if enabled {
+                    write!(shell, "{0:}Telemetry: every {1:}ms{0:}", CR, interval).ok();
+                } else {
+                    write!(shell, "{0:}Telemetry: Off{0:}", CR).ok();
+                }
+            }
+            "on" => match btoi::btoi(arg.as_bytes()) {
+                Ok(interval)
+                    if (Telemetry::MIN_INTERVAL_MS..=Telemetry::MAX_INTERVAL_MS)
+                        .contains(&interval) =>
+                {
+                    self.telemetry.lock(|t| t.start(interval));
+                    shell.write_str(CR).ok();
+                }
+                _ => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                }
+            },
+            "off" => {
+                self.telemetry.lock(|t| t.stop());
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    /// Hexdump of memory read a word at a time, so peripheral registers read safely too
+    pub fn md_command(shell: &mut Shell, args: &str) {
+        const DEFAULT_LEN: u32 = 64;
+
+        let mut args = args.split_whitespace();
+        let addr = args.next().and_then(parse_num);
+        let len = match args.next() {
+            Some(len) => parse_num(len),
+            None => Some(DEFAULT_LEN),
+        };
+        let (addr, len) = match (addr, len, args.next()) {
+            (Some(addr), Some(len), None) => (addr, len),
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        if len > mem::MAX_DUMP_LEN {
+            write!(shell, "{0:}at most {1:} bytes{0:}", CR, mem::MAX_DUMP_LEN).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return;
+        }
+        let len = (len + 3) & !0b11;
+        if !mem::is_valid_range(addr, len) {
+            write!(shell, "{0:}invalid address or length{0:}", CR).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return;
+        }
+
+        let mut buf = [0; mem::MAX_DUMP_LEN as usize];
+        for (idx, word) in buf[..len as usize].chunks_mut(4).enumerate() {
+            word.copy_from_slice(&mem::read_word(addr + idx as u32 * 4).to_le_bytes());
+        }
+        shell.write_str(CR).ok();
+        hex::dump_at(shell, addr, &buf[..len as usize]);
+    }
+
+    pub fn mw_command(shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        match (
+            args.next().and_then(parse_num),
+            args.next().and_then(parse_num),
+            args.next(),
+        ) {
+            (Some(addr), Some(_), None) if !mem::is_valid_word(addr) => {
+                write!(shell, "{0:}invalid address{0:}", CR).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+            (Some(addr), Some(value), None) => {
+                let before = mem::read_word(addr);
+                mem::write_word(addr, value);
+                let after = mem::read_word(addr);
+                write!(
+                    shell,
+                    "{0:}0x{1:08x}: 0x{2:08x} -> 0x{3:08x}{0:}",
+                    CR, addr, before, after
+                )
+                .ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn bits_command(shell: &mut Shell, args: &str) {
+        let (args, value) = match args.split_once('=') {
+            Some((args, value)) => (args, Some(parse_num(value.trim()))),
+            None => (args, None),
+        };
+        let mut args = args.split_whitespace();
+        let addr = args.next().and_then(parse_num);
+        let field = args.next().and_then(parse_field);
+        match (addr, field, value, args.next()) {
+            (Some(addr), _, _, None) if !mem::is_valid_word(addr) => {
+                write!(shell, "{0:}invalid address{0:}", CR).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+            (Some(_), Some((_, lsb, msb)), Some(Some(value)), None)
+                if value > field_mask(lsb, msb) >> lsb =>
+            {
+                write!(shell, "{0:}value does not fit field{0:}", CR).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+            (Some(addr), Some((name, lsb, msb)), Some(Some(value)), None) => {
+                let mask = field_mask(lsb, msb);
+                let before = mem::read_word(addr);
+                mem::write_word(addr, (before & !mask) | (value << lsb));
+                let after = mem::read_word(addr);
+                write!(
+                    shell,
+                    "{0:}{1:}[{2:}:{3:}]: 0x{4:x} -> 0x{5:x}{0:}Register 0x{6:08x}: 0x{7:08x} -> 0x{8:08x}{0:}",
+                    CR,
+                    name,
+                    msb,
+                    lsb,
+                    (before & mask) >> lsb,
+                    (after & mask) >> lsb,
+                    addr,
+                    before,
+                    after
+                )
+                .ok();
+                if (after & mask) >> lsb != value {
+                    write!(
+                        shell,
+                        "verify failed: field reads back 0x{:x}{}",
+                        (after & mask) >> lsb,
+                        CR
+                    )
+                    .ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            }
+            (Some(addr), Some((name, lsb, msb)), None, None) => {
+                let value = mem::read_word(addr);
+                let mask = field_mask(lsb, msb);
+                write!(
+                    shell,
+                    "{0:}{1:}[{2:}:{3:}]: 0x{4:x}{0:}Register 0x{5:08x}: 0x{6:08x}{0:}",
+                    CR,
+                    name,
+                    msb,
+                    lsb,
+                    (value & mask) >> lsb,
+                    addr,
+                    value
+                )
+                .ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    /// `reboot safe` starts in safe mode, skipping the `rc` script and the drivers
+    pub fn reboot_command(shell: &mut Shell, args: &str) {
+        match args {
+            "" => {}
+            "safe" => safemode::request(),
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        }
+        write!(shell, "{0:}rebooting{0:}", CR).ok();
+        nb::block!(shell.serial().flush()).ok();
+        boot::reboot()
+    }
+
+    /// Jumps to the ROM bootloader after a y/N prompt, `dfu now` skips it. The shell
+    /// blocks on the answer for up to `DFU_CONFIRM_S`.
+    pub fn dfu_command(shell: &mut Shell, args: &str) {
+        let skip_prompt = match args {
+            "" => false,
+            "now" => true,
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        if !BootConfig::read().bootloader_reachable() {
+            write!(
+                shell,
+                "{0:}ROM bootloader is unreachable, see dfu-check{0:}",
+                CR
+            )
+            .ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return;
+        }
+        if !skip_prompt {
+            write!(shell, "{}jump to the ROM bootloader? [y/N] ", CR).ok();
+            nb::block!(shell.serial().flush()).ok();
+            let timeout = cycles::freq() * DFU_CONFIRM_S;
+            let start = cycles::now();
+            let answer = loop {
+                if let Ok(byte) = shell.serial().read() {
+                    break Some(byte);
+                }
+                if cycles::since(start) > timeout || budget::expired() {
+                    break None;
+                }
+            };
+            if !matches!(answer, Some(b'y') | Some(b'Y')) {
+                write!(shell, "{0:}cancelled{0:}", CR).ok();
+                return;
+            }
+        }
+        write!(
+            shell,
+            "{0:}entering the ROM bootloader, reset to come back{0:}",
+            CR
+        )
+        .ok();
+        nb::block!(shell.serial().flush()).ok();
+        boot::jump_to_bootloader()
+    }
+
+    pub fn dfu_check(shell: &mut Shell) {
+        let boot = BootConfig::read();
+        let boot0_source = if boot.boot0_from_pin {
+            "PA14 pin"
+        } else {
+            "nBOOT0 option bit"
+        };
+        let bootloader = if boot.bootloader_reachable() {
+            "reachable"
+        } else {
+            "unreachable"
+        };
+        write!(
+            shell,
+            "{0:}RDP level:   {1:}{0:}BOOT0:       {2:} ({3:}){0:}nBOOT1:      {4:}{0:}\
+             Flash empty: {5:}{0:}Next reset:  {6:}{0:}Bootloader:  {7:}, SP 0x{8:08x}, entry 0x{9:08x}{0:}",
+            CR,
+            boot.rdp_level,
+            boot.boot0 as u8,
+            boot0_source,
+            !boot.boot1 as u8,
+            boot.flash_empty,
+            boot.reset_target().name(),
+            bootloader,
+            boot.bootloader_sp,
+            boot.bootloader_entry
+        )
+        .ok();
+
+        if boot.rdp_level == 2 {
+            write!(
+                shell,
+                "warning: RDP level 2 disables the ROM bootloader{}",
+                CR
+            )
+            .ok();
+        }
+        if boot.reset_target() != BootTarget::Flash {
+            write!(
+                shell,
+                "warning: next reset boots {}, the shell is unreachable until BOOT0 is changed{}",
+                boot.reset_target().name(),
+                CR
+            )
+            .ok();
+        }
+        if boot.boot0_from_pin {
+            write!(
+                shell,
+                "note: PA14 (SWCLK) high during reset selects the bootloader{}",
+                CR
+            )
+            .ok();
+        }
+    }
+
+    pub fn dma_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        match args.next() {
+            Some("copy") => {
+                let src = args.next().and_then(parse_num);
+                let dst = args.next().and_then(parse_num);
+                let len = args.next().and_then(parse_num);
+                match (src, dst, len) {
+                    (Some(src), Some(dst), Some(len)) => {
+                        let msg = match self.mem_dma.lock(|d| d.copy(src, dst, len)) {
+                            Ok(()) => {
+                                shell.write_str(CR).ok();
+                                return;
+                            }
+                            Err(DmaError::InvalidRange) => "destination out of RAM",
+                            Err(DmaError::InvalidSource) => "source out of mapped memory",
+                            Err(DmaError::TransferError) => "transfer error",
+                        };
+                        write!(shell, "{0:}{1:}{0:}", CR, msg).ok();
+                        metrics::set_exit_status(ExitStatus::Error);
+                    }
+                    _ => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                }
+            }
+            Some("bench") => self.dma_bench(shell),
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    fn dma_bench(&mut self, shell: &mut Shell) {
+        const WORDS: usize = 256;
+
+        let mut src = [0u32; WORDS];
+        for (idx, word) in src.iter_mut().enumerate() {
+            *word = idx as u32 ^ 0x5a5a_a5a5;
+        }
+        let mut dst = [0u32; WORDS];
+
+        let start = cycles::now();
+        dst.copy_from_slice(&src);
+        core::hint::black_box(&mut dst);
+        let cpu = cycles::since(start);
+
+        dst = [0; WORDS];
+        let start = cycles::now();
+        let res = self.mem_dma.lock(|d| d.copy_words(&src, &mut dst));
+        let dma = cycles::since(start);
+
+        if res.is_err() || dst != src {
+            write!(shell, "{0:}DMA copy failed{0:}", CR).ok();
+            return;
+        }
+
+        let bytes = (WORDS * 4) as u64;
+        let freq = cycles::freq() as u64;
+        write!(shell, "{0:}Copy {1:} bytes:{0:}", CR, bytes).ok();
+        for (name, cycles) in [("CPU", cpu), ("DMA", dma)].iter() {
+            let rate = bytes * freq / (*cycles as u64).max(1) / 1024;
+            write!(shell, "  {}: {} cycles, {} KB/s{}", name, cycles, rate, CR).ok();
+        }
+    }
+
+    pub fn gpio_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        let (subcmd, pin, arg) = (args.next(), args.next(), args.next());
+        let pin = pin.and_then(trigger::parse_pin);
+        let res = match (subcmd, pin, arg, args.next()) {
+            (None, ..) => {
+                shell.write_str(CR).ok();
+                for pin in 0..16 {
+                    let owner = match self.gpio.lock(|g| g.mode(pin)) {
+                        Some(mode) => mode.name(),
+                        None => "-",
+                    };
+                    write!(
+                        shell,
+                        "PA{:<2} {:<9} {:<4} {}{}",
+                        pin,
+                        gpio::moder_name(pin),
+                        if gpio::read(pin) { "high" } else { "low" },
+                        owner,
+                        CR
+                    )
+                    .ok();
+                }
+                return;
+            }
+            (Some("get"), Some(pin), None, None) => {
+                let level = if gpio::read(pin) { "high" } else { "low" };
+                write!(shell, "{0:}PA{1:}: {2:}{0:}", CR, pin, level).ok();
+                return;
+            }
+            (Some("set"), Some(pin), Some(level @ ("high" | "low")), None) => {
+                self.gpio.lock(|g| g.write(pin, level == "high"))
+            }
+            (Some("mode"), Some(pin), Some(mode), None) => match PinMode::from_name(mode) {
+                Some(mode) => self.gpio.lock(|g| g.set_mode(pin, mode)),
+                None => {
+                    write!(shell, "{0:}Modes:", CR).ok();
+                    for (name, _) in PIN_MODES.iter() {
+                        write!(shell, " {}", name).ok();
+                    }
+                    shell.write_str(CR).ok();
+                    return;
+                }
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        match res {
+            Ok(()) => {
+                shell.write_str(CR).ok();
+            }
+            Err(GpioError::Pin(err)) => pins::write_error(shell, err),
+            Err(err) => {
+                metrics::set_exit_status(ExitStatus::Error);
+                write!(shell, "{0:}gpio: {1:}{0:}", CR, err.message()).ok();
+            }
+        }
+    }
+
+    pub fn health_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args
+            .split_whitespace()
+            .map(|arg| (arg, btoi::btoi::<u32>(arg.as_bytes())));
+        let mut limits = self.health.lock(|h| h.limits());
+        match (args.next(), args.next(), args.next(), args.next()) {
+            (None, _, _, _) => {
+                let (temp, vdd) = self.sensors.lock(|s| (s.temp_c(), s.vdda_mv()));
+                let (enabled, throttle) = self.health.lock(|h| (h.is_enabled(), h.throttle()));
+                write!(
+                    shell,
+                    "{0:}Health: {1:}{0:}Temperature: {2:}C (max {3:}C){0:}\
+                     Supply: {4:}mV ({5:}-{6:}mV){0:}Throttle: {7:}{0:}Warnings:",
+                    CR,
+                    if enabled { "On" } else { "Off" },
+                    temp,
+                    limits.temp_max,
+                    vdd,
+                    limits.vdd_min,
+                    limits.vdd_max,
+                    if throttle { "On" } else { "Off" }
+                )
+                .ok();
+                let mut none = true;
+                for (name, alarm) in ALARMS.iter() {
+                    if self.health.lock(|h| h.is_raised(*alarm)) {
+                        write!(shell, " {},", name).ok();
+                        none = false;
+                    }
+                }
+                if none {
+                    shell.write_str(" none").ok();
+                }
+                shell.write_str(CR).ok();
+            }
+            (Some(("on", _)), None, _, _) => {
+                self.health.lock(|h| h.set_enabled(true));
+                shell.write_str(CR).ok();
+            }
+            (Some(("off", _)), None, _, _) => {
+                self.health.lock(|h| h.set_enabled(false));
+                self.clock.lock(|c| c.set_throttled(false));
+                shell.write_str(CR).ok();
+            }
+            (Some(("throttle", _)), Some((mode @ ("on" | "off"), _)), None, _) => {
+                self.health.lock(|h| h.set_throttle(mode == "on"));
+                let throttling = self.health.lock(|h| h.is_throttling());
+                self.clock.lock(|c| c.set_throttled(throttling));
+                shell.write_str(CR).ok();
+            }
+            (Some(("temp", _)), Some((_, Ok(max))), None, _) if max <= 125 => {
+                limits.temp_max = max as i32;
+                self.health.lock(|h| h.set_limits(limits));
+                shell.write_str(CR).ok();
+            }
+            (Some(("vdd", _)), Some((_, Ok(min))), Some((_, Ok(max))), None)
+                if min < max && max <= 3600 =>
+            {
+                limits.vdd_min = min;
+                limits.vdd_max = max;
+                self.health.lock(|h| h.set_limits(limits));
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn clkgate_command(shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next(), args.next()) {
+            (None, _, _) => clkgate::write_report(shell),
+            (Some("off"), Some(periph), None) => match clkgate::off(periph) {
+                Ok(()) => {
+                    shell.write_str(CR).ok();
+                }
+                Err(err) => {
+                    metrics::set_exit_status(ExitStatus::Error);
+                    clkgate::write_error(shell, err);
+                }
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn after_command(&mut self, shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            self.jobs_list(shell);
+            return;
+        }
+        self.schedule(shell, "after", args);
+    }
+
+    pub fn every_command(&mut self, shell: &mut Shell, args: &str) {
+        self.schedule(shell, "every", args);
+    }
+
+    pub fn jobs_list(&mut self, shell: &mut Shell) {
+        let now = mono::now_ms();
+        write!(shell, "{0:}ID     Next   Every  Command{0:}", CR).ok();
+        self.jobs.lock(|j| {
+            for job in j.pending() {
+                let left = job.at_ms.saturating_sub(now).div_ceil(1000);
+                write!(shell, "{:<4} {:>5}s ", job.id, left).ok();
+                match job.every {
+                    Some(every) => write!(shell, "{:>6}s", every),
+                    None => write!(shell, "{:>7}", "-"),
+                }
+                .ok();
+                write!(shell, "  {}{}", job.line, CR).ok();
+            }
+        });
+    }
+
+    pub fn killjob_command(&mut self, shell: &mut Shell, args: &str) {
+        let id = match btoi::btoi::<u16>(args.trim().as_bytes()) {
+            Ok(id) => id,
+            Err(_) => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        if self.jobs.lock(|j| j.remove(id)) {
+            shell.write_str(CR).ok();
+            detail!(shell, "Job {} killed{}", id, CR);
+        } else {
+            write!(shell, "{0:}killjob: no job {1:}{0:}", CR, id).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+        }
+    }
+
+    /// Command line to store for later. A signed command is checked now, its nonce would
+    /// be stale by the time it runs, and is kept without the signature. The replay takes
+    /// it on trust, so an alias standing for a signed command is refused here already.
+    fn deferred_line(shell: &mut Shell, line: &str) -> Option<String<CMD_MAX_LEN>> {
+        let (cmd, cmd_args) = line.split_once(" ").unwrap_or((line, ""));
+        if !provision::signing() {
+            return Some(line.into());
+        }
+        let aliased = match aliases::expand(cmd, "") {
+            Ok(Some(expanded)) => expanded
+                .split(' ')
+                .next()
+                .is_some_and(signing::is_dangerous),
+            _ => false,
+        };
+        if aliased {
+            let err = SignError::Alias;
+            write!(shell, "{0:}{1:}: {2:}{0:}", CR, cmd, err.message()).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return None;
+        }
+        if !signing::is_dangerous(cmd) {
+            return Some(line.into());
+        }
+        let (cmd_args, signature) = signing::split(cmd_args);
+        if let Err(err) = signing::verify(cmd, cmd_args, signature) {
+            write!(shell, "{0:}{1:}: {2:}{0:}", CR, cmd, err.message()).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return None;
+        }
+        let mut unsigned: String<CMD_MAX_LEN> = String::new();
+        write!(unsigned, "{} {}", cmd, cmd_args).ok();
+        Some(unsigned.trim_end().into())
+    }
+
+    /// Shared by `after` and `every`, the latter repeats the command every `secs`
+    fn schedule(&mut self, shell: &mut Shell, name: &str, args: &str) {
+        let (secs, line) = args.split_once(" ").unwrap_or((args, ""));
+        let line = line.trim();
+        let secs = match btoi::btoi::<u32>(secs.as_bytes()) {
+            Ok(secs) if (1..=jobs::MAX_DELAY_S).contains(&secs) && !line.is_empty() => secs,
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        let line = match Self::deferred_line(shell, line) {
+            Some(line) => line,
+            None => return,
+        };
+
+        let every = if name == "every" { Some(secs) } else { None };
+        let at_ms = mono::now_ms() + secs as u64 * 1000;
+        let res = self
+            .jobs
+            .lock(|j| j.add(&line, at_ms, every))
+            .and_then(|id| {
+                job_due::spawn_at(Instant::new(at_ms), id).map_err(|id| {
+                    self.jobs.lock(|j| j.remove(id));
+                    JobError::Full
+                })?;
+                Ok(id)
+            });
+        match res {
+            Ok(id) => {
+                shell.write_str(CR).ok();
+                match every {
+                    Some(_) => detail!(shell, "Job {} runs every {}s{}", id, secs, CR),
+                    None => detail!(shell, "Job {} runs in {}s{}", id, secs, CR),
+                }
+            }
+            Err(err) => {
+                write!(shell, "{0:}{1:}: {2:}{0:}", CR, name, err.message()).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+        }
+    }
+
+    /// Identity of the chip and the firmware in one block, for labels and bug reports
+    pub fn info_command(shell: &mut Shell) {
+        let (dev_id, rev_id) = sysinfo::idcode();
+        let uid = sysinfo::uid();
+        let info = &BUILD_INFO;
+        write!(
+            shell,
+            "{0:}Device:   {1:} (0x{2:03x}){0:}Revision: {3:} (0x{4:04x}){0:}\
+             UID:      {5:08x}{6:08x}{7:08x}{0:}Flash:    {8:}KiB{0:}Core:     {9:}Hz{0:}\
+             Firmware: {10:} ({11:}, built {12:}){0:}",
+            CR,
+            sysinfo::device_name(dev_id).unwrap_or("unknown"),
+            dev_id,
+            sysinfo::revision_name(rev_id).unwrap_or("unknown"),
+            rev_id,
+            uid[2],
+            uid[1],
+            uid[0],
+            sysinfo::flash_kib(),
+            cycles::freq(),
+            info.version,
+            info.git_hash,
+            info.timestamp
+        )
+        .ok();
+    }
+
+    pub fn uptime_command(shell: &mut Shell) {
+        let secs = monotonics::now().duration_since_epoch().integer() / 1000;
+        write!(
+            shell,
+            "{0:}Uptime: {1:}d {2:02}h {3:02}m {4:02}s{0:}",
+            CR,
+            secs / 86_400,
+            secs / 3600 % 24,
+            secs / 60 % 60,
+            secs % 60
+        )
+        .ok();
+    }
+
+    pub fn hw_command(&mut self, shell: &mut Shell) {
+        self.hw.lock(|hw| {
+            let state = if !hw.has_i2c() {
+                State::Absent
+            } else if hw.bus_on(BusKind::I2c) {
+                State::On
+            } else {
+                State::Off
+            };
+            write!(
+                shell,
+                "{}{:<8} {:<8} I2C1 bus on PB8/PB9",
+                CR,
+                "i2c",
+                state.name()
+            )
+            .ok();
+            for (name, desc, _, state) in hw.devices() {
+                write!(shell, "{}{:<8} {:<8} {}", CR, name, state.name(), desc).ok();
+            }
+        });
+        shell.write_str(CR).ok();
+    }
+
+    pub fn driver_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        let res = match (args.next(), args.next(), args.next()) {
+            (None, _, _) | (Some("list"), None, _) => {
+                self.hw.lock(|hw| {
+                    for (name, _, bus, state) in hw.devices() {
+                        let bus_state = if hw.bus_on(bus) { "up" } else { "down" };
+                        write!(
+                            shell,
+                            "{}{:<8} {:<8} {} {}",
+                            CR,
+                            name,
+                            state.name(),
+                            bus.name(),
+                            bus_state
+                        )
+                        .ok();
+                    }
+                });
+                shell.write_str(CR).ok();
+                return;
+            }
+            (Some("enable"), Some(name), None) => self.hw.lock(|hw| hw.enable(name)),
+            (Some("disable"), Some(name), None) => self.hw.lock(|hw| hw.disable(name)),
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        match res {
+            Ok(()) => {
+                shell.write_str(CR).ok();
+            }
+            Err(DriverError::Pin(err)) => pins::write_error(shell, err),
+            Err(err) => {
+                metrics::set_exit_status(ExitStatus::Error);
+                write!(shell, "{0:}{1:}{0:}", CR, err.message()).ok();
+            }
+        }
+    }
+
+    pub fn i2c_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next(), args.next()) {
+            (Some("scan"), None, _) => self.i2c_scan(shell),
+            (Some("sniff"), Some(ms), None) => match btoi::btoi::<u32>(ms.as_bytes()) {
+                Ok(ms) if (1..=sniff::MAX_DURATION_MS).contains(&ms) => {
+                    Sniff::run(ms).write(shell);
+                }
+                _ => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                }
+            },
+            (Some("w"), Some(addr), Some(first)) => {
+                let addr = hex::parse_byte(addr).filter(|addr| *addr <= 0x7f);
+                let bytes =
+                    hex::parse_bytes::<{ i2c::MAX_LEN }>(Some(first).into_iter().chain(args));
+                match (addr, bytes) {
+                    (None, _) => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                    (Some(addr), Some(bytes)) => {
+                        let res = self
+                            .hw
+                            .lock(|hw| hw.i2c().map(|i2c| i2c.write(addr, &bytes)));
+                        if Self::i2c_done(shell, res) {
+                            shell.write_str(CR).ok();
+                        }
+                    }
+                    _ => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                }
+            }
+            (Some("r"), Some(addr), Some(len)) if args.next().is_none() => {
+                let addr = hex::parse_byte(addr).filter(|addr| *addr <= 0x7f);
+                let len = btoi::btoi::<usize>(len.as_bytes())
+                    .ok()
+                    .filter(|len| (1..=i2c::MAX_LEN).contains(len));
+                match (addr, len) {
+                    (None, _) => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                    (Some(addr), Some(len)) => {
+                        let mut buf = [0; i2c::MAX_LEN];
+                        let res = self
+                            .hw
+                            .lock(|hw| hw.i2c().map(|i2c| i2c.read(addr, &mut buf[..len])));
+                        if Self::i2c_done(shell, res) {
+                            shell.write_str(CR).ok();
+                            hex::dump(shell, &buf[..len]);
+                        }
+                    }
+                    _ => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                }
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    /// Reports a missing bus or a failed transfer, true when the transfer went through
+    fn i2c_done(shell: &mut Shell, res: Option<Result<(), I2cError>>) -> bool {
+        match res {
+            Some(Ok(())) => true,
+            Some(Err(err)) => {
+                drivers::write_i2c_error(shell, err);
+                false
+            }
+            None => {
+                write!(
+                    shell,
+                    "{0:}i2c: bus not detected, check the pull-ups{0:}",
+                    CR
+                )
+                .ok();
+                false
+            }
+        }
+    }
+
+    pub fn slave_command(&mut self, shell: &mut Shell, args: &str) {
+        match args.trim() {
+            "" => {
+                let (enabled, frames, resyncs, received) = self
+                    .slave
+                    .lock(|s| (s.is_enabled(), s.frames(), s.resyncs(), s.received()));
+                if !enabled {
+                    write!(shell, "{0:}SPI slave: Off{0:}", CR).ok();
+                    return;
+                }
+                write!(
+                    shell,
+                    "{0:}SPI slave: On{0:}Transactions: {1:}{0:}Resyncs: {2:}{0:}Received:",
+                    CR, frames, resyncs
+                )
+                .ok();
+                hex::write_bytes(shell, &received);
+                shell.write_str(CR).ok();
+            }
+            "on" => {
+                let status = slave::Status {
+                    animation: self.blink_enabled.lock(|e| *e),
+                    led: self.led.lock(|l| l.is_on()),
+                    freq: self.blink_freq.lock(|f| *f),
+                    ticks: self.ticks.lock(|t| *t),
+                    edges: self.counter.lock(|c| c.count()),
+                };
+                match self.slave.lock(|s| s.start(&status)) {
+                    Ok(()) => {
+                        shell.write_str(CR).ok();
+                    }
+                    Err(err) => pins::write_error(shell, err),
+                }
+            }
+            "off" => {
+                self.slave.lock(|s| s.stop());
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn spi_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next(), args.next()) {
+            (Some("cfg"), None, _) => {
+                let config = self.hw.lock(|hw| hw.spi().xfer_config());
+                write!(
+                    shell,
+                    "{0:}Mode: {1:}{0:}Clock: {2:}Hz{0:}",
+                    CR,
+                    config.mode(),
+                    config.freq()
+                )
+                .ok();
+            }
+            (Some("cfg"), Some(mode), Some(freq)) if args.next().is_none() => {
+                let mode = btoi::btoi::<u8>(mode.as_bytes());
+                let freq = btoi::btoi::<u32>(freq.as_bytes());
+                match (mode, freq) {
+                    (Ok(mode), Ok(freq)) if mode <= 3 && freq > 0 => {
+                        let config = self.hw.lock(|hw| {
+                            hw.spi().set_xfer_config(mode, freq);
+                            hw.spi().xfer_config()
+                        });
+                        shell.write_str(CR).ok();
+                        detail!(shell, "Clock: {}Hz{}", config.freq(), CR);
+                    }
+                    _ => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                }
+            }
+            (Some("xfer"), Some(first), _) => {
+                let bytes =
+                    hex::parse_bytes::<{ spi::MAX_XFER }>(Some(first).into_iter().chain(args));
+                match bytes {
+                    Some(mut bytes) => match self.hw.lock(|hw| hw.spi().xfer(&mut bytes)) {
+                        Ok(()) => {
+                            shell.write_str(CR).ok();
+                            shell.write_str("RX").ok();
+                            hex::write_bytes(shell, &bytes);
+                            shell.write_str(CR).ok();
+                        }
+                        Err(SpiError::Timeout) => {
+                            write!(shell, "{0:}spi: timeout{0:}", CR).ok();
+                        }
+                        Err(SpiError::Off) => {
+                            write!(shell, "{0:}spi: bus is off, enable a driver on it{0:}", CR)
+                                .ok();
+                        }
+                    },
+                    None => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                }
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    /// Same layout as i2cdetect: one row per 16 addresses, reserved ones left blank
+    fn i2c_scan(&mut self, shell: &mut Shell) {
+        let mut found = [false; 0x80];
+        let res = self.hw.lock(|hw| {
+            hw.i2c().map(|i2c| {
+                if !i2c.is_on() {
+                    return Err(I2cError::Off);
+                }
+                for addr in i2c::SCAN_FIRST..=i2c::SCAN_LAST {
+                    found[addr as usize] = i2c.probe(addr);
+                }
+                Ok(())
+            })
+        });
+        if !Self::i2c_done(shell, res) {
+            return;
+        }
+
+        write!(shell, "{}    ", CR).ok();
+        for col in 0..0x10 {
+            write!(shell, "  {:x}", col).ok();
+        }
+        for row in (0..0x80).step_by(0x10) {
+            write!(shell, "{}{:02x}:", CR, row).ok();
+            for (addr, found) in found.iter().enumerate().skip(row).take(0x10) {
+                if addr < i2c::SCAN_FIRST as usize || addr > i2c::SCAN_LAST as usize {
+                    shell.write_str("   ").ok();
+                } else if *found {
+                    write!(shell, " {:02x}", addr).ok();
+                } else {
+                    shell.write_str(" --").ok();
+                }
+            }
+        }
+        let count = found.iter().filter(|found| **found).count();
+        write!(shell, "{0:}Found: {1:}{0:}", CR, count).ok();
+    }
+
+    pub fn cpu_command(&mut self, shell: &mut Shell, split: bool) {
+        let usage = match self.cpu.lock(|c| c.last()) {
+            Some(usage) => usage,
+            None => {
+                write!(shell, "{0:}no full second measured yet{0:}", CR).ok();
+                return;
+            }
+        };
+        let busy_us = usage.total_us - usage.idle_us;
+        write!(
+            shell,
+            "{0:}CPU load: {1:}%{0:}",
+            CR,
+            Usage::percent(busy_us, usage.total_us)
+        )
+        .ok();
+        if !split {
+            return;
+        }
+        let other_us = busy_us.saturating_sub(usage.load_us);
+        for (name, us) in [
+            ("idle", usage.idle_us),
+            ("load_tick", usage.load_us),
+            ("other", other_us),
+        ] {
+            write!(
+                shell,
+                "{:<10}{:>3}% {:>8}us{}",
+                name,
+                Usage::percent(us, usage.total_us),
+                us,
+                CR
+            )
+            .ok();
+        }
+    }
+
+    /// Last CPU load window with the current estimated from it
+    fn energy_estimate(&mut self) -> Option<(Usage, Estimate)> {
+        let usage = self.cpu.lock(|c| c.last())?;
+        let estimate = energy::estimate(&usage, clocks::timer_clk(), clkgate::running_load());
+        Some((usage, estimate))
+    }
+
+    pub fn energy_command(&mut self, shell: &mut Shell, args: &str) {
+        match args {
+            "" => {}
+            "on" | "off" => {
+                self.monitor.lock(|m| {
+                    if args == "on" {
+                        m.add(Watch::Current)
+                    } else {
+                        m.remove(Watch::Current)
+                    }
+                });
+                shell.write_str(CR).ok();
+                return;
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        }
+        let (usage, estimate) = match self.energy_estimate() {
+            Some(res) => res,
+            None => {
+                write!(shell, "{0:}no full second measured yet{0:}", CR).ok();
+                return;
+            }
+        };
+        let stop_us = usage.stop_us.min(usage.idle_us);
+        write!(
+            shell,
+            "{0:}Clock: {1:}kHz{0:}Mode  Time  Current{0:}",
+            CR,
+            clocks::timer_clk() / 1000
+        )
+        .ok();
+        for (name, us, ua) in [
+            ("run", usage.total_us - usage.idle_us, estimate.run_ua),
+            ("sleep", usage.idle_us - stop_us, estimate.sleep_ua),
+            ("stop", stop_us, estimate.stop_ua),
+        ] {
+            write!(
+                shell,
+                "{:<5} {:>3}%  {:>5}uA{}",
+                name,
+                Usage::percent(us, usage.total_us),
+                ua,
+                CR
+            )
+            .ok();
+        }
+        write!(
+            shell,
+            "Peripheral clocks: {1:}uA while clocked{0:}Average: {2:}uA, estimated{0:}",
+            CR, estimate.periph_ua, estimate.average_ua
+        )
+        .ok();
+    }
+
+    /// Plays in the background one unit per blink timer tick, Ctrl+C aborts
+    pub fn morse_command(&mut self, shell: &mut Shell, args: &str) {
+        match self.morse.lock(|m| m.start(args)) {
+            Ok(()) => {
+                // PARIS is 50 units, two ticks per animation period
+                let wpm = self.blink_freq.lock(|f| *f) as u32 * 2 * 60 / 50;
+                shell.write_str(CR).ok();
+                detail!(shell, "Sending at {} wpm, Ctrl+C aborts{}", wpm, CR);
+            }
+            Err(MorseError::Empty) => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+            Err(err) => {
+                morse::write_error(shell, err);
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+        }
+    }
+
+    pub fn pattern_command(&mut self, shell: &mut Shell, args: &str) {
+        match args {
+            "" => {
+                let (name, steps) = self.pattern.lock(|p| (p.name(), p.steps()));
+                match name {
+                    Some(name) => write!(shell, "{0:}Pattern: {1:} ({2:}){0:}", CR, name, steps),
+                    None => write!(shell, "{0:}Pattern: {1:}{0:}", CR, steps),
+                }
+                .ok();
+            }
+            "list" => {
+                shell.write_str(CR).ok();
+                for (name, steps) in pattern::BUILTINS.iter() {
+                    write!(shell, "{:<10} {}{}", name, steps, CR).ok();
+                }
+            }
+            arg => match self.pattern.lock(|p| p.set(arg)) {
+                Ok(()) => {
+                    shell.write_str(CR).ok();
+                    detail!(shell, "Pattern: {}{}", arg, CR);
+                }
+                Err(err) => {
+                    write!(shell, "{0:}pattern: {1:}{0:}", CR, err.message()).ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            },
+        }
+    }
+
+    pub fn mco_command(shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        let (source, div) = match (args.next(), args.next(), args.next()) {
+            (None, _, _) => {
+                match mco::current() {
+                    Some((source, div)) => {
+                        write!(
+                            shell,
+                            "{0:}MCO: {1:} / {2:} on PA9{0:}",
+                            CR,
+                            source.name(),
+                            div
+                        )
+                    }
+                    None => write!(shell, "{0:}MCO: off{0:}", CR),
+                }
+                .ok();
+                return;
+            }
+            (Some("off"), None, _) => {
+                mco::stop();
+                shell.write_str(CR).ok();
+                return;
+            }
+            (Some(source), Some(div), None) => (
+                Source::from_name(source),
+                btoi::btoi::<u32>(div.as_bytes()).unwrap_or(0),
+            ),
+            _ => (None, 0),
+        };
+        let source = match source {
+            Some(source) => source,
+            None => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        match mco::start(source, div) {
+            Ok(()) => {
+                shell.write_str(CR).ok();
+                if let Some(freq) = source.freq() {
+                    detail!(shell, "Output: {}Hz{}", freq / div, CR);
+                }
+            }
+            Err(McoError::Pin(err)) => pins::write_error(shell, err),
+            Err(err) => {
+                write!(shell, "{0:}mco: {1:}{0:}", CR, err.message()).ok();
+            }
+        }
+    }
+
+    pub fn loadgen_command(&mut self, shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            let percent = self.loadgen.lock(|l| l.percent());
+            write!(shell, "{0:}Load: {1:}%{0:}", CR, percent).ok();
+            return;
+        }
+        match btoi::btoi::<u8>(args.as_bytes()) {
+            Ok(percent) if percent <= LoadGen::MAX_PERCENT => {
+                self.loadgen.lock(|l| l.set_percent(percent));
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn metrics_command(&mut self, shell: &mut Shell) {
+        let ticks = self.ticks.lock(|t| *t);
+        let temp = self.sensors.lock(|s| s.temp_c());
+        let metrics = [
+            (
+                "uptime_seconds",
+                "counter",
+                "Time since boot",
+                ticks / TICK_HZ,
+            ),
+            (
+                "commands_total",
+                "counter",
+                "Shell commands dispatched",
+                metrics::COMMANDS.get(),
+            ),
+            (
+                "command_timeouts_total",
+                "counter",
+                "Commands that ran over the budget",
+                metrics::COMMAND_TIMEOUTS.get(),
+            ),
+            (
+                "script_aborts_total",
+                "counter",
+                "Scripts stopped by a run limit",
+                metrics::SCRIPT_ABORTS.get(),
+            ),
+            (
+                "uart_errors_total",
+                "counter",
+                "Shell UART read and write errors",
+                metrics::UART_ERRORS.get(),
+            ),
+            (
+                "uart_tx_stalls_total",
+                "counter",
+                "Shell writes that waited for a full output queue",
+                metrics::UART_TX_STALLS.get(),
+            ),
+            (
+                "uart_rx_drops_total",
+                "counter",
+                "Received bytes dropped while the shell task was busy",
+                metrics::UART_RX_DROPS.get(),
+            ),
+            (
+                "exit_status",
+                "gauge",
+                "Outcome of the last command, 1 for a failed assert",
+                metrics::exit_status() as u32,
+            ),
+        ];
+        shell.write_str(CR).ok();
+        for (name, kind, help, value) in metrics.iter() {
+            write!(
+                shell,
+                "# HELP {1:} {2:}{0:}# TYPE {1:} {3:}{0:}{1:} {4:}{0:}",
+                CR, name, help, kind, value
+            )
+            .ok();
+        }
+        write!(
+            shell,
+            "# HELP temp_celsius Die temperature{0:}# TYPE temp_celsius gauge{0:}temp_celsius {1:}{0:}",
+            CR, temp
+        )
+        .ok();
+    }
+
+    pub fn latency_command(shell: &mut Shell, args: &str) {
+        match args {
+            "irq" => {
+                Self::write_latency(shell, "UART RX -> shell", &latency::RX);
+                Self::write_latency(shell, "Timer -> LED    ", &latency::BLINK);
+                shell.write_str(CR).ok();
+            }
+            "reset" => {
+                latency::RX.reset();
+                latency::BLINK.reset();
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    /// Warns when higher priority work or critical sections starve the animation
+    fn deadline_check(shell: &mut Shell) {
+        let missed = latency::BLINK_DEADLINE.take_unreported();
+        if missed > 0 {
+            write!(
+                shell,
+                "\r\x1b[Ktimerstat: blink missed {} activations{}{}",
+                missed,
+                CR,
+                settings::prompt()
+            )
+            .ok();
+        }
+    }
+
+    pub fn timerstat_command(shell: &mut Shell, args: &str) {
+        let stat = &latency::BLINK_DEADLINE;
+        match args {
+            "" => {
+                let cycles_per_us = (cycles::freq() / 1_000_000).max(1);
+                write!(
+                    shell,
+                    "{0:}Blink period: {1:}us{0:}Activations: {2:}{0:}Late: {3:} (worst +{4:}us){0:}Missed: {5:}{0:}",
+                    CR,
+                    stat.period() / cycles_per_us,
+                    stat.count(),
+                    stat.late(),
+                    stat.worst_late() / cycles_per_us,
+                    stat.missed()
+                )
+                .ok();
+            }
+            "reset" => {
+                stat.reset();
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    fn write_latency(shell: &mut Shell, name: &str, stat: &Stat) {
+        let cycles_per_us = (cycles::freq() / 1_000_000).max(1);
+        write!(
+            shell,
+            "{}{}: last {}us, worst {}us ({} samples)",
+            CR,
+            name,
+            stat.last() / cycles_per_us,
+            stat.worst() / cycles_per_us,
+            stat.count()
+        )
+        .ok();
+    }
+
+    pub fn monitor_command(&mut self, shell: &mut Shell, args: &str) {
+        let (subcmd, arg) = args.split_once(" ").unwrap_or((args, ""));
+        match (subcmd, Watch::from_name(arg)) {
+            ("", _) => {
+                let interval = self.monitor.lock(|m| m.interval_ms());
+                write!(shell, "{0:}Interval: {1:}ms{0:}Watches:", CR, interval).ok();
+                for (name, watch) in WATCHES.iter() {
+                    let watched = self.monitor.lock(|m| m.is_watched(*watch));
+                    let mark = if watched { '*' } else { ' ' };
+                    write!(shell, "{} {}{}", CR, mark, name).ok();
+                }
+                shell.write_str(CR).ok();
+            }
+            ("add", Some(watch)) => {
+                self.monitor.lock(|m| m.add(watch));
+                shell.write_str(CR).ok();
+            }
+            ("remove", Some(watch)) => {
+                self.monitor.lock(|m| m.remove(watch));
+                shell.write_str(CR).ok();
+            }
+            ("interval", _) => match btoi::btoi(arg.as_bytes()) {
+                Ok(interval)
+                    if (Monitor::MIN_INTERVAL_MS..=Monitor::MAX_INTERVAL_MS)
+                        .contains(&interval)
+                        && interval % Monitor::MIN_INTERVAL_MS == 0 =>
+                {
+                    self.monitor.lock(|m| m.set_interval_ms(interval));
+                    shell.write_str(CR).ok();
+                }
+                _ => {
+                    write!(
+                        shell,
+                        "{0:}interval must be a multiple of {1:}ms up to {2:}ms{0:}",
+                        CR,
+                        Monitor::MIN_INTERVAL_MS,
+                        Monitor::MAX_INTERVAL_MS
+                    )
+                    .ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            },
+            ("off", _) => {
+                self.monitor.lock(|m| m.clear());
+                shell.write_str(CR).ok();
+            }
+            ("add", None) | ("remove", None) => {
+                write!(shell, "{0:}unknown variable{0:}", CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    fn record_line(&mut self, shell: &mut Shell, cmd: &str, args: &str) {
+        let mut line: String<CMD_MAX_LEN> = String::new();
+        write!(line, "{} {}", cmd, args).ok();
+        if let Err(err) = self.scripts.lock(|s| s.record(line.trim_end())) {
+            write!(shell, "{0:}record: {1:}{0:}", CR, err.message()).ok();
+        }
+    }
+
+    pub fn record_command(&mut self, shell: &mut Shell, args: &str) {
+        let (subcmd, name) = args.split_once(" ").unwrap_or((args, ""));
+        let res = match subcmd {
+            "start" => self.scripts.lock(|s| s.start(name)),
+            "stop" => match self.scripts.lock(|s| s.stop()) {
+                Ok(lines) => {
+                    shell.write_str(CR).ok();
+                    say!(shell, "Saved {} lines{}", lines, CR);
+                    return;
+                }
+                Err(err) => Err(err),
+            },
+            "delete" => Scripts::delete(name),
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        match res {
+            Ok(()) => shell.write_str(CR),
+            Err(err) => write!(shell, "{0:}record: {1:}{0:}", CR, err.message()),
+        }
+        .ok();
+    }
+
+    pub fn run_command(&mut self, shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            shell.write_str(CR).ok();
+            for (name, len) in Scripts::list() {
+                write!(shell, "{:<16} {} bytes{}", name, len, CR).ok();
+            }
+            return;
+        }
+        let (keep_going, args) = match args.strip_prefix("-k ") {
+            Some(args) => (true, args.trim_start()),
+            None => (false, args),
+        };
+        let (name, args) = args.split_once(" ").unwrap_or((args, ""));
+        let text = match Scripts::find(name) {
+            Some(text) => text,
+            None => {
+                let err = ScriptError::NotFound;
+                write!(shell, "{0:}run: {1:}{0:}", CR, err.message()).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+                return;
+            }
+        };
+        if let Err(err) = self.scripts.lock(|s| s.enter()) {
+            write!(shell, "{0:}run: {1:}{0:}", CR, err.message()).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return;
+        }
+        // A failed line fails the script, the first failure is kept with -k
+        let mut status = ExitStatus::Ok;
+        for (idx, line) in text.lines().enumerate() {
+            // Over a limit even -k stops, a runaway startup script must hand back the shell
+            if let Err(err) = self.scripts.lock(|s| s.step()) {
+                write!(
+                    shell,
+                    "{}run: {} stopped at line {}: {}",
+                    CR,
+                    name,
+                    idx + 1,
+                    err.message()
+                )
+                .ok();
+                err.write_limit(shell);
+                shell.write_str(CR).ok();
+                metrics::SCRIPT_ABORTS.inc();
+                status = ExitStatus::Error;
+                break;
+            }
+            match scripts::expand(line, args) {
+                Ok(line) => {
+                    say!(shell, "{}> {}", CR, line);
+                    self.run_stored(shell, &line);
+                }
+                Err(err) => {
+                    let idx = idx + 1;
+                    write!(shell, "{0:}run: line {1:}: {2:}", CR, idx, err.message()).ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            }
+            let line_status = metrics::exit_status();
+            if line_status == ExitStatus::Ok {
+                continue;
+            }
+            if status == ExitStatus::Ok {
+                status = line_status;
+            }
+            if !keep_going {
+                write!(
+                    shell,
+                    "{0:}run: {1:} stopped at line {2:}{0:}",
+                    CR,
+                    name,
+                    idx + 1
+                )
+                .ok();
+                break;
+            }
+        }
+        self.scripts.lock(|s| s.leave());
+        metrics::set_exit_status(status);
+    }
+
+    /// First output after a reset, before the shell reads the first typed byte. Input
+    /// that arrives meanwhile waits in the RX queue.
+    pub fn boot(&mut self, shell: &mut Shell) {
+        if safemode::is_active() {
+            write!(
+                shell,
+                "{0:}safe mode: rc and drivers skipped, reboot to leave{0:}{1:}",
+                CR,
+                settings::prompt()
+            )
+            .ok();
+        } else {
+            self.rc_run(shell);
+        }
+    }
+
+    /// Runs the `rc` script, the first prompt follows its last line
+    fn rc_run(&mut self, shell: &mut Shell) {
+        if Scripts::find(RC_SCRIPT).is_none() {
+            return;
+        }
+        let mut args: String<CMD_MAX_LEN> = String::new();
+        write!(args, "-k {}", RC_SCRIPT).ok();
+        write!(shell, "{}rc:", CR).ok();
+        self.command(shell, "run", &args);
+        write!(shell, "{}{}", CR, settings::prompt()).ok();
+    }
+
+    pub fn rc_command(shell: &mut Shell, args: &str) {
+        let (subcmd, line) = args.split_once(" ").unwrap_or((args, ""));
+        match (subcmd, line.trim()) {
+            ("" | "list", "") => {
+                shell.write_str(CR).ok();
+                let text = Scripts::find(RC_SCRIPT).unwrap_or("");
+                for (idx, line) in text.lines().enumerate() {
+                    write!(shell, "{:>2} {}{}", idx + 1, line, CR).ok();
+                }
+            }
+            ("add", line) if !line.is_empty() => match Scripts::append(RC_SCRIPT, line) {
+                Ok(count) => {
+                    shell.write_str(CR).ok();
+                    detail!(shell, "rc: {} lines{}", count, CR);
+                }
+                Err(err) => {
+                    write!(shell, "{0:}rc: {1:}{0:}", CR, err.message()).ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            },
+            ("clear", "") => match Scripts::delete(RC_SCRIPT) {
+                Ok(()) | Err(ScriptError::NotFound) => {
+                    shell.write_str(CR).ok();
+                }
+                Err(err) => {
+                    write!(shell, "{0:}rc: {1:}{0:}", CR, err.message()).ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn cal_command(shell: &mut Shell, args: &str) {
+        let (subcmd, args) = args.split_once(" ").unwrap_or((args, ""));
+        match subcmd {
+            "" | "show" => {
+                let stored = cal::load();
+                let state = if stored.is_some() { "stored" } else { "blank" };
+                let lock = if cal::is_unlocked() {
+                    "unlocked"
+                } else {
+                    "locked"
+                };
+                write!(shell, "{0:}Calibration: {1:}, {2:}{0:}", CR, state, lock).ok();
+                for (idx, field) in cal::FIELDS.iter().enumerate() {
+                    let value = stored.map_or(field.default, |values| values[idx]);
+                    write!(shell, "{:<16} {} {}{}", field.name, value, field.unit, CR).ok();
+                }
+            }
+            "unlock" => {
+                cal::unlock();
+                shell.write_str(CR).ok();
+                say!(
+                    shell,
+                    "Calibration writes enabled until the next other command{}",
+                    CR
+                );
+            }
+            "lock" => {
+                cal::lock();
+                shell.write_str(CR).ok();
+            }
+            "write" => {
+                let (name, value) = args.split_once(" ").unwrap_or((args, ""));
+                let value = match btoi::btoi::<i32>(value.as_bytes()) {
+                    Ok(value) => value,
+                    Err(_) => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                        return;
+                    }
+                };
+                let res = cal::find(name)
+                    .ok_or(CalError::UnknownField)
+                    .and_then(|field| cal::write(field, value));
+                match res {
+                    Ok(()) => write!(shell, "{0:}{1:} = {2:}{0:}", CR, name, value).ok(),
+                    Err(err) => {
+                        metrics::set_exit_status(ExitStatus::Error);
+                        write!(shell, "{0:}cal: {1:}{0:}", CR, err.message()).ok()
+                    }
+                };
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn alarm_command(&mut self, shell: &mut Shell, args: &str) {
+        match args {
+            "" => {
+                let line = self
+                    .alarm
+                    .lock(|a| a.line().map(String::<CMD_MAX_LEN>::from));
+                match (rtc::alarm(), line) {
+                    (Some((hour, minute)), Some(line)) => write!(
+                        shell,
+                        "{0:}Alarm: {1:02}:{2:02} daily, runs {3:}{0:}",
+                        CR, hour, minute, line
+                    ),
+                    _ => write!(shell, "{0:}Alarm: off{0:}", CR),
+                }
+                .ok();
+                return;
+            }
+            "off" => {
+                rtc::clear_alarm();
+                self.alarm.lock(|a| a.clear());
+                shell.write_str(CR).ok();
+                return;
+            }
+            _ => {}
+        }
+        let (time, line) = args.split_once(" ").unwrap_or((args, ""));
+        let line = line.trim();
+        let mut time = time
+            .splitn(2, ':')
+            .map(|part| btoi::btou::<u8>(part.as_bytes()));
+        let (hour, minute) = match (time.next(), time.next()) {
+            (Some(Ok(hour)), Some(Ok(minute))) if hour < 24 && minute < 60 && !line.is_empty() => {
+                (hour, minute)
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        if !rtc::is_set() {
+            write!(shell, "{0:}alarm: date not set, use date set{0:}", CR).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return;
+        }
+        let line = match Self::deferred_line(shell, line) {
+            Some(line) => line,
+            None => return,
+        };
+        self.alarm.lock(|a| a.set(&line));
+        rtc::set_alarm(hour, minute);
+        shell.write_str(CR).ok();
+        detail!(shell, "Alarm at {:02}:{:02} every day{}", hour, minute, CR);
+    }
+
+    pub fn stopwatch_command(&mut self, shell: &mut Shell, args: &str) {
+        let now = mono::now_ms();
+        match args {
+            "" => {
+                let (running, laps, elapsed) = self
+                    .stopwatch
+                    .lock(|s| (s.is_running(), s.laps(), s.elapsed_ms(now)));
+                write!(shell, "{}Stopwatch: ", CR).ok();
+                stopwatch::write_time(shell, elapsed);
+                let state = if running { "running" } else { "stopped" };
+                write!(shell, " {}, {} laps{}", state, laps, CR).ok();
+            }
+            "start" => {
+                self.stopwatch.lock(|s| s.start(now));
+                shell.write_str(CR).ok();
+                detail!(shell, "Stopwatch started{}", CR);
+            }
+            "lap" => match self.stopwatch.lock(|s| s.lap(now)) {
+                Some((lap, split, total)) => {
+                    write!(shell, "{}Lap {}: ", CR, lap).ok();
+                    stopwatch::write_time(shell, split);
+                    shell.write_str(" total ").ok();
+                    stopwatch::write_time(shell, total);
+                    shell.write_str(CR).ok();
+                }
+                None => {
+                    write!(shell, "{0:}stopwatch: not running{0:}", CR).ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            },
+            "stop" => match self.stopwatch.lock(|s| s.stop(now)) {
+                Some(total) => {
+                    write!(shell, "{}Stopped at ", CR).ok();
+                    stopwatch::write_time(shell, total);
+                    shell.write_str(CR).ok();
+                }
+                None => {
+                    write!(shell, "{0:}stopwatch: not running{0:}", CR).ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn countdown_command(&mut self, shell: &mut Shell, args: &str) {
+        let now = mono::now_ms();
+        match args {
+            "" => {
+                let (left, line) = self.countdown.lock(|c| {
+                    (
+                        c.remaining_ms(now),
+                        c.line().map(String::<CMD_MAX_LEN>::from),
+                    )
+                });
+                match left {
+                    Some(left) => {
+                        write!(shell, "{}Countdown: ", CR).ok();
+                        stopwatch::write_time(shell, left);
+                        if let Some(line) = line {
+                            write!(shell, " left, then {}", line).ok();
+                        } else {
+                            shell.write_str(" left").ok();
+                        }
+                        shell.write_str(CR).ok();
+                    }
+                    None => {
+                        write!(shell, "{0:}Countdown: off{0:}", CR).ok();
+                    }
+                }
+                return;
+            }
+            "off" => {
+                if self.countdown.lock(|c| c.cancel()) {
+                    shell.write_str(CR).ok();
+                    detail!(shell, "Countdown cancelled{}", CR);
+                } else {
+                    write!(shell, "{0:}countdown: not running{0:}", CR).ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+                return;
+            }
+            _ => {}
+        }
+        let (secs, line) = args.split_once(" ").unwrap_or((args, ""));
+        let line = line.trim();
+        let secs = match btoi::btoi::<u32>(secs.as_bytes()) {
+            Ok(secs) if (1..=countdown::MAX_SECS).contains(&secs) => secs,
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        let line = if line.is_empty() {
+            None
+        } else {
+            match Self::deferred_line(shell, line) {
+                Some(line) => Some(line),
+                None => return,
+            }
+        };
+
+        let id = self.countdown.lock(|c| c.start(secs, now, line.as_deref()));
+        let at_ms = now + 1000;
+        if countdown_tick::spawn_at(Instant::new(at_ms), id).is_err() {
+            self.countdown.lock(|c| c.cancel());
+            write!(
+                shell,
+                "{0:}countdown: restarted too fast, try again{0:}",
+                CR
+            )
+            .ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return;
+        }
+        shell.write_str(CR).ok();
+        detail!(shell, "Countdown of {}s started{}", secs, CR);
+    }
+
+    pub fn bundle_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        match (
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+        ) {
+            (None, ..) => {
+                let ringing = self.ringer.lock(|r| r.ringing());
+                shell.write_str(CR).ok();
+                for bundle in bundle::list() {
+                    write!(
+                        shell,
+                        "{:<12} {:<10} {:<6} ",
+                        bundle.name,
+                        bundle.pattern,
+                        bundle::RINGTONES[bundle.ringtone].0,
+                    )
+                    .ok();
+                    match bundle.channel {
+                        Some(channel) => write!(shell, "PA{}", channel.pin()),
+                        None => shell.write_str("off"),
+                    }
+                    .ok();
+                    if ringing == Some(bundle.name) {
+                        shell.write_str(" ringing").ok();
+                    }
+                    shell.write_str(CR).ok();
+                }
+            }
+            (Some("tones"), None, ..) => {
+                shell.write_str(CR).ok();
+                for (name, melody) in bundle::RINGTONES.iter() {
+                    let ms: u32 = melody.iter().map(|(_, ms)| *ms as u32).sum();
+                    write!(shell, "{:<6} {} notes, {}ms{}", name, melody.len(), ms, CR).ok();
+                }
+            }
+            (Some("set"), Some(name), Some(pattern), Some(tone), Some(pin)) => {
+                let channel = match pin {
+                    "off" => Some(None),
+                    pin => trigger::parse_pin(pin)
+                        .and_then(Channel::from_pin)
+                        .map(Some),
+                };
+                let channel = match channel {
+                    Some(channel) => channel,
+                    None => {
+                        write!(
+                            shell,
+                            "{0:}bundle: ringtone pin must be pa6, pa7 or off{0:}",
+                            CR
+                        )
+                        .ok();
+                        metrics::set_exit_status(ExitStatus::Error);
+                        return;
+                    }
+                };
+                let res = bundle::ringtone(tone)
+                    .ok_or(BundleError::Ringtone)
+                    .and_then(|tone| bundle::save(name, pattern, tone, channel));
+                match res {
+                    Ok(()) => {
+                        shell.write_str(CR).ok();
+                        detail!(shell, "Bundle {} saved{}", name, CR);
+                    }
+                    Err(err) => Self::bundle_error(shell, err),
+                }
+            }
+            (Some("del"), Some(name), None, ..) => match bundle::delete(name) {
+                Ok(()) => {
+                    shell.write_str(CR).ok();
+                }
+                Err(err) => Self::bundle_error(shell, err),
+            },
+            (Some("ring"), Some(name), None, ..) => self.ring_start(shell, name),
+            (Some("stop"), None, ..) => {
+                self.ring_stop();
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    fn bundle_error(shell: &mut Shell, err: BundleError) {
+        write!(shell, "{0:}bundle: {1:}{0:}", CR, err.message()).ok();
+        metrics::set_exit_status(ExitStatus::Error);
+    }
+
+    /// Plays a bundle until `RING_MS` runs out or Ctrl+C, a ring replaces one ringing.
+    /// The ringtone takes the PWM output over from a wave or audio stream.
+    fn ring_start(&mut self, shell: &mut Shell, name: &str) {
+        let bundle = match bundle::find(name) {
+            Some(bundle) => bundle,
+            None => return Self::bundle_error(shell, BundleError::NotFound),
+        };
+        if let Some(channel) = bundle.channel {
+            if let Err(err) = pins::check(pins::Port::A, channel.pin(), pins::Owner::Pwmout) {
+                pins::write_error(shell, err);
+                return;
+            }
+            self.wave.lock(|w| w.stop());
+            self.sensors.lock(|s| s.stop_stream());
+            self.pwmout.lock(|p| p.stop());
+        }
+        let previous = self
+            .pattern
+            .lock(|p| p.name().map_or_else(|| p.steps(), String::from));
+        if let Err(err) = self.pattern.lock(|p| p.set(bundle.pattern)) {
+            return Self::bundle_error(shell, BundleError::Pattern(err));
+        }
+        let enabled = self.blink_enabled.lock(|e| core::mem::replace(e, true));
+        let now = mono::now_ms();
+        let id = self
+            .ringer
+            .lock(|r| r.start(bundle, now, (previous, enabled)));
+        if ring_step::spawn(id).is_err() {
+            self.ring_stop();
+            write!(shell, "{0:}bundle: restarted too fast, try again{0:}", CR).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return;
+        }
+        shell.write_str(CR).ok();
+        detail!(shell, "Ringing {}{}", name, CR);
+    }
+
+    /// Ends a ring and puts back the pattern and animation state it replaced, false if
+    /// nothing was ringing
+    fn ring_stop(&mut self) -> bool {
+        let (channel, pattern, enabled) = match self.ringer.lock(|r| r.stop()) {
+            Some(restore) => restore,
+            None => return false,
+        };
+        if channel.is_some() {
+            self.pwmout.lock(|p| p.stop());
+        }
+        self.pattern.lock(|p| p.set(&pattern).ok());
+        self.blink_enabled.lock(|e| *e = enabled);
+        true
+    }
+
+    pub fn date_command(shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            if !rtc::is_set() {
+                write!(shell, "{0:}Date not set, use date set{0:}", CR).ok();
+                return;
+            }
+            let now = rtc::now();
+            write!(
+                shell,
+                "{0:}{1:04}-{2:02}-{3:02} {4:02}:{5:02}:{6:02}{0:}",
+                CR, now.year, now.month, now.day, now.hour, now.minute, now.second
+            )
+            .ok();
+            return;
+        }
+        let mut args = args.split_whitespace();
+        let dt = match (args.next(), args.next(), args.next(), args.next()) {
+            (Some("set"), Some(date), Some(time), None) => DateTime::parse(date, time),
+            _ => None,
+        };
+        match dt {
+            Some(dt) => {
+                rtc::set(&dt);
+                shell.write_str(CR).ok();
+            }
+            None => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn time_command(shell: &mut Shell) {
+        let now = rtc::now();
+        write!(
+            shell,
+            "{0:}{1:02}:{2:02}:{3:02}{0:}",
+            CR, now.hour, now.minute, now.second
+        )
+        .ok();
+    }
+
+    /// Takes the host's Unix time in milliseconds, e.g. from `date +%s%3N`
+    pub fn timesync_command(shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            let (last_ms, drift) = timesync::last();
+            match last_ms {
+                Some(last_ms) => {
+                    let ago = timesync::rtc_ms().saturating_sub(last_ms) / 1000;
+                    write!(shell, "{0:}Last sync: {1:}s ago{0:}", CR, ago).ok();
+                }
+                None => {
+                    write!(shell, "{0:}Not synced since reset{0:}", CR).ok();
+                }
+            }
+            if let Some(drift) = drift {
+                write!(shell, "Drift: {:+} ppm{}", drift, CR).ok();
+            }
+            return;
+        }
+        let host_ms = match btoi::btou::<u64>(args.as_bytes()) {
+            Ok(ms) => ms,
+            Err(_) => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        match timesync::sync(host_ms) {
+            Ok(Some(drift)) => {
+                write!(
+                    shell,
+                    "{0:}RTC drift since last sync: {1:+} ppm{0:}",
+                    CR, drift
+                )
+                .ok();
+            }
+            Ok(None) => {
+                shell.write_str(CR).ok();
+                detail!(shell, "RTC set, sync again later to measure drift{}", CR);
+            }
+            Err(err) => {
+                write!(shell, "{0:}timesync: {1:}{0:}", CR, err.message()).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+        }
+    }
+
+    pub fn rtccal_command(&mut self, shell: &mut Shell, args: &str) {
+        let (cmd, arg) = args.split_once(" ").unwrap_or((args, ""));
+        match (cmd, arg) {
+            ("", _) => {
+                write!(
+                    shell,
+                    "{0:}RTC calibration: {1:+} ppm{0:}",
+                    CR,
+                    rtc::calibration_ppm()
+                )
+                .ok();
+                if let Some(span) = rtc::drift_span_ms() {
+                    write!(shell, "Drift reference: {}s ago{}", span / 1000, CR).ok();
+                }
+            }
+            ("host", ms) => match btoi::btoi::<u64>(ms.as_bytes()) {
+                Ok(ms) => Self::drift_report(shell, rtc::drift_mark(ms)),
+                Err(_) => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                }
+            },
+            ("pps", "") => self.rtccal_pps(shell),
+            ("reset", "") => {
+                rtc::drift_reset();
+                shell.write_str(CR).ok();
+            }
+            (ppm, "") => match btoi::btoi::<i32>(ppm.as_bytes()) {
+                Ok(ppm) if ppm.abs() <= rtc::MAX_CAL_PPM => {
+                    rtc::set_calibration(ppm);
+                    shell.write_str(CR).ok();
+                    detail!(
+                        shell,
+                        "RTC calibration: {:+} ppm{}",
+                        rtc::calibration_ppm(),
+                        CR
+                    );
+                }
+                _ => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                }
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    /// Marks the next PPS edge counted by `count <pin> rise`, each edge is one second
+    fn rtccal_pps(&mut self, shell: &mut Shell) {
+        if self.counter.lock(|c| c.pin()).is_none() {
+            write!(shell, "{0:}rtccal: count the PPS pin first{0:}", CR).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+            return;
+        }
+        let start = self.counter.lock(|c| c.count());
+        let since = cycles::now();
+        let edges = loop {
+            let edges = self.counter.lock(|c| c.count());
+            if edges != start {
+                break edges;
+            }
+            if cycles::since(since) > cycles::freq() * 2 || budget::expired() {
+                write!(shell, "{0:}rtccal: no PPS edge{0:}", CR).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+                return;
+            }
+        };
+        Self::drift_report(shell, rtc::drift_mark(edges as u64 * 1000));
+    }
+
+    /// The suggested correction undoes the drift on top of the calibration in use
+    fn drift_report(shell: &mut Shell, drift: Option<i32>) {
+        match drift {
+            Some(drift) => write!(
+                shell,
+                "{0:}RTC drift: {1:+} ppm, rtccal {2:} corrects it{0:}",
+                CR,
+                drift,
+                (rtc::calibration_ppm() - drift).clamp(-rtc::MAX_CAL_PPM, rtc::MAX_CAL_PPM)
+            ),
+            None => write!(
+                shell,
+                "{0:}Reference marked, mark again a few minutes later{0:}",
+                CR
+            ),
+        }
+        .ok();
+    }
+
+    pub fn hsical_command(shell: &mut Shell, args: &str) {
+        let (cmd, arg) = args.split_once(" ").unwrap_or((args, ""));
+        let trim = hsical::trim();
+        let res = match (cmd, arg) {
+            ("", _) => {
+                write!(
+                    shell,
+                    "{0:}HSI trim: {1:} (saved {2:}, reset {3:}){0:}",
+                    CR,
+                    trim,
+                    cal::get(cal::HSI_TRIM),
+                    hsical::TRIM_DEFAULT
+                )
+                .ok();
+                return;
+            }
+            ("up", "") => hsical::set_trim(trim.saturating_add(1)),
+            ("down", "") => hsical::set_trim(trim.saturating_sub(1)),
+            ("save", "") => hsical::save(),
+            ("pps", pin) => match trigger::parse_pin(pin) {
+                Some(pin) => hsical::measure_pps(pin).map(|ppm| Self::hsical_report(shell, ppm)),
+                None => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                    return;
+                }
+            },
+            ("uart", "") => {
+                write!(shell, "{0:}Send U within 10s{0:}", CR).ok();
+                nb::block!(shell.serial().flush()).ok();
+                hsical::measure_uart().map(|ppm| Self::hsical_report(shell, ppm))
+            }
+            (trim, "") => match btoi::btoi::<u8>(trim.as_bytes()) {
+                Ok(trim) => hsical::set_trim(trim),
+                Err(_) => Err(HsiError::Range),
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        match res {
+            Ok(()) => {
+                shell.write_str(CR).ok();
+                detail!(shell, "HSI trim: {}{}", hsical::trim(), CR);
+            }
+            Err(HsiError::Pin(err)) => pins::write_error(shell, err),
+            Err(err) => {
+                write!(shell, "{0:}hsical: {1:}{0:}", CR, err.message()).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+        }
+    }
+
+    /// Prints a measured error and trims it out, `save` keeps the result
+    fn hsical_report(shell: &mut Shell, ppm: i32) {
+        let before = hsical::trim();
+        let after = hsical::correct(ppm);
+        write!(
+            shell,
+            "{0:}HSI16 error: {1:+} ppm, trim {2:} -> {3:}",
+            CR, ppm, before, after
+        )
+        .ok();
+    }
+
+    pub fn sign_command(shell: &mut Shell, args: &str) {
+        let res = match args {
+            "" => {
+                write!(
+                    shell,
+                    "{0:}Signed commands: {1:}{0:}Key: {2:}{0:}Nonce: {3:}{0:}",
+                    CR,
+                    if provision::signing() { "On" } else { "Off" },
+                    if provision::key().is_some() {
+                        "set"
+                    } else {
+                        "missing"
+                    },
+                    signing::nonce()
+                )
+                .ok();
+                return;
+            }
+            "on" if provision::key().is_none() => {
+                metrics::set_exit_status(ExitStatus::Error);
+                let err = SignError::NoKey;
+                write!(shell, "{0:}sign: {1:}{0:}", CR, err.message()).ok();
+                return;
+            }
+            "on" => provision::set_signing(true),
+            "off" => provision::set_signing(false),
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        match res {
+            Ok(()) => shell.write_str(CR).ok(),
+            Err(err) => {
+                metrics::set_exit_status(ExitStatus::Error);
+                write!(shell, "{0:}sign: {1:}{0:}", CR, err.message()).ok()
+            }
+        };
+    }
+
+    /// One block for bug reports between fixed delimiters, the CRC-16 after the end line
+    /// covers the visible characters in between
+    pub fn report_command(&mut self, shell: &mut Shell) {
+        write!(shell, "{0:}-----BEGIN USHELL REPORT-----{0:}", CR).ok();
+        shell.serial().start_digest();
+
+        write!(shell, "[sysinfo]").ok();
+        self.report_section(shell, "version");
+        let uptime_s = self.ticks.lock(|t| *t) / TICK_HZ;
+        write!(shell, "Uptime:   {}s{}", uptime_s, CR).ok();
+
+        write!(shell, "[settings]{}", CR).ok();
+        for setting in catalog::SETTINGS.iter() {
+            write!(shell, "{}:", setting.name).ok();
+            self.report_section(shell, setting.get);
+        }
+
+        write!(shell, "[stats]").ok();
+        self.report_section(shell, "metrics");
+        self.cpu_command(shell, false);
+
+        write!(shell, "[log]{}", CR).ok();
+        Self::recent_commands(shell);
+
+        // A panic blinks SOS until reset, `coredump` has the rest of the crash
+        let failed = self.power.lock(|p| p.failed_before_reset());
+        let crash = coredump::load().map_or("none", |dump| dump.kind.name());
+        write!(
+            shell,
+            "[panic]{0:}Reset cause: {1:}{0:}Power failed before reset: {2:}{0:}Coredump: {3:}{0:}",
+            CR,
+            boot::reset_cause(),
+            if failed { "yes" } else { "no" },
+            crash
+        )
+        .ok();
+
+        let crc = shell.serial().take_digest().unwrap_or(0);
+        write!(shell, "-----END USHELL REPORT----- crc={:04x}{}", crc, CR).ok();
+        metrics::set_exit_status(ExitStatus::Ok);
+    }
+
+    /// Runs a read-only command for the report straight from the catalog, so it is not
+    /// recorded into a script, counted or fired as a dispatch
+    fn report_section(&mut self, shell: &mut Shell, cmd: &str) {
+        if let Some(info) = catalog::find(cmd) {
+            (info.run)(self, shell, "");
+        }
+    }
+
+    /// Lines typed recently, rebuilt from the received bytes in the trace buffer
+    fn recent_commands(shell: &mut Shell) {
+        const LINES: usize = 8;
+
+        let trace = shell.serial().inner().trace().clone();
+        let mut lines: [String<CMD_MAX_LEN>; LINES] = Default::default();
+        let mut count = 0;
+        let mut line: String<CMD_MAX_LEN> = String::new();
+        for (_, byte) in trace.iter().filter(|(dir, _)| *dir == Direction::Rx) {
+            match byte {
+                b'\r' if !line.is_empty() => {
+                    lines[count % LINES] = line.clone();
+                    count += 1;
+                    line.clear();
+                }
+                0x08 | 0x7f => {
+                    line.pop();
+                }
+                0x20..=0x7e => {
+                    line.push(byte as char).ok();
+                }
+                _ => {}
+            }
+        }
+        for idx in count.saturating_sub(LINES)..count {
+            write!(shell, "> {}{}", lines[idx % LINES], CR).ok();
+        }
+    }
+
+    pub fn res_command(shell: &mut Shell, args: &str) {
+        match args {
+            "" => {
+                write!(
+                    shell,
+                    "{0:}RESOURCE       CEIL    LOCKS  WAITS  MAX(us)  TASKS",
+                    CR
+                )
+                .ok();
+                let cycles_per_us = (cycles::freq() / 1_000_000).max(1);
+                for (idx, (name, tasks)) in RESOURCES.iter().enumerate() {
+                    let stats = resources::stats(idx);
+                    write!(
+                        shell,
+                        "{}{:<14} {:>4} {:>8} {:>6} {:>8} ",
+                        CR,
+                        name,
+                        resources::ceiling(tasks),
+                        stats.locks(),
+                        stats.contended(),
+                        stats.max_hold() / cycles_per_us
+                    )
+                    .ok();
+                    for task in tasks.iter() {
+                        let (task, priority) = TASKS[*task];
+                        write!(shell, " {}({})", task, priority).ok();
+                    }
+                }
+                write!(
+                    shell,
+                    "{0:}Counters cover locks taken by shell_poll{0:}",
+                    CR
+                )
+                .ok();
+            }
+            "reset" => {
+                for idx in 0..RESOURCES.len() {
+                    resources::stats(idx).reset();
+                }
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    fn sweep_step(&mut self, shell: &mut Shell) {
+        let freq = match self.sweep.lock(|s| s.take_step()) {
+            Some(freq) => freq,
+            None => return,
+        };
+        match self.sweep.lock(|s| s.target()) {
+            Target::Led => self.set_blink_freq(freq as u8),
+            Target::Pwm => {
+                let channel = self.pwmout.lock(|p| p.channel());
+                match channel {
+                    Some(channel) => {
+                        let duty = self.pwmout.lock(|p| p.duty());
+                        // Same channel as before, the pin is already ours
+                        self.pwmout.lock(|p| p.start(channel, freq, duty)).ok();
+                    }
+                    None => {
+                        self.sweep.lock(|s| s.cancel());
+                        write!(
+                            shell,
+                            "\r\x1b[Ksweep: PWM output stopped{}{}",
+                            CR,
+                            settings::prompt()
+                        )
+                        .ok();
+                        return;
+                    }
+                }
+            }
+        }
+        let done = if self.sweep.lock(|s| s.is_active()) {
+            ""
+        } else {
+            " done"
+        };
+        write!(
+            shell,
+            "\r\x1b[Ksweep: {}Hz{}{}{}",
+            freq,
+            done,
+            CR,
+            settings::prompt()
+        )
+        .ok();
+    }
+
+    pub fn phase_command(&mut self, shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            let phase = self.blink_sync.lock(|s| s.phase_ms());
+            write!(shell, "{0:}Phase: {1:}ms{0:}", CR, phase).ok();
+            return;
+        }
+        match btoi::btoi::<u32>(args.as_bytes()) {
+            Ok(phase) if phase <= BlinkSync::MAX_PHASE_MS => {
+                self.blink_sync.lock(|s| s.set_phase_ms(phase));
+                shell.write_str(CR).ok();
+                detail!(shell, "Phase: {}ms{}", phase, CR);
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn sync_command(&mut self, shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            let now = self.ticks.lock(|t| *t);
+            let (mode, phase, pulses, last) = self
+                .blink_sync
+                .lock(|s| (s.mode(), s.phase_ms(), s.pulses(), s.last()));
+            write!(
+                shell,
+                "{0:}Sync: {1:}{0:}Phase: {2:}ms{0:}Pulses: {3:}",
+                CR,
+                mode.name(),
+                phase,
+                pulses
+            )
+            .ok();
+            if let Some(ticks) = last {
+                write!(shell, ", last {}s ago", now.wrapping_sub(ticks) / TICK_HZ).ok();
+            }
+            write!(shell, "{0:}Modes:", CR).ok();
+            for (name, _) in MODES.iter() {
+                write!(shell, " {}", name).ok();
+            }
+            shell.write_str(CR).ok();
+            return;
+        }
+        match Mode::from_name(args) {
+            Some(mode) => match self.blink_sync.lock(|s| s.set_mode(mode)) {
+                Ok(()) => {
+                    shell.write_str(CR).ok();
+                    detail!(shell, "Sync: {}{}", mode.name(), CR);
+                }
+                Err(err) => pins::write_error(shell, err),
+            },
+            None => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn sweep_command(&mut self, shell: &mut Shell, args: &str) {
+        match args {
+            "" => {
+                let active = self.sweep.lock(|s| s.is_active());
+                let status = if active { "On" } else { "Off" };
+                write!(shell, "{0:}Sweep: {1:}{0:}", CR, status).ok();
+                return;
+            }
+            "off" => {
+                self.sweep.lock(|s| s.cancel());
+                shell.write_str(CR).ok();
+                return;
+            }
+            _ => {}
+        }
+
+        let (target, max_freq) = match self.pwmout.lock(|p| p.channel()) {
+            Some(_) => (Target::Pwm, PwmOut::MAX_FREQ),
+            None => (Target::Led, 100),
+        };
+        let freq_range = 1..=max_freq;
+        let dwell_range = Monitor::MIN_INTERVAL_MS..=Monitor::MAX_INTERVAL_MS;
+        let mut args = args
+            .split_whitespace()
+            .map(|arg| btoi::btoi::<u32>(arg.as_bytes()));
+        match (
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+            args.next(),
+        ) {
+            (Some(Ok(start)), Some(Ok(stop)), Some(Ok(step)), Some(Ok(dwell)), None)
+                if freq_range.contains(&start)
+                    && freq_range.contains(&stop)
+                    && step > 0
+                    && dwell_range.contains(&dwell) =>
+            {
+                if dwell % Monitor::MIN_INTERVAL_MS != 0 {
+                    write!(
+                        shell,
+                        "{0:}dwell must be a multiple of {1:}ms up to {2:}ms{0:}",
+                        CR,
+                        Monitor::MIN_INTERVAL_MS,
+                        Monitor::MAX_INTERVAL_MS
+                    )
+                    .ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                    return;
+                }
+                self.sweep
+                    .lock(|s| s.start(target, start, stop, step, dwell));
+                shell.write_str(CR).ok();
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn pvd_command(&mut self, shell: &mut Shell, args: &str) {
+        match args {
+            "" => {
+                let (level, low, failures, last, before_reset) = self.power.lock(|p| {
+                    (
+                        p.level(),
+                        p.is_low(),
+                        p.failures(),
+                        p.last_failure(),
+                        p.failed_before_reset(),
+                    )
+                });
+                match level {
+                    Some(level) => write!(
+                        shell,
+                        "{0:}PVD: level {1:} ({2:}mV), supply {3:}",
+                        CR,
+                        level,
+                        PVD_LEVELS_MV[level as usize],
+                        if low { "low" } else { "ok" }
+                    ),
+                    None => write!(shell, "{}PVD: Off", CR),
+                }
+                .ok();
+                write!(shell, "{0:}Failures: {1:}", CR, failures).ok();
+                if let Some(ticks) = last {
+                    write!(shell, ", last at {}s", ticks / TICK_HZ).ok();
+                }
+                if before_reset {
+                    write!(shell, "{}Power failure detected before last reset", CR).ok();
+                }
+                write!(shell, "{0:}Levels:", CR).ok();
+                for (level, mv) in PVD_LEVELS_MV.iter().enumerate() {
+                    write!(shell, " {}={}mV", level, mv).ok();
+                }
+                shell.write_str(CR).ok();
+            }
+            "off" => {
+                self.power.lock(|p| p.disable());
+                shell.write_str(CR).ok();
+            }
+            _ => match btoi::btoi::<u8>(args.as_bytes()) {
+                Ok(level) if (level as usize) < PVD_LEVELS_MV.len() => {
+                    self.power.lock(|p| p.enable(level));
+                    shell.write_str(CR).ok();
+                }
+                _ => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                }
+            },
+        }
+    }
+
+    pub fn stamp_command(shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            let stamp = shell.serial().stamp();
+            write!(shell, "{0:}Stamp: {1:}{0:}Formats:", CR, stamp.name()).ok();
+            for (name, _) in STAMPS.iter() {
+                write!(shell, " {}", name).ok();
+            }
+            shell.write_str(CR).ok();
+            return;
+        }
+        match Stamp::from_name(args) {
+            Some(stamp) => {
+                shell.serial().set_stamp(stamp);
+                shell.write_str(CR).ok();
+            }
+            None => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn verbosity_command(shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            let level = verbosity::level();
+            write!(shell, "{0:}Verbosity: {1:}{0:}Levels:", CR, level.name()).ok();
+            for (name, _) in LEVELS.iter() {
+                write!(shell, " {}", name).ok();
+            }
+            shell.write_str(CR).ok();
+            return;
+        }
+        match Level::from_name(args) {
+            Some(level) => {
+                verbosity::set_level(level);
+                shell.write_str(CR).ok();
+                detail!(shell, "Verbosity: {}{}", level.name(), CR);
+            }
+            None => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn powerprofile_command(&mut self, shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            let (profile, speed) = self.clock.lock(|c| (c.profile(), c.speed()));
+            let speed = match speed {
+                Speed::Full => "full",
+                Speed::Low => "low",
+            };
+            write!(
+                shell,
+                "{0:}Profile: {1:}{0:}Core: {2:} ({3:}Hz){0:}Profiles:",
+                CR,
+                profile.name(),
+                speed,
+                clocks::timer_clk()
+            )
+            .ok();
+            for (name, _) in PROFILES.iter() {
+                write!(shell, " {}", name).ok();
+            }
+            shell.write_str(CR).ok();
+            return;
+        }
+        match Profile::from_name(args) {
+            Some(profile) => {
+                let previous = Previous::Profile(self.clock.lock(|c| c.profile()));
+                if !self.arm_rollback(shell, previous) {
+                    return;
+                }
+                self.clock.lock(|c| c.set_profile(profile));
+                self.apply_clock_policy();
+                write!(
+                    shell,
+                    "{0:}confirm within {1:}s or it rolls back{0:}",
+                    CR,
+                    CONFIRM_MS / 1000
+                )
+                .ok();
+            }
+            None => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn pwmout_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next(), args.next()) {
+            (None, _, _) => match self.pwmout.lock(|p| p.channel()) {
+                Some(channel) => {
+                    let (freq, duty) = self.pwmout.lock(|p| (p.freq(), p.duty()));
+                    write!(
+                        shell,
+                        "{0:}Pin: PA{1:}{0:}Frequency: {2:}Hz{0:}Duty: {3:}%{0:}",
+                        CR,
+                        channel.pin(),
+                        freq,
+                        duty
+                    )
+                    .ok();
+                }
+                None => {
+                    write!(shell, "{0:}PWM output: Off{0:}", CR).ok();
+                }
+            },
+            (Some("off"), None, _) => {
+                self.pwmout.lock(|p| p.stop());
+                shell.write_str(CR).ok();
+            }
+            (Some(pin), Some(freq), Some(duty)) => {
+                let channel = trigger::parse_pin(pin).and_then(Channel::from_pin);
+                let freq = btoi::btoi::<u32>(freq.as_bytes());
+                let duty = btoi::btoi::<u8>(duty.as_bytes());
+                match (channel, freq, duty) {
+                    (None, _, _) => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                    (Some(channel), Ok(freq), Ok(duty))
+                        if freq > 0 && freq <= PwmOut::MAX_FREQ && duty <= 100 =>
+                    {
+                        match self.pwmout.lock(|p| p.start(channel, freq, duty)) {
+                            Ok(()) => {
+                                shell.write_str(CR).ok();
+                            }
+                            Err(err) => pins::write_error(shell, err),
+                        }
+                    }
+                    _ => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                }
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn bitbang_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        let (subcmd, arg, extra) = (args.next(), args.next(), args.next());
+        let op = match (subcmd, arg, extra, args.next()) {
+            (None, ..) => {
+                shell.write_str(CR).ok();
+                let ops = self.bitbang.lock(|b| b.ops());
+                for (idx, op) in ops.iter().enumerate() {
+                    write!(shell, "{:>2}: ", idx + 1).ok();
+                    match *op {
+                        Op::Set(pin, high) => {
+                            write!(shell, "set PA{} {}", pin, if high { "high" } else { "low" })
+                        }
+                        Op::Delay(us) => write!(shell, "delay {}us", us),
+                        Op::Read(pin) => write!(shell, "read PA{}", pin),
+                        Op::Loop(count) => write!(shell, "loop {}", count),
+                    }
+                    .ok();
+                    shell.write_str(CR).ok();
+                }
+                return;
+            }
+            (Some("clear"), None, ..) => {
+                self.bitbang.lock(|b| b.clear());
+                shell.write_str(CR).ok();
+                return;
+            }
+            (Some("run"), None, ..) => {
+                self.bitbang_run(shell);
+                return;
+            }
+            (Some("set"), Some(pin), Some(level @ ("high" | "low")), None) => {
+                trigger::parse_pin(pin).map(|pin| Op::Set(pin, level == "high"))
+            }
+            (Some("read"), Some(pin), None, _) => trigger::parse_pin(pin).map(Op::Read),
+            (Some("delay"), Some(us), None, _) => match btoi::btoi::<u32>(us.as_bytes()) {
+                Ok(us) if us as u64 <= bitbang::MAX_RUN_US => Some(Op::Delay(us)),
+                _ => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                    return;
+                }
+            },
+            (Some("loop"), Some(count), None, _) => match btoi::btoi::<u32>(count.as_bytes()) {
+                Ok(count) if (1..=bitbang::MAX_LOOP).contains(&count) => Some(Op::Loop(count)),
+                _ => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                    return;
+                }
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        match op.map(|op| self.bitbang.lock(|b| b.push(op))) {
+            Some(Ok(())) => {
+                shell.write_str(CR).ok();
+            }
+            Some(Err(err)) => {
+                metrics::set_exit_status(ExitStatus::Error);
+                write!(shell, "{0:}bitbang: {1:}{0:}", CR, err.message()).ok();
+            }
+            None => metrics::set_exit_status(ExitStatus::Usage),
+        }
+    }
+
+    /// Only pins the gpio command made outputs are driven, the sequence must not fight
+    /// a peripheral
+    fn bitbang_run(&mut self, shell: &mut Shell) {
+        let outputs = self
+            .bitbang
+            .lock(|b| b.outputs().fold(0u16, |mask, pin| mask | 1 << pin));
+        for pin in 0..16 {
+            if outputs & 1 << pin != 0 && self.gpio.lock(|g| g.mode(pin)) != Some(PinMode::Output) {
+                let err = GpioError::NotOutput;
+                write!(
+                    shell,
+                    "{0:}bitbang: PA{1:} {2:}{0:}",
+                    CR,
+                    pin,
+                    err.message()
+                )
+                .ok();
+                return;
+            }
+        }
+        match self.bitbang.lock(|b| b.run()) {
+            Ok(reads) => {
+                shell.write_str(CR).ok();
+                if !reads.levels.is_empty() {
+                    shell.write_str("Reads: ").ok();
+                    for high in reads.levels.iter() {
+                        shell.write_str(if *high { "1" } else { "0" }).ok();
+                    }
+                    shell.write_str(CR).ok();
+                }
+                if reads.dropped > 0 {
+                    write!(shell, "Dropped: {}{}", reads.dropped, CR).ok();
+                }
+            }
+            Err(err @ BitbangError::TooLong) => {
+                write!(
+                    shell,
+                    "{0:}bitbang: {1:}, limit is {2:}ms{0:}",
+                    CR,
+                    err.message(),
+                    bitbang::MAX_RUN_US / 1000
+                )
+                .ok();
+            }
+            Err(err) => {
+                write!(shell, "{0:}bitbang: {1:}{0:}", CR, err.message()).ok();
+            }
+        }
+    }
+
+    pub fn burst_command(&mut self, shell: &mut Shell, args: &str) {
+        let mut args = args.split_whitespace();
+        match (args.next(), args.next(), args.next()) {
+            (None, _, _) => {
+                let (last, running) = self.burst.lock(|b| (b.last(), b.is_running()));
+                match last {
+                    Some((pin, pulses, freq)) => {
+                        let state = if running { "Running" } else { "Done" };
+                        write!(
+                            shell,
+                            "{0:}Burst: {1:}{0:}Pin: PA{2:}{0:}Pulses: {3:}{0:}Frequency: {4:}Hz{0:}",
+                            CR, state, pin, pulses, freq
+                        )
+                        .ok();
+                    }
+                    None => {
+                        write!(shell, "{0:}Burst: Off{0:}", CR).ok();
+                    }
+                }
+            }
+            (Some("off"), None, _) => {
+                self.burst.lock(|b| b.stop());
+                shell.write_str(CR).ok();
+            }
+            (Some(pin), Some(pulses), Some(freq)) => {
+                let pin = trigger::parse_pin(pin);
+                let pulses = btoi::btoi::<u32>(pulses.as_bytes());
+                let freq = btoi::btoi::<u32>(freq.as_bytes());
+                match (pulses, freq) {
+                    (Ok(pulses), Ok(freq))
+                        if (1..=Burst::MAX_PULSES).contains(&pulses)
+                            && (1..=Burst::MAX_FREQ).contains(&freq) =>
+                    {
+                        let res = match pin {
+                            Some(pin) => self.burst.lock(|b| b.start(pin, pulses, freq)),
+                            None => Err(PinError::Unsupported),
+                        };
+                        match res {
+                            Ok(()) => {
+                                shell.write_str(CR).ok();
+                                detail!(
+                                    shell,
+                                    "Duration: {}ms{}",
+                                    pulses as u64 * 1000 / freq as u64,
+                                    CR
+                                );
+                            }
+                            Err(err) => pins::write_error(shell, err),
+                        }
+                    }
+                    _ => {
+                        metrics::set_exit_status(ExitStatus::Usage);
+                    }
+                }
+            }
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn trig_command(&mut self, shell: &mut Shell, args: &str) {
+        let (subcmd, arg) = args.split_once(" ").unwrap_or((args, ""));
+        match (subcmd, Event::from_name(arg)) {
+            ("", _) => {
+                let (pin, width) = self.trigger.lock(|t| (t.pin(), t.width()));
+                match pin {
+                    Some(pin) => write!(shell, "{}Pin: PA{}", CR, pin).ok(),
+                    None => write!(shell, "{}Pin: none", CR).ok(),
+                };
+                write!(shell, "{0:}Width: {1:} cycles{0:}Events:", CR, width).ok();
+                for (name, event) in EVENTS.iter() {
+                    let listening = self.trigger.lock(|t| t.is_listening(*event));
+                    let mark = if listening { '*' } else { ' ' };
+                    write!(shell, "{} {}{}", CR, mark, name).ok();
+                }
+                shell.write_str(CR).ok();
+            }
+            ("width", _) => match btoi::btoi(arg.as_bytes()) {
+                Ok(width) if width > 0 && width <= Trigger::MAX_WIDTH => {
+                    self.trigger.lock(|t| t.set_width(width));
+                    shell.write_str(CR).ok();
+                }
+                _ => {
+                    write!(
+                        shell,
+                        "{0:}width must be 1-{1:} cycles{0:}",
+                        CR,
+                        Trigger::MAX_WIDTH
+                    )
+                    .ok();
+                }
+            },
+            ("on", Some(event)) => {
+                self.trigger.lock(|t| t.listen(event));
+                shell.write_str(CR).ok();
+            }
+            ("off", Some(event)) => {
+                self.trigger.lock(|t| t.unlisten(event));
+                shell.write_str(CR).ok();
+            }
+            ("on", None) | ("off", None) => {
+                write!(shell, "{0:}unknown event{0:}", CR).ok();
+            }
+            (pin, _) => {
+                let res = match trigger::parse_pin(pin) {
+                    Some(pin) => self.trigger.lock(|t| t.set_pin(pin)),
+                    None => Err(PinError::Unsupported),
+                };
+                match res {
+                    Ok(()) => {
+                        self.trigger.lock(|t| t.fire());
+                        shell.write_str(CR).ok();
+                    }
+                    Err(err) => pins::write_error(shell, err),
+                }
+            }
+        }
+    }
+
+    fn trace_dump(&mut self, shell: &mut Shell) {
+        let trace = shell.serial().inner().trace().clone();
+        let mut line = [0; hex::LINE_LEN];
+        let mut line_len = 0;
+        let mut line_dir = Direction::Rx;
+
+        shell.write_str(CR).ok();
+        for (dir, byte) in trace.iter() {
+            if line_len == hex::LINE_LEN || (line_len > 0 && dir != line_dir) {
+                Self::write_trace_line(shell, line_dir, &line[..line_len]);
+                line_len = 0;
+            }
+            line_dir = dir;
+            line[line_len] = byte;
+            line_len += 1;
+        }
+        if line_len > 0 {
+            Self::write_trace_line(shell, line_dir, &line[..line_len]);
+        }
+    }
+
+    fn write_trace_line(shell: &mut Shell, dir: Direction, bytes: &[u8]) {
+        let dir = if dir == Direction::Rx { "RX" } else { "TX" };
+        shell.write_str(dir).ok();
+        hex::write_bytes(shell, bytes);
+        hex::write_ascii(shell, bytes);
+        shell.write_str(CR).ok();
+    }
+
+    pub fn assert_command(&mut self, shell: &mut Shell, args: &str) {
+        let res = calc::eval(args, &mut |name| {
+            Watch::from_name(name).map(|watch| self.watch_value(watch))
+        });
+        let status = match res {
+            Ok(0) => {
+                write!(shell, "{0:}FAIL: {1:}{0:}", CR, args).ok();
+                ExitStatus::Fail
+            }
+            Ok(_) => {
+                write!(shell, "{0:}PASS{0:}", CR).ok();
+                ExitStatus::Ok
+            }
+            Err(err) => {
+                write!(shell, "{0:}assert: {1:}{0:}", CR, err.message()).ok();
+                ExitStatus::Error
+            }
+        };
+        metrics::set_exit_status(status);
+    }
+
+    /// Watch as an integer: animation is 0 or 1, uptime is in seconds
+    fn watch_value(&mut self, watch: Watch) -> i32 {
+        match watch {
+            Watch::Animation => self.blink_enabled.lock(|e| *e) as i32,
+            Watch::BlinkFreq => self.blink_freq.lock(|f| *f) as i32,
+            Watch::Uptime => (self.ticks.lock(|t| *t) / TICK_HZ) as i32,
+            Watch::Current => self
+                .energy_estimate()
+                .map_or(0, |(_, estimate)| estimate.average_ua as i32),
+        }
+    }
+
+    fn write_watch(&mut self, shell: &mut Shell, watch: Watch) {
+        match watch {
+            Watch::Animation => {
+                let on = self.blink_enabled.lock(|e| *e);
+                shell.write_str(if on { "on" } else { "off" }).ok();
+            }
+            Watch::BlinkFreq => {
+                let freq = self.blink_freq.lock(|f| *f);
+                write!(shell, "{}Hz", freq).ok();
+            }
+            Watch::Uptime => {
+                let ticks = self.ticks.lock(|t| *t);
+                write!(
+                    shell,
+                    "{}.{}s",
+                    ticks / TICK_HZ,
+                    ticks % TICK_HZ * 10 / TICK_HZ
+                )
+                .ok();
+            }
+            Watch::Current => {
+                match self.energy_estimate() {
+                    Some((_, estimate)) => write!(shell, "{}uA", estimate.average_ua),
+                    None => shell.write_str("-"),
+                }
+                .ok();
+            }
+        }
+    }
+
+    fn current_settings(&mut self) -> Settings {
+        Settings {
+            blink_freq: self.blink_freq.lock(|f| *f),
+            blink_enabled: self.blink_enabled.lock(|e| *e),
+            prompt: settings::prompt_text(),
+        }
+    }
+
+    fn apply_settings(&mut self, saved: &Settings) {
+        self.blink_enabled.lock(|e| *e = saved.blink_enabled);
+        self.set_blink_freq(saved.blink_freq);
+        settings::set_prompt(&saved.prompt).ok();
+    }
+
+    /// History lines oldest first, walking back leaves the cursor where typing resumes
+    fn history_lines(shell: &mut Shell) -> HistoryLines {
+        let history = shell.get_history_mut();
+        let mut lines = HistoryLines::new();
+        while let Some(line) = history.go_back() {
+            lines.push(line).ok();
+        }
+        history.push("").ok();
+        lines.reverse();
+        lines
+    }
+
+    pub fn save_command(&mut self, shell: &mut Shell) {
+        let history = Self::history_lines(shell);
+        let res =
+            settings::save(&self.current_settings()).and_then(|_| settings::save_history(&history));
+        match res {
+            Ok(()) => {
+                shell.write_str(CR).ok();
+                detail!(
+                    shell,
+                    "Saved, record {}/{} of the page{}",
+                    settings::used_slots(),
+                    settings::SLOTS,
+                    CR
+                );
+            }
+            Err(err) => {
+                write!(shell, "{0:}save: {1:}{0:}", CR, err.message()).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+        }
+    }
+
+    pub fn load_command(&mut self, shell: &mut Shell) {
+        match settings::load() {
+            Some(saved) => {
+                self.apply_settings(&saved);
+                shell.write_str(CR).ok();
+            }
+            None => {
+                write!(shell, "{0:}no saved settings{0:}", CR).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+        }
+    }
+
+    /// Only the running settings go back to defaults, `save` makes it stick
+    pub fn defaults_command(&mut self, shell: &mut Shell) {
+        self.apply_settings(&Settings::defaults());
+        shell.write_str(CR).ok();
+        detail!(shell, "Defaults applied, save to keep them{}", CR);
+    }
+
+    pub fn prompt_command(shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            write!(shell, "{0:}Prompt: {1:}{0:}", CR, settings::prompt_text()).ok();
+            return;
+        }
+        match settings::set_prompt(args) {
+            Ok(()) => {
+                shell.write_str(CR).ok();
+            }
+            Err(err) => {
+                write!(shell, "{0:}prompt: {1:}{0:}", CR, err.message()).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+        }
+    }
+
+    /// Opens the confirm window of a risky change before it is applied, false when
+    /// another change still waits for its confirm
+    fn arm_rollback(&mut self, shell: &mut Shell, previous: Previous) -> bool {
+        let now = mono::now_ms();
+        let wakeup = match self.rollback.lock(|r| r.arm(previous, now)) {
+            Ok(wakeup) => wakeup,
+            Err(err) => {
+                write!(
+                    shell,
+                    "{0:}{1:}: {2:}{0:}",
+                    CR,
+                    previous.command(),
+                    err.message()
+                )
+                .ok();
+                metrics::set_exit_status(ExitStatus::Error);
+                return false;
+            }
+        };
+        if let Some((id, at_ms)) = wakeup {
+            // Without the wakeup nothing would roll back, so the change is refused
+            if rollback_due::spawn_at(Instant::new(at_ms), id).is_err() {
+                self.rollback.lock(|r| r.revert());
+                write!(
+                    shell,
+                    "{0:}{1:}: too many changes, try again{0:}",
+                    CR,
+                    previous.command()
+                )
+                .ok();
+                metrics::set_exit_status(ExitStatus::Error);
+                return false;
+            }
+        }
+        true
+    }
+
+    fn restore(&mut self, shell: &mut Shell, previous: Previous) {
+        match previous {
+            Previous::Baud(baud) => {
+                nb::block!(shell.serial().flush()).ok();
+                port::set_baud(baud);
+            }
+            Previous::Profile(profile) => {
+                self.clock.lock(|c| c.set_profile(profile));
+                self.apply_clock_policy();
+            }
+        }
+    }
+
+    /// Puts back a risky change nobody confirmed in time
+    fn rollback_run(&mut self, shell: &mut Shell) {
+        if let Some(previous) = self.rollback.lock(|r| r.take_due()) {
+            self.restore(shell, previous);
+            write!(
+                shell,
+                "\r\x1b[K{} not confirmed, rolled back{}{}",
+                previous.command(),
+                CR,
+                settings::prompt()
+            )
+            .ok();
+        }
+    }
+
+    pub fn coredump_command(shell: &mut Shell, args: &str) {
+        match args {
+            "" | "info" => match coredump::load() {
+                Some(dump) => {
+                    write!(
+                        shell,
+                        "{0:}Crash: {1:}{0:}SP: {2:08x} ({3:} stack bytes saved){0:}",
+                        CR,
+                        dump.kind.name(),
+                        dump.sp,
+                        dump.stack().len()
+                    )
+                    .ok();
+                    if dump.kind == coredump::Kind::HardFault {
+                        let frame = dump.frame;
+                        write!(
+                            shell,
+                            "PC: {1:08x}  LR: {2:08x}  xPSR: {3:08x}{0:}R0: {4:08x}  R1: {5:08x}  R2: {6:08x}  R3: {7:08x}  R12: {8:08x}{0:}",
+                            CR, frame.pc, frame.lr, frame.xpsr, frame.r0, frame.r1, frame.r2, frame.r3, frame.r12
+                        )
+                        .ok();
+                    }
+                    if !dump.text.is_empty() {
+                        write!(shell, "{}{}", dump.text, CR).ok();
+                    }
+                }
+                None => {
+                    write!(shell, "{0:}no coredump saved{0:}", CR).ok();
+                }
+            },
+            "read" => match coredump::load() {
+                Some(dump) => {
+                    shell.write_str(CR).ok();
+                    hex::dump(shell, dump.header());
+                    hex::dump_at(shell, dump.sp, dump.stack());
+                }
+                None => {
+                    write!(shell, "{0:}no coredump saved{0:}", CR).ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            },
+            "erase" => match coredump::erase() {
+                Ok(()) => {
+                    shell.write_str(CR).ok();
+                }
+                Err(err) => {
+                    write!(shell, "{0:}coredump: {1:}{0:}", CR, err.message()).ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn alias_command(shell: &mut Shell, args: &str) {
+        match args {
+            "" | "list" => {
+                shell.write_str(CR).ok();
+                for (name, line) in aliases::list() {
+                    write!(shell, "{}=\"{}\"{}", name, line, CR).ok();
+                }
+            }
+            def if def.contains('=') => match aliases::define(def) {
+                Ok(()) => {
+                    shell.write_str(CR).ok();
+                }
+                Err(err) => {
+                    write!(shell, "{0:}alias: {1:}{0:}", CR, err.message()).ok();
+                    metrics::set_exit_status(ExitStatus::Error);
+                }
+            },
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+            }
+        }
+    }
+
+    pub fn unalias_command(shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            metrics::set_exit_status(ExitStatus::Usage);
+            return;
+        }
+        match aliases::remove(args) {
+            Ok(()) => {
+                shell.write_str(CR).ok();
+            }
+            Err(err) => {
+                write!(shell, "{0:}unalias: {1:}{0:}", CR, err.message()).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+        }
+    }
+
+    /// Without arguments prints the budget and the last command that ran over it
+    pub fn budget_command(shell: &mut Shell, args: &str) {
+        let budget_ms = match args {
+            "" => {
+                match budget::budget_ms() {
+                    Some(ms) => write!(shell, "{0:}Budget: {1:}ms{0:}", CR, ms),
+                    None => write!(shell, "{0:}Budget: off{0:}", CR),
+                }
+                .ok();
+                if let Some(overrun) = budget::last() {
+                    write!(
+                        shell,
+                        "Last overrun: {} took {}ms of {}ms{}",
+                        overrun.cmd, overrun.elapsed_ms, overrun.budget_ms, CR
+                    )
+                    .ok();
+                }
+                return;
+            }
+            "off" => None,
+            _ => match btoi::btoi::<u32>(args.as_bytes()) {
+                Ok(ms) => Some(ms),
+                Err(_) => {
+                    metrics::set_exit_status(ExitStatus::Usage);
+                    return;
+                }
+            },
+        };
+        match budget::set_budget_ms(budget_ms) {
+            Ok(()) => {
+                shell.write_str(CR).ok();
+            }
+            Err(err) => {
+                write!(shell, "{0:}budget: {1:}{0:}", CR, err.message()).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+        }
+    }
+
+    pub fn baud_command(&mut self, shell: &mut Shell, args: &str) {
+        if args.is_empty() {
+            write!(shell, "{0:}Baud: {1:}{0:}", CR, port::baud()).ok();
+            let now = mono::now_ms();
+            if let Some(Previous::Baud(baud)) = self.rollback.lock(|r| r.pending()) {
+                let left_ms = self.rollback.lock(|r| r.remaining_ms(now)).unwrap_or(0);
+                write!(
+                    shell,
+                    "Unconfirmed, back to {} in {}s{}",
+                    baud,
+                    left_ms.div_ceil(1000),
+                    CR
+                )
+                .ok();
+            }
+            return;
+        }
+        let baud = match btoi::btoi::<u32>(args.as_bytes()) {
+            Ok(baud) if (port::MIN_BAUD..=port::MAX_BAUD).contains(&baud) => baud,
+            _ => {
+                metrics::set_exit_status(ExitStatus::Usage);
+                return;
+            }
+        };
+        if !self.arm_rollback(shell, Previous::Baud(port::baud())) {
+            return;
+        }
+        write!(
+            shell,
+            "{0:}switching to {1:} baud, confirm within {2:}s at the new rate{0:}",
+            CR,
+            baud,
+            CONFIRM_MS / 1000
+        )
+        .ok();
+        nb::block!(shell.serial().flush()).ok();
+        port::set_baud(baud);
+    }
+
+    pub fn confirm_command(&mut self, shell: &mut Shell) {
+        if self.rollback.lock(|r| r.confirm()) {
+            shell.write_str(CR).ok();
+            detail!(shell, "Change kept{}", CR);
+        } else {
+            write!(shell, "{0:}nothing to confirm{0:}", CR).ok();
+            metrics::set_exit_status(ExitStatus::Error);
+        }
+    }
+
+    pub fn revert_command(&mut self, shell: &mut Shell) {
+        match self.rollback.lock(|r| r.revert()) {
+            Some(previous) => {
+                write!(shell, "{0:}{1:} rolled back{0:}", CR, previous.command()).ok();
+                self.restore(shell, previous);
+            }
+            None => {
+                write!(shell, "{0:}nothing to revert{0:}", CR).ok();
+                metrics::set_exit_status(ExitStatus::Error);
+            }
+        }
+    }
+
+    fn set_blink_freq(&mut self, freq: u8) {
+        self.blink_freq.lock(|f| *f = freq);
+        self.blink_timer.lock(|t| {
+            t.start(freq as u32 * 2);
+        });
+    }
+}
+
+/// Arguments without the `--dry-run` flag, `None` when it is not there
+fn strip_dry_run(args: &str) -> Option<String<CMD_MAX_LEN>> {
+    if !args.split_whitespace().any(|arg| arg == "--dry-run") {
+        return None;
+    }
+    let mut stripped = String::new();
+    for arg in args.split_whitespace().filter(|arg| *arg != "--dry-run") {
+        if !stripped.is_empty() {
+            stripped.push(' ').ok();
+        }
+        stripped.push_str(arg).ok();
+    }
+    Some(stripped)
+}
+
+fn parse_num(arg: &str) -> Option<u32> {
+    match arg.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => btoi::btoi(arg.as_bytes()).ok(),
+    }
+}
+
+/// Parses bit field spec `[name:]<lsb>..<msb>`
+fn parse_field(arg: &str) -> Option<(&str, u32, u32)> {
+    let (name, range) = arg.split_once(':').unwrap_or(("field", arg));
+    let (lsb, msb) = range.split_once("..")?;
+    let lsb = btoi::btoi(lsb.as_bytes()).ok()?;
+    let msb = btoi::btoi(msb.as_bytes()).ok()?;
+    if lsb <= msb && msb < 32 {
+        Some((name, lsb, msb))
+    } else {
+        None
+    }
+}
+
+fn field_mask(lsb: u32, msb: u32) -> u32 {
+    (u32::MAX >> (31 - msb + lsb)) << lsb
+}
@@ -0,0 +1,114 @@
+//! Signed command mode for links through untrusted hands. A dangerous command ends with
+//! ` @<nonce>:<mac>`: `mac` is the first 8 bytes in hex of HMAC-SHA256 over
+//! `<nonce>:<command line>` keyed with the provisioning key, and `nonce` must exceed the
+//! last accepted one. The last nonce lives in a backup register. Nonces are reserved in
+//! flash a block at a time before one is accepted, so after a power loss clears the
+//! register every nonce up to the reservation stays used.
+
+use crate::backup::{self, Slot};
+use crate::catalog;
+use crate::provision;
+use crate::settings;
+use crate::sha256;
+
+const MAC_LEN: usize = 8;
+/// Nonces reserved by one flash record
+const NONCE_BLOCK: u32 = 64;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SignError {
+    NoKey,
+    Missing,
+    BadFormat,
+    Replay,
+    BadMac,
+    /// The nonce reservation could not be written
+    Flash,
+    /// A replayed line reached a dangerous command through an alias
+    Alias,
+}
+
+impl SignError {
+    pub fn message(self) -> &'static str {
+        match self {
+            SignError::NoKey => "no signing key provisioned",
+            SignError::Missing => "signature required",
+            SignError::BadFormat => "malformed signature",
+            SignError::Replay => "stale nonce",
+            SignError::BadMac => "bad signature",
+            SignError::Flash => "nonce reservation failed, command not run",
+            SignError::Alias => "signed command can't run from an alias unattended",
+        }
+    }
+}
+
+/// Commands flagged `dangerous` in the catalog. Driver commands only read their device or
+/// switch a display.
+pub fn is_dangerous(cmd: &str) -> bool {
+    catalog::find(cmd).is_some_and(|info| info.dangerous)
+}
+
+/// Last accepted nonce. A cleared backup register means the backup domain lost power,
+/// the flash reservation then bounds every nonce accepted before.
+pub fn nonce() -> u32 {
+    match backup::read(Slot::SignNonce) {
+        0 => settings::nonce_ceiling(),
+        nonce => nonce,
+    }
+}
+
+/// Splits the trailing signature off the arguments
+pub fn split(args: &str) -> (&str, Option<&str>) {
+    let (rest, last) = args.rsplit_once(' ').unwrap_or(("", args));
+    match last.strip_prefix('@') {
+        Some(signature) => (rest, Some(signature)),
+        None => (args, None),
+    }
+}
+
+/// Checks the signature of `cmd args` and consumes its nonce
+pub fn verify(cmd: &str, args: &str, signature: Option<&str>) -> Result<(), SignError> {
+    let key = provision::key().ok_or(SignError::NoKey)?;
+    let (nonce_str, mac) = signature
+        .ok_or(SignError::Missing)?
+        .split_once(':')
+        .ok_or(SignError::BadFormat)?;
+    let nonce = btoi::btoi::<u32>(nonce_str.as_bytes()).map_err(|_| SignError::BadFormat)?;
+    // A multibyte character would split a hex pair off a char boundary
+    if mac.len() != MAC_LEN * 2 || !mac.is_ascii() {
+        return Err(SignError::BadFormat);
+    }
+    let mut expected = [0; MAC_LEN];
+    for (idx, byte) in expected.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&mac[idx * 2..][..2], 16).map_err(|_| SignError::BadFormat)?;
+    }
+    if nonce <= self::nonce() {
+        return Err(SignError::Replay);
+    }
+
+    let sep: &[u8] = if args.is_empty() { b"" } else { b" " };
+    let digest = sha256::hmac(
+        &key,
+        &[
+            nonce_str.as_bytes(),
+            b":",
+            cmd.as_bytes(),
+            sep,
+            args.as_bytes(),
+        ],
+    );
+    // Every byte is compared so the time taken tells nothing about the mismatch
+    let diff = digest[..MAC_LEN]
+        .iter()
+        .zip(expected.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b));
+    if diff != 0 {
+        return Err(SignError::BadMac);
+    }
+    if nonce > settings::nonce_ceiling() {
+        settings::reserve_nonces(nonce.saturating_add(NONCE_BLOCK - 1))
+            .map_err(|_| SignError::Flash)?;
+    }
+    backup::write(Slot::SignNonce, nonce);
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use hal::dma::{self, Channel, Direction, Event, Priority, WordSize};
+use hal::dmamux::DmaMuxIndex;
+use hal::stm32;
+
+use crate::pins::{self, Owner, PinError, Port};
+
+/// SPI1 slave on PD8 (SCK, AF1) with PA11 (MISO), PA12 (MOSI) and PA15 (NSS) on AF0
+const SCK_PIN: u32 = 8;
+const SCK_AF: u32 = 1;
+const PORT_A_PINS: [u32; 3] = [11, 12, 15];
+const NSS_PIN: u32 = 15;
+
+/// Register map, every transaction clocks out the whole map from offset 0
+pub const MAP_LEN: usize = 16;
+const MAGIC: u8 = 0xa5;
+const FLAG_ANIMATION: u8 = 1 << 0;
+const FLAG_LED: u8 = 1 << 1;
+
+/// Values published in the register map
+pub struct Status {
+    pub animation: bool,
+    pub led: bool,
+    pub freq: u8,
+    pub ticks: u32,
+    pub edges: u32,
+}
+
+/// Lets a host poll the board as an SPI slave. DMA shifts the register map out and the
+/// host bytes in, the map is refreshed between transactions.
+///
+/// | Offset | Size | Content                               |
+/// |--------|------|---------------------------------------|
+/// | 0      | 1    | 0xa5                                  |
+/// | 1      | 1    | bit 0 animation on, bit 1 LED lit     |
+/// | 2      | 1    | animation frequency in Hertz          |
+/// | 4      | 4    | system ticks, little endian           |
+/// | 8      | 4    | transactions served, little endian    |
+/// | 12     | 4    | edge counter count, little endian     |
+pub struct SpiSlave {
+    rb: stm32::SPI1,
+    rx: dma::C3,
+    tx: dma::C4,
+    map: &'static mut [u8; MAP_LEN],
+    received: &'static mut [u8; MAP_LEN],
+    enabled: bool,
+    frames: u32,
+    resyncs: u32,
+}
+
+impl SpiSlave {
+    pub fn new(rb: stm32::SPI1, mut rx: dma::C3, mut tx: dma::C4) -> Self {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        rcc.apbenr2.modify(|_, w| w.spi1en().set_bit());
+        rcc.iopenr.modify(|_, w| w.iopden().set_bit());
+
+        rx.select_peripheral(DmaMuxIndex::SPI1_RX);
+        rx.set_direction(Direction::FromPeripheral);
+        tx.select_peripheral(DmaMuxIndex::SPI1_TX);
+        tx.set_direction(Direction::FromMemory);
+        rx.set_priority_level(Priority::High);
+        rx.set_word_size::<u8>(WordSize::BITS8);
+        tx.set_priority_level(Priority::High);
+        tx.set_word_size::<u8>(WordSize::BITS8);
+        Self {
+            rb,
+            rx,
+            tx,
+            map: cortex_m::singleton!(: [u8; MAP_LEN] = [0; MAP_LEN]).unwrap(),
+            received: cortex_m::singleton!(: [u8; MAP_LEN] = [0; MAP_LEN]).unwrap(),
+            enabled: false,
+            frames: 0,
+            resyncs: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Complete transactions since the slave was enabled
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Transactions the host cut short
+    pub fn resyncs(&self) -> u32 {
+        self.resyncs
+    }
+
+    /// Bytes the host sent in the latest complete transaction
+    pub fn received(&self) -> [u8; MAP_LEN] {
+        *self.received
+    }
+
+    /// Takes the pins over and waits for the host, SPI mode 0
+    pub fn start(&mut self, status: &Status) -> Result<(), PinError> {
+        for pin in PORT_A_PINS.iter() {
+            pins::check(Port::A, *pin as u8, Owner::Slave)?;
+        }
+        pins::check(Port::D, SCK_PIN as u8, Owner::Slave)?;
+        let gpio_a = unsafe { &*stm32::GPIOA::ptr() };
+        let gpio_d = unsafe { &*stm32::GPIOD::ptr() };
+        for pin in PORT_A_PINS.iter() {
+            let shift = pin * 2;
+            let af_shift = (pin - 8) * 4;
+            gpio_a
+                .afrh
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(0xf << af_shift)) });
+            gpio_a
+                .moder
+                .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b10 << shift)) });
+            pins::claim(Port::A, *pin as u8, Owner::Slave)?;
+        }
+        // Keeps the slave deselected while no host is attached
+        gpio_a.pupdr.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << (NSS_PIN * 2))) | (0b01 << (NSS_PIN * 2)))
+        });
+        let af_shift = (SCK_PIN - 8) * 4;
+        gpio_d.afrh.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0xf << af_shift)) | (SCK_AF << af_shift))
+        });
+        gpio_d.moder.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << (SCK_PIN * 2))) | (0b10 << (SCK_PIN * 2)))
+        });
+        pins::claim(Port::D, SCK_PIN as u8, Owner::Slave)?;
+
+        self.rx.disable();
+        self.tx.disable();
+        self.rx
+            .set_peripheral_address(&self.rb.dr as *const _ as u32, false);
+        self.rx
+            .set_memory_address(self.received.as_ptr() as u32, true);
+        self.tx
+            .set_peripheral_address(&self.rb.dr as *const _ as u32, false);
+        self.tx.set_memory_address(self.map.as_ptr() as u32, true);
+        self.rx.listen(Event::TransferComplete);
+
+        self.frames = 0;
+        self.resyncs = 0;
+        self.refresh(status);
+        self.restart();
+        self.enabled = true;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.rb.cr1.modify(|_, w| w.spe().clear_bit());
+        self.rx.unlisten(Event::TransferComplete);
+        self.rx.disable();
+        self.tx.disable();
+        for pin in PORT_A_PINS.iter() {
+            pins::release(Port::A, *pin as u8, Owner::Slave);
+        }
+        pins::release(Port::D, SCK_PIN as u8, Owner::Slave);
+        self.enabled = false;
+    }
+
+    /// A transaction completed, publishes fresh values for the next one
+    pub fn on_dma(&mut self, status: &Status) {
+        self.rx.clear_event(Event::Any);
+        if !self.enabled {
+            return;
+        }
+        self.frames = self.frames.wrapping_add(1);
+        self.refresh(status);
+        self.arm();
+    }
+
+    /// Checks for a transaction the host abandoned halfway, the FIFOs would shift every
+    /// following one. Runs on the system tick while NSS is released.
+    pub fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let released = gpio.idr.read().bits() & (1 << NSS_PIN) != 0;
+        let dma = unsafe { &*stm32::DMA::ptr() };
+        let pending = dma.ch3.ndtr.read().bits() as usize;
+        if released && pending != MAP_LEN {
+            self.resyncs = self.resyncs.wrapping_add(1);
+            self.restart();
+        }
+    }
+
+    fn refresh(&mut self, status: &Status) {
+        let mut flags = 0;
+        if status.animation {
+            flags |= FLAG_ANIMATION;
+        }
+        if status.led {
+            flags |= FLAG_LED;
+        }
+        self.map[..4].copy_from_slice(&[MAGIC, flags, status.freq, 0]);
+        self.map[4..8].copy_from_slice(&status.ticks.to_le_bytes());
+        self.map[8..12].copy_from_slice(&self.frames.to_le_bytes());
+        self.map[12..16].copy_from_slice(&status.edges.to_le_bytes());
+    }
+
+    /// Resets SPI1 to flush both FIFOs, then arms a fresh transaction
+    fn restart(&mut self) {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        rcc.apbrstr2.modify(|_, w| w.spi1rst().set_bit());
+        rcc.apbrstr2.modify(|_, w| w.spi1rst().clear_bit());
+
+        self.rb
+            .cr2
+            .write(|w| unsafe { w.ds().bits(0b0111).frxth().set_bit().rxdmaen().set_bit() });
+        self.arm();
+        self.rb.cr2.modify(|_, w| w.txdmaen().set_bit());
+        self.rb.cr1.write(|w| w.spe().set_bit());
+    }
+
+    fn arm(&mut self) {
+        self.rx.disable();
+        self.tx.disable();
+        self.rx.set_transfer_length(MAP_LEN as u16);
+        self.tx.set_transfer_length(MAP_LEN as u16);
+        self.rx.enable();
+        self.tx.enable();
+    }
+}
@@ -0,0 +1,95 @@
+const ESC: u8 = 0x1b;
+
+/// Range and step of a value the arrow keys adjust
+#[derive(Clone, Copy)]
+pub struct Spec {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub min: u32,
+    pub max: u32,
+    pub step: u32,
+}
+
+impl Spec {
+    /// Value one step up or down, clamped to the range
+    pub fn next(&self, value: u32, key: Key) -> u32 {
+        match key {
+            Key::Up => value.saturating_add(self.step).min(self.max),
+            Key::Down => value.saturating_sub(self.step).max(self.min),
+            Key::Exit => value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    Exit,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Esc,
+    Csi,
+}
+
+/// Arrow key slider of `adjust`, it takes the raw shell input over until Esc. The
+/// caller keeps the table of values and applies each step itself.
+pub struct Slider {
+    /// Index of the value in the caller's table
+    active: Option<usize>,
+    escape: Escape,
+}
+
+impl Slider {
+    pub fn new() -> Self {
+        Self {
+            active: None,
+            escape: Escape::None,
+        }
+    }
+
+    pub fn active(&self) -> Option<usize> {
+        self.active
+    }
+
+    pub fn start(&mut self, idx: usize) {
+        self.active = Some(idx);
+        self.escape = Escape::None;
+    }
+
+    pub fn stop(&mut self) {
+        self.active = None;
+    }
+
+    /// Decodes one input byte, arrows arrive as ESC [ A and ESC [ B
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        let (escape, key) = match (self.escape, byte) {
+            (Escape::None, ESC) => (Escape::Esc, None),
+            (Escape::None, b'q') | (Escape::None, b'\r') => (Escape::None, Some(Key::Exit)),
+            (Escape::None, b'+') => (Escape::None, Some(Key::Up)),
+            (Escape::None, b'-') => (Escape::None, Some(Key::Down)),
+            (Escape::None, _) => (Escape::None, None),
+            (Escape::Esc, b'[') | (Escape::Esc, b'O') => (Escape::Csi, None),
+            // Esc followed by another key
+            (Escape::Esc, _) => (Escape::None, Some(Key::Exit)),
+            (Escape::Csi, b'A') => (Escape::None, Some(Key::Up)),
+            (Escape::Csi, b'B') => (Escape::None, Some(Key::Down)),
+            (Escape::Csi, 0x40..=0x7e) => (Escape::None, None),
+            (Escape::Csi, _) => (Escape::Csi, None),
+        };
+        self.escape = escape;
+        key
+    }
+
+    /// An arrow key comes in one burst, an Esc with nothing after it is the Esc key
+    pub fn end_of_input(&mut self) -> Option<Key> {
+        if self.escape == Escape::Esc {
+            self.escape = Escape::None;
+            return Some(Key::Exit);
+        }
+        None
+    }
+}
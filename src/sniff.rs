@@ -0,0 +1,121 @@
+use core::fmt::Write;
+
+use hal::stm32;
+use heapless::Vec;
+
+use crate::cycles;
+use crate::i2c::{SCL_PIN, SDA_PIN};
+use crate::shell::CR;
+
+pub const MAX_EVENTS: usize = 256;
+pub const MAX_DURATION_MS: u32 = 1000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Event {
+    Start,
+    /// Byte and whether the receiver acknowledged it
+    Byte(u8, bool),
+    Stop,
+}
+
+/// I2C traffic on PB8/PB9 decoded from the pin levels. The pins stay with the I2C1
+/// master, GPIO input data reads them in any mode, so nothing on the bus is disturbed.
+pub struct Sniff {
+    events: Vec<Event, MAX_EVENTS>,
+    overflow: bool,
+}
+
+impl Sniff {
+    /// Polls the bus for `duration_ms`, blocking the caller. 100kHz leaves plenty of
+    /// polls per bit, a long interrupt in between can still cost a clock edge.
+    pub fn run(duration_ms: u32) -> Self {
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        let scl = 1 << SCL_PIN;
+        let sda = 1 << SDA_PIN;
+        let mut sniff = Self {
+            events: Vec::new(),
+            overflow: false,
+        };
+        let duration = (cycles::freq() as u64 * duration_ms as u64 / 1000) as u32;
+
+        let start = cycles::now();
+        let mut prev = gpio.idr.read().bits() & (scl | sda);
+        let mut active = false;
+        let mut bits = 0;
+        let mut byte = 0u8;
+        while cycles::since(start) < duration {
+            let now = gpio.idr.read().bits() & (scl | sda);
+            if now == prev {
+                continue;
+            }
+            let event = if now & prev & scl != 0 {
+                // SDA moving while SCL is high frames a transfer
+                if now & sda == 0 {
+                    active = true;
+                    bits = 0;
+                    Some(Event::Start)
+                } else if active {
+                    active = false;
+                    Some(Event::Stop)
+                } else {
+                    None
+                }
+            } else if active && now & scl != 0 && prev & scl == 0 {
+                let bit = now & sda != 0;
+                if bits < 8 {
+                    byte = byte << 1 | bit as u8;
+                    bits += 1;
+                    None
+                } else {
+                    bits = 0;
+                    Some(Event::Byte(byte, !bit))
+                }
+            } else {
+                None
+            };
+            prev = now;
+            if let Some(event) = event {
+                if sniff.events.push(event).is_err() {
+                    sniff.overflow = true;
+                    break;
+                }
+            }
+        }
+        sniff
+    }
+
+    /// One line per transfer: address and direction, then the data bytes. Bytes the
+    /// receiver did not acknowledge are marked with NACK.
+    pub fn write(&self, out: &mut dyn Write) {
+        let mut transfers = 0;
+        let mut first = false;
+        for event in self.events.iter() {
+            match *event {
+                Event::Start => {
+                    transfers += 1;
+                    first = true;
+                    out.write_str(CR).ok();
+                }
+                Event::Byte(addr, ack) if first => {
+                    first = false;
+                    let dir = if addr & 1 == 0 { "W" } else { "R" };
+                    write!(out, "{:02x} {}", addr >> 1, dir).ok();
+                    if !ack {
+                        out.write_str(" NACK").ok();
+                    }
+                }
+                Event::Byte(byte, ack) => {
+                    write!(out, " {:02x}", byte).ok();
+                    if !ack {
+                        out.write_str(" NACK").ok();
+                    }
+                }
+                Event::Stop => {}
+            }
+        }
+        if self.overflow {
+            write!(out, "{}event buffer full", CR).ok();
+        }
+        write!(out, "{0:}Transfers: {1:}{0:}", CR, transfers).ok();
+    }
+}
@@ -0,0 +1,215 @@
+use core::ptr;
+
+use hal::stm32;
+
+use crate::clocks;
+use crate::pins::{self, Owner, PinError, Port};
+
+/// SPI2 on PB13 (SCK), PB14 (MISO), PB15 (MOSI) with alternate function 0, PB12 drives CS
+const CS_PIN: u32 = 12;
+const SPI_PINS: [u32; 3] = [13, 14, 15];
+const BUS_PINS: [u32; 4] = [CS_PIN, 13, 14, 15];
+const MISO_PIN: u32 = 14;
+const DR_OFFSET: usize = 0x0c;
+/// Status polls before a transfer is abandoned
+const TIMEOUT: u32 = 10_000;
+/// Longest shell transfer
+pub const MAX_XFER: usize = 32;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SpiError {
+    Timeout,
+    /// The bus is suspended
+    Off,
+}
+
+/// Clock polarity and phase as the SPI mode, clock as the APB divider exponent
+#[derive(Clone, Copy, PartialEq)]
+pub struct SpiConfig {
+    mode: u8,
+    br: u8,
+}
+
+impl SpiConfig {
+    /// Mode 0 at a quarter of the APB clock, what the drivers expect
+    const DRIVERS: SpiConfig = SpiConfig { mode: 0, br: 0b001 };
+
+    pub fn mode(&self) -> u8 {
+        self.mode
+    }
+
+    /// SCK frequency at the current clock speed, the APB runs undivided
+    pub fn freq(&self) -> u32 {
+        clocks::timer_clk() >> (self.br + 1)
+    }
+}
+
+/// Blocking SPI master with 8-bit frames. Drivers always get mode 0, shell transfers
+/// use their own configuration so any slave can be tried without upsetting them.
+pub struct Spi {
+    rb: stm32::SPI2,
+    xfer: SpiConfig,
+    on: bool,
+}
+
+impl Spi {
+    pub fn new(rb: stm32::SPI2) -> Self {
+        let mut spi = Self {
+            rb,
+            xfer: SpiConfig::DRIVERS,
+            on: false,
+        };
+        spi.configure();
+        for pin in BUS_PINS.iter() {
+            pins::reserve(Port::B, *pin as u8, Owner::Spi);
+        }
+        spi
+    }
+
+    /// False while suspended, transfers then fail with `SpiError::Off`
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /// Stops the peripheral and its clock and hands the pins back as analog inputs
+    pub fn suspend(&mut self) {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        self.rb.cr1.modify(|_, w| w.spe().clear_bit());
+        rcc.apbenr1.modify(|_, w| w.spi2en().clear_bit());
+        gpio.pupdr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << (MISO_PIN * 2))) });
+        for pin in BUS_PINS.iter() {
+            gpio.moder
+                .modify(|r, w| unsafe { w.bits(r.bits() | (0b11 << (pin * 2))) });
+            pins::release(Port::B, *pin as u8, Owner::Spi);
+        }
+        self.on = false;
+    }
+
+    /// Brings the bus back as `new` left it, unless another subsystem took a pin meanwhile
+    pub fn resume(&mut self) -> Result<(), PinError> {
+        for pin in BUS_PINS.iter() {
+            pins::check(Port::B, *pin as u8, Owner::Spi)?;
+        }
+        self.configure();
+        for pin in BUS_PINS.iter() {
+            pins::claim(Port::B, *pin as u8, Owner::Spi)?;
+        }
+        Ok(())
+    }
+
+    fn configure(&mut self) {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        rcc.iopenr.modify(|_, w| w.iopben().set_bit());
+        rcc.apbenr1.modify(|_, w| w.spi2en().set_bit());
+
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << CS_PIN) });
+        gpio.moder.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << (CS_PIN * 2))) | (0b01 << (CS_PIN * 2)))
+        });
+        for pin in SPI_PINS.iter() {
+            let shift = pin * 2;
+            let af_shift = (pin - 8) * 4;
+            gpio.afrh
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(0xf << af_shift)) });
+            gpio.moder
+                .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b10 << shift)) });
+        }
+        // Idle MISO reads 0xff without a device
+        gpio.pupdr.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << (MISO_PIN * 2))) | (0b01 << (MISO_PIN * 2)))
+        });
+
+        self.rb
+            .cr2
+            .write(|w| unsafe { w.ds().bits(0b0111).frxth().set_bit() });
+        self.rb.cr1.write(|w| unsafe {
+            w.mstr()
+                .set_bit()
+                .ssm()
+                .set_bit()
+                .ssi()
+                .set_bit()
+                .br()
+                .bits(SpiConfig::DRIVERS.br)
+                .spe()
+                .set_bit()
+        });
+        self.on = true;
+    }
+
+    pub fn xfer_config(&self) -> SpiConfig {
+        self.xfer
+    }
+
+    /// Picks the fastest clock not above `freq`, the slowest one when none is
+    pub fn set_xfer_config(&mut self, mode: u8, freq: u32) {
+        let br = (0..7)
+            .find(|br| clocks::timer_clk() >> (br + 1) <= freq)
+            .unwrap_or(7);
+        self.xfer = SpiConfig { mode, br };
+    }
+
+    /// Shell transfer framed by CS with the shell configuration
+    pub fn xfer(&mut self, buf: &mut [u8]) -> Result<(), SpiError> {
+        if !self.on {
+            return Err(SpiError::Off);
+        }
+        self.apply(self.xfer);
+        self.select();
+        let res = self.transfer(buf);
+        self.deselect();
+        self.apply(SpiConfig::DRIVERS);
+        res
+    }
+
+    fn apply(&mut self, config: SpiConfig) {
+        self.rb.cr1.modify(|_, w| w.spe().clear_bit());
+        self.rb.cr1.modify(|_, w| unsafe {
+            w.cpol()
+                .bit(config.mode & 0b10 != 0)
+                .cpha()
+                .bit(config.mode & 0b01 != 0)
+                .br()
+                .bits(config.br)
+        });
+        self.rb.cr1.modify(|_, w| w.spe().set_bit());
+    }
+
+    pub fn select(&mut self) {
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << (CS_PIN + 16)) });
+    }
+
+    pub fn deselect(&mut self) {
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << CS_PIN) });
+    }
+
+    /// Exchanges bytes in place
+    pub fn transfer(&mut self, buf: &mut [u8]) -> Result<(), SpiError> {
+        if !self.on {
+            return Err(SpiError::Off);
+        }
+        // 8-bit access, a 16-bit write to DR would queue two frames
+        let dr = unsafe { (stm32::SPI2::ptr() as *mut u8).add(DR_OFFSET) };
+        for byte in buf.iter_mut() {
+            self.wait(|sr| sr.txe().bit_is_set())?;
+            unsafe { ptr::write_volatile(dr, *byte) };
+            self.wait(|sr| sr.rxne().bit_is_set())?;
+            *byte = unsafe { ptr::read_volatile(dr) };
+        }
+        Ok(())
+    }
+
+    fn wait(&self, ready: impl Fn(&stm32::spi1::sr::R) -> bool) -> Result<(), SpiError> {
+        for _ in 0..TIMEOUT {
+            if ready(&self.rb.sr.read()) {
+                return Ok(());
+            }
+        }
+        Err(SpiError::Timeout)
+    }
+}
@@ -0,0 +1,93 @@
+use cortex_m::peripheral::SCB;
+use hal::stm32;
+
+use crate::backup::{self, Slot};
+
+const RESUME_MARKER: u32 = 0x5b00_0000;
+const RESUME_MASK: u32 = 0xff00_0000;
+const RTCSEL_LSI: u8 = 0b10;
+
+/// Animation state carried across Standby in a backup register
+#[derive(Clone, Copy)]
+pub struct ResumeState {
+    pub blink_enabled: bool,
+    pub blink_freq: u8,
+}
+
+impl ResumeState {
+    fn pack(self) -> u32 {
+        RESUME_MARKER | (self.blink_enabled as u32) << 8 | self.blink_freq as u32
+    }
+
+    fn unpack(raw: u32) -> Option<Self> {
+        let blink_freq = raw as u8;
+        if raw & RESUME_MASK != RESUME_MARKER || blink_freq == 0 || blink_freq > 100 {
+            return None;
+        }
+        Some(Self {
+            blink_enabled: raw & (1 << 8) != 0,
+            blink_freq,
+        })
+    }
+}
+
+/// Returns the saved state when the core has just left Standby
+pub fn resume() -> Option<ResumeState> {
+    let pwr = unsafe { &*stm32::PWR::ptr() };
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+
+    if pwr.sr1.read().sbf().bit_is_clear() {
+        return None;
+    }
+    pwr.scr.write(|w| w.csbf().set_bit());
+
+    rtc.wpr.write(|w| unsafe { w.bits(0xca) });
+    rtc.wpr.write(|w| unsafe { w.bits(0x53) });
+    rtc.cr
+        .modify(|_, w| w.wute().clear_bit().wutie().clear_bit());
+    rtc.scr.write(|w| w.cwutf().set_bit());
+    rtc.wpr.write(|w| unsafe { w.bits(0xff) });
+
+    let state = ResumeState::unpack(backup::read(Slot::Standby));
+    backup::write(Slot::Standby, 0);
+    state
+}
+
+/// Enters Standby and wakes up through a system reset after `seconds`
+pub fn enter(seconds: u16, state: ResumeState) -> ! {
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    let pwr = unsafe { &*stm32::PWR::ptr() };
+    let rtc = unsafe { &*stm32::RTC::ptr() };
+
+    backup::write(Slot::Standby, state.pack());
+
+    // LSI keeps the RTC running in Standby, keep the clock if one was already selected
+    rcc.csr.modify(|_, w| w.lsion().set_bit());
+    while rcc.csr.read().lsirdy().bit_is_clear() {}
+    if rcc.bdcr.read().rtcsel().bits() == 0 {
+        rcc.bdcr
+            .modify(|_, w| unsafe { w.rtcsel().bits(RTCSEL_LSI).rtcen().set_bit() });
+    }
+
+    rtc.wpr.write(|w| unsafe { w.bits(0xca) });
+    rtc.wpr.write(|w| unsafe { w.bits(0x53) });
+    rtc.cr.modify(|_, w| w.wute().clear_bit());
+    while rtc.icsr.read().wutwf().bit_is_clear() {}
+    // ck_spre (1Hz) wakeup clock
+    rtc.wutr.write(|w| unsafe { w.bits(seconds as u32 - 1) });
+    rtc.cr
+        .modify(|_, w| unsafe { w.wucksel().bits(0b100).wutie().set_bit().wute().set_bit() });
+    rtc.scr.write(|w| w.cwutf().set_bit());
+    rtc.wpr.write(|w| unsafe { w.bits(0xff) });
+
+    pwr.cr3.modify(|_, w| w.eiwul().set_bit());
+    pwr.scr.write(|w| unsafe { w.bits(0x3f) });
+    pwr.cr1.modify(|_, w| unsafe { w.lpms().bits(0b011) });
+
+    cortex_m::interrupt::disable();
+    unsafe { (*SCB::ptr()).scr.modify(|scr| scr | 1 << 2) };
+    loop {
+        cortex_m::asm::dsb();
+        cortex_m::asm::wfi();
+    }
+}
@@ -0,0 +1,124 @@
+use core::fmt::Write;
+
+use crate::config::TICK_HZ;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Edge {
+    Top,
+    Bottom,
+}
+
+/// Terminal row kept out of the scroll region and redrawn once per second
+pub struct StatusBar {
+    enabled: bool,
+    edge: Edge,
+    rows: u16,
+    elapsed: u32,
+    due: bool,
+}
+
+impl StatusBar {
+    pub const MIN_ROWS: u16 = 4;
+    pub const MAX_ROWS: u16 = 200;
+    const INTERVAL: u32 = TICK_HZ;
+
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            edge: Edge::Bottom,
+            rows: 24,
+            elapsed: 0,
+            due: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn edge(&self) -> Edge {
+        self.edge
+    }
+
+    /// Terminal height, the bar cannot ask the terminal for it
+    pub fn rows(&self) -> u16 {
+        self.rows
+    }
+
+    /// Pins the bar to a terminal edge, `draw` or `release` have to follow
+    pub fn set(&mut self, enabled: bool, edge: Edge, rows: u16) {
+        self.enabled = enabled;
+        self.edge = edge;
+        self.rows = rows;
+        self.elapsed = 0;
+        self.due = false;
+    }
+
+    fn row(&self) -> u16 {
+        match self.edge {
+            Edge::Top => 1,
+            Edge::Bottom => self.rows,
+        }
+    }
+
+    /// Sets the scroll region around the bar and draws it, the cursor stays put
+    pub fn draw(&self, out: &mut dyn Write, text: &str) {
+        let (top, bottom) = match self.edge {
+            Edge::Top => (2, self.rows),
+            Edge::Bottom => (1, self.rows - 1),
+        };
+        // DECSC, scroll region, bar row in reverse video, DECRC
+        write!(
+            out,
+            "\x1b7\x1b[{};{}r\x1b[{};1H\x1b[2K\x1b[7m{}\x1b[0m\x1b8",
+            top,
+            bottom,
+            self.row(),
+            text
+        )
+        .ok();
+    }
+
+    /// Gives the whole screen back to scrolling and clears the bar row
+    pub fn release(&self, out: &mut dyn Write) {
+        write!(out, "\x1b7\x1b[r\x1b[{};1H\x1b[2K\x1b8", self.row()).ok();
+    }
+
+    /// Advances by one system tick, returns true when a redraw is due
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances by a number of ticks slept through in Stop mode
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        self.elapsed += ticks;
+        if self.elapsed >= Self::INTERVAL {
+            self.elapsed = 0;
+            self.due = true;
+        }
+        self.due
+    }
+
+    /// Ticks left until the next redraw
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        if self.enabled {
+            Some(Self::INTERVAL.saturating_sub(self.elapsed))
+        } else {
+            None
+        }
+    }
+
+    /// Redraws on the next background pass
+    pub fn redraw(&mut self) {
+        self.due = self.enabled;
+    }
+
+    pub fn take_due(&mut self) -> bool {
+        let due = self.due;
+        self.due = false;
+        due
+    }
+}
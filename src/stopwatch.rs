@@ -0,0 +1,81 @@
+use core::fmt::Write;
+
+/// Writes milliseconds as `M:SS.mmm`, hours only show up once there are any
+pub fn write_time(out: &mut dyn Write, ms: u64) {
+    let secs = ms / 1000;
+    if secs >= 3600 {
+        write!(out, "{}:{:02}", secs / 3600, secs / 60 % 60)
+    } else {
+        write!(out, "{}", secs / 60)
+    }
+    .ok();
+    write!(out, ":{:02}.{:03}", secs % 60, ms % 1000).ok();
+}
+
+/// Stopwatch on the monotonic, a lap splits the time without stopping it
+pub struct Stopwatch {
+    running: bool,
+    started_ms: u64,
+    /// Start of the current lap
+    lap_ms: u64,
+    laps: u16,
+    /// Time of a stopped watch
+    total_ms: u64,
+}
+
+impl Stopwatch {
+    pub fn new() -> Self {
+        Self {
+            running: false,
+            started_ms: 0,
+            lap_ms: 0,
+            laps: 0,
+            total_ms: 0,
+        }
+    }
+
+    /// Starts over from zero, also while running
+    pub fn start(&mut self, now_ms: u64) {
+        *self = Self::new();
+        self.running = true;
+        self.started_ms = now_ms;
+        self.lap_ms = now_ms;
+    }
+
+    /// Lap number, lap time and total time, `None` while stopped
+    pub fn lap(&mut self, now_ms: u64) -> Option<(u16, u64, u64)> {
+        if !self.running {
+            return None;
+        }
+        let split = now_ms - self.lap_ms;
+        self.lap_ms = now_ms;
+        self.laps += 1;
+        Some((self.laps, split, now_ms - self.started_ms))
+    }
+
+    /// Total time, `None` if the watch was not running
+    pub fn stop(&mut self, now_ms: u64) -> Option<u64> {
+        if !self.running {
+            return None;
+        }
+        self.running = false;
+        self.total_ms = now_ms - self.started_ms;
+        Some(self.total_ms)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn laps(&self) -> u16 {
+        self.laps
+    }
+
+    pub fn elapsed_ms(&self, now_ms: u64) -> u64 {
+        if self.running {
+            now_ms - self.started_ms
+        } else {
+            self.total_ms
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::config::TICK_HZ;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Target {
+    Led,
+    Pwm,
+}
+
+/// Background frequency sweep, stepped by the system tick
+pub struct Sweep {
+    target: Target,
+    next: Option<u32>,
+    stop: u32,
+    step: u32,
+    dwell: u32,
+    elapsed: u32,
+    due: bool,
+}
+
+impl Sweep {
+    pub fn new() -> Self {
+        Self {
+            target: Target::Led,
+            next: None,
+            stop: 0,
+            step: 0,
+            dwell: 0,
+            elapsed: 0,
+            due: false,
+        }
+    }
+
+    pub fn start(&mut self, target: Target, start: u32, stop: u32, step: u32, dwell_ms: u32) {
+        self.target = target;
+        self.next = Some(start);
+        self.stop = stop;
+        self.step = step;
+        self.dwell = (dwell_ms * TICK_HZ / 1000).max(1);
+        self.elapsed = 0;
+        self.due = true;
+    }
+
+    pub fn cancel(&mut self) {
+        self.next = None;
+        self.due = false;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.next.is_some()
+    }
+
+    pub fn target(&self) -> Target {
+        self.target
+    }
+
+    /// Advances sweep by one system tick, returns true when a step is due
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances sweep by a number of ticks slept through in Stop mode
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        if self.next.is_none() {
+            return false;
+        }
+        self.elapsed += ticks;
+        if self.elapsed >= self.dwell {
+            self.elapsed = 0;
+            self.due = true;
+        }
+        self.due
+    }
+
+    /// Ticks left until the next step
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        self.next.map(|_| self.dwell.saturating_sub(self.elapsed))
+    }
+
+    /// Takes the frequency of a due step and schedules the following one
+    pub fn take_step(&mut self) -> Option<u32> {
+        if !self.due {
+            return None;
+        }
+        self.due = false;
+        let freq = self.next?;
+        self.next = if freq < self.stop {
+            Some((freq + self.step).min(self.stop))
+        } else if freq > self.stop {
+            Some(freq.saturating_sub(self.step).max(self.stop))
+        } else {
+            None
+        };
+        Some(freq)
+    }
+}
@@ -0,0 +1,146 @@
+use hal::stm32;
+
+use crate::config::TICK_HZ;
+use crate::pins::{self, Owner, Port};
+
+/// Relay or MOSFET drivers on PB2..PB5, active high
+pub const CHANNELS: usize = 4;
+const FIRST_PIN: u32 = 2;
+
+#[derive(Clone, Copy)]
+struct Channel {
+    /// Ticks switched on, `None` while off
+    on_for: Option<u32>,
+    pulse: Option<u32>,
+    max_on: Option<u32>,
+    tripped: bool,
+}
+
+/// Switched output channels, each with an optional max-on-time watchdog
+pub struct Switches {
+    channels: [Channel; CHANNELS],
+}
+
+impl Switches {
+    pub const MAX_PULSE_MS: u32 = 600_000;
+
+    pub fn new() -> Self {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        rcc.iopenr.modify(|_, w| w.iopben().set_bit());
+        for n in 0..CHANNELS {
+            let pin = FIRST_PIN + n as u32;
+            gpio.bsrr.write(|w| unsafe { w.bits(1 << (pin + 16)) });
+            gpio.moder.modify(|r, w| unsafe {
+                w.bits((r.bits() & !(0b11 << (pin * 2))) | (0b01 << (pin * 2)))
+            });
+            pins::reserve(Port::B, pin as u8, Owner::Switches);
+        }
+        Self {
+            channels: [Channel {
+                on_for: None,
+                pulse: None,
+                max_on: None,
+                tripped: false,
+            }; CHANNELS],
+        }
+    }
+
+    pub fn is_on(&self, n: usize) -> bool {
+        self.channels[n].on_for.is_some()
+    }
+
+    /// Seconds the channel has been on
+    pub fn on_for_s(&self, n: usize) -> Option<u32> {
+        self.channels[n].on_for.map(|ticks| ticks / TICK_HZ)
+    }
+
+    pub fn max_on_ms(&self, n: usize) -> Option<u32> {
+        self.channels[n].max_on.map(|ticks| ticks * 1000 / TICK_HZ)
+    }
+
+    /// Longest time the channel may stay on before the watchdog switches it off
+    pub fn set_max_on_ms(&mut self, n: usize, ms: Option<u32>) {
+        self.channels[n].max_on = ms.map(Self::ms_to_ticks);
+    }
+
+    pub fn on(&mut self, n: usize) {
+        let channel = &mut self.channels[n];
+        channel.pulse = None;
+        if channel.on_for.is_none() {
+            channel.on_for = Some(0);
+        }
+        Self::drive(n, true);
+    }
+
+    pub fn off(&mut self, n: usize) {
+        self.channels[n].on_for = None;
+        self.channels[n].pulse = None;
+        Self::drive(n, false);
+    }
+
+    /// Switches the channel on for `ms`, the watchdog still applies
+    pub fn pulse(&mut self, n: usize, ms: u32) {
+        self.on(n);
+        self.channels[n].pulse = Some(Self::ms_to_ticks(ms));
+    }
+
+    /// Advances by one system tick, returns true when a watchdog tripped
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances by a number of ticks slept through in Stop mode. Pulses end and
+    /// watchdogs trip here, so channels switch off on time without the shell
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        for n in 0..CHANNELS {
+            let channel = &mut self.channels[n];
+            let on_for = match channel.on_for {
+                Some(on_for) => on_for + ticks,
+                None => continue,
+            };
+            channel.on_for = Some(on_for);
+            if channel.pulse.is_some_and(|pulse| on_for >= pulse) {
+                self.off(n);
+            } else if channel.max_on.is_some_and(|max_on| on_for >= max_on) {
+                self.off(n);
+                self.channels[n].tripped = true;
+            }
+        }
+        self.channels.iter().any(|channel| channel.tripped)
+    }
+
+    /// Ticks left until the first pulse ends or watchdog trips
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        self.channels
+            .iter()
+            .filter_map(|channel| {
+                let on_for = channel.on_for?;
+                [channel.pulse, channel.max_on]
+                    .iter()
+                    .flatten()
+                    .min()
+                    .map(|limit| limit.saturating_sub(on_for))
+            })
+            .min()
+    }
+
+    /// Next channel switched off by its watchdog since the last call
+    pub fn take_tripped(&mut self) -> Option<usize> {
+        let n = self.channels.iter().position(|channel| channel.tripped)?;
+        self.channels[n].tripped = false;
+        Some(n)
+    }
+
+    fn ms_to_ticks(ms: u32) -> u32 {
+        let tick_ms = 1000 / TICK_HZ;
+        ms.div_ceil(tick_ms).max(1)
+    }
+
+    fn drive(n: usize, on: bool) {
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        let pin = FIRST_PIN + n as u32;
+        let bit = if on { pin } else { pin + 16 };
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << bit) });
+    }
+}
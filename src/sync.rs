@@ -0,0 +1,156 @@
+use hal::stm32;
+
+use crate::clocks;
+use crate::config::TICK_HZ;
+use crate::pins::{self, Owner, PinError, Port};
+
+/// Sync line on PA10, pulsed by the master or watched on EXTI line 10 by the others
+const SYNC_PIN: u32 = 10;
+const SYNC_EXTI_LINE: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Off,
+    Out,
+    In,
+}
+
+pub const MODES: [(&str, Mode); 3] = [("off", Mode::Off), ("out", Mode::Out), ("in", Mode::In)];
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        MODES
+            .iter()
+            .find(|(mode_name, _)| *mode_name == name)
+            .map(|(_, mode)| *mode)
+    }
+
+    pub fn name(self) -> &'static str {
+        MODES[self as usize].0
+    }
+}
+
+/// Blink phase against a second boundary shared by several boards. The master pulses the
+/// sync line on its own second boundary, the others lock to the rising edge of that pulse.
+pub struct BlinkSync {
+    mode: Mode,
+    phase_ms: u32,
+    pulses: u32,
+    last: Option<u32>,
+}
+
+impl BlinkSync {
+    pub const MAX_PHASE_MS: u32 = 999;
+
+    pub fn new() -> Self {
+        Self {
+            mode: Mode::Off,
+            phase_ms: 0,
+            pulses: 0,
+            last: None,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Drives PA10 low for the master, otherwise leaves it an input with pull-down so an
+    /// open line reads idle. Only an input listens to EXTI.
+    pub fn set_mode(&mut self, mode: Mode) -> Result<(), PinError> {
+        if mode != Mode::Off {
+            pins::claim(Port::A, SYNC_PIN as u8, Owner::Sync)?;
+        }
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        let line = 1 << SYNC_EXTI_LINE;
+        let shift = SYNC_PIN * 2;
+        exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() & !line) });
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << (SYNC_PIN + 16)) });
+        gpio.pupdr
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b10 << shift)) });
+        let moder = if mode == Mode::Out { 0b01 } else { 0b00 };
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (moder << shift)) });
+
+        if mode == Mode::In {
+            let cr_shift = (SYNC_EXTI_LINE % 4) * 8;
+            exti.exticr3
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(0xff << cr_shift)) });
+            exti.rtsr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+            exti.rpr1.write(|w| unsafe { w.bits(line) });
+            exti.imr1.modify(|r, w| unsafe { w.bits(r.bits() | line) });
+        }
+        if mode == Mode::Off {
+            pins::release(Port::A, SYNC_PIN as u8, Owner::Sync);
+        }
+        self.mode = mode;
+        self.pulses = 0;
+        self.last = None;
+        Ok(())
+    }
+
+    pub fn phase_ms(&self) -> u32 {
+        self.phase_ms
+    }
+
+    /// Delay from the second boundary to the LED switching on
+    pub fn set_phase_ms(&mut self, phase_ms: u32) {
+        self.phase_ms = phase_ms;
+    }
+
+    /// Pulses sent or received since the mode was set
+    pub fn pulses(&self) -> u32 {
+        self.pulses
+    }
+
+    /// Tick of the latest pulse
+    pub fn last(&self) -> Option<u32> {
+        self.last
+    }
+
+    /// Advances the master by one system tick, the pulse is high for the first tick of
+    /// every second. Returns true on the second boundary.
+    pub fn tick(&mut self, now: u32) -> bool {
+        if self.mode != Mode::Out {
+            return false;
+        }
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let edge = now.is_multiple_of(TICK_HZ);
+        let bit = if edge { SYNC_PIN } else { SYNC_PIN + 16 };
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << bit) });
+        if edge {
+            self.on_pulse(now);
+        }
+        edge
+    }
+
+    pub fn on_pulse(&mut self, now: u32) {
+        self.pulses = self.pulses.wrapping_add(1);
+        self.last = Some(now);
+    }
+
+    /// LED level right at a sync edge and blink timer cycles until its next toggle, so the
+    /// LED switches on `phase` after every edge. `half_period` is the blink timer period.
+    pub fn align(&self, half_period: u32) -> (bool, u32) {
+        let half_period = half_period.max(1);
+        let phase = self.phase_ms as u64 * clocks::timer_clk() as u64 / 1000;
+        let phase = (phase % (2 * half_period as u64)) as u32;
+        let next = match phase % half_period {
+            0 => half_period,
+            rest => rest,
+        };
+        (phase == 0 || phase > half_period, next)
+    }
+
+    /// EXTI line 10 shares its interrupt with the PIR input
+    pub fn is_pending() -> bool {
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        exti.rpr1.read().bits() & (1 << SYNC_EXTI_LINE) != 0
+    }
+
+    pub fn clear_irq() {
+        let exti = unsafe { &*stm32::EXTI::ptr() };
+        exti.rpr1.write(|w| unsafe { w.bits(1 << SYNC_EXTI_LINE) });
+    }
+}
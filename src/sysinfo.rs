@@ -0,0 +1,60 @@
+use core::ptr;
+
+use hal::stm32;
+
+/// Factory registers in system memory
+const UID: *const u32 = 0x1fff_7590 as *const u32;
+const FLASH_SIZE: *const u16 = 0x1fff_75e0 as *const u16;
+
+/// DEV_ID values of the G0 lines, RM0444 and RM0454
+const DEVICES: [(u16, &str); 4] = [
+    (0x456, "STM32G05x/G06x"),
+    (0x460, "STM32G07x/G08x"),
+    (0x466, "STM32G03x/G04x"),
+    (0x467, "STM32G0Bx/G0Cx"),
+];
+/// REV_ID of the die revisions shipped so far
+const REVISIONS: [(u16, &str); 2] = [(0x1000, "A"), (0x2000, "B")];
+
+/// 96-bit unique device ID, lowest word first
+pub fn uid() -> [u32; 3] {
+    let mut uid = [0; 3];
+    for (n, word) in uid.iter_mut().enumerate() {
+        *word = unsafe { ptr::read_volatile(UID.add(n)) };
+    }
+    uid
+}
+
+/// Main flash size in KiB
+pub fn flash_kib() -> u16 {
+    unsafe { ptr::read_volatile(FLASH_SIZE) }
+}
+
+/// DEV_ID and REV_ID from DBG_IDCODE. The register sits behind the DBG clock, it is
+/// left as found.
+pub fn idcode() -> (u16, u16) {
+    let rcc = unsafe { &*stm32::RCC::ptr() };
+    let enabled = rcc.apbenr1.read().dbgen().bit_is_set();
+    rcc.apbenr1.modify(|_, w| w.dbgen().set_bit());
+    let dbg = unsafe { &*stm32::DBG::ptr() };
+    let idcode = dbg.idcode.read();
+    let ids = (idcode.dev_id().bits(), idcode.rev_id().bits());
+    if !enabled {
+        rcc.apbenr1.modify(|_, w| w.dbgen().clear_bit());
+    }
+    ids
+}
+
+pub fn device_name(dev_id: u16) -> Option<&'static str> {
+    DEVICES
+        .iter()
+        .find(|(id, _)| *id == dev_id)
+        .map(|(_, name)| *name)
+}
+
+pub fn revision_name(rev_id: u16) -> Option<&'static str> {
+    REVISIONS
+        .iter()
+        .find(|(id, _)| *id == rev_id)
+        .map(|(_, name)| *name)
+}
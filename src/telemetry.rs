@@ -0,0 +1,136 @@
+use crate::config::TICK_HZ;
+
+const PACKET_TYPE: u8 = 0x01;
+pub const PACKET_LEN: usize = 14;
+
+/// Snapshot of the board state sent in one packet
+pub struct Sample {
+    pub animation: bool,
+    pub pwm: bool,
+    pub throttling: bool,
+    pub blink_freq: u8,
+    pub vdda_mv: u16,
+    pub temp_c: i16,
+    pub ticks: u32,
+}
+
+impl Sample {
+    /// Little-endian packet: type, sequence, state flags, blink frequency, VDDA in mV,
+    /// temperature in C, uptime in ticks, CRC-16/CCITT-FALSE of the preceding bytes
+    pub fn encode(&self, seq: u8) -> [u8; PACKET_LEN] {
+        let flags = self.animation as u8 | (self.pwm as u8) << 1 | (self.throttling as u8) << 2;
+        let mut packet = [0; PACKET_LEN];
+        packet[0] = PACKET_TYPE;
+        packet[1] = seq;
+        packet[2] = flags;
+        packet[3] = self.blink_freq;
+        packet[4..6].copy_from_slice(&self.vdda_mv.to_le_bytes());
+        packet[6..8].copy_from_slice(&self.temp_c.to_le_bytes());
+        packet[8..12].copy_from_slice(&self.ticks.to_le_bytes());
+        let crc = crc16(&packet[..12]);
+        packet[12..].copy_from_slice(&crc.to_le_bytes());
+        packet
+    }
+}
+
+pub const CRC16_INIT: u16 = 0xffff;
+
+/// CRC-16/CCITT-FALSE
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes
+        .iter()
+        .fold(CRC16_INIT, |crc, byte| crc16_step(crc, *byte))
+}
+
+pub fn crc16_step(mut crc: u16, byte: u8) -> u16 {
+    crc ^= (byte as u16) << 8;
+    for _ in 0..8 {
+        crc = if crc & 0x8000 != 0 {
+            crc << 1 ^ 0x1021
+        } else {
+            crc << 1
+        };
+    }
+    crc
+}
+
+/// Periodic push of binary packets alongside the shell
+pub struct Telemetry {
+    enabled: bool,
+    interval: u32,
+    elapsed: u32,
+    due: bool,
+    seq: u8,
+}
+
+impl Telemetry {
+    pub const MIN_INTERVAL_MS: u32 = 1000 / TICK_HZ;
+    pub const MAX_INTERVAL_MS: u32 = 60_000;
+
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            interval: TICK_HZ,
+            elapsed: 0,
+            due: false,
+            seq: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn interval_ms(&self) -> u32 {
+        self.interval * 1000 / TICK_HZ
+    }
+
+    pub fn start(&mut self, interval_ms: u32) {
+        self.enabled = true;
+        self.interval = interval_ms * TICK_HZ / 1000;
+        self.elapsed = 0;
+        self.due = false;
+    }
+
+    pub fn stop(&mut self) {
+        self.enabled = false;
+        self.due = false;
+    }
+
+    /// Advances telemetry by one system tick, returns true when a packet is due
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances telemetry by a number of ticks slept through in Stop mode
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        self.elapsed += ticks;
+        if self.elapsed >= self.interval {
+            self.elapsed = 0;
+            self.due = true;
+        }
+        self.due
+    }
+
+    /// Ticks left until the next packet
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        if self.enabled {
+            Some(self.interval.saturating_sub(self.elapsed))
+        } else {
+            None
+        }
+    }
+
+    /// Sequence number for the next packet when one is due
+    pub fn take_due(&mut self) -> Option<u8> {
+        if !self.due {
+            return None;
+        }
+        self.due = false;
+        self.seq = self.seq.wrapping_add(1);
+        Some(self.seq)
+    }
+}
@@ -0,0 +1,121 @@
+use crate::config::TICK_HZ;
+
+/// Bang-bang heater control on an output channel, checked once per second
+pub struct Thermostat {
+    enabled: bool,
+    setpoint: i32,
+    hysteresis: i32,
+    channel: usize,
+    heating: bool,
+    elapsed: u32,
+    due: bool,
+}
+
+impl Thermostat {
+    pub const MIN_SETPOINT: i32 = -40;
+    pub const MAX_SETPOINT: i32 = 85;
+    pub const MAX_HYSTERESIS: i32 = 20;
+    const INTERVAL: u32 = TICK_HZ;
+
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            setpoint: 25,
+            hysteresis: 1,
+            channel: 0,
+            heating: false,
+            elapsed: 0,
+            due: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.heating = false;
+        self.elapsed = 0;
+        self.due = enabled;
+    }
+
+    pub fn setpoint(&self) -> i32 {
+        self.setpoint
+    }
+
+    pub fn set_setpoint(&mut self, setpoint: i32) {
+        self.setpoint = setpoint;
+    }
+
+    pub fn hysteresis(&self) -> i32 {
+        self.hysteresis
+    }
+
+    pub fn set_hysteresis(&mut self, hysteresis: i32) {
+        self.hysteresis = hysteresis;
+    }
+
+    /// Output channel driving the heater
+    pub fn channel(&self) -> usize {
+        self.channel
+    }
+
+    pub fn set_channel(&mut self, channel: usize) {
+        self.channel = channel;
+    }
+
+    pub fn is_heating(&self) -> bool {
+        self.heating
+    }
+
+    /// Heats up to `setpoint` and resumes below `setpoint - hysteresis`, returns the
+    /// new heater state when it changes
+    pub fn update(&mut self, temp: i32) -> Option<bool> {
+        let heating = if temp >= self.setpoint {
+            false
+        } else if temp < self.setpoint - self.hysteresis {
+            true
+        } else {
+            self.heating
+        };
+        if heating == self.heating {
+            return None;
+        }
+        self.heating = heating;
+        Some(heating)
+    }
+
+    /// Advances control by one system tick, returns true when a check is due
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances control by a number of ticks slept through in Stop mode
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        self.elapsed += ticks;
+        if self.elapsed >= Self::INTERVAL {
+            self.elapsed = 0;
+            self.due = true;
+        }
+        self.due
+    }
+
+    /// Ticks left until the next check
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        if self.enabled {
+            Some(Self::INTERVAL.saturating_sub(self.elapsed))
+        } else {
+            None
+        }
+    }
+
+    pub fn take_due(&mut self) -> bool {
+        let due = self.due;
+        self.due = false;
+        due
+    }
+}
@@ -0,0 +1,51 @@
+use cortex_m::peripheral::{NVIC, SCB};
+use hal::stm32::{self, Interrupt};
+
+use crate::config::ShellUsart;
+use crate::rtc;
+
+/// USART CR3 UCESM: keep the kernel clock requestable in Stop mode
+const UCESM: u32 = 1 << 23;
+
+/// Lets shell reception and the RTC wakeup timer bring the core out of Stop mode
+pub fn init() {
+    let usart = unsafe { &*ShellUsart::ptr() };
+    usart.cr1.modify(|_, w| w.uesm().set_bit());
+    usart.cr3.modify(|r, w| unsafe { w.bits(r.bits() | UCESM) });
+    // The pending wakeup is cleared before interrupts are enabled again, only the RTC
+    // alarm gets serviced
+    unsafe { NVIC::unmask(Interrupt::RTC_STAMP) };
+}
+
+/// True when stopping the clocks would not cut a serial transfer short
+pub fn is_quiet() -> bool {
+    let isr = unsafe { &*ShellUsart::ptr() }.isr.read();
+    isr.tc().bit_is_set() && isr.busy().bit_is_clear()
+}
+
+/// Enters Stop 1 until an interrupt or `limit_ms` elapses, returns time asleep measured by the RTC.
+/// Must be called with interrupts disabled.
+pub fn stop(limit_ms: Option<u32>) -> u32 {
+    let pwr = unsafe { &*stm32::PWR::ptr() };
+    let scb = unsafe { &*SCB::ptr() };
+
+    if let Some(ms) = limit_ms {
+        rtc::set_wakeup(ms);
+    }
+    let start = rtc::millis();
+
+    pwr.cr1.modify(|_, w| unsafe { w.lpms().bits(0b001) });
+    unsafe { scb.scr.modify(|scr| scr | 1 << 2) };
+    cortex_m::asm::dsb();
+    cortex_m::asm::wfi();
+    unsafe { scb.scr.modify(|scr| scr & !(1 << 2)) };
+
+    let elapsed = (rtc::millis() + rtc::DAY_MS - start) % rtc::DAY_MS;
+    if limit_ms.is_some() {
+        rtc::clear_wakeup();
+        if !rtc::alarm_fired() {
+            NVIC::unpend(Interrupt::RTC_STAMP);
+        }
+    }
+    elapsed
+}
@@ -0,0 +1,110 @@
+use core::cell::Cell;
+
+use cortex_m::interrupt::{self, Mutex};
+
+use crate::cycles;
+use crate::rtc::{self, DateTime};
+
+/// Unix time of 2000-01-01 and 2100-01-01, the span the RTC calendar covers
+const EPOCH_2000_S: u64 = 946_684_800;
+const EPOCH_2100_S: u64 = 4_102_444_800;
+const DAY_S: u64 = 86_400;
+
+/// Host time of the last sync in Unix milliseconds and the drift it measured. Kept in
+/// RAM, the first sync after a reset only sets the clock.
+static LAST_SYNC: Mutex<Cell<Option<u64>>> = Mutex::new(Cell::new(None));
+static LAST_DRIFT: Mutex<Cell<Option<i32>>> = Mutex::new(Cell::new(None));
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SyncError {
+    /// Outside the years 2000 to 2099
+    Range,
+}
+
+impl SyncError {
+    pub fn message(self) -> &'static str {
+        match self {
+            SyncError::Range => "time must fall in 2000-2099",
+        }
+    }
+}
+
+/// Civil date of a day count since 1970-01-01, Howard Hinnant's algorithm
+fn datetime(epoch_s: u64) -> DateTime {
+    let days = (epoch_s / DAY_S) as i64 + 719_468;
+    let secs = epoch_s % DAY_S;
+    let era = days / 146_097;
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = (yoe + era * 400 + (month <= 2) as i64) as u16;
+    DateTime {
+        year,
+        month,
+        day,
+        hour: (secs / 3600) as u8,
+        minute: (secs / 60 % 60) as u8,
+        second: (secs % 60) as u8,
+    }
+}
+
+/// Unix seconds of a calendar date, the inverse of `datetime`
+fn epoch_s(dt: &DateTime) -> u64 {
+    let year = dt.year as i64 - (dt.month <= 2) as i64;
+    let era = year / 400;
+    let yoe = year - era * 400;
+    let mp = (dt.month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + dt.day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146_097 + doe - 719_468) as u64;
+    days * DAY_S + dt.hour as u64 * 3600 + dt.minute as u64 * 60 + dt.second as u64
+}
+
+/// RTC time in Unix milliseconds
+pub fn rtc_ms() -> u64 {
+    loop {
+        let before = rtc::millis();
+        let now = rtc::now();
+        let after = rtc::millis();
+        if before / 1000 == after / 1000 {
+            return epoch_s(&now) * 1000 + (after % 1000) as u64;
+        }
+    }
+}
+
+/// Sets the RTC to the host time and returns how far the RTC ran ahead of the host
+/// since the previous sync, in ppm. The calendar only takes whole seconds, so the
+/// write waits for the host's next second to begin.
+pub fn sync(host_ms: u64) -> Result<Option<i32>, SyncError> {
+    if !(EPOCH_2000_S * 1000..(EPOCH_2100_S - 1) * 1000).contains(&host_ms) {
+        return Err(SyncError::Range);
+    }
+    let drift = match interrupt::free(|cs| LAST_SYNC.borrow(cs).get()) {
+        Some(last_ms) if rtc::is_set() && host_ms > last_ms => {
+            let ahead = rtc_ms() as i64 - host_ms as i64;
+            Some((ahead * 1_000_000 / (host_ms - last_ms) as i64) as i32)
+        }
+        _ => None,
+    };
+
+    let wait_ms = ((1000 - host_ms % 1000) % 1000) as u32;
+    cortex_m::asm::delay(cycles::freq() / 1000 * wait_ms);
+    let host_ms = host_ms + wait_ms as u64;
+    rtc::set(&datetime(host_ms / 1000));
+
+    interrupt::free(|cs| {
+        LAST_SYNC.borrow(cs).set(Some(host_ms));
+        if drift.is_some() {
+            LAST_DRIFT.borrow(cs).set(drift);
+        }
+    });
+    Ok(drift)
+}
+
+/// Host time of the last sync and the last drift measured
+pub fn last() -> (Option<u64>, Option<i32>) {
+    interrupt::free(|cs| (LAST_SYNC.borrow(cs).get(), LAST_DRIFT.borrow(cs).get()))
+}
@@ -0,0 +1,153 @@
+use hal::stm32;
+
+use crate::cal;
+use crate::cycles;
+use crate::pins::{self, Owner, Port};
+
+/// PB0 charges the pad on PB1 through a series resistor (around 1M)
+const SEND_PIN: u32 = 0;
+const SENSE_PIN: u32 = 1;
+const SAMPLES: u32 = 8;
+const CAL_ROUNDS: u32 = 4;
+/// Cycles before a measurement gives up on an open or shorted pad
+const TIMEOUT: u32 = 100_000;
+
+/// Charge-transfer touch button, checked on every system tick while enabled
+pub struct Touch {
+    baseline: u32,
+    threshold: u32,
+    enabled: bool,
+    touched: bool,
+    due: bool,
+}
+
+impl Touch {
+    /// Percent over baseline counted as touch, half of it releases
+    pub const DEFAULT_THRESHOLD: u32 = 20;
+
+    pub fn new() -> Self {
+        let rcc = unsafe { &*stm32::RCC::ptr() };
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        rcc.iopenr.modify(|_, w| w.iopben().set_bit());
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << (SEND_PIN + 16)) });
+        gpio.moder.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << (SEND_PIN * 2))) | (0b01 << (SEND_PIN * 2)))
+        });
+        pins::reserve(Port::B, SEND_PIN as u8, Owner::Touch);
+        pins::reserve(Port::B, SENSE_PIN as u8, Owner::Touch);
+        Self {
+            baseline: cal::get(cal::TOUCH_BASELINE) as u32,
+            threshold: Self::DEFAULT_THRESHOLD,
+            enabled: false,
+            touched: false,
+            due: false,
+        }
+    }
+
+    /// Charge time of the pad in timer cycles, averaged
+    pub fn measure(&self) -> u32 {
+        (0..SAMPLES).map(|_| Self::charge_time()).sum::<u32>() / SAMPLES
+    }
+
+    /// Takes the untouched pad as reference
+    pub fn calibrate(&mut self) -> u32 {
+        self.baseline = (0..CAL_ROUNDS).map(|_| self.measure()).sum::<u32>() / CAL_ROUNDS;
+        self.touched = false;
+        self.baseline
+    }
+
+    pub fn baseline(&self) -> u32 {
+        self.baseline
+    }
+
+    pub fn threshold(&self) -> u32 {
+        self.threshold
+    }
+
+    pub fn set_threshold(&mut self, threshold: u32) {
+        self.threshold = threshold;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.due = false;
+    }
+
+    pub fn is_touched(&self) -> bool {
+        self.touched
+    }
+
+    /// Applies a reading with hysteresis, returns true on a new touch
+    pub fn update(&mut self, reading: u32) -> bool {
+        let press = self.baseline + self.baseline * self.threshold / 100;
+        let release = self.baseline + self.baseline * self.threshold / 200;
+        let was_touched = self.touched;
+        if reading > press {
+            self.touched = true;
+        } else if reading < release {
+            self.touched = false;
+        }
+        self.touched && !was_touched
+    }
+
+    /// Advances by one system tick, returns true when a check is due
+    pub fn tick(&mut self) -> bool {
+        self.advance(1)
+    }
+
+    /// Advances by a number of ticks slept through in Stop mode, a wakeup short of a
+    /// whole tick leaves the loop where it was
+    pub fn advance(&mut self, ticks: u32) -> bool {
+        if ticks > 0 {
+            self.due = self.enabled;
+        }
+        self.due
+    }
+
+    /// A check is due on the next tick
+    pub fn ticks_until_due(&self) -> Option<u32> {
+        if self.enabled {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    pub fn take_due(&mut self) -> bool {
+        let due = self.due;
+        self.due = false;
+        due
+    }
+
+    fn charge_time() -> u32 {
+        let gpio = unsafe { &*stm32::GPIOB::ptr() };
+        let sense = 1 << SENSE_PIN;
+        // Discharge the pad, then let it float
+        gpio.bsrr
+            .write(|w| unsafe { w.bits(1 << (SEND_PIN + 16) | 1 << (SENSE_PIN + 16)) });
+        gpio.moder.modify(|r, w| unsafe {
+            w.bits((r.bits() & !(0b11 << (SENSE_PIN * 2))) | (0b01 << (SENSE_PIN * 2)))
+        });
+        cortex_m::asm::delay(200);
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << (SENSE_PIN * 2))) });
+
+        // Interrupts would show up as extra capacitance
+        let elapsed = cortex_m::interrupt::free(|_| {
+            let start = cycles::now();
+            gpio.bsrr.write(|w| unsafe { w.bits(1 << SEND_PIN) });
+            while gpio.idr.read().bits() & sense == 0 {
+                if cycles::since(start) > TIMEOUT {
+                    break;
+                }
+            }
+            cycles::since(start)
+        });
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << (SEND_PIN + 16)) });
+        elapsed
+    }
+}
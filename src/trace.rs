@@ -0,0 +1,107 @@
+use hal::hal::serial::{Read, Write};
+use hal::nb;
+
+use crate::config::TRACE_LEN;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+/// Circular capture of the most recent serial traffic
+#[derive(Clone)]
+pub struct Trace {
+    entries: [u16; TRACE_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl Trace {
+    const TX_FLAG: u16 = 0x100;
+
+    pub fn new() -> Self {
+        Self {
+            entries: [0; TRACE_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn record(&mut self, dir: Direction, byte: u8) {
+        let flag = if dir == Direction::Tx {
+            Self::TX_FLAG
+        } else {
+            0
+        };
+        self.entries[self.head] = flag | byte as u16;
+        self.head = (self.head + 1) % TRACE_LEN;
+        self.len = (self.len + 1).min(TRACE_LEN);
+    }
+
+    /// Iterates captured bytes from oldest to newest
+    pub fn iter(&self) -> impl Iterator<Item = (Direction, u8)> + '_ {
+        let start = (self.head + TRACE_LEN - self.len) % TRACE_LEN;
+        (0..self.len).map(move |idx| {
+            let entry = self.entries[(start + idx) % TRACE_LEN];
+            let dir = if entry & Self::TX_FLAG != 0 {
+                Direction::Tx
+            } else {
+                Direction::Rx
+            };
+            (dir, entry as u8)
+        })
+    }
+}
+
+/// Serial port wrapper recording every byte into a trace buffer
+pub struct Traced<S> {
+    serial: S,
+    trace: Trace,
+}
+
+impl<S> Traced<S> {
+    pub fn new(serial: S) -> Self {
+        Self {
+            serial,
+            trace: Trace::new(),
+        }
+    }
+
+    pub fn trace(&mut self) -> &mut Trace {
+        &mut self.trace
+    }
+}
+
+impl<S: Read<u8>> Read<u8> for Traced<S> {
+    type Error = S::Error;
+
+    fn read(&mut self) -> nb::Result<u8, Self::Error> {
+        let byte = self.serial.read()?;
+        self.trace.record(Direction::Rx, byte);
+        Ok(byte)
+    }
+}
+
+impl<S: Write<u8>> Write<u8> for Traced<S> {
+    type Error = S::Error;
+
+    fn write(&mut self, byte: u8) -> nb::Result<(), Self::Error> {
+        self.serial.write(byte)?;
+        self.trace.record(Direction::Tx, byte);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> nb::Result<(), Self::Error> {
+        self.serial.flush()
+    }
+}
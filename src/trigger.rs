@@ -0,0 +1,121 @@
+use hal::stm32;
+
+use crate::pins::{self, Owner, PinError, Port};
+
+#[derive(Clone, Copy)]
+pub enum Event {
+    Dispatch,
+    Tick,
+}
+
+pub const EVENTS: [(&str, Event); 2] = [("dispatch", Event::Dispatch), ("tick", Event::Tick)];
+
+impl Event {
+    pub fn from_name(name: &str) -> Option<Event> {
+        EVENTS
+            .iter()
+            .find(|(event_name, _)| *event_name == name)
+            .map(|(_, event)| *event)
+    }
+
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Scope trigger: single pulse of a fixed width in core cycles
+pub struct Trigger {
+    pin: Option<u8>,
+    width: u32,
+    events: u8,
+}
+
+impl Trigger {
+    /// The pulse runs with interrupts masked. 64 cycles are 4us at 16MHz and 32us at
+    /// the 2MHz low power clock, shorter than a byte on the shell USART.
+    pub const MAX_WIDTH: u32 = 64;
+
+    pub fn new() -> Self {
+        Self {
+            pin: None,
+            width: 16,
+            events: 0,
+        }
+    }
+
+    pub fn pin(&self) -> Option<u8> {
+        self.pin
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn set_width(&mut self, width: u32) {
+        self.width = width;
+    }
+
+    pub fn is_listening(&self, event: Event) -> bool {
+        self.events & event.mask() != 0
+    }
+
+    pub fn listen(&mut self, event: Event) {
+        self.events |= event.mask();
+    }
+
+    pub fn unlisten(&mut self, event: Event) {
+        self.events &= !event.mask();
+    }
+
+    /// Configures port A pin as push-pull output driven low, the previous pin goes back
+    /// to an analog input
+    pub fn set_pin(&mut self, pin: u8) -> Result<(), PinError> {
+        if pin > 15 {
+            return Err(PinError::Unsupported);
+        }
+        pins::check(Port::A, pin, Owner::Trigger)?;
+        let gpio = unsafe { &*stm32::GPIOA::ptr() };
+        let old = self.pin.take().filter(|old| *old != pin);
+        if let Some(old) = old.filter(|old| pins::owner(Port::A, *old) == Some(Owner::Trigger)) {
+            gpio.moder
+                .modify(|r, w| unsafe { w.bits(r.bits() | (0b11 << (old * 2))) });
+            pins::release(Port::A, old, Owner::Trigger);
+        }
+        let shift = pin * 2;
+        gpio.bsrr.write(|w| unsafe { w.bits(1 << (pin + 16)) });
+        gpio.otyper
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << pin)) });
+        gpio.ospeedr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (0b11 << shift)) });
+        gpio.moder
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0b11 << shift)) | (0b01 << shift)) });
+        self.pin = Some(pin);
+        pins::claim(Port::A, pin, Owner::Trigger)
+    }
+
+    pub fn fire(&self) {
+        if let Some(pin) = self.pin {
+            let gpio = unsafe { &*stm32::GPIOA::ptr() };
+            cortex_m::interrupt::free(|_| {
+                gpio.bsrr.write(|w| unsafe { w.bits(1 << pin) });
+                cortex_m::asm::delay(self.width);
+                gpio.bsrr.write(|w| unsafe { w.bits(1 << (pin + 16)) });
+            });
+        }
+    }
+
+    pub fn fire_on(&self, event: Event) {
+        if self.is_listening(event) {
+            self.fire();
+        }
+    }
+}
+
+/// Parses port A pin names like `a6` or `pa6`
+pub fn parse_pin(name: &str) -> Option<u8> {
+    let num = name.strip_prefix("pa").or_else(|| name.strip_prefix("a"))?;
+    match btoi::btoi::<u8>(num.as_bytes()) {
+        Ok(pin) if pin <= 15 => Some(pin),
+        _ => None,
+    }
+}
@@ -0,0 +1,60 @@
+//! How chatty commands are. Errors and requested readouts always print, `say!` lines are
+//! confirmations dropped when quiet and `detail!` lines only print when verbose.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+pub const LEVELS: [(&str, Level); 3] = [
+    ("quiet", Level::Quiet),
+    ("normal", Level::Normal),
+    ("verbose", Level::Verbose),
+];
+
+static LEVEL: AtomicU32 = AtomicU32::new(Level::Normal as u32);
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Level> {
+        LEVELS
+            .iter()
+            .find(|(level_name, _)| *level_name == name)
+            .map(|(_, level)| *level)
+    }
+
+    pub fn name(self) -> &'static str {
+        LEVELS[self as usize].0
+    }
+}
+
+pub fn level() -> Level {
+    LEVELS[LEVEL.load(Ordering::Relaxed) as usize].1
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u32, Ordering::Relaxed);
+}
+
+/// Writes a confirmation unless quiet
+macro_rules! say {
+    ($out:expr, $($arg:tt)*) => {
+        if crate::verbosity::level() >= crate::verbosity::Level::Normal {
+            write!($out, $($arg)*).ok();
+        }
+    };
+}
+
+/// Writes extra detail when verbose
+macro_rules! detail {
+    ($out:expr, $($arg:tt)*) => {
+        if crate::verbosity::level() >= crate::verbosity::Level::Verbose {
+            write!($out, $($arg)*).ok();
+        }
+    };
+}
+
+pub(crate) use {detail, say};
@@ -0,0 +1,105 @@
+use heapless::Vec;
+
+pub const MAX_SAMPLES: usize = 256;
+/// PWM carrier of the LED, samples are switched on its update events
+pub const CARRIER_HZ: u32 = 1000;
+pub const MAX_RATE: u32 = CARRIER_HZ;
+
+/// What the PWM update interrupt does next
+pub enum Step {
+    Hold,
+    Duty(u8),
+    Done,
+}
+
+/// Brightness envelope in percent, played on a PWM output
+pub struct Wave {
+    samples: Vec<u8, MAX_SAMPLES>,
+    uploading: bool,
+    playing: bool,
+    looped: bool,
+    pos: usize,
+    divider: u32,
+    countdown: u32,
+}
+
+impl Wave {
+    pub fn new() -> Self {
+        Self {
+            samples: Vec::new(),
+            uploading: false,
+            playing: false,
+            looped: false,
+            pos: 0,
+            divider: 1,
+            countdown: 0,
+        }
+    }
+
+    /// Discards the table, following shell lines are parsed as samples
+    pub fn begin_upload(&mut self) {
+        self.playing = false;
+        self.samples.clear();
+        self.uploading = true;
+    }
+
+    pub fn end_upload(&mut self) {
+        self.uploading = false;
+    }
+
+    pub fn is_uploading(&self) -> bool {
+        self.uploading
+    }
+
+    /// Appends a sample, false when the table is full
+    pub fn push(&mut self, sample: u8) -> bool {
+        self.samples.push(sample).is_ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Starts playback at `rate` samples per second, false for an empty table
+    pub fn play(&mut self, rate: u32, looped: bool) -> bool {
+        if self.samples.is_empty() {
+            return false;
+        }
+        self.looped = looped;
+        self.pos = 0;
+        self.divider = CARRIER_HZ / rate;
+        self.countdown = 0;
+        self.playing = true;
+        true
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    /// Called on every carrier period
+    pub fn step(&mut self) -> Step {
+        if !self.playing {
+            return Step::Done;
+        }
+        if self.countdown > 1 {
+            self.countdown -= 1;
+            return Step::Hold;
+        }
+        self.countdown = self.divider;
+        if self.pos == self.samples.len() {
+            if !self.looped {
+                self.playing = false;
+                return Step::Done;
+            }
+            self.pos = 0;
+        }
+        let duty = self.samples[self.pos];
+        self.pos += 1;
+        Step::Duty(duty)
+    }
+}